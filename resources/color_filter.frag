@@ -0,0 +1,46 @@
+#version 100
+
+//_DEFINES_
+
+#if defined(EXTERNAL)
+#extension GL_OES_EGL_image_external : require
+#endif
+
+precision mediump float;
+#if defined(EXTERNAL)
+uniform samplerExternalOES tex;
+#else
+uniform sampler2D tex;
+#endif
+
+uniform float alpha;
+varying vec2 v_coords;
+
+#if defined(DEBUG_FLAGS)
+uniform float tint;
+#endif
+
+// Rows of the color matrix and the offset added after it, for colors without
+// premultiplied alpha
+uniform vec3 red;
+uniform vec3 green;
+uniform vec3 blue;
+uniform vec3 offset;
+
+void main() {
+    vec4 color = texture2D(tex, v_coords);
+#if defined(NO_ALPHA)
+    color = vec4(color.rgb, 1.0);
+#endif
+
+    vec3 rgb = color.a > 0.0 ? color.rgb / color.a : vec3(0.0);
+    rgb = vec3(dot(red, rgb), dot(green, rgb), dot(blue, rgb)) + offset;
+    color = vec4(clamp(rgb, 0.0, 1.0) * color.a, color.a) * alpha;
+
+#if defined(DEBUG_FLAGS)
+    if (tint == 1.0)
+        color = vec4(0.0, 0.2, 0.0, 0.2) + color * 0.8;
+#endif
+
+    gl_FragColor = color;
+}
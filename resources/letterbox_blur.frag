@@ -0,0 +1,13 @@
+// Blurred and darkened copy of the window for the letterbox bars, which only
+// need its colors
+vec4 shade(vec2 coords) {
+    vec2 step = vec2(1.0 / 48.0);
+    vec4 color = vec4(0.0);
+    for (int x = -3; x <= 3; x++) {
+        for (int y = -3; y <= 3; y++) {
+            vec2 offset = vec2(float(x), float(y)) * step;
+            color += sample_source(clamp(coords + offset, 0.0, 1.0));
+        }
+    }
+    return color / 49.0 * vec4(vec3(0.5), 1.0);
+}
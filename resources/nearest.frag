@@ -0,0 +1,4 @@
+// Sharp pixels, sampling the center of the texel under each fragment
+vec4 shade(vec2 coords) {
+    return sample_source((floor(coords * texture_size) + 0.5) / texture_size);
+}
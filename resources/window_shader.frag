@@ -0,0 +1,50 @@
+#version 100
+
+//_DEFINES_
+
+#if defined(EXTERNAL)
+#extension GL_OES_EGL_image_external : require
+#endif
+
+precision highp float;
+#if defined(EXTERNAL)
+uniform samplerExternalOES tex;
+#else
+uniform sampler2D tex;
+#endif
+
+uniform float alpha;
+varying vec2 v_coords;
+
+#if defined(DEBUG_FLAGS)
+uniform float tint;
+#endif
+
+// Size of the part of the window buffer drawn, and of the area it is drawn into,
+// in pixels
+uniform vec2 source_size;
+uniform vec2 output_size;
+// Size of the whole window buffer in pixels, which `coords` go across
+uniform vec2 texture_size;
+
+// Color of the window at `coords`, from 0 to 1 across the buffer
+vec4 sample_source(vec2 coords) {
+    vec4 color = texture2D(tex, coords);
+#if defined(NO_ALPHA)
+    color = vec4(color.rgb, 1.0);
+#endif
+    return color;
+}
+
+//_SHADER_
+
+void main() {
+    vec4 color = shade(v_coords) * alpha;
+
+#if defined(DEBUG_FLAGS)
+    if (tint == 1.0)
+        color = vec4(0.0, 0.2, 0.0, 0.2) + color * 0.8;
+#endif
+
+    gl_FragColor = color;
+}
@@ -0,0 +1,40 @@
+use std::time::{Duration, Instant};
+
+/// Time source of compositor animations.
+/// With a rate set, it only advances in steps of that rate, so an animation running on a
+/// high refresh output only causes a repaint on every step instead of on every refresh.
+#[derive(Debug, Clone, Copy)]
+pub struct AnimationClock {
+    epoch: Instant,
+    tick: Option<Duration>,
+}
+
+impl AnimationClock {
+    pub fn new(rate: Option<f64>) -> Self {
+        Self {
+            epoch: Instant::now(),
+            tick: rate
+                .filter(|rate| *rate > 0.0)
+                .map(|rate| Duration::from_secs_f64(1.0 / rate)),
+        }
+    }
+
+    /// Change the rate, keeping the time
+    pub fn set_rate(&mut self, rate: Option<f64>) {
+        *self = Self {
+            epoch: self.epoch,
+            ..Self::new(rate)
+        };
+    }
+
+    pub fn now(&self) -> Duration {
+        let elapsed = self.epoch.elapsed();
+        match self.tick {
+            Some(tick) => {
+                let tick = tick.as_nanos().max(1);
+                Duration::from_nanos((elapsed.as_nanos() / tick * tick) as u64)
+            }
+            None => elapsed,
+        }
+    }
+}
@@ -0,0 +1,409 @@
+use std::{
+    fmt,
+    fs::{self, read_dir},
+    io,
+    path::PathBuf,
+    sync::mpsc,
+    thread,
+    time::{Duration, Instant},
+};
+
+use rusttype::Font;
+use smithay::{
+    backend::{
+        allocator::Fourcc,
+        renderer::{
+            element::{
+                memory::{MemoryRenderBuffer, MemoryRenderBufferRenderElement},
+                Kind,
+            },
+            ImportMem, Renderer,
+        },
+    },
+    input::keyboard::Keysym,
+    output::Output,
+    reexports::calloop::timer::{TimeoutAction, Timer},
+    utils::{Physical, Point, Size, Transform},
+};
+use tracing::{debug, info, warn};
+use xkbcommon::xkb;
+use zbus::blocking::Connection;
+
+use crate::{
+    dbus::Requests,
+    input_handler::KeyAction,
+    state::{AnvilState, Backend},
+    text::{draw_text, fill_rect, load_font},
+};
+
+const SYSFS: &str = "/sys/class/backlight";
+const BUS_NAME: &str = "org.consolation.Backlight";
+const OBJECT_PATH: &str = "/org/consolation/Backlight";
+const BOX_WIDTH: i32 = 320;
+const BOX_MARGIN: i32 = 32;
+const BOX_PADDING: i32 = 12;
+const LINE_HEIGHT: i32 = 22;
+const BAR_HEIGHT: i32 = 8;
+const FONT_SIZE: f32 = 16.0;
+/// Premultiplied BGRA
+const BACKDROP_COLOR: [u8; 4] = [20, 20, 20, 230];
+const TRACK_COLOR: [u8; 4] = [70, 70, 70, 255];
+const BAR_COLOR: [u8; 4] = [230, 230, 230, 255];
+
+#[zbus::proxy(
+    interface = "org.freedesktop.login1.Session",
+    default_service = "org.freedesktop.login1",
+    default_path = "/org/freedesktop/login1/session/auto"
+)]
+trait LoginSession {
+    fn set_brightness(&self, subsystem: &str, name: &str, brightness: u32) -> zbus::Result<()>;
+}
+
+#[derive(Debug)]
+enum Request {
+    Get,
+    Set(f64),
+    Step(i32),
+}
+
+/// `org.consolation.Backlight` object, forwarding calls of scripts to the event loop
+struct BacklightServer {
+    /// Answered with the brightness after the request
+    requests: Requests<Request, f64>,
+}
+
+#[zbus::interface(name = "org.consolation.Backlight")]
+impl BacklightServer {
+    /// The brightness between 0 and 1, or a negative one without a backlight
+    async fn get(&self) -> f64 {
+        self.requests.call(Request::Get).await.unwrap_or(-1.0)
+    }
+
+    /// Set the brightness between 0 and 1, kept above the configured minimum
+    fn set(&self, brightness: f64) {
+        self.requests.send(Request::Set(brightness));
+    }
+
+    /// Go `steps` configured steps brighter, or darker when negative
+    fn step(&self, steps: i32) {
+        self.requests.send(Request::Step(steps));
+    }
+}
+
+#[derive(Debug)]
+struct Device {
+    name: String,
+    max: u32,
+}
+
+/// Brightness shown briefly after a change
+#[derive(Debug)]
+struct Indicator {
+    until: Instant,
+    /// Drawn indicator, its size and the output scale it was drawn for
+    buffer: Option<(f64, MemoryRenderBuffer, Size<i32, Physical>)>,
+}
+
+/// Screen backlight of handhelds and laptops, written from its own thread
+#[derive(Default)]
+pub struct Backlight {
+    device: Option<Device>,
+    /// Current brightness in steps of the device
+    level: u32,
+    writer: Option<mpsc::Sender<u32>>,
+    font: Option<Font<'static>>,
+    indicator: Option<Indicator>,
+}
+
+impl fmt::Debug for Backlight {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Backlight")
+            .field("device", &self.device)
+            .field("level", &self.level)
+            .field("font", &self.font.is_some())
+            .field("indicator", &self.indicator)
+            .finish()
+    }
+}
+
+fn read_value(name: &str, file: &str) -> io::Result<u32> {
+    let path = PathBuf::from(SYSFS).join(name).join(file);
+    fs::read_to_string(path)?
+        .trim()
+        .parse()
+        .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))
+}
+
+/// The configured device, else the first one with a brightness range
+fn find_device(name: Option<&str>) -> Option<Device> {
+    let names = match name {
+        Some(name) => vec![name.to_string()],
+        None => {
+            let mut names = read_dir(SYSFS)
+                .ok()?
+                .flatten()
+                .map(|entry| entry.file_name().to_string_lossy().into_owned())
+                .collect::<Vec<_>>();
+            names.sort();
+            names
+        }
+    };
+    names.into_iter().find_map(|name| {
+        let max = read_value(&name, "max_brightness").ok()?;
+        (max > 0).then_some(Device { name, max })
+    })
+}
+
+/// Write levels as they come, skipping those already replaced by newer ones.
+/// The brightness file usually belongs to root, logind then sets it for the session
+fn run_writer(name: String, levels: mpsc::Receiver<u32>) {
+    let path = PathBuf::from(SYSFS).join(&name).join("brightness");
+    let mut session = None;
+    while let Ok(level) = levels.recv() {
+        let level = levels.try_iter().last().unwrap_or(level);
+        let err = match fs::write(&path, level.to_string()) {
+            Ok(()) => continue,
+            Err(err) => err,
+        };
+        if session.is_none() {
+            session = Connection::system()
+                .and_then(|connection| LoginSessionProxyBlocking::new(&connection))
+                .map_err(|err| warn!(?err, "Unable to connect to logind"))
+                .ok();
+        }
+        let Some(session) = session.as_ref() else {
+            warn!(?err, "Unable to set the brightness");
+            continue;
+        };
+        if let Err(err) = session.set_brightness("backlight", &name, level) {
+            warn!(?err, "logind refused to set the brightness");
+        }
+    }
+}
+
+/// Backdrop with the brightness in percent above a bar, in physical pixels
+fn draw_indicator(
+    font: Option<&Font<'static>>,
+    fraction: f64,
+    scale: f64,
+) -> (MemoryRenderBuffer, Size<i32, Physical>) {
+    let scaled = |value: i32| (value as f64 * scale).round() as i32;
+    let width = scaled(BOX_WIDTH);
+    let padding = scaled(BOX_PADDING);
+    let line_height = scaled(LINE_HEIGHT);
+    let bar_height = scaled(BAR_HEIGHT).max(1);
+    let height = padding * 3 + line_height + bar_height;
+
+    let mut pixels = BACKDROP_COLOR.repeat((width * height) as usize);
+    if let Some(font) = font {
+        let label = format!("Brightness {}%", (fraction * 100.0).round());
+        let origin = (padding as f32, padding as f32);
+        let size = FONT_SIZE * scale as f32;
+        draw_text(
+            &mut pixels,
+            width,
+            font,
+            &label,
+            size,
+            origin,
+            width - padding,
+        );
+    }
+    let track = (
+        padding,
+        padding * 2 + line_height,
+        width - padding * 2,
+        bar_height,
+    );
+    fill_rect(&mut pixels, width, track, TRACK_COLOR);
+    let done = (track.2 as f64 * fraction).round() as i32;
+    fill_rect(
+        &mut pixels,
+        width,
+        (track.0, track.1, done, track.3),
+        BAR_COLOR,
+    );
+
+    let buffer = MemoryRenderBuffer::from_slice(
+        &pixels,
+        Fourcc::Argb8888,
+        (width, height),
+        1,
+        Transform::Normal,
+        None,
+    );
+    (buffer, Size::from((width, height)))
+}
+
+impl Backlight {
+    fn fraction(&self) -> Option<f64> {
+        let device = self.device.as_ref()?;
+        Some(self.level as f64 / device.max as f64)
+    }
+
+    /// The brightness at the top center of `output` after a change
+    pub fn render_elements<R>(
+        &mut self,
+        renderer: &mut R,
+        output: &Output,
+    ) -> Vec<MemoryRenderBufferRenderElement<R>>
+    where
+        R: Renderer + ImportMem,
+        <R as Renderer>::TextureId: Send + Clone + 'static,
+    {
+        let Some(fraction) = self.fraction() else {
+            return Vec::new();
+        };
+        let Some(indicator) = self.indicator.as_mut() else {
+            return Vec::new();
+        };
+        let Some(mode) = output.current_mode() else {
+            return Vec::new();
+        };
+        let size: Size<i32, Physical> = output.current_transform().transform_size(mode.size);
+        let scale = output.current_scale().fractional_scale();
+        let margin = (BOX_MARGIN as f64 * scale).round() as i32;
+
+        if indicator.buffer.as_ref().map(|(drawn, ..)| *drawn) != Some(scale) {
+            let (buffer, size) = draw_indicator(self.font.as_ref(), fraction, scale);
+            indicator.buffer = Some((scale, buffer, size));
+        }
+        let (_, buffer, indicator_size) = indicator.buffer.as_ref().unwrap();
+        let location = Point::<i32, Physical>::from(((size.w - indicator_size.w) / 2, margin));
+        match MemoryRenderBufferRenderElement::from_buffer(
+            renderer,
+            location.to_f64(),
+            buffer,
+            None,
+            None,
+            None,
+            Kind::Unspecified,
+        ) {
+            Ok(element) => vec![element],
+            Err(err) => {
+                warn!(?err, "Unable to upload brightness");
+                Vec::new()
+            }
+        }
+    }
+}
+
+impl<BackendData: Backend> AnvilState<BackendData> {
+    /// Find the backlight, and serve `org.consolation.Backlight` if enabled
+    pub fn start_backlight(&mut self) {
+        let config = self.config.backlight.clone();
+        let Some(device) = find_device(config.device.as_deref()) else {
+            info!(device = ?config.device, "No backlight found");
+            return;
+        };
+        self.backlight.level = read_value(&device.name, "brightness").unwrap_or(device.max);
+        info!(
+            device = device.name,
+            level = self.backlight.level,
+            max = device.max,
+            "Found backlight"
+        );
+
+        let (writer, levels) = mpsc::channel();
+        let name = device.name.clone();
+        thread::spawn(move || run_writer(name, levels));
+        self.backlight.writer = Some(writer);
+        self.backlight.device = Some(device);
+        if config.osd_secs > 0.0 {
+            self.backlight.font = load_font(config.font.as_deref());
+        }
+        if !config.serve_dbus {
+            return;
+        }
+
+        let Some(requests) = self.insert_requests(|data, request| {
+            match request {
+                Request::Get => {}
+                Request::Set(brightness) => data.set_brightness(brightness),
+                Request::Step(steps) => data.step_brightness(steps),
+            }
+            data.backlight.fraction().unwrap_or(-1.0)
+        }) else {
+            return;
+        };
+        let server = BacklightServer { requests };
+        match self.dbus.serve(BUS_NAME, OBJECT_PATH, server, false) {
+            Ok(true) => info!("Serving backlight control"),
+            Ok(false) => warn!("Backlight bus name is already taken"),
+            Err(err) => warn!(?err, "Unable to serve backlight control"),
+        }
+    }
+
+    /// Check if `keysym` is a brightness key, while there is a backlight to change
+    pub fn backlight_key_action(&self, keysym: Keysym) -> Option<KeyAction> {
+        self.backlight.device.as_ref()?;
+        let config = &self.config.backlight;
+        let matches = |name: &str| {
+            xkb::keysym_from_name(name, xkb::KEYSYM_CASE_INSENSITIVE).raw() == keysym.raw()
+        };
+        if matches(&config.up_key) {
+            Some(KeyAction::BrightnessUp)
+        } else if matches(&config.down_key) {
+            Some(KeyAction::BrightnessDown)
+        } else {
+            None
+        }
+    }
+
+    /// Go `steps` configured steps brighter, or darker when negative
+    pub fn step_brightness(&mut self, steps: i32) {
+        let Some(fraction) = self.backlight.fraction() else {
+            return;
+        };
+        self.set_brightness(fraction + steps as f64 * self.config.backlight.step);
+    }
+
+    /// Set the brightness between 0 and 1, kept above the configured minimum so
+    /// the screen stays readable
+    pub fn set_brightness(&mut self, brightness: f64) {
+        let config = &self.config.backlight;
+        let osd_secs = config.osd_secs;
+        let Some(device) = self.backlight.device.as_ref() else {
+            return;
+        };
+        if !brightness.is_finite() {
+            warn!(brightness, "Ignoring invalid brightness");
+            return;
+        }
+        let brightness = brightness.clamp(config.minimum.clamp(0.0, 1.0), 1.0);
+        let level = (brightness * device.max as f64).round() as u32;
+        debug!(level, max = device.max, "Setting brightness");
+        self.backlight.level = level;
+        if let Some(writer) = self.backlight.writer.as_ref() {
+            let _ = writer.send(level);
+        }
+        if osd_secs > 0.0 {
+            self.show_brightness(Duration::from_secs_f64(osd_secs));
+        }
+    }
+
+    fn show_brightness(&mut self, duration: Duration) {
+        let until = Instant::now() + duration;
+        self.backlight.indicator = Some(Indicator {
+            until,
+            buffer: None,
+        });
+        let res = self
+            .handle
+            .insert_source(Timer::from_duration(duration), move |_, _, data| {
+                // Changing the brightness again started a new timer
+                let backlight = &mut data.backlight;
+                if backlight
+                    .indicator
+                    .as_ref()
+                    .is_some_and(|indicator| indicator.until == until)
+                {
+                    backlight.indicator = None;
+                }
+                TimeoutAction::Drop
+            });
+        if let Err(err) = res {
+            warn!(?err, "Unable to insert brightness timer");
+        }
+    }
+}
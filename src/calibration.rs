@@ -0,0 +1,131 @@
+use smithay::{
+    backend::renderer::element::{
+        solid::{SolidColorBuffer, SolidColorRenderElement},
+        Kind,
+    },
+    output::Output,
+    reexports::input::Device,
+    utils::{Logical, Physical, Point, Scale, Size},
+};
+use tracing::warn;
+
+/// Where the targets are shown, in normalized coordinates of the touchscreen.
+/// They must not be on a line, otherwise the matrix can't be solved.
+const TARGETS: [(f64, f64); 3] = [(0.1, 0.1), (0.9, 0.5), (0.1, 0.9)];
+const IDENTITY_MATRIX: [f32; 6] = [1.0, 0.0, 0.0, 0.0, 1.0, 0.0];
+
+const TARGET_SIZE: i32 = 48;
+const TARGET_THICKNESS: i32 = 4;
+const TARGET_COLOR: [f32; 4] = [1.0, 0.2, 0.2, 1.0];
+const BACKDROP_COLOR: [f32; 4] = [0.0, 0.0, 0.0, 0.8];
+
+/// Interactive calibration of a touchscreen.
+/// The user touches a sequence of targets and the libinput calibration matrix
+/// mapping the raw touches onto them is computed from that.
+#[derive(Debug)]
+pub struct TouchCalibration {
+    device: Device,
+    samples: Vec<(f64, f64)>,
+    backdrop: SolidColorBuffer,
+    horizontal: SolidColorBuffer,
+    vertical: SolidColorBuffer,
+}
+
+impl TouchCalibration {
+    /// Reset the calibration of `device`, so that touches report raw coordinates
+    pub fn new(mut device: Device) -> Self {
+        let _ = device.config_calibration_set_matrix(IDENTITY_MATRIX);
+        Self {
+            device,
+            samples: Vec::new(),
+            backdrop: SolidColorBuffer::new((0, 0), BACKDROP_COLOR),
+            horizontal: SolidColorBuffer::new((TARGET_SIZE, TARGET_THICKNESS), TARGET_COLOR),
+            vertical: SolidColorBuffer::new((TARGET_THICKNESS, TARGET_SIZE), TARGET_COLOR),
+        }
+    }
+
+    pub fn device(&self) -> &Device {
+        &self.device
+    }
+
+    /// Record a touch at `position`, normalized to the touchscreen.
+    /// Returns the calibration matrix once all targets got touched.
+    pub fn add_sample(&mut self, position: (f64, f64)) -> Option<[f32; 6]> {
+        self.samples.push(position);
+        if self.samples.len() < TARGETS.len() {
+            return None;
+        }
+        let matrix = solve_matrix(&self.samples);
+        if matrix.is_none() {
+            warn!("Touches too close together, restarting calibration");
+            self.samples.clear();
+        }
+        matrix
+    }
+
+    pub fn render_elements(&mut self, output: &Output) -> Vec<SolidColorRenderElement> {
+        let Some(mode) = output.current_mode() else {
+            return Vec::new();
+        };
+        let Some(target) = TARGETS.get(self.samples.len()) else {
+            return Vec::new();
+        };
+
+        let transform = output.current_transform();
+        let mode_size: Size<i32, Logical> = mode.size.to_logical(1);
+        let touch_size = transform.invert().transform_size(mode_size).to_f64();
+        let center = transform.transform_point_in(
+            Point::<f64, Logical>::from((target.0 * touch_size.w, target.1 * touch_size.h)),
+            &touch_size,
+        );
+        let center: Point<i32, Physical> = center.to_physical(1.0).to_i32_round();
+
+        self.backdrop.resize(transform.transform_size(mode_size));
+        vec![
+            SolidColorRenderElement::from_buffer(
+                &self.horizontal,
+                center - Point::from((TARGET_SIZE / 2, TARGET_THICKNESS / 2)),
+                Scale::from(1.0),
+                1.0,
+                Kind::Unspecified,
+            ),
+            SolidColorRenderElement::from_buffer(
+                &self.vertical,
+                center - Point::from((TARGET_THICKNESS / 2, TARGET_SIZE / 2)),
+                Scale::from(1.0),
+                1.0,
+                Kind::Unspecified,
+            ),
+            SolidColorRenderElement::from_buffer(
+                &self.backdrop,
+                (0, 0),
+                Scale::from(1.0),
+                1.0,
+                Kind::Unspecified,
+            ),
+        ]
+    }
+}
+
+/// Find the affine matrix moving every sample onto its target
+fn solve_matrix(samples: &[(f64, f64)]) -> Option<[f32; 6]> {
+    let [(x1, y1), (x2, y2), (x3, y3)] = samples else {
+        return None;
+    };
+    let det = x1 * (y2 - y3) - y1 * (x2 - x3) + (x2 * y3 - x3 * y2);
+    if det.abs() < 1e-6 {
+        return None;
+    }
+
+    // Cramer's rule on [x y 1] * [a b c] = target, once per axis
+    let solve = |t1: f64, t2: f64, t3: f64| {
+        let a = (t1 * (y2 - y3) - y1 * (t2 - t3) + (t2 * y3 - t3 * y2)) / det;
+        let b = (x1 * (t2 - t3) - t1 * (x2 - x3) + (x2 * t3 - x3 * t2)) / det;
+        let c =
+            (x1 * (y2 * t3 - y3 * t2) - y1 * (x2 * t3 - x3 * t2) + t1 * (x2 * y3 - x3 * y2)) / det;
+        (a as f32, b as f32, c as f32)
+    };
+    let (a, b, c) = solve(TARGETS[0].0, TARGETS[1].0, TARGETS[2].0);
+    let (d, e, f) = solve(TARGETS[0].1, TARGETS[1].1, TARGETS[2].1);
+    Some([a, b, c, d, e, f])
+}
@@ -0,0 +1,203 @@
+use std::{
+    collections::HashMap,
+    fs::File,
+    io::{ErrorKind, Read, Write},
+    os::unix::{io::OwnedFd, net::UnixStream},
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
+};
+
+use smithay::{
+    reexports::calloop::{generic::Generic, Interest, Mode, PostAction},
+    wayland::selection::{
+        data_device::{request_data_device_client_selection, set_data_device_selection},
+        SelectionSource, SelectionTarget,
+    },
+};
+use tracing::{debug, warn};
+
+use crate::state::{AnvilState, Backend};
+
+/// Clients that went away so far. Whichever client owns the selection, data control
+/// clients included, it is cleared before its client counts as disconnected
+pub static DISCONNECTED_CLIENTS: AtomicU64 = AtomicU64::new(0);
+
+/// Who provides the data of a selection owned by the compositor
+#[derive(Debug, Clone)]
+pub enum SelectionOrigin {
+    /// Forwarded to Xwayland on request
+    Xwayland,
+    /// Contents cached from a client that went away
+    Cached(Arc<HashMap<String, Vec<u8>>>),
+}
+
+#[derive(Debug, Default)]
+pub struct ClipboardStore {
+    /// Bumped for every new selection, so late transfers of older ones are dropped
+    generation: u64,
+    mime_types: Vec<String>,
+    contents: HashMap<String, Vec<u8>>,
+    /// Bytes read of the selection so far, over all its mime types
+    size: usize,
+}
+
+impl<BackendData: Backend> AnvilState<BackendData> {
+    /// Start copying the contents of a new clipboard selection made by a client
+    pub fn cache_clipboard_selection(&mut self, source: &SelectionSource) {
+        let persisted = &self.config.clipboard.mime_types;
+        let store = &mut self.clipboard;
+        store.generation += 1;
+        store.contents.clear();
+        store.size = 0;
+        store.mime_types = source
+            .mime_types()
+            .into_iter()
+            .filter(|mime_type| persisted.contains(mime_type))
+            .collect();
+
+        let generation = store.generation;
+        for mime_type in store.mime_types.clone() {
+            // The selection is only current once the handler returned
+            self.handle.insert_idle(move |data| {
+                data.request_clipboard_contents(generation, mime_type);
+            });
+        }
+    }
+
+    fn request_clipboard_contents(&mut self, generation: u64, mime_type: String) {
+        if self.clipboard.generation != generation {
+            return;
+        }
+
+        let (read, write) = match UnixStream::pair() {
+            Ok(pair) => pair,
+            Err(err) => {
+                warn!(?err, "Unable to create clipboard pipe");
+                return;
+            }
+        };
+        if let Err(err) = read.set_nonblocking(true) {
+            warn!(?err, "Unable to create clipboard pipe");
+            return;
+        }
+        if let Err(err) =
+            request_data_device_client_selection(&self.seat, mime_type.clone(), OwnedFd::from(write))
+        {
+            debug!(?err, mime_type, "Clipboard selection went away before caching");
+            return;
+        }
+
+        let mut buffer = Vec::new();
+        let res = self.handle.insert_source(
+            Generic::new(read, Interest::READ, Mode::Level),
+            move |_, read, data| {
+                let mut stream: &UnixStream = read;
+                let mut chunk = [0u8; 4096];
+                loop {
+                    match stream.read(&mut chunk) {
+                        Ok(0) => {
+                            if data.clipboard.generation == generation {
+                                data.clipboard
+                                    .contents
+                                    .insert(mime_type.clone(), std::mem::take(&mut buffer));
+                            }
+                            return Ok(PostAction::Remove);
+                        }
+                        Ok(len) => {
+                            let store = &mut data.clipboard;
+                            if store.generation != generation {
+                                return Ok(PostAction::Remove);
+                            }
+                            store.size += len;
+                            if store.size > data.config.clipboard.max_size {
+                                store.size -= buffer.len() + len;
+                                warn!(mime_type, "Clipboard selection too large to cache");
+                                return Ok(PostAction::Remove);
+                            }
+                            buffer.extend_from_slice(&chunk[..len]);
+                        }
+                        Err(err) if err.kind() == ErrorKind::WouldBlock => {
+                            return Ok(PostAction::Continue);
+                        }
+                        Err(err) if err.kind() == ErrorKind::Interrupted => {}
+                        Err(err) => {
+                            warn!(?err, mime_type, "Failed reading clipboard selection");
+                            return Ok(PostAction::Remove);
+                        }
+                    }
+                }
+            },
+        );
+        if let Err(err) = res {
+            warn!(?err, "Unable to insert clipboard source");
+        }
+    }
+
+    /// The clipboard was cleared. Checked once the clients are dispatched, as its
+    /// owner going away restores the cached selection, while a client clearing it
+    /// drops it.
+    pub fn clipboard_cleared(&mut self) {
+        let generation = self.clipboard.generation;
+        let disconnected = DISCONNECTED_CLIENTS.load(Ordering::Relaxed);
+        self.handle.insert_idle(move |data| {
+            if data.clipboard.generation != generation {
+                return;
+            }
+            let owner_gone = DISCONNECTED_CLIENTS.load(Ordering::Relaxed) != disconnected;
+            if !owner_gone {
+                debug!("Clipboard cleared by its owner");
+                data.clipboard = ClipboardStore {
+                    generation: generation + 1,
+                    ..ClipboardStore::default()
+                };
+                return;
+            }
+            let mime_types = data.restore_clipboard_selection();
+            #[cfg(feature = "xwayland")]
+            if let Some(xwm) = data.xwm.as_mut() {
+                if let Err(err) = xwm.new_selection(SelectionTarget::Clipboard, mime_types) {
+                    warn!(?err, "Failed to set Xwayland selection");
+                }
+            }
+            #[cfg(not(feature = "xwayland"))]
+            let _ = mime_types;
+        });
+    }
+
+    /// Offer the cached clipboard again once its source is gone.
+    /// Returns the offered mime types, if anything was cached.
+    fn restore_clipboard_selection(&mut self) -> Option<Vec<String>> {
+        let store = &self.clipboard;
+        let mime_types: Vec<String> = store
+            .mime_types
+            .iter()
+            .filter(|mime_type| store.contents.contains_key(*mime_type))
+            .cloned()
+            .collect();
+        if mime_types.is_empty() {
+            return None;
+        }
+
+        set_data_device_selection(
+            &self.display_handle,
+            &self.seat,
+            mime_types.clone(),
+            SelectionOrigin::Cached(Arc::new(store.contents.clone())),
+        );
+        Some(mime_types)
+    }
+}
+
+/// Write cached selection contents into `fd` without blocking the event loop
+pub fn send_cached_selection(contents: &HashMap<String, Vec<u8>>, mime_type: &str, fd: OwnedFd) {
+    let Some(data) = contents.get(mime_type).cloned() else {
+        return;
+    };
+    std::thread::spawn(move || {
+        if let Err(err) = File::from(fd).write_all(&data) {
+            debug!(?err, "Failed to send cached clipboard selection");
+        }
+    });
+}
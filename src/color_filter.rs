@@ -0,0 +1,302 @@
+use smithay::{
+    backend::{
+        allocator::Fourcc,
+        renderer::{
+            damage::OutputDamageTracker,
+            element::{Element, Id, RenderElement, RenderElementStates},
+            gles::{GlesTexProgram, GlesTexture, Uniform, UniformName, UniformType, UniformValue},
+            utils::CommitCounter,
+            Bind, Offscreen, Texture,
+        },
+    },
+    input::keyboard::Keysym,
+    output::Output,
+    utils::{Buffer, Physical, Rectangle, Scale, Size, Transform},
+};
+use tracing::{debug, warn};
+use xkbcommon::xkb;
+
+use crate::{
+    input_handler::KeyAction,
+    shaders::GlesAccess,
+    state::{AnvilState, Backend, ColorFilter},
+};
+
+static SHADER: &str = include_str!("../resources/color_filter.frag");
+
+type Matrix = [[f32; 3]; 3];
+
+const IDENTITY: Matrix = [[1.0, 0.0, 0.0], [0.0, 1.0, 0.0], [0.0, 0.0, 1.0]];
+
+const RGB_TO_LMS: Matrix = [
+    [17.8824, 43.5161, 4.11935],
+    [3.45565, 27.1554, 3.86714],
+    [0.0299566, 0.184309, 1.46709],
+];
+
+const LMS_TO_RGB: Matrix = [
+    [0.0809444479, -0.130504409, 0.116721066],
+    [-0.0102485335, 0.0540193266, -0.113614708],
+    [-0.000365296938, -0.00412161469, 0.693511405],
+];
+
+/// Colors seen without L cones, in LMS
+const PROTANOPIA: Matrix = [[0.0, 2.02344, -2.52581], [0.0, 1.0, 0.0], [0.0, 0.0, 1.0]];
+
+/// Colors seen without M cones, in LMS
+const DEUTERANOPIA: Matrix = [[1.0, 0.0, 0.0], [0.494207, 0.0, 1.24827], [0.0, 0.0, 1.0]];
+
+/// Where the red and green lost are moved to, in RGB
+const ERROR_SHIFT: Matrix = [[0.0, 0.0, 0.0], [0.7, 1.0, 0.0], [0.7, 0.0, 1.0]];
+
+fn multiply(a: &Matrix, b: &Matrix) -> Matrix {
+    let mut product = [[0.0; 3]; 3];
+    for (row, product_row) in product.iter_mut().enumerate() {
+        for (column, value) in product_row.iter_mut().enumerate() {
+            *value = (0..3).map(|i| a[row][i] * b[i][column]).sum();
+        }
+    }
+    product
+}
+
+/// Sum of `a` and `b` scaled by `factor`
+fn add_scaled(a: &Matrix, b: &Matrix, factor: f32) -> Matrix {
+    let mut sum = *a;
+    for (row, sum_row) in sum.iter_mut().enumerate() {
+        for (column, value) in sum_row.iter_mut().enumerate() {
+            *value += b[row][column] * factor;
+        }
+    }
+    sum
+}
+
+/// Daltonization of the colors lost to `simulation`, moving the difference to
+/// colors still seen
+fn correction(simulation: &Matrix) -> Matrix {
+    let simulated = multiply(&LMS_TO_RGB, &multiply(simulation, &RGB_TO_LMS));
+    let error = add_scaled(&IDENTITY, &simulated, -1.0);
+    add_scaled(&IDENTITY, &multiply(&ERROR_SHIFT, &error), 1.0)
+}
+
+impl ColorFilter {
+    /// Matrix applied to colors and the offset added after it
+    fn transform(self) -> (Matrix, [f32; 3]) {
+        match self {
+            Self::Grayscale => {
+                let luma = [0.2126, 0.7152, 0.0722];
+                ([luma; 3], [0.0; 3])
+            }
+            Self::Invert => (
+                [[-1.0, 0.0, 0.0], [0.0, -1.0, 0.0], [0.0, 0.0, -1.0]],
+                [1.0; 3],
+            ),
+            Self::Protanopia => (correction(&PROTANOPIA), [0.0; 3]),
+            Self::Deuteranopia => (correction(&DEUTERANOPIA), [0.0; 3]),
+        }
+    }
+
+    fn uniforms(self) -> Vec<Uniform<'static>> {
+        let ([red, green, blue], offset) = self.transform();
+        [
+            ("red", red),
+            ("green", green),
+            ("blue", blue),
+            ("offset", offset),
+        ]
+        .into_iter()
+        .map(|(name, [x, y, z])| Uniform::new(name, UniformValue::_3f(x, y, z)))
+        .collect()
+    }
+}
+
+/// Composited frame of an output, drawn through the color filter
+#[derive(Debug, Clone)]
+pub struct ColorFilterElement {
+    id: Id,
+    commit: CommitCounter,
+    texture: GlesTexture,
+    program: GlesTexProgram,
+    filter: ColorFilter,
+    size: Size<i32, Physical>,
+}
+
+impl Element for ColorFilterElement {
+    fn id(&self) -> &Id {
+        &self.id
+    }
+
+    fn current_commit(&self) -> CommitCounter {
+        self.commit
+    }
+
+    fn src(&self) -> Rectangle<f64, Buffer> {
+        Rectangle::from_loc_and_size((0, 0), self.texture.size()).to_f64()
+    }
+
+    fn geometry(&self, _scale: Scale<f64>) -> Rectangle<i32, Physical> {
+        Rectangle::from_loc_and_size((0, 0), self.size)
+    }
+
+    fn opaque_regions(&self, scale: Scale<f64>) -> Vec<Rectangle<i32, Physical>> {
+        vec![self.geometry(scale)]
+    }
+}
+
+impl<R: GlesAccess> RenderElement<R> for ColorFilterElement {
+    fn draw(
+        &self,
+        frame: &mut R::Frame<'_>,
+        src: Rectangle<f64, Buffer>,
+        dst: Rectangle<i32, Physical>,
+        damage: &[Rectangle<i32, Physical>],
+        opaque_regions: &[Rectangle<i32, Physical>],
+    ) -> Result<(), R::Error> {
+        R::gles_frame(frame)
+            .render_texture_from_to(
+                &self.texture,
+                src,
+                dst,
+                damage,
+                opaque_regions,
+                Transform::Normal,
+                1.0,
+                Some(&self.program),
+                &self.filter.uniforms(),
+            )
+            .map_err(R::gles_error)
+    }
+}
+
+/// Offscreen copy of the composited frame of an output, for the color filter to
+/// draw from
+#[derive(Debug)]
+pub struct ColorFilterPass {
+    id: Id,
+    commit: CommitCounter,
+    program: Option<GlesTexProgram>,
+    texture: Option<GlesTexture>,
+    damage_tracker: Option<OutputDamageTracker>,
+}
+
+impl Default for ColorFilterPass {
+    fn default() -> Self {
+        Self {
+            id: Id::new(),
+            commit: CommitCounter::default(),
+            program: None,
+            texture: None,
+            damage_tracker: None,
+        }
+    }
+}
+
+impl ColorFilterPass {
+    /// Draw `elements` of `output` offscreen, returning the element drawing them
+    /// through `filter` and their states, which frame callbacks and presentation
+    /// feedback go by. The renderer is left bound to the offscreen texture, so the
+    /// target has to be bound after. `None` if that failed, to draw them unfiltered
+    pub fn render<R, E>(
+        &mut self,
+        renderer: &mut R,
+        output: &Output,
+        elements: &[E],
+        clear_color: [f32; 4],
+        filter: ColorFilter,
+    ) -> Option<(ColorFilterElement, RenderElementStates)>
+    where
+        R: GlesAccess + Bind<GlesTexture> + Offscreen<GlesTexture>,
+        R::TextureId: 'static,
+        E: RenderElement<R>,
+    {
+        let mode = output.current_mode()?;
+        // Drawn untransformed, the output transform applies to the filtered frame
+        let size = output.current_transform().transform_size(mode.size);
+        let buffer_size = size.to_logical(1).to_buffer(1, Transform::Normal);
+
+        if self.program.is_none() {
+            let uniforms = ["red", "green", "blue", "offset"]
+                .map(|name| UniformName::new(name, UniformType::_3f));
+            match renderer
+                .gles_renderer()
+                .compile_custom_texture_shader(SHADER, &uniforms)
+            {
+                Ok(program) => self.program = Some(program),
+                Err(err) => {
+                    warn!(?err, "Unable to compile the color filter shader");
+                    return None;
+                }
+            }
+        }
+        if self.texture.as_ref().map(|texture| texture.size()) != Some(buffer_size) {
+            let texture: GlesTexture = match renderer.create_buffer(Fourcc::Abgr8888, buffer_size) {
+                Ok(texture) => texture,
+                Err(err) => {
+                    warn!(?err, "Unable to create the color filter texture");
+                    return None;
+                }
+            };
+            self.texture = Some(texture);
+            self.damage_tracker = Some(OutputDamageTracker::new(
+                size,
+                output.current_scale().fractional_scale(),
+                Transform::Normal,
+            ));
+        }
+        let texture = self.texture.clone()?;
+        let damage_tracker = self.damage_tracker.as_mut()?;
+
+        if let Err(err) = renderer.bind(texture.clone()) {
+            warn!(?err, "Unable to bind the color filter texture");
+            return None;
+        }
+        // The texture keeps the previous frame, so only damage is drawn again
+        let states = match damage_tracker.render_output(renderer, 1, elements, clear_color) {
+            Ok(result) => {
+                if result.damage.is_some() {
+                    self.commit.increment();
+                }
+                result.states
+            }
+            Err(err) => {
+                warn!(?err, "Unable to render for the color filter");
+                self.damage_tracker = None;
+                self.texture = None;
+                return None;
+            }
+        };
+
+        let element = ColorFilterElement {
+            id: self.id.clone(),
+            commit: self.commit,
+            texture,
+            program: self.program.clone()?,
+            filter,
+            size,
+        };
+        Some((element, states))
+    }
+}
+
+impl<BackendData: Backend> AnvilState<BackendData> {
+    /// Filter drawn over the outputs, if turned on
+    pub fn active_color_filter(&self) -> Option<ColorFilter> {
+        self.color_filter_enabled
+            .then_some(self.config.color_filter.filter)
+    }
+
+    pub fn toggle_color_filter(&mut self) {
+        self.color_filter_enabled = !self.color_filter_enabled;
+        debug!(
+            enabled = self.color_filter_enabled,
+            filter = ?self.config.color_filter.filter,
+            "Toggled color filter"
+        );
+    }
+
+    /// Check if `keysym` turns the color filter on or off
+    pub fn color_filter_key_action(&self, keysym: Keysym) -> Option<KeyAction> {
+        let toggle_key = self.config.color_filter.toggle_key.as_ref()?;
+        let toggle_key = xkb::keysym_from_name(toggle_key, xkb::KEYSYM_CASE_INSENSITIVE);
+        (toggle_key.raw() == keysym.raw()).then_some(KeyAction::ToggleColorFilter)
+    }
+}
@@ -1,11 +1,13 @@
 use std::{io::Read, time::Duration};
 
-use tracing::warn;
+use tracing::{info, warn};
 use xcursor::{
     parser::{parse_xcursor, Image},
     CursorTheme,
 };
 
+use crate::state::CursorConfiguration;
+
 static FALLBACK_CURSOR_DATA: &[u8] = include_bytes!("../resources/cursor.rgba");
 
 pub struct Cursor {
@@ -14,14 +16,22 @@ pub struct Cursor {
 }
 
 impl Cursor {
-    pub fn load() -> Cursor {
-        let name = std::env::var("XCURSOR_THEME")
-            .ok()
+    /// Theme and size from the configuration, else from XCURSOR_THEME and XCURSOR_SIZE
+    pub fn load(config: &CursorConfiguration) -> Cursor {
+        let name = config
+            .theme
+            .clone()
+            .or_else(|| std::env::var("XCURSOR_THEME").ok())
             .unwrap_or_else(|| "default".into());
-        let size = std::env::var("XCURSOR_SIZE")
-            .ok()
-            .and_then(|s| s.parse().ok())
+        let size = config
+            .size
+            .or_else(|| {
+                std::env::var("XCURSOR_SIZE")
+                    .ok()
+                    .and_then(|s| s.parse().ok())
+            })
             .unwrap_or(24);
+        info!(name, size, "Loading cursor theme");
 
         let theme = CursorTheme::load(&name);
         let icons = load_icon(&theme)
@@ -42,6 +52,7 @@ impl Cursor {
         Cursor { icons, size }
     }
 
+    /// Frame shown at `time` of the image nearest to the nominal size times `scale`
     pub fn get_image(&self, scale: u32, time: Duration) -> Image {
         let size = self.size * scale;
         frame(time.as_millis() as u32, size, &self.icons)
@@ -55,9 +66,9 @@ fn nearest_images(size: u32, images: &[Image]) -> impl Iterator<Item = &Image> {
         .min_by_key(|image| (size as i32 - image.size as i32).abs())
         .unwrap();
 
-    images
-        .iter()
-        .filter(move |image| image.width == nearest_image.width && image.height == nearest_image.height)
+    images.iter().filter(move |image| {
+        image.width == nearest_image.width && image.height == nearest_image.height
+    })
 }
 
 fn frame(mut millis: u32, size: u32, images: &[Image]) -> Image {
@@ -0,0 +1,84 @@
+use std::time::{Duration, Instant};
+
+use smithay::{
+    backend::input::{InputBackend, InputEvent},
+    reexports::calloop::timer::{TimeoutAction, Timer},
+};
+use tracing::{debug, warn};
+
+use crate::{state::AnvilState, udev::UdevData};
+
+/// How often the timer checks again while hiding is disabled
+const DISABLED_POLL_INTERVAL: Duration = Duration::from_secs(5);
+const MIN_POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+/// Hides the pointer while a controller or the keyboard is used instead of the mouse
+#[derive(Debug)]
+pub struct CursorVisibility {
+    last_motion: Instant,
+    hidden: bool,
+}
+
+impl Default for CursorVisibility {
+    fn default() -> Self {
+        Self {
+            last_motion: Instant::now(),
+            hidden: false,
+        }
+    }
+}
+
+impl CursorVisibility {
+    pub fn hidden(&self) -> bool {
+        self.hidden
+    }
+}
+
+impl AnvilState<UdevData> {
+    /// Hide the pointer once it didn't move for the configured time
+    pub fn start_cursor_hiding(&mut self) {
+        let res = self.handle.insert_source(Timer::immediate(), |_, _, data| {
+            let Some(hide_after) = data.config.cursor.hide_after else {
+                return TimeoutAction::ToDuration(DISABLED_POLL_INTERVAL);
+            };
+            let hide_after = Duration::from_secs_f64(hide_after.max(0.0));
+            let idle = data.cursor_visibility.last_motion.elapsed();
+            if idle < hide_after {
+                return TimeoutAction::ToDuration((hide_after - idle).max(MIN_POLL_INTERVAL));
+            }
+            data.hide_cursor();
+            TimeoutAction::ToDuration(hide_after.max(MIN_POLL_INTERVAL))
+        });
+        if let Err(err) = res {
+            warn!(?err, "Unable to insert cursor hiding timer");
+        }
+    }
+
+    /// Show the pointer on motion, and hide it on key presses if configured.
+    /// Controllers come through as keyboards
+    pub fn update_cursor_visibility<B: InputBackend>(&mut self, event: &InputEvent<B>) {
+        match event {
+            InputEvent::PointerMotion { .. } | InputEvent::PointerMotionAbsolute { .. } => {
+                self.cursor_moved()
+            }
+            InputEvent::Keyboard { .. } if self.config.cursor.hide_on_keys => self.hide_cursor(),
+            _ => {}
+        }
+    }
+
+    /// Show the pointer and restart the idle time
+    pub fn cursor_moved(&mut self) {
+        self.cursor_visibility.last_motion = Instant::now();
+        if self.cursor_visibility.hidden {
+            debug!("Showing the cursor");
+            self.cursor_visibility.hidden = false;
+        }
+    }
+
+    fn hide_cursor(&mut self) {
+        if !self.cursor_visibility.hidden {
+            debug!("Hiding the cursor");
+            self.cursor_visibility.hidden = true;
+        }
+    }
+}
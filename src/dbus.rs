@@ -0,0 +1,117 @@
+use std::time::Duration;
+
+use async_io::Timer;
+use futures_lite::FutureExt;
+use smithay::reexports::calloop::channel::{channel, Event, Sender};
+use tracing::warn;
+use zbus::{
+    blocking::Connection,
+    fdo::{self, RequestNameFlags, RequestNameReply},
+    object_server::Interface,
+};
+
+use crate::state::{AnvilState, Backend};
+
+/// How long a bus call waits for the event loop to answer
+const REPLY_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Session bus connection of the compositor, serving all its objects
+#[derive(Debug, Default)]
+pub struct DBusServer {
+    connection: Option<Connection>,
+}
+
+impl DBusServer {
+    /// The connection, opened on first use
+    pub fn connection(&mut self) -> zbus::Result<&Connection> {
+        let connection = match self.connection.take() {
+            Some(connection) => connection,
+            None => Connection::session()?,
+        };
+        Ok(self.connection.insert(connection))
+    }
+
+    /// Serve `object` at `path` under the bus `name`, returning whether the name was
+    /// free. The object is removed again if it wasn't. A `replaceable` name goes to
+    /// the next owner asking for it
+    pub fn serve<I: Interface>(
+        &mut self,
+        name: &str,
+        path: &str,
+        object: I,
+        replaceable: bool,
+    ) -> zbus::Result<bool> {
+        let connection = self.connection()?;
+        connection.object_server().at(path, object)?;
+        let flags = if replaceable {
+            RequestNameFlags::AllowReplacement | RequestNameFlags::DoNotQueue
+        } else {
+            RequestNameFlags::DoNotQueue.into()
+        };
+        match connection.request_name_with_flags(name, flags)? {
+            RequestNameReply::PrimaryOwner | RequestNameReply::AlreadyOwner => Ok(true),
+            _ => {
+                connection.object_server().remove::<I, _>(path)?;
+                Ok(false)
+            }
+        }
+    }
+}
+
+/// Calls of bus objects forwarded to the event loop, which answers each of them
+#[derive(Debug)]
+pub struct Requests<T, R> {
+    sender: Sender<(T, async_channel::Sender<R>)>,
+}
+
+impl<T, R> Requests<T, R> {
+    /// Handle `request` on the event loop and wait for the answer without blocking
+    /// the bus executor, `None` once the loop is gone or if it doesn't answer in time
+    pub async fn call(&self, request: T) -> Option<R> {
+        let (reply, answer) = async_channel::bounded(1);
+        self.sender.send((request, reply)).ok()?;
+        let timeout = async {
+            Timer::after(REPLY_TIMEOUT).await;
+            warn!("The event loop didn't answer a bus call in time");
+            None
+        };
+        async { answer.recv().await.ok() }.or(timeout).await
+    }
+
+    /// Handle `request` on the event loop without waiting for it
+    pub fn send(&self, request: T) {
+        let (reply, _) = async_channel::bounded(1);
+        let _ = self.sender.send((request, reply));
+    }
+}
+
+impl<T> Requests<T, Result<(), String>> {
+    /// [`call`](Self::call) for requests that can fail, with the error message
+    /// returned over the bus
+    pub async fn try_call(&self, request: T) -> fdo::Result<()> {
+        self.call(request)
+            .await
+            .ok_or_else(|| fdo::Error::Failed("The compositor didn't answer".into()))?
+            .map_err(fdo::Error::Failed)
+    }
+}
+
+impl<BackendData: Backend> AnvilState<BackendData> {
+    /// Forward the calls of a bus object to `handler` on the event loop
+    pub fn insert_requests<T: 'static, R: 'static>(
+        &self,
+        mut handler: impl FnMut(&mut Self, T) -> R + 'static,
+    ) -> Option<Requests<T, R>> {
+        let (sender, requests) = channel::<(T, async_channel::Sender<R>)>();
+        let res = self.handle.insert_source(requests, move |event, _, data| {
+            if let Event::Msg((request, reply)) = event {
+                let _ = reply.try_send(handler(data, request));
+            }
+        });
+        if let Err(err) = res {
+            warn!(?err, "Unable to insert bus request source");
+            return None;
+        }
+        Some(Requests { sender })
+    }
+}
@@ -0,0 +1,240 @@
+use std::{
+    fs::{read_dir, read_to_string},
+    process::Command,
+    sync::mpsc,
+    thread,
+    time::Duration,
+};
+
+use smithay::{
+    output::{Output, Scale},
+    reexports::{
+        calloop::timer::{TimeoutAction, Timer},
+        wayland_protocols::xdg::shell::server::xdg_toplevel,
+    },
+};
+use tracing::{info, warn};
+
+use crate::state::{AnvilState, Backend, ButtonBinding, DockConfiguration};
+
+const POLL_INTERVAL: Duration = Duration::from_secs(2);
+/// Connectors of built-in panels
+const INTERNAL_CONNECTORS: &[&str] = &["eDP", "LVDS", "DSI"];
+
+#[derive(Debug, Default)]
+pub struct DockState {
+    docked: Option<Docked>,
+    polling: bool,
+    /// Thread changing the performance profile, started on the first dock
+    profiles: Option<mpsc::Sender<ProfileChange>>,
+}
+
+#[derive(Debug)]
+struct Docked {
+    output: Output,
+    previous_scale: Scale,
+}
+
+/// Made in order on a thread, powerprofilesctl talks to D-Bus
+#[derive(Debug)]
+enum ProfileChange {
+    /// Switch to the profile, remembering the current one
+    Dock(String),
+    /// Switch back to the profile before docking
+    Undock,
+}
+
+fn spawn_profile_changes() -> mpsc::Sender<ProfileChange> {
+    let (sender, changes) = mpsc::channel();
+    thread::spawn(move || {
+        let mut previous = None;
+        for change in changes {
+            match change {
+                ProfileChange::Dock(profile) => {
+                    previous = performance_profile();
+                    set_performance_profile(&profile);
+                }
+                ProfileChange::Undock => {
+                    if let Some(profile) = previous.take() {
+                        set_performance_profile(&profile);
+                    }
+                }
+            }
+        }
+    });
+    sender
+}
+
+fn is_external(output: &Output) -> bool {
+    !INTERNAL_CONNECTORS
+        .iter()
+        .any(|prefix| output.name().starts_with(prefix))
+}
+
+fn external_power() -> bool {
+    let Ok(supplies) = read_dir("/sys/class/power_supply") else {
+        return false;
+    };
+    supplies.flatten().any(|supply| {
+        let path = supply.path();
+        let kind = read_to_string(path.join("type")).unwrap_or_default();
+        matches!(kind.trim(), "Mains" | "USB")
+            && read_to_string(path.join("online")).is_ok_and(|online| online.trim() == "1")
+    })
+}
+
+fn usb_hub() -> bool {
+    let Ok(devices) = read_dir("/sys/bus/usb/devices") else {
+        return false;
+    };
+    devices.flatten().any(|device| {
+        // The root hubs of the host controllers are always there
+        !device.file_name().to_string_lossy().starts_with("usb")
+            && read_to_string(device.path().join("bDeviceClass"))
+                .is_ok_and(|class| class.trim() == "09")
+    })
+}
+
+fn performance_profile() -> Option<String> {
+    let output = Command::new("powerprofilesctl").arg("get").output().ok()?;
+    output
+        .status
+        .success()
+        .then(|| String::from_utf8_lossy(&output.stdout).trim().to_string())
+}
+
+fn set_performance_profile(profile: &str) {
+    match Command::new("powerprofilesctl")
+        .args(["set", profile])
+        .status()
+    {
+        Ok(status) if status.success() => info!(profile, "Changed performance profile"),
+        Ok(status) => warn!(profile, %status, "Unable to change performance profile"),
+        Err(err) => warn!(profile, ?err, "Unable to change performance profile"),
+    }
+}
+
+impl<BackendData: Backend> AnvilState<BackendData> {
+    /// The external output while docked
+    pub fn docked_output(&self) -> Option<&Output> {
+        self.dock.docked.as_ref().map(|docked| &docked.output)
+    }
+
+    /// The button bindings in effect, which depend on being docked
+    pub fn button_bindings(&self) -> &[ButtonBinding] {
+        match (&self.dock.docked, &self.config.dock) {
+            (
+                Some(_),
+                Some(DockConfiguration {
+                    button_bindings: Some(bindings),
+                    ..
+                }),
+            ) => bindings,
+            _ => &self.config.button_bindings,
+        }
+    }
+}
+
+impl<BackendData: Backend + 'static> AnvilState<BackendData> {
+    /// Watch for dock events if a dock profile is configured
+    pub fn start_dock_detection(&mut self) {
+        if self.config.dock.is_none() || self.dock.polling {
+            return;
+        }
+        let res = self.handle.insert_source(Timer::immediate(), |_, _, data| {
+            data.refresh_dock();
+            TimeoutAction::ToDuration(POLL_INTERVAL)
+        });
+        match res {
+            Ok(_) => self.dock.polling = true,
+            Err(err) => warn!(?err, "Unable to insert dock timer"),
+        }
+    }
+
+    fn refresh_dock(&mut self) {
+        let Some(config) = self.config.dock.clone() else {
+            self.undock();
+            return;
+        };
+        let external = self
+            .outputs
+            .iter()
+            .find(|output| is_external(output))
+            .cloned();
+        match (&self.dock.docked, external) {
+            (None, Some(output)) if external_power() && usb_hub() => self.dock(output, &config),
+            (Some(docked), external) if external.as_ref() != Some(&docked.output) => self.undock(),
+            _ => {}
+        }
+    }
+
+    fn dock(&mut self, output: Output, config: &DockConfiguration) {
+        info!(output = output.name(), "Docked");
+
+        let previous_scale = output.current_scale();
+        if let Some(scale) = config.scale {
+            output.change_current_state(None, None, Some(Scale::Fractional(scale)), None);
+        }
+        // The external display becomes the primary output
+        self.outputs.retain(|other| other != &output);
+        self.outputs.insert(0, output.clone());
+
+        if let Some(profile) = config.performance_profile.clone() {
+            let _ = self
+                .dock
+                .profiles
+                .get_or_insert_with(spawn_profile_changes)
+                .send(ProfileChange::Dock(profile));
+        }
+
+        self.dock.docked = Some(Docked {
+            output,
+            previous_scale,
+        });
+        self.refit_windows();
+    }
+
+    fn undock(&mut self) {
+        let Some(docked) = self.dock.docked.take() else {
+            return;
+        };
+        info!("Undocked");
+
+        if self.outputs.contains(&docked.output) {
+            docked
+                .output
+                .change_current_state(None, None, Some(docked.previous_scale), None);
+        }
+        if let Some(index) = self.outputs.iter().position(|output| !is_external(output)) {
+            let internal = self.outputs.remove(index);
+            self.outputs.insert(0, internal);
+        }
+
+        if let Some(profiles) = self.dock.profiles.as_ref() {
+            let _ = profiles.send(ProfileChange::Undock);
+        }
+        self.refit_windows();
+    }
+
+    /// Configure fullscreen and maximized windows for the new primary output
+    fn refit_windows(&mut self) {
+        for window in self.elements.clone() {
+            if let Some(toplevel) = window.toplevel() {
+                let states = toplevel.current_state().states;
+                if states.contains(xdg_toplevel::State::Fullscreen) {
+                    self.fullscreen_window(&window, None);
+                } else if states.contains(xdg_toplevel::State::Maximized) {
+                    self.maximize_window(&window);
+                }
+            }
+            #[cfg(feature = "xwayland")]
+            if let Some(xwindow) = window.x11_surface() {
+                if xwindow.is_fullscreen() {
+                    self.fullscreen_window(&window, None);
+                } else if xwindow.is_maximized() {
+                    self.maximize_window(&window);
+                }
+            }
+        }
+    }
+}
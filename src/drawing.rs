@@ -9,6 +9,7 @@ use smithay::{
         },
         ImportAll, ImportMem, Renderer, Texture,
     },
+    desktop::utils::bbox_from_surface_tree,
     input::pointer::CursorImageStatus,
     render_elements,
     utils::{Physical, Point, Scale},
@@ -22,19 +23,30 @@ use smithay::{
     utils::{Buffer, Logical, Rectangle, Size, Transform},
 };
 
+use crate::state::BackgroundScale;
+
 pub static CLEAR_COLOR: [f32; 4] = [0.0, 0.0, 0.0, 1.0];
 pub static CLEAR_COLOR_FULLSCREEN: [f32; 4] = [0.0, 0.0, 0.0, 0.0];
 
 pub struct PointerElement {
     buffer: Option<MemoryRenderBuffer>,
+    /// Of `buffer`, drawn at the pointer location
+    hotspot: Point<f64, Logical>,
     status: CursorImageStatus,
+    /// Overrides `status` while the pointer is not in use
+    hidden: bool,
+    /// Largest image the cursor plane takes, `None` without one
+    cursor_size: Option<Size<i32, Physical>>,
 }
 
 impl Default for PointerElement {
     fn default() -> Self {
         Self {
             buffer: Default::default(),
+            hotspot: Default::default(),
             status: CursorImageStatus::default_named(),
+            hidden: false,
+            cursor_size: None,
         }
     }
 }
@@ -44,8 +56,26 @@ impl PointerElement {
         self.status = status;
     }
 
-    pub fn set_buffer(&mut self, buffer: MemoryRenderBuffer) {
+    pub fn set_buffer(&mut self, buffer: MemoryRenderBuffer, hotspot: Point<f64, Logical>) {
         self.buffer = Some(buffer);
+        self.hotspot = hotspot;
+    }
+
+    pub fn set_hidden(&mut self, hidden: bool) {
+        self.hidden = hidden;
+    }
+
+    /// Set the cursor plane size of the output about to be rendered
+    pub fn set_cursor_size(&mut self, size: Option<Size<i32, Physical>>) {
+        self.cursor_size = size;
+    }
+
+    /// Images too large for the cursor plane are composited with the rest of the frame
+    fn kind(&self, size: Size<i32, Physical>) -> Kind {
+        match self.cursor_size {
+            Some(max) if size.w <= max.w && size.h <= max.h => Kind::Cursor,
+            _ => Kind::Unspecified,
+        }
     }
 }
 
@@ -80,12 +110,16 @@ where
     where
         E: From<PointerRenderElement<R>>,
     {
+        if self.hidden {
+            return vec![];
+        }
         match &self.status {
             CursorImageStatus::Hidden => vec![],
             // Always render `Default` for a named shape.
             CursorImageStatus::Named(_) => {
                 if let Some(buffer) = self.buffer.as_ref() {
-                    vec![PointerRenderElement::<R>::from(
+                    let location = location - self.hotspot.to_physical_precise_round(scale);
+                    let mut from_buffer = |kind| {
                         MemoryRenderBufferRenderElement::from_buffer(
                             renderer,
                             location.to_f64(),
@@ -93,16 +127,24 @@ where
                             None,
                             None,
                             None,
-                            Kind::Cursor,
+                            kind,
                         )
-                        .expect("Lost system pointer buffer"),
-                    )
-                    .into()]
+                        .expect("Lost system pointer buffer")
+                    };
+                    let mut element = from_buffer(Kind::Cursor);
+                    let kind = self.kind(element.geometry(scale).size);
+                    if kind != Kind::Cursor {
+                        element = from_buffer(kind);
+                    }
+                    vec![PointerRenderElement::<R>::from(element).into()]
                 } else {
                     vec![]
                 }
             }
             CursorImageStatus::Surface(surface) => {
+                let size = bbox_from_surface_tree(surface, (0, 0))
+                    .size
+                    .to_physical_precise_round(scale);
                 let elements: Vec<PointerRenderElement<R>> =
                     smithay::backend::renderer::element::surface::render_elements_from_surface_tree(
                         renderer,
@@ -110,7 +152,7 @@ where
                         location,
                         scale,
                         alpha,
-                        Kind::Cursor,
+                        self.kind(size),
                     );
                 elements.into_iter().map(E::from).collect()
             }
@@ -124,16 +166,20 @@ pub static BACKGROUND_PNG: &[u8] = include_bytes!("../consolation.png");
 
 pub struct BackgroundElement<T: Texture> {
     texture: T,
+    scale: BackgroundScale,
     id: Id,
+    src: Rectangle<f64, Buffer>,
     position: Rectangle<i32, Physical>,
     commit_counter: CommitCounter,
 }
 
 impl<T: Texture> BackgroundElement<T> {
-    pub fn new(texture: T) -> Self {
+    pub fn new(texture: T, scale: BackgroundScale) -> Self {
         BackgroundElement {
             id: Id::new(),
+            src: Rectangle::from_loc_and_size((0, 0), texture.size()).to_f64(),
             texture,
+            scale,
             position: Rectangle::default(),
             commit_counter: CommitCounter::default(),
         }
@@ -141,13 +187,44 @@ impl<T: Texture> BackgroundElement<T> {
 
     /* Position self based on size of output */
     pub fn position(&mut self, pos: Size<i32, Physical>) {
-        let x: i32 = pos.w / 2 - self.texture.width() as i32 / 2;
-        let y: i32 = pos.h / 2 - self.texture.height() as i32 / 2;
-        self.position = Rectangle::<i32, Physical> {
-            loc: (x, y).into(),
-            size: (self.texture.width() as i32, self.texture.height() as i32).into(),
+        let texture = self.texture.size();
+        let full = Rectangle::from_loc_and_size((0, 0), texture).to_f64();
+        let texture = Size::<i32, Physical>::from((texture.w, texture.h));
+        let centered = |size: Size<i32, Physical>| {
+            Rectangle::from_loc_and_size(((pos.w - size.w) / 2, (pos.h - size.h) / 2), size)
         };
-        self.commit_counter.increment();
+        let (src, position) = match self.scale {
+            BackgroundScale::Center => (full, centered(texture)),
+            BackgroundScale::Fit => {
+                let scale = f64::min(
+                    pos.w as f64 / texture.w as f64,
+                    pos.h as f64 / texture.h as f64,
+                );
+                (
+                    full,
+                    centered(texture.to_f64().upscale(scale).to_i32_round()),
+                )
+            }
+            BackgroundScale::Fill => {
+                let scale = f64::max(
+                    pos.w as f64 / texture.w as f64,
+                    pos.h as f64 / texture.h as f64,
+                );
+                // The part of the image covering the output
+                let size = Size::<f64, Buffer>::from((pos.w as f64, pos.h as f64)).downscale(scale);
+                let src = Rectangle::from_loc_and_size(
+                    ((full.size.w - size.w) / 2.0, (full.size.h - size.h) / 2.0),
+                    size,
+                );
+                (src, Rectangle::from_loc_and_size((0, 0), pos))
+            }
+            BackgroundScale::Tile => (full, Rectangle::from_loc_and_size((0, 0), pos)),
+        };
+        if (src, position) != (self.src, self.position) {
+            self.src = src;
+            self.position = position;
+            self.commit_counter.increment();
+        }
     }
 }
 impl<T> Element for BackgroundElement<T>
@@ -163,7 +240,7 @@ where
     }
 
     fn src(&self) -> Rectangle<f64, Buffer> {
-        Rectangle::from_loc_and_size((0, 0), (self.texture.width(), self.texture.height())).to_f64()
+        self.src
     }
 
     fn current_commit(&self) -> CommitCounter {
@@ -171,7 +248,7 @@ where
     }
 
     fn geometry(&self, _scale: Scale<f64>) -> Rectangle<i32, Physical> {
-        self.position.clone()
+        self.position
     }
 }
 
@@ -188,15 +265,47 @@ where
         damage: &[Rectangle<i32, Physical>],
         opaque_regions: &[Rectangle<i32, Physical>],
     ) -> Result<(), R::Error> {
-        frame.render_texture_from_to(
-            &self.texture,
-            src,
-            dst,
-            &damage,
-            opaque_regions,
-            Transform::Normal,
-            1.0,
-        )?;
+        if self.scale != BackgroundScale::Tile {
+            frame.render_texture_from_to(
+                &self.texture,
+                src,
+                dst,
+                &damage,
+                opaque_regions,
+                Transform::Normal,
+                1.0,
+            )?;
+            return Ok(());
+        }
+
+        let tile = self.texture.size();
+        let tile = Size::<i32, Physical>::from((tile.w.max(1), tile.h.max(1)));
+        for y in (0..dst.size.h).step_by(tile.h as usize) {
+            for x in (0..dst.size.w).step_by(tile.w as usize) {
+                let bounds = Rectangle::from_loc_and_size((x, y), tile);
+                // Damage is relative to the element, and has to be made relative to the tile
+                let tile_damage: Vec<_> = damage
+                    .iter()
+                    .filter_map(|damage| damage.intersection(bounds))
+                    .map(|mut damage| {
+                        damage.loc -= bounds.loc;
+                        damage
+                    })
+                    .collect();
+                if tile_damage.is_empty() {
+                    continue;
+                }
+                frame.render_texture_from_to(
+                    &self.texture,
+                    Rectangle::from_loc_and_size((0, 0), self.texture.size()).to_f64(),
+                    Rectangle::from_loc_and_size(dst.loc + bounds.loc, tile),
+                    &tile_damage,
+                    &[],
+                    Transform::Normal,
+                    1.0,
+                )?;
+            }
+        }
 
         Ok(())
     }
@@ -0,0 +1,276 @@
+use std::time::{Duration, Instant};
+
+use smithay::{
+    backend::input::KeyState,
+    input::keyboard::Keysym,
+    reexports::calloop::timer::{TimeoutAction, Timer},
+    utils::{Logical, Point},
+};
+use tracing::{debug, warn};
+use xkbcommon::xkb;
+
+use crate::{
+    input_handler::KeyAction,
+    state::{AnvilState, Backend, ButtonBinding, ButtonGesture, SwipeAction},
+};
+
+/// How long a button has to be held to count as a long press
+const LONG_PRESS: Duration = Duration::from_millis(500);
+/// How fast the second tap of a double tap has to follow the first
+const DOUBLE_TAP: Duration = Duration::from_millis(300);
+
+/// Turns presses of bound buttons into short presses, long presses, double taps and chords
+#[derive(Debug, Default)]
+pub struct GestureDetector {
+    held: Vec<Keysym>,
+    /// Bumped on every press, so timers of older presses do nothing
+    generation: u64,
+    /// The held buttons already triggered something and their release is ignored
+    consumed: bool,
+    last_tap: Option<(Keysym, Instant)>,
+    /// Distance covered by the ongoing touchpad swipe, if the compositor handles it
+    swipe: Option<Point<f64, Logical>>,
+}
+
+impl From<SwipeAction> for KeyAction {
+    fn from(action: SwipeAction) -> Self {
+        match action {
+            SwipeAction::None => KeyAction::None,
+            SwipeAction::NextWindow => KeyAction::NextWindow,
+            SwipeAction::PreviousWindow => KeyAction::PreviousWindow,
+            SwipeAction::BrightnessUp => KeyAction::BrightnessUp,
+            SwipeAction::BrightnessDown => KeyAction::BrightnessDown,
+            SwipeAction::ToggleStatus => KeyAction::ToggleStatus,
+            SwipeAction::TogglePictureInPicture => KeyAction::TogglePictureInPicture,
+            SwipeAction::MovePictureInPicture => KeyAction::MovePictureInPicture,
+            SwipeAction::ToggleSplitScreen => KeyAction::ToggleSplitScreen,
+            SwipeAction::ToggleZoom => KeyAction::ToggleZoom,
+            SwipeAction::ToggleColorFilter => KeyAction::ToggleColorFilter,
+            SwipeAction::SwitchToPeek => KeyAction::SwitchToPeek,
+            SwipeAction::DismissNotification => KeyAction::DismissNotification,
+            SwipeAction::OpenTimeSettings => KeyAction::OpenTimeSettings,
+            SwipeAction::Quit => KeyAction::Quit,
+            SwipeAction::Run(command) => KeyAction::Run(command),
+            SwipeAction::Custom(name) => KeyAction::Custom(name),
+        }
+    }
+}
+
+impl ButtonBinding {
+    fn matches(&self, keysyms: &[Keysym], gesture: ButtonGesture) -> bool {
+        self.gesture == gesture
+            && self.keys.len() == keysyms.len()
+            && self.keys.iter().all(|name| {
+                let keysym = xkb::keysym_from_name(name, xkb::KEYSYM_NO_FLAGS);
+                keysyms.iter().any(|held| held.raw() == keysym.raw())
+            })
+    }
+
+    fn uses(&self, keysym: Keysym) -> bool {
+        self.keys
+            .iter()
+            .any(|name| xkb::keysym_from_name(name, xkb::KEYSYM_NO_FLAGS).raw() == keysym.raw())
+    }
+}
+
+impl<BackendData: Backend> AnvilState<BackendData> {
+    /// Feed a key into the gesture detector.
+    /// Returns true if the key is bound to a gesture and must not reach clients.
+    pub fn button_gesture_input(&mut self, keysym: Keysym, state: KeyState) -> bool {
+        match state {
+            KeyState::Pressed => self.button_gesture_pressed(keysym),
+            KeyState::Released => self.button_gesture_released(keysym),
+        }
+    }
+
+    /// Forget buttons held when input stopped reaching us, their releases never come
+    pub fn release_held_buttons(&mut self) {
+        let detector = &mut self.button_gestures;
+        detector.held.clear();
+        detector.generation += 1;
+        detector.consumed = false;
+        detector.last_tap = None;
+    }
+
+    fn button_gesture_pressed(&mut self, keysym: Keysym) -> bool {
+        if self.kiosk_enabled()
+            || !self
+                .button_bindings()
+                .iter()
+                .any(|binding| binding.uses(keysym))
+        {
+            return false;
+        }
+
+        let detector = &mut self.button_gestures;
+        if !detector.held.contains(&keysym) {
+            detector.held.push(keysym);
+        }
+        detector.generation += 1;
+        detector.consumed = false;
+
+        let held = detector.held.clone();
+        if held.len() > 1 {
+            if let Some(action) = self.button_binding(&held, ButtonGesture::Press) {
+                self.button_gestures.consumed = true;
+                self.run_button_action(action);
+            }
+            return true;
+        }
+
+        if self
+            .button_binding(&held, ButtonGesture::LongPress)
+            .is_some()
+        {
+            let generation = self.button_gestures.generation;
+            let res =
+                self.handle
+                    .insert_source(Timer::from_duration(LONG_PRESS), move |_, _, data| {
+                        let detector = &data.button_gestures;
+                        if detector.generation == generation
+                            && !detector.consumed
+                            && detector.held == [keysym]
+                        {
+                            data.button_gestures.consumed = true;
+                            if let Some(action) =
+                                data.button_binding(&[keysym], ButtonGesture::LongPress)
+                            {
+                                data.run_button_action(action);
+                            }
+                        }
+                        TimeoutAction::Drop
+                    });
+            if let Err(err) = res {
+                warn!(?err, "Unable to insert long press timer");
+            }
+        }
+        true
+    }
+
+    fn button_gesture_released(&mut self, keysym: Keysym) -> bool {
+        let detector = &mut self.button_gestures;
+        if !detector.held.contains(&keysym) {
+            return false;
+        }
+        detector.held.retain(|held| *held != keysym);
+        if detector.consumed {
+            // Wait until all buttons of a chord or long press are up again
+            if detector.held.is_empty() {
+                detector.consumed = false;
+            }
+            return true;
+        }
+        if !detector.held.is_empty() {
+            // Releasing part of a chord that matched nothing, ignore the rest of it
+            detector.consumed = true;
+            return true;
+        }
+
+        if self
+            .button_binding(&[keysym], ButtonGesture::DoubleTap)
+            .is_none()
+        {
+            if let Some(action) = self.button_binding(&[keysym], ButtonGesture::Press) {
+                self.run_button_action(action);
+            }
+            return true;
+        }
+
+        let now = Instant::now();
+        match self.button_gestures.last_tap.take() {
+            Some((last, at)) if last == keysym && now.duration_since(at) < DOUBLE_TAP => {
+                if let Some(action) = self.button_binding(&[keysym], ButtonGesture::DoubleTap) {
+                    self.run_button_action(action);
+                }
+            }
+            _ => {
+                // A short press has to wait, the second tap might still come
+                self.button_gestures.last_tap = Some((keysym, now));
+                let res = self.handle.insert_source(
+                    Timer::from_duration(DOUBLE_TAP),
+                    move |_, _, data| {
+                        if data.button_gestures.last_tap == Some((keysym, now)) {
+                            data.button_gestures.last_tap = None;
+                            if let Some(action) =
+                                data.button_binding(&[keysym], ButtonGesture::Press)
+                            {
+                                data.run_button_action(action);
+                            }
+                        }
+                        TimeoutAction::Drop
+                    },
+                );
+                if let Err(err) = res {
+                    warn!(?err, "Unable to insert double tap timer");
+                }
+            }
+        }
+        true
+    }
+
+    fn button_binding(&self, keysyms: &[Keysym], gesture: ButtonGesture) -> Option<SwipeAction> {
+        self.button_bindings()
+            .iter()
+            .find(|binding| binding.matches(keysyms, gesture))
+            .map(|binding| match binding.action {
+                SwipeAction::None => SwipeAction::Run(binding.command.clone()),
+                ref action => action.clone(),
+            })
+    }
+
+    fn run_button_action(&mut self, action: SwipeAction) {
+        debug!(?action, "Button gesture");
+        self.run_gesture_action(action);
+    }
+
+    fn run_gesture_action(&mut self, action: SwipeAction) {
+        self.process_common_key_action(action.into());
+    }
+}
+
+impl<BackendData: Backend + 'static> AnvilState<BackendData> {
+    /// Start tracking a touchpad swipe.
+    /// Returns true if the swipe is handled by the compositor and must not reach clients.
+    pub fn swipe_gesture_begin(&mut self, fingers: u32) -> bool {
+        let handled = !self.kiosk_enabled() && fingers == self.config.swipe_gestures.fingers;
+        self.button_gestures.swipe = handled.then(Point::default);
+        handled
+    }
+
+    pub fn swipe_gesture_update(&mut self, delta: Point<f64, Logical>) -> bool {
+        match self.button_gestures.swipe.as_mut() {
+            Some(swipe) => {
+                *swipe += delta;
+                true
+            }
+            None => false,
+        }
+    }
+
+    pub fn swipe_gesture_end(&mut self, cancelled: bool) -> bool {
+        let Some(swipe) = self.button_gestures.swipe.take() else {
+            return false;
+        };
+        if cancelled {
+            return true;
+        }
+
+        let config = &self.config.swipe_gestures;
+        let action = if swipe.x.abs().max(swipe.y.abs()) < config.distance {
+            SwipeAction::None
+        } else if swipe.x.abs() >= swipe.y.abs() {
+            if swipe.x < 0.0 {
+                config.left.clone()
+            } else {
+                config.right.clone()
+            }
+        } else if swipe.y < 0.0 {
+            config.up.clone()
+        } else {
+            config.down.clone()
+        };
+        debug!(?action, "Swipe gesture");
+        self.run_gesture_action(action);
+        true
+    }
+}
@@ -0,0 +1,416 @@
+use std::{
+    sync::atomic::Ordering,
+    time::{Duration, Instant},
+};
+
+#[cfg(feature = "egl")]
+use smithay::backend::renderer::ImportEgl;
+use smithay::{
+    backend::{
+        allocator::{dmabuf::Dmabuf, Fourcc},
+        drm::DrmNode,
+        egl::{EGLContext, EGLDevice, EGLDisplay},
+        input::LedState,
+        renderer::{
+            damage::OutputDamageTracker,
+            gles::{GlesRenderbuffer, GlesRenderer},
+            ImportDma, ImportMemWl, Offscreen,
+        },
+    },
+    delegate_dmabuf,
+    desktop::Window,
+    output::{Mode as WlMode, Output, PhysicalProperties, Scale as OutputScale, Subpixel},
+    reexports::{
+        calloop::{
+            timer::{TimeoutAction, Timer},
+            EventLoop,
+        },
+        wayland_server::{protocol::wl_surface::WlSurface, Display},
+    },
+    utils::{Physical, Size, Transform},
+    wayland::dmabuf::{DmabufGlobal, DmabufHandler, DmabufState, ImportNotifier},
+};
+use tracing::{error, info, warn};
+
+use crate::{
+    color_filter::ColorFilterPass,
+    render::{render_output, CustomRenderElements},
+    state::{
+        load_config, post_repaint, AnvilState, Backend, Configuration, HeadlessOutputConfiguration,
+    },
+};
+
+/// An output rendered offscreen on a timer
+struct HeadlessOutput {
+    output: Output,
+    buffer: GlesRenderbuffer,
+    damage_tracker: OutputDamageTracker,
+    color_filter: ColorFilterPass,
+    /// Whether `buffer` holds the previous frame
+    drawn: bool,
+    /// Time between frames at the refresh rate
+    interval: Duration,
+}
+
+pub struct HeadlessData {
+    renderer: GlesRenderer,
+    outputs: Vec<HeadlessOutput>,
+    dmabuf_state: Option<(DmabufState, DmabufGlobal)>,
+    /// Render times of the frames so far when their number is limited, summed up
+    /// once they ran
+    frame_times: Vec<Duration>,
+}
+
+impl std::fmt::Debug for HeadlessData {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("HeadlessData")
+            .field("outputs", &self.outputs.len())
+            .field("frames", &self.frame_times.len())
+            .finish_non_exhaustive()
+    }
+}
+
+impl DmabufHandler for AnvilState<HeadlessData> {
+    fn dmabuf_state(&mut self) -> &mut DmabufState {
+        &mut self.backend_data.dmabuf_state.as_mut().unwrap().0
+    }
+
+    fn dmabuf_imported(
+        &mut self,
+        _global: &DmabufGlobal,
+        dmabuf: Dmabuf,
+        notifier: ImportNotifier,
+    ) {
+        if self
+            .backend_data
+            .renderer
+            .import_dmabuf(&dmabuf, None)
+            .is_ok()
+        {
+            let _ = notifier.successful::<AnvilState<HeadlessData>>();
+        } else {
+            notifier.failed();
+        }
+    }
+}
+delegate_dmabuf!(AnvilState<HeadlessData>);
+
+impl Backend for HeadlessData {
+    fn seat_name(&self) -> String {
+        String::from("headless")
+    }
+
+    fn reset_buffers(&mut self, output: &Output) {
+        if let Some(headless) = self
+            .outputs
+            .iter_mut()
+            .find(|headless| &headless.output == output)
+        {
+            headless.damage_tracker = OutputDamageTracker::from_output(output);
+            headless.drawn = false;
+        }
+    }
+
+    fn early_import(&mut self, _surface: &WlSurface) {}
+
+    fn update_led_state(&mut self, _led_state: LedState) {}
+
+    fn capture_window(&mut self, _window: &Window, _size: Size<i32, Physical>) -> Option<Dmabuf> {
+        None
+    }
+
+    fn sample_window(&mut self, _window: &Window, _size: Size<i32, Physical>) -> Option<Vec<u8>> {
+        None
+    }
+}
+
+/// A renderer without any display, on the configured gpu, else one with a render
+/// node, else any like a software one
+fn offscreen_renderer(config: &Configuration) -> Result<GlesRenderer, String> {
+    let configured = config
+        .primary_gpu
+        .as_ref()
+        .and_then(|path| DrmNode::from_path(path).ok());
+    let devices = EGLDevice::enumerate()
+        .map_err(|err| format!("Unable to list EGL devices: {err}"))?
+        .collect::<Vec<_>>();
+    let render_node = |device: &EGLDevice| device.try_get_render_node().ok().flatten();
+    let device = devices
+        .iter()
+        .find(|device| configured.is_some() && render_node(device) == configured)
+        .or_else(|| devices.iter().find(|device| render_node(device).is_some()))
+        .or_else(|| devices.first())
+        .ok_or("No EGL device")?;
+    info!(render_node = ?render_node(device), "Rendering headless");
+
+    let display = unsafe { EGLDisplay::new(device.clone()) }
+        .map_err(|err| format!("Unable to create EGL display: {err}"))?;
+    let context =
+        EGLContext::new(&display).map_err(|err| format!("Unable to create EGL context: {err}"))?;
+    unsafe { GlesRenderer::new(context) }.map_err(|err| format!("Unable to create renderer: {err}"))
+}
+
+fn create_output(
+    renderer: &mut GlesRenderer,
+    config: &HeadlessOutputConfiguration,
+) -> Result<HeadlessOutput, String> {
+    let output = Output::new(
+        config.name.clone(),
+        PhysicalProperties {
+            size: (0, 0).into(),
+            subpixel: Subpixel::Unknown,
+            make: "Consolation".into(),
+            model: "Headless".into(),
+        },
+    );
+    let mode = WlMode {
+        size: (config.width, config.height).into(),
+        refresh: (config.refresh_rate * 1000.0).round() as i32,
+    };
+    output.add_mode(mode);
+    output.set_preferred(mode);
+    output.change_current_state(
+        Some(mode),
+        None,
+        Some(OutputScale::Fractional(config.scale)),
+        None,
+    );
+    let buffer = renderer
+        .create_buffer(
+            Fourcc::Abgr8888,
+            mode.size.to_logical(1).to_buffer(1, Transform::Normal),
+        )
+        .map_err(|err| format!("Unable to create buffer for {}: {err}", config.name))?;
+    Ok(HeadlessOutput {
+        damage_tracker: OutputDamageTracker::from_output(&output),
+        color_filter: ColorFilterPass::default(),
+        output,
+        buffer,
+        drawn: false,
+        interval: Duration::from_secs_f64(1.0 / config.refresh_rate.max(1.0)),
+    })
+}
+
+/// Run without DRM or input devices, rendering the configured outputs offscreen,
+/// for tests and benchmarks of the render path
+pub fn run_headless(app: Option<Vec<String>>, socket: Option<String>) {
+    let mut event_loop = EventLoop::try_new().unwrap();
+    let display = Display::new().unwrap();
+    let display_handle = display.handle();
+
+    let startup_config = load_config().unwrap_or_default();
+    let mut renderer = match offscreen_renderer(&startup_config) {
+        Ok(renderer) => renderer,
+        Err(err) => {
+            error!("Could not initialize a renderer: {}", err);
+            return;
+        }
+    };
+    let mut outputs = Vec::new();
+    for config in &startup_config.headless.outputs {
+        match create_output(&mut renderer, config) {
+            Ok(output) => outputs.push(output),
+            Err(err) => warn!("Skipping output: {}", err),
+        }
+    }
+
+    let data = HeadlessData {
+        renderer,
+        outputs,
+        dmabuf_state: None,
+        frame_times: Vec::new(),
+    };
+    let mut state = AnvilState::init(display, event_loop.handle(), data, true, socket);
+    state.start_metrics_server();
+    state.start_letterbox_detection();
+    state.start_reaping();
+    state.start_notifications();
+    state.start_progress_reports();
+    state.start_window_cycling();
+    state.start_clock();
+    state.start_status_overlay();
+    state.start_config_reload();
+    state.start_log_retrieval();
+    state.start_inspector();
+    state.start_protocol_trace();
+    state.start_picture_in_picture();
+    state.start_split_screen();
+
+    state
+        .shm_state
+        .update_formats(state.backend_data.renderer.shm_formats());
+    #[cfg(feature = "egl")]
+    if let Err(err) = state.backend_data.renderer.bind_wl_display(&display_handle) {
+        info!(?err, "Failed to initialize EGL hardware-acceleration");
+    }
+    let dmabuf_formats = state.backend_data.renderer.dmabuf_formats();
+    let mut dmabuf_state = DmabufState::new();
+    let global =
+        dmabuf_state.create_global::<AnvilState<HeadlessData>>(&display_handle, dmabuf_formats);
+    state.backend_data.dmabuf_state = Some((dmabuf_state, global));
+
+    for (index, headless) in state.backend_data.outputs.iter().enumerate() {
+        let output = headless.output.clone();
+        let _global = output.create_global::<AnvilState<HeadlessData>>(&display_handle);
+        let interval = headless.interval;
+        let res = state
+            .handle
+            .insert_source(Timer::from_duration(interval), move |_, _, data| {
+                data.render_headless(index);
+                TimeoutAction::ToDuration(interval)
+            });
+        if let Err(err) = res {
+            warn!(?err, "Unable to insert render timer");
+        }
+        info!(name = output.name(), "Added headless output");
+        state.outputs.push(output);
+    }
+
+    state.start_session_applications();
+    if let Some(app) = app {
+        state.start_app_watchdog(app);
+    }
+
+    while state.running.load(Ordering::SeqCst) {
+        let timeout = state.dispatch_timeout();
+        let result = event_loop.dispatch(timeout, &mut state);
+        if result.is_err() {
+            state.running.store(false, Ordering::SeqCst);
+        } else {
+            state.refresh_windows();
+            state.refresh_protocol_state();
+            display_handle.flush_clients().unwrap();
+        }
+    }
+}
+
+impl AnvilState<HeadlessData> {
+    fn render_headless(&mut self, index: usize) {
+        let start = Instant::now();
+        let layout = self.window_layout();
+        let zoom = self
+            .backend_data
+            .outputs
+            .get(index)
+            .and_then(|headless| self.output_zoom(&headless.output));
+        let color_filter = self.active_color_filter();
+        let Some(headless) = self.backend_data.outputs.get_mut(index) else {
+            return;
+        };
+        let renderer = &mut self.backend_data.renderer;
+        let output = headless.output.clone();
+
+        let mut custom_elements: Vec<CustomRenderElements<GlesRenderer>> = Vec::new();
+        if let Some(watchdog) = self.app_watchdog.as_mut() {
+            custom_elements.extend(
+                watchdog
+                    .render_elements(&output)
+                    .into_iter()
+                    .map(CustomRenderElements::Solid),
+            );
+        }
+        if let Some(text_entry) = self.text_entry.as_mut() {
+            custom_elements.extend(
+                text_entry
+                    .render_elements(renderer, &output)
+                    .into_iter()
+                    .map(CustomRenderElements::Memory),
+            );
+        }
+        custom_elements.extend(
+            self.notifications
+                .render_elements(renderer, &output)
+                .into_iter()
+                .map(CustomRenderElements::Memory),
+        );
+        custom_elements.extend(
+            self.progress
+                .render_elements(renderer, &output)
+                .into_iter()
+                .map(CustomRenderElements::Memory),
+        );
+        custom_elements.extend(
+            self.window_cycling
+                .render_elements(renderer, &output)
+                .into_iter()
+                .map(CustomRenderElements::Memory),
+        );
+        custom_elements.extend(
+            self.time
+                .render_elements(renderer, &output, &self.config.time)
+                .into_iter()
+                .map(CustomRenderElements::Memory),
+        );
+        custom_elements.extend(
+            self.status
+                .render_elements(renderer, &output, self.config.time.twenty_four_hour)
+                .into_iter()
+                .map(CustomRenderElements::Memory),
+        );
+
+        let age = usize::from(headless.drawn);
+        let states = match render_output(
+            &output,
+            &self.elements,
+            &layout,
+            self.window_transitions.current.as_ref(),
+            &self.config,
+            custom_elements,
+            None,
+            renderer,
+            headless.buffer.clone(),
+            &mut headless.damage_tracker,
+            age,
+            zoom,
+            color_filter.map(|filter| (filter, &mut headless.color_filter)),
+        ) {
+            Ok(result) => {
+                // Frame times include the gpu finishing the frame
+                let _ = result.sync.wait();
+                result.states
+            }
+            Err(err) => {
+                warn!(?err, "Unable to render headless output");
+                return;
+            }
+        };
+        headless.drawn = true;
+        post_repaint(
+            &output,
+            &states,
+            &self.elements,
+            None,
+            self.clock.now(),
+            &self.config,
+        );
+
+        let elapsed = start.elapsed();
+        self.metrics.frame_time.observe(elapsed);
+        let Some(frames) = self.config.headless.frames else {
+            return;
+        };
+        self.backend_data.frame_times.push(elapsed);
+        if self.backend_data.frame_times.len() as u64 >= frames {
+            self.report_frame_times();
+            self.running.store(false, Ordering::SeqCst);
+        }
+    }
+
+    fn report_frame_times(&self) {
+        let mut times = self.backend_data.frame_times.clone();
+        times.sort();
+        let Some(max) = times.last() else {
+            return;
+        };
+        let percentile = |p: f64| times[((times.len() - 1) as f64 * p).round() as usize];
+        let total: Duration = times.iter().sum();
+        info!(
+            frames = times.len(),
+            mean = ?total / times.len() as u32,
+            median = ?percentile(0.5),
+            p99 = ?percentile(0.99),
+            ?max,
+            "Headless frame times"
+        );
+    }
+}
@@ -0,0 +1,138 @@
+use std::{
+    fmt::Write as _,
+    fs::{read_dir, read_to_string},
+    time::{Duration, Instant},
+};
+
+use smithay::reexports::calloop::timer::{TimeoutAction, Timer};
+use tracing::{info, warn};
+
+use crate::{
+    shell::window_app_id,
+    state::{AnvilState, HibernationConfiguration},
+    udev::UdevData,
+};
+
+const POLL_INTERVAL: Duration = Duration::from_secs(60);
+
+#[derive(Debug)]
+pub struct HibernationState {
+    last_input: Instant,
+    hibernating: bool,
+}
+
+impl Default for HibernationState {
+    fn default() -> Self {
+        Self {
+            last_input: Instant::now(),
+            hibernating: false,
+        }
+    }
+}
+
+/// Whether any ALSA playback stream runs. PipeWire keeps the devices of its
+/// playing streams running and suspends them shortly after playback stops
+fn playback_active() -> bool {
+    let Ok(cards) = read_dir("/proc/asound") else {
+        return false;
+    };
+    cards
+        .flatten()
+        .filter(|card| card.file_name().to_string_lossy().starts_with("card"))
+        .filter_map(|card| read_dir(card.path()).ok())
+        .flatten()
+        .flatten()
+        .filter(|pcm| {
+            let name = pcm.file_name();
+            let name = name.to_string_lossy();
+            name.starts_with("pcm") && name.ends_with('p')
+        })
+        .filter_map(|pcm| read_dir(pcm.path()).ok())
+        .flatten()
+        .flatten()
+        .any(|substream| {
+            read_to_string(substream.path().join("status"))
+                .is_ok_and(|status| status.contains("state: RUNNING"))
+        })
+}
+
+impl AnvilState<UdevData> {
+    /// Check for a long idle time once a minute
+    pub fn start_hibernation_watch(&mut self) {
+        let res = self
+            .handle
+            .insert_source(Timer::from_duration(POLL_INTERVAL), |_, _, data| {
+                data.poll_hibernation();
+                TimeoutAction::ToDuration(POLL_INTERVAL)
+            });
+        if let Err(err) = res {
+            warn!(?err, "Unable to insert hibernation timer");
+        }
+    }
+
+    /// Note user input. The backend turns the outputs back on
+    pub fn input_activity(&mut self) {
+        self.hibernation.last_input = Instant::now();
+        if self.hibernation.hibernating {
+            info!("Waking up from hibernation");
+            self.hibernation.hibernating = false;
+        }
+    }
+
+    fn poll_hibernation(&mut self) {
+        let Some(config) = self.config.hibernation.clone() else {
+            return;
+        };
+        let idle = Duration::from_secs(config.idle_minutes.saturating_mul(60));
+        if self.hibernation.hibernating
+            || self.hibernation.last_input.elapsed() < idle
+            || playback_active()
+        {
+            return;
+        }
+        self.hibernate(&config);
+    }
+
+    fn hibernate(&mut self, config: &HibernationConfiguration) {
+        info!("Hibernating after a long idle time");
+        if config.checkpoint {
+            self.write_checkpoint();
+        }
+        if config.close_windows {
+            for window in self.elements.clone() {
+                self.close_window(&window);
+            }
+        }
+        self.hibernation.hibernating = true;
+        self.set_outputs_active(false);
+    }
+
+    /// Record which programs had windows, so they can be started again later
+    fn write_checkpoint(&self) {
+        let mut checkpoint = String::new();
+        for window in &self.elements {
+            let pid = self.window_pid(window);
+            let command = pid
+                .and_then(|pid| read_to_string(format!("/proc/{pid}/cmdline")).ok())
+                .map(|cmdline| cmdline.trim_end_matches('\0').replace('\0', " "))
+                .unwrap_or_default();
+            let _ = writeln!(
+                checkpoint,
+                "{}\t{}\t{command}",
+                pid.unwrap_or_default(),
+                window_app_id(window).unwrap_or_default(),
+            );
+        }
+        let path = match xdg::BaseDirectories::with_prefix("consolation") {
+            Ok(dirs) => dirs.place_state_file("session-checkpoint"),
+            Err(err) => {
+                warn!(?err, "Unable to find session checkpoint path");
+                return;
+            }
+        };
+        match path.and_then(|path| std::fs::write(&path, checkpoint).map(|_| path)) {
+            Ok(path) => info!(?path, "Wrote session checkpoint"),
+            Err(err) => warn!(?err, "Unable to write session checkpoint"),
+        }
+    }
+}
@@ -2,6 +2,8 @@ use std::{convert::TryInto, process::Command, sync::atomic::Ordering};
 
 use crate::{
     focus::{KeyboardFocusTarget, PointerFocusTarget},
+    screenshot::ScreenshotKind,
+    shell::{is_hidden, shown_window, window_geometry},
     AnvilState,
 };
 
@@ -22,19 +24,19 @@ use smithay::{
     },
     output::Output,
     reexports::wayland_server::protocol::wl_pointer,
-    utils::{Logical, Point, SERIAL_COUNTER as SCOUNTER},
+    utils::{Logical, Point, Size, SERIAL_COUNTER as SCOUNTER},
     wayland::input_method::InputMethodSeat,
 };
 
 #[cfg(any(feature = "winit", feature = "x11", feature = "udev"))]
 use smithay::backend::input::AbsolutePositionEvent;
 
-#[cfg(any(feature = "winit", feature = "x11"))]
-use smithay::output::Output;
-use tracing::{debug, error, info};
+use tracing::{debug, error, info, warn};
 
 use crate::state::Backend;
 #[cfg(feature = "udev")]
+use crate::{calibration::TouchCalibration, state::AccelProfile};
+#[cfg(feature = "udev")]
 use smithay::{
     backend::{
         input::{
@@ -54,6 +56,7 @@ use smithay::{
     },
     reexports::wayland_server::DisplayHandle,
     wayland::{
+        compositor::{RectangleKind, RegionAttributes},
         pointer_constraints::{with_pointer_constraint, PointerConstraint},
         seat::WaylandFocus,
         tablet_manager::{TabletDescriptor, TabletSeatTrait},
@@ -61,7 +64,7 @@ use smithay::{
 };
 
 impl<BackendData: Backend> AnvilState<BackendData> {
-    fn process_common_key_action(&mut self, action: KeyAction) {
+    pub(crate) fn process_common_key_action(&mut self, action: KeyAction) {
         match action {
             KeyAction::None => (),
 
@@ -70,28 +73,52 @@ impl<BackendData: Backend> AnvilState<BackendData> {
                 self.running.store(false, Ordering::SeqCst);
             }
 
+            KeyAction::Run(cmd) if self.kiosk_enabled() => {
+                info!(cmd, "Not starting program in kiosk mode");
+            }
+
             KeyAction::Run(cmd) => {
                 info!(cmd, "Starting program");
 
-                if let Err(e) = Command::new(&cmd)
-                    .envs(
-                        self.socket_name
-                            .clone()
-                            .map(|v| ("WAYLAND_DISPLAY", v))
-                            .into_iter()
-                            .chain(
-                                #[cfg(feature = "xwayland")]
-                                self.xdisplay.map(|v| ("DISPLAY", format!(":{}", v))),
-                                #[cfg(not(feature = "xwayland"))]
-                                None,
-                            ),
-                    )
-                    .spawn()
-                {
-                    error!(cmd, err = %e, "Failed to start program");
+                match Command::new(&cmd).envs(self.client_environment()).spawn() {
+                    Ok(child) => self.launched.insert(child.id()),
+                    Err(e) => error!(cmd, err = %e, "Failed to start program"),
                 }
             }
 
+            KeyAction::ArrowUp if self.time_settings_open() => self.move_time_settings(0, -1),
+            KeyAction::ArrowDown if self.time_settings_open() => self.move_time_settings(0, 1),
+            KeyAction::ArrowLeft if self.time_settings_open() => self.move_time_settings(-1, 0),
+            KeyAction::ArrowRight if self.time_settings_open() => self.move_time_settings(1, 0),
+            KeyAction::Select if self.time_settings_open() => self.press_time_settings(),
+            KeyAction::Back if self.time_settings_open() => self.close_time_settings(),
+
+            KeyAction::ArrowUp => self.move_text_entry(0, -1),
+            KeyAction::ArrowDown => self.move_text_entry(0, 1),
+            KeyAction::ArrowLeft => self.move_text_entry(-1, 0),
+            KeyAction::ArrowRight => self.move_text_entry(1, 0),
+            KeyAction::Select => self.press_text_entry(),
+            KeyAction::Back => self.text_entry_back(),
+
+            KeyAction::SwitchToPeek => self.switch_to_peek(),
+            KeyAction::TogglePictureInPicture => self.toggle_picture_in_picture(),
+            KeyAction::MovePictureInPicture => self.move_picture_in_picture_clockwise(),
+            KeyAction::ToggleSplitScreen => self.toggle_split_screen(),
+            KeyAction::ToggleZoom => self.toggle_zoom(),
+            KeyAction::ToggleColorFilter => self.toggle_color_filter(),
+            KeyAction::MoveScreenshotRegion(dx, dy) => self.move_screenshot_region(dx, dy),
+            KeyAction::CancelScreenshot => self.cancel_screenshot_region(),
+            KeyAction::RetryApp => self.retry_app(),
+            KeyAction::DismissApp => self.dismiss_app(),
+            KeyAction::DismissNotification => self.dismiss_notification(),
+            KeyAction::OpenTimeSettings => self.open_time_settings(),
+            KeyAction::NextWindow => self.next_window(),
+            KeyAction::PreviousWindow => self.previous_window(),
+            KeyAction::BrightnessUp => self.step_brightness(1),
+            KeyAction::BrightnessDown => self.step_brightness(-1),
+            KeyAction::ToggleStatus => self.toggle_status(),
+            KeyAction::Custom(name) => self.run_custom_key_action(&name),
+
             _ => unreachable!(
                 "Common key action handler encountered backend specific action {:?}",
                 action
@@ -116,7 +143,7 @@ impl<BackendData: Backend> AnvilState<BackendData> {
                 state,
                 serial,
                 time,
-                |_, modifiers, handle| {
+                |data, modifiers, handle| {
                     let keysym = handle.modified_sym();
 
                     debug!(
@@ -126,6 +153,11 @@ impl<BackendData: Backend> AnvilState<BackendData> {
                         "keysym"
                     );
 
+                    // Buttons bound to gestures are handled by the gesture detector
+                    if !inhibited && data.button_gesture_input(keysym, state) {
+                        return FilterResult::Intercept(KeyAction::None);
+                    }
+
                     // If the key is pressed and triggered a action
                     // we will not forward the key to the client.
                     // Additionally add the key to the suppressed keys
@@ -133,7 +165,21 @@ impl<BackendData: Backend> AnvilState<BackendData> {
                     // should be forwarded to the client or not.
                     if let KeyState::Pressed = state {
                         if !inhibited {
-                            let action = process_keyboard_shortcut(*modifiers, keysym);
+                            let action = data
+                                .custom_key_action(*modifiers, keysym)
+                                .or_else(|| process_keyboard_shortcut(*modifiers, keysym))
+                                .or_else(|| data.app_watchdog_key_action(keysym))
+                                .or_else(|| data.time_settings_key_action(keysym))
+                                .or_else(|| data.text_entry_key_action(keysym))
+                                .or_else(|| data.screenshot_key_action(keysym))
+                                .or_else(|| data.notification_key_action(keysym))
+                                .or_else(|| data.backlight_key_action(keysym))
+                                .or_else(|| data.status_key_action(keysym))
+                                .or_else(|| data.peek_key_action(keysym))
+                                .or_else(|| data.picture_in_picture_key_action(keysym))
+                                .or_else(|| data.split_screen_key_action(keysym))
+                                .or_else(|| data.zoom_key_action(keysym))
+                                .or_else(|| data.color_filter_key_action(keysym));
 
                             if action.is_some() {
                                 suppressed_keys.push(keysym);
@@ -146,6 +192,10 @@ impl<BackendData: Backend> AnvilState<BackendData> {
                             FilterResult::Forward
                         }
                     } else {
+                        // Letting go of Alt settles on the window Alt+Tab switched to
+                        if !modifiers.alt {
+                            data.end_window_cycle();
+                        }
                         let suppressed = suppressed_keys.contains(&keysym);
                         if suppressed {
                             suppressed_keys.retain(|k| *k != keysym);
@@ -163,10 +213,12 @@ impl<BackendData: Backend> AnvilState<BackendData> {
     }
 
     fn on_pointer_button<B: InputBackend>(&mut self, evt: B::PointerButtonEvent) {
-        let button = evt.button_code();
-
         let state = wl_pointer::ButtonState::from(evt.state());
+        self.pointer_button(evt.button_code(), state, evt.time_msec());
+    }
 
+    /// Press or release `button`, from an input device or a virtual pointer
+    pub fn pointer_button(&mut self, button: u32, state: wl_pointer::ButtonState, time: u32) {
         if wl_pointer::ButtonState::Pressed == state {
             self.update_keyboard_focus();
         };
@@ -178,7 +230,7 @@ impl<BackendData: Backend> AnvilState<BackendData> {
                 button,
                 state: state.try_into().unwrap(),
                 serial,
-                time: evt.time_msec(),
+                time,
             },
         );
         pointer.frame(self);
@@ -193,34 +245,18 @@ impl<BackendData: Backend> AnvilState<BackendData> {
             && (!keyboard.is_grabbed() || input_method.keyboard_grabbed())
             && !touch.map(|touch| touch.is_grabbed()).unwrap_or(false)
         {
-            let kbd = match self.elements.get(0) {
+            let kbd = match self.elements.iter().find(|window| !is_hidden(window)) {
                 Some(window) => Some(KeyboardFocusTarget::from(window.clone())),
                 None => None,
             };
             keyboard.set_focus(self, kbd, serial);
         }
+        #[cfg(feature = "xwayland")]
+        self.update_x11_window_states();
     }
 
     pub fn current_window(&self) -> (Option<Window>, Vec<Window>) {
-        // We want the first wayland or X window(excluding override redirect)
-
-        let mut popups = vec![];
-        for element in self.elements.clone() {
-            if element.is_wayland() {
-                return (Some(element.clone()), vec![]);
-            }
-            match element.x11_surface() {
-                Some(x11surface) => {
-                    if x11surface.is_override_redirect() {
-                        popups.push(element.clone());
-                    } else {
-                        return (Some(element.clone()), popups);
-                    }
-                }
-                None => {}
-            }
-        }
-        (None, vec![])
+        shown_window(&self.elements)
     }
 
     pub fn get_pointer_focus(
@@ -264,184 +300,149 @@ impl<BackendData: Backend> AnvilState<BackendData> {
         unimplemented!()
     }
 
+    /// Move the pointer to the `position` within the current window of the given size,
+    /// from an input device or a virtual pointer
+    pub fn pointer_motion_absolute(
+        &mut self,
+        position: impl FnOnce(Size<i32, Logical>) -> Point<f64, Logical>,
+        time: u32,
+    ) {
+        /* WARNING This assumes a position in window space... between 0,0 and width,height
+        If the value is in output space it should be shifted & scaled */
+        let serial = SCOUNTER.next_serial();
+        let pointer = self.pointer.clone();
+
+        if let (Some(window), _xpopups) = self.current_window() {
+            let geometry = window_geometry(&window);
+
+            let mut pointer_location = geometry.loc.to_f64() + position(geometry.size);
+
+            // clamp to screen limits
+            pointer_location = self.clamp_coords(pointer_location);
+
+            pointer.motion(
+                self,
+                Some((
+                    PointerFocusTarget::from(window.wl_surface().unwrap().into_owned()),
+                    pointer_location,
+                )),
+                &MotionEvent {
+                    location: pointer_location,
+                    serial,
+                    time,
+                },
+            );
+        }
+
+        pointer.frame(self);
+    }
+
+    fn clamp_coords(&self, pos: Point<f64, Logical>) -> Point<f64, Logical> {
+        if let (Some(window), _xpopups) = self.current_window() {
+            let (pos_x, pos_y) = pos.into();
+            let geometry = window_geometry(&window).to_f64();
+
+            let clamped_x = pos_x.clamp(geometry.loc.x, geometry.loc.x + geometry.size.w);
+            let clamped_y = pos_y.clamp(geometry.loc.y, geometry.loc.y + geometry.size.h);
+            return (clamped_x, clamped_y).into();
+        }
+        return pos;
+    }
+
     fn on_pointer_axis<B: InputBackend>(&mut self, evt: B::PointerAxisEvent) {
-        let horizontal_amount = evt.amount(input::Axis::Horizontal).unwrap_or_else(|| {
+        let frame = self.pointer_axis_frame::<B>(&evt);
+        if frame.axis.1 != 0.0 && self.zoom_scroll(frame.axis.1) {
+            return;
+        }
+        let pointer = self.pointer.clone();
+        pointer.axis(self, frame);
+        pointer.frame(self);
+    }
+
+    /// Scroll amounts of `evt`, with the response of analog axes applied
+    pub(crate) fn pointer_axis_frame<B: InputBackend>(
+        &self,
+        evt: &B::PointerAxisEvent,
+    ) -> AxisFrame {
+        let mut horizontal_amount = evt.amount(input::Axis::Horizontal).unwrap_or_else(|| {
             evt.amount_v120(input::Axis::Horizontal).unwrap_or(0.0) * 15.0 / 120.
         });
-        let vertical_amount = evt
+        let mut vertical_amount = evt
             .amount(input::Axis::Vertical)
             .unwrap_or_else(|| evt.amount_v120(input::Axis::Vertical).unwrap_or(0.0) * 15.0 / 120.);
+        if evt.source() == AxisSource::Continuous {
+            let device = input::Device::name(&evt.device());
+            if let Some(axis) = self.config.input.analog_axis(&device) {
+                horizontal_amount = axis.apply(horizontal_amount);
+                vertical_amount = axis.apply(vertical_amount);
+            }
+        }
         let horizontal_amount_discrete = evt.amount_v120(input::Axis::Horizontal);
         let vertical_amount_discrete = evt.amount_v120(input::Axis::Vertical);
 
-        {
-            let mut frame = AxisFrame::new(evt.time_msec()).source(evt.source());
-            if horizontal_amount != 0.0 {
-                frame = frame
-                    .relative_direction(Axis::Horizontal, evt.relative_direction(Axis::Horizontal));
-                frame = frame.value(Axis::Horizontal, horizontal_amount);
-                if let Some(discrete) = horizontal_amount_discrete {
-                    frame = frame.v120(Axis::Horizontal, discrete as i32);
-                }
+        let mut frame = AxisFrame::new(evt.time_msec()).source(evt.source());
+        if horizontal_amount != 0.0 {
+            frame = frame
+                .relative_direction(Axis::Horizontal, evt.relative_direction(Axis::Horizontal));
+            frame = frame.value(Axis::Horizontal, horizontal_amount);
+            if let Some(discrete) = horizontal_amount_discrete {
+                frame = frame.v120(Axis::Horizontal, discrete as i32);
             }
-            if vertical_amount != 0.0 {
-                frame = frame
-                    .relative_direction(Axis::Vertical, evt.relative_direction(Axis::Vertical));
-                frame = frame.value(Axis::Vertical, vertical_amount);
-                if let Some(discrete) = vertical_amount_discrete {
-                    frame = frame.v120(Axis::Vertical, discrete as i32);
-                }
+        }
+        if vertical_amount != 0.0 {
+            frame =
+                frame.relative_direction(Axis::Vertical, evt.relative_direction(Axis::Vertical));
+            frame = frame.value(Axis::Vertical, vertical_amount);
+            if let Some(discrete) = vertical_amount_discrete {
+                frame = frame.v120(Axis::Vertical, discrete as i32);
             }
-            if evt.source() == AxisSource::Finger {
-                if evt.amount(Axis::Horizontal) == Some(0.0) {
-                    frame = frame.stop(Axis::Horizontal);
-                }
-                if evt.amount(Axis::Vertical) == Some(0.0) {
-                    frame = frame.stop(Axis::Vertical);
-                }
+        }
+        if evt.source() == AxisSource::Finger {
+            if evt.amount(Axis::Horizontal) == Some(0.0) {
+                frame = frame.stop(Axis::Horizontal);
+            }
+            if evt.amount(Axis::Vertical) == Some(0.0) {
+                frame = frame.stop(Axis::Vertical);
             }
-            let pointer = self.pointer.clone();
-            pointer.axis(self, frame);
-            pointer.frame(self);
         }
+        frame
     }
 }
 
 #[cfg(any(feature = "winit", feature = "x11"))]
 impl<BackendData: Backend> AnvilState<BackendData> {
+    /// Handle input of a nested backend, whose pointer moves within the host
+    /// window showing `output_name`
     pub fn process_input_event_windowed<B: InputBackend>(
         &mut self,
         event: InputEvent<B>,
         output_name: &str,
     ) {
         match event {
-            InputEvent::Keyboard { event } => match self.keyboard_key_to_action::<B>(event) {
-                KeyAction::ScaleUp => {
-                    let output = self
-                        .space
-                        .outputs()
-                        .find(|o| o.name() == output_name)
-                        .unwrap()
-                        .clone();
-
-                    let current_scale = output.current_scale().fractional_scale();
-                    let new_scale = current_scale + 0.25;
-                    output.change_current_state(
-                        None,
-                        None,
-                        Some(Scale::Fractional(new_scale)),
-                        None,
-                    );
-
-                    crate::shell::fixup_positions(&mut self.space, self.pointer.current_location());
-                    self.backend_data.reset_buffers(&output);
-                }
-
-                KeyAction::ScaleDown => {
-                    let output = self
-                        .space
-                        .outputs()
-                        .find(|o| o.name() == output_name)
-                        .unwrap()
-                        .clone();
-
-                    let current_scale = output.current_scale().fractional_scale();
-                    let new_scale = f64::max(1.0, current_scale - 0.25);
-                    output.change_current_state(
-                        None,
-                        None,
-                        Some(Scale::Fractional(new_scale)),
-                        None,
+            InputEvent::Keyboard { event, .. } => match self.keyboard_key_to_action::<B>(event) {
+                action @ (KeyAction::VtSwitch(_)
+                | KeyAction::ToggleTint
+                | KeyAction::CalibrateTouch
+                | KeyAction::Screenshot(_)
+                | KeyAction::NextMode) => {
+                    warn!(
+                        ?action,
+                        output_name, "Key action unsupported on nested backend"
                     );
-
-                    crate::shell::fixup_positions(&mut self.space, self.pointer.current_location());
-                    self.backend_data.reset_buffers(&output);
                 }
-
-                KeyAction::RotateOutput => {
-                    let output = self
-                        .space
-                        .outputs()
-                        .find(|o| o.name() == output_name)
-                        .unwrap()
-                        .clone();
-
-                    let current_transform = output.current_transform();
-                    let new_transform = match current_transform {
-                        Transform::Normal => Transform::_90,
-                        Transform::_90 => Transform::_180,
-                        Transform::_180 => Transform::_270,
-                        Transform::_270 => Transform::Flipped,
-                        Transform::Flipped => Transform::Flipped90,
-                        Transform::Flipped90 => Transform::Flipped180,
-                        Transform::Flipped180 => Transform::Flipped270,
-                        Transform::Flipped270 => Transform::Normal,
-                    };
-                    tracing::info!(?current_transform, ?new_transform, output = ?output.name(), "changing output transform");
-                    output.change_current_state(None, Some(new_transform), None, None);
-                    crate::shell::fixup_positions(&mut self.space, self.pointer.current_location());
-                    self.backend_data.reset_buffers(&output);
-                }
-
-                action => match action {
-                    KeyAction::None
-                    | KeyAction::Quit
-                    | KeyAction::Run(_)
-                    | KeyAction::ToggleMenu
-                    | KeyAction::ArrowUp
-                    | KeyAction::ArrowDown
-                    | KeyAction::ArrowLeft
-                    | KeyAction::ArrowRight
-                    | KeyAction::Select
-                    | KeyAction::Back => self.process_common_key_action(action),
-
-                    _ => tracing::warn!(
-                        ?action,
-                        output_name,
-                        "Key action unsupported on on output backend.",
-                    ),
-                },
+                action => self.process_common_key_action(action),
             },
-
-            InputEvent::PointerMotionAbsolute { event } => {
-                let output = self
-                    .space
-                    .outputs()
-                    .find(|o| o.name() == output_name)
-                    .unwrap()
-                    .clone();
-                self.on_pointer_move_absolute_windowed::<B>(event, &output)
-            }
-            InputEvent::PointerButton { event } => self.on_pointer_button::<B>(event),
-            InputEvent::PointerAxis { event } => self.on_pointer_axis::<B>(event),
+            // The host window shows the output, which shows the window stretched
+            InputEvent::PointerMotionAbsolute { event, .. } => self.pointer_motion_absolute(
+                |size| Point::from((event.x_transformed(size.w), event.y_transformed(size.h))),
+                event.time_msec(),
+            ),
+            InputEvent::PointerButton { event, .. } => self.on_pointer_button::<B>(event),
+            InputEvent::PointerAxis { event, .. } => self.on_pointer_axis::<B>(event),
             _ => (), // other events are not handled in anvil (yet)
         }
     }
 
-    fn on_pointer_move_absolute_windowed<B: InputBackend>(
-        &mut self,
-        evt: B::PointerMotionAbsoluteEvent,
-        output: &Output,
-    ) {
-        let output_geo = self.space.output_geometry(output).unwrap();
-
-        let pos = evt.position_transformed(output_geo.size) + output_geo.loc.to_f64();
-        let serial = SCOUNTER.next_serial();
-
-        let pointer = self.pointer.clone();
-        let under = self.surface_under(pos);
-        pointer.motion(
-            self,
-            under,
-            &MotionEvent {
-                location: pos,
-                serial,
-                time: evt.time_msec(),
-            },
-        );
-        pointer.frame(self);
-    }
-
     pub fn release_all_keys(&mut self) {
         let keyboard = self.seat.get_keyboard().unwrap();
         for keycode in keyboard.pressed_keys() {
@@ -464,6 +465,9 @@ impl AnvilState<UdevData> {
         dh: &DisplayHandle,
         event: InputEvent<B>,
     ) {
+        let Some(event) = self.split_screen_input(event) else {
+            return;
+        };
         match event {
             InputEvent::Keyboard { event, .. } => match self.keyboard_key_to_action::<B>(event) {
                 #[cfg(feature = "udev")]
@@ -478,11 +482,31 @@ impl AnvilState<UdevData> {
                     debug_flags.toggle(DebugFlags::TINT);
                     self.backend_data.set_debug_flags(debug_flags);
                 }
+                KeyAction::CalibrateTouch => self.toggle_touch_calibration(),
+                KeyAction::Screenshot(kind) => self.take_screenshot(kind),
 
                 action => match action {
                     KeyAction::None
                     | KeyAction::Quit
                     | KeyAction::Run(_)
+                    | KeyAction::SwitchToPeek
+                    | KeyAction::TogglePictureInPicture
+                    | KeyAction::MovePictureInPicture
+                    | KeyAction::ToggleSplitScreen
+                    | KeyAction::ToggleZoom
+                    | KeyAction::ToggleColorFilter
+                    | KeyAction::MoveScreenshotRegion(..)
+                    | KeyAction::CancelScreenshot
+                    | KeyAction::RetryApp
+                    | KeyAction::DismissApp
+                    | KeyAction::DismissNotification
+                    | KeyAction::OpenTimeSettings
+                    | KeyAction::NextWindow
+                    | KeyAction::PreviousWindow
+                    | KeyAction::BrightnessUp
+                    | KeyAction::BrightnessDown
+                    | KeyAction::ToggleStatus
+                    | KeyAction::Custom(_)
                     | KeyAction::ArrowUp
                     | KeyAction::ArrowDown
                     | KeyAction::ArrowLeft
@@ -518,6 +542,15 @@ impl AnvilState<UdevData> {
             InputEvent::GestureHoldBegin { event, .. } => self.on_gesture_hold_begin::<B>(event),
             InputEvent::GestureHoldEnd { event, .. } => self.on_gesture_hold_end::<B>(event),
 
+            // Touches only go to the calibration while it runs
+            InputEvent::TouchDown { event } if self.backend_data.touch_calibration.is_some() => {
+                self.on_calibration_touch_down::<B>(event)
+            }
+            InputEvent::TouchUp { .. }
+            | InputEvent::TouchMotion { .. }
+            | InputEvent::TouchFrame { .. }
+            | InputEvent::TouchCancel { .. }
+                if self.backend_data.touch_calibration.is_some() => {}
             InputEvent::TouchDown { event } => self.on_touch_down::<B>(event),
             InputEvent::TouchUp { event } => self.on_touch_up::<B>(event),
             InputEvent::TouchMotion { event } => self.on_touch_motion::<B>(event),
@@ -557,6 +590,23 @@ impl AnvilState<UdevData> {
         &mut self,
         _dh: &DisplayHandle,
         evt: B::PointerMotionEvent,
+    ) {
+        self.pointer_motion(
+            evt.delta(),
+            evt.delta_unaccel(),
+            evt.time(),
+            evt.time_msec(),
+        );
+    }
+
+    /// Move the pointer by `delta`, from an input device or a virtual pointer.
+    /// `utime` is in microseconds, `time` in milliseconds
+    pub fn pointer_motion(
+        &mut self,
+        delta: Point<f64, Logical>,
+        delta_unaccel: Point<f64, Logical>,
+        utime: u64,
+        time: u32,
     ) {
         let mut pointer_location = self.pointer.current_location();
         let serial = SCOUNTER.next_serial();
@@ -564,6 +614,8 @@ impl AnvilState<UdevData> {
         let pointer = self.pointer.clone();
 
         let mut pointer_locked = false;
+        let mut pointer_confined = false;
+        let mut confine_region = None;
 
         if let (Some(window), _xpopups) = self.current_window() {
             // Pass relative movement if possible
@@ -573,7 +625,10 @@ impl AnvilState<UdevData> {
                         PointerConstraint::Locked(_locked) => {
                             pointer_locked = true;
                         }
-                        _ => {}
+                        PointerConstraint::Confined(confined) => {
+                            pointer_confined = true;
+                            confine_region = confined.region().cloned();
+                        }
                     },
                     _ => {}
                 });
@@ -583,9 +638,9 @@ impl AnvilState<UdevData> {
                 self,
                 self.get_pointer_focus(pointer_location),
                 &RelativeMotionEvent {
-                    delta: evt.delta(),
-                    delta_unaccel: evt.delta_unaccel(),
-                    utime: evt.time(),
+                    delta,
+                    delta_unaccel,
+                    utime,
                 },
             );
         }
@@ -596,18 +651,27 @@ impl AnvilState<UdevData> {
             return;
         }
 
-        pointer_location += evt.delta();
+        let previous_location = pointer_location;
+        pointer_location += delta;
 
         // clamp to screen limits
         pointer_location = self.clamp_coords(pointer_location);
 
+        // The focused window is always placed at the origin of pointer space,
+        // so the pointer location is already surface-local for the constraint region
+        if pointer_confined {
+            if let Some(region) = confine_region {
+                pointer_location = clamp_to_region(&region, pointer_location, previous_location);
+            }
+        }
+
         pointer.motion(
             self,
             self.get_pointer_focus(pointer_location),
             &MotionEvent {
                 location: pointer_location,
                 serial,
-                time: evt.time_msec(),
+                time,
             },
         );
         pointer.frame(self);
@@ -618,35 +682,10 @@ impl AnvilState<UdevData> {
         _dh: &DisplayHandle,
         evt: B::PointerMotionAbsoluteEvent,
     ) {
-        /* WARNING This assumes a position in window space... between 0,0 and width,height
-        If the value is in output space it should be shifted & scaled */
-        let serial = SCOUNTER.next_serial();
-        let pointer = self.pointer.clone();
-
-        if let (Some(window), _xpopups) = self.current_window() {
-            let max_x = window.bbox().size.w;
-            let max_y = window.bbox().size.h;
-
-            let mut pointer_location = (evt.x_transformed(max_x), evt.y_transformed(max_y)).into();
-
-            // clamp to screen limits
-            pointer_location = self.clamp_coords(pointer_location);
-
-            pointer.motion(
-                self,
-                Some((
-                    PointerFocusTarget::from(window.wl_surface().unwrap().into_owned()),
-                    pointer_location,
-                )),
-                &MotionEvent {
-                    location: pointer_location,
-                    serial,
-                    time: evt.time_msec(),
-                },
-            );
-        }
-
-        pointer.frame(self);
+        self.pointer_motion_absolute(
+            |size| Point::from((evt.x_transformed(size.w), evt.y_transformed(size.h))),
+            evt.time_msec(),
+        );
     }
 
     fn on_tablet_tool_axis<B: InputBackend>(&mut self, _evt: B::TabletToolAxisEvent) {
@@ -804,6 +843,9 @@ impl AnvilState<UdevData> {
     }
 
     fn on_gesture_swipe_begin<B: InputBackend>(&mut self, evt: B::GestureSwipeBeginEvent) {
+        if self.swipe_gesture_begin(evt.fingers()) {
+            return;
+        }
         let serial = SCOUNTER.next_serial();
         let pointer = self.pointer.clone();
         pointer.gesture_swipe_begin(
@@ -817,6 +859,9 @@ impl AnvilState<UdevData> {
     }
 
     fn on_gesture_swipe_update<B: InputBackend>(&mut self, evt: B::GestureSwipeUpdateEvent) {
+        if self.swipe_gesture_update(evt.delta()) {
+            return;
+        }
         let pointer = self.pointer.clone();
         pointer.gesture_swipe_update(
             self,
@@ -828,6 +873,9 @@ impl AnvilState<UdevData> {
     }
 
     fn on_gesture_swipe_end<B: InputBackend>(&mut self, evt: B::GestureSwipeEndEvent) {
+        if self.swipe_gesture_end(evt.cancelled()) {
+            return;
+        }
         let serial = SCOUNTER.next_serial();
         let pointer = self.pointer.clone();
         pointer.gesture_swipe_end(
@@ -997,24 +1045,153 @@ impl AnvilState<UdevData> {
         handle.cancel(self);
     }
 
-    fn clamp_coords(&self, pos: Point<f64, Logical>) -> Point<f64, Logical> {
-        if let (Some(window), _xpopups) = self.current_window() {
-            let (pos_x, pos_y) = pos.into();
-            let max_x = window.bbox().size.w;
-            let max_y = window.bbox().size.h;
+    /// Apply the configured accel profile, speed, scrolling and tapping to a libinput device
+    pub fn apply_input_config(&self, device: &mut smithay::reexports::input::Device) {
+        let config = &self.config.input;
+        if device.config_accel_is_available() {
+            if let Some(profile) = config.accel_profile {
+                let profile = match profile {
+                    AccelProfile::Flat => smithay::reexports::input::AccelProfile::Flat,
+                    AccelProfile::Adaptive => smithay::reexports::input::AccelProfile::Adaptive,
+                };
+                if let Err(err) = device.config_accel_set_profile(profile) {
+                    warn!(device = device.name(), ?err, "Unable to set accel profile");
+                }
+            }
+            if let Some(speed) = config.accel_speed {
+                if let Err(err) = device.config_accel_set_speed(speed.clamp(-1.0, 1.0)) {
+                    warn!(device = device.name(), ?err, "Unable to set accel speed");
+                }
+            }
+        }
+        if let Some(natural_scroll) = config.natural_scroll {
+            if device.config_scroll_has_natural_scroll() {
+                if let Err(err) = device.config_scroll_set_natural_scroll_enabled(natural_scroll) {
+                    warn!(device = device.name(), ?err, "Unable to set natural scroll");
+                }
+            }
+        }
+        if let Some(tap_to_click) = config.tap_to_click {
+            if device.config_tap_finger_count() > 0 {
+                if let Err(err) = device.config_tap_set_enabled(tap_to_click) {
+                    warn!(device = device.name(), ?err, "Unable to set tap to click");
+                }
+            }
+        }
+        if device.config_calibration_has_matrix() {
+            let matrix = config
+                .touch_calibration
+                .get(device.name())
+                .copied()
+                .or_else(|| device.config_calibration_default_matrix());
+            if let Some(matrix) = matrix {
+                if let Err(err) = device.config_calibration_set_matrix(matrix) {
+                    warn!(
+                        device = device.name(),
+                        ?err,
+                        "Unable to set calibration matrix"
+                    );
+                }
+            }
+        }
+    }
 
-            let clamped_x = pos_x.clamp(0.0, max_x as f64);
-            let clamped_y = pos_y.clamp(0.0, max_y as f64);
-            return (clamped_x, clamped_y).into();
+    /// Start calibrating the first touchscreen, or cancel a running calibration
+    fn toggle_touch_calibration(&mut self) {
+        if let Some(calibration) = self.backend_data.touch_calibration.take() {
+            info!("Touch calibration cancelled");
+            self.apply_input_config(&mut calibration.device().clone());
+            return;
+        }
+
+        let Some(device) = self
+            .backend_data
+            .touchscreens
+            .iter()
+            .find(|device| device.config_calibration_has_matrix())
+            .cloned()
+        else {
+            warn!("No touchscreen to calibrate");
+            return;
+        };
+        info!(device = device.name(), "Starting touch calibration");
+        self.backend_data.touch_calibration = Some(TouchCalibration::new(device));
+    }
+
+    fn on_calibration_touch_down<B: InputBackend>(&mut self, evt: B::TouchDownEvent) {
+        let Some(calibration) = self.backend_data.touch_calibration.as_mut() else {
+            return;
+        };
+        if evt.device().name() != calibration.device().name() {
+            return;
+        }
+        let Some(matrix) = calibration.add_sample((evt.x_transformed(1), evt.y_transformed(1)))
+        else {
+            return;
+        };
+
+        let mut device = calibration.device().clone();
+        self.backend_data.touch_calibration = None;
+        info!(
+            device = device.name(),
+            ?matrix,
+            "Touch calibration finished"
+        );
+        if let Err(err) = device.config_calibration_set_matrix(matrix) {
+            warn!(
+                device = device.name(),
+                ?err,
+                "Unable to set calibration matrix"
+            );
         }
+        self.config
+            .input
+            .touch_calibration
+            .insert(device.name().to_string(), matrix);
+        if let Err(err) = crate::state::store_config(&self.config) {
+            error!(?err, "Unable to store touch calibration");
+        }
+    }
+}
+
+/// Move `pos` to the nearest point inside `region`, falling back to `previous` if no
+/// rectangle of the region can hold the pointer
+#[cfg(feature = "udev")]
+fn clamp_to_region(
+    region: &RegionAttributes,
+    pos: Point<f64, Logical>,
+    previous: Point<f64, Logical>,
+) -> Point<f64, Logical> {
+    if region.contains(pos.to_i32_round()) {
         return pos;
     }
+
+    region
+        .rects
+        .iter()
+        .filter(|(kind, _)| *kind == RectangleKind::Add)
+        .map(|(_, rect)| {
+            let (x, y) = pos.into();
+            let max_x = (rect.loc.x + rect.size.w - 1).max(rect.loc.x) as f64;
+            let max_y = (rect.loc.y + rect.size.h - 1).max(rect.loc.y) as f64;
+            Point::<f64, Logical>::from((
+                x.clamp(rect.loc.x as f64, max_x),
+                y.clamp(rect.loc.y as f64, max_y),
+            ))
+        })
+        .filter(|clamped| region.contains(clamped.to_i32_round()))
+        .min_by(|a, b| {
+            let dist_a = (a.x - pos.x).powi(2) + (a.y - pos.y).powi(2);
+            let dist_b = (b.x - pos.x).powi(2) + (b.y - pos.y).powi(2);
+            dist_a.total_cmp(&dist_b)
+        })
+        .unwrap_or(previous)
 }
 
 /// Possible results of a keyboard action
 #[allow(dead_code)] // some of these are only read if udev is enabled
 #[derive(Debug)]
-enum KeyAction {
+pub(crate) enum KeyAction {
     /// Quit the compositor
     Quit,
     /// Trigger a vt-switch
@@ -1028,6 +1205,45 @@ enum KeyAction {
     ArrowRight,
     Select,
     Back,
+    /// Start or cancel touchscreen calibration
+    CalibrateTouch,
+    /// Raise the window shown in the attention peek
+    SwitchToPeek,
+    /// Show the most recently used other window small above the shown one, or hide it
+    TogglePictureInPicture,
+    /// Move picture-in-picture to the next corner clockwise
+    MovePictureInPicture,
+    /// Show the most recently used other window next to the shown one, or end the split
+    ToggleSplitScreen,
+    /// Magnify the screen around the pointer, or stop
+    ToggleZoom,
+    /// Turn the configured color filter on or off
+    ToggleColorFilter,
+    /// Save a screenshot, or start or finish selecting its region
+    Screenshot(ScreenshotKind),
+    /// Move the corner of the screenshot region by whole steps
+    MoveScreenshotRegion(i32, i32),
+    /// Stop selecting a screenshot region
+    CancelScreenshot,
+    /// Start the application of `--app` again right away
+    RetryApp,
+    /// Stop waiting for the application of `--app`
+    DismissApp,
+    /// Close the newest notification
+    DismissNotification,
+    /// Show the time zone and NTP settings
+    OpenTimeSettings,
+    /// Switch to the most recently used other window
+    NextWindow,
+    /// Raise the window that was used the longest time ago
+    PreviousWindow,
+    /// Make the screen a configured step brighter
+    BrightnessUp,
+    BrightnessDown,
+    /// Show or hide the battery, time and network overlay
+    ToggleStatus,
+    /// Run the action registered with `AnvilState::register_key_action` under this name
+    Custom(String),
     /// Do nothing more
     NextMode,
     None,
@@ -1048,12 +1264,19 @@ fn process_keyboard_shortcut(modifiers: ModifiersState, keysym: Keysym) -> Optio
     } else if modifiers.logo && keysym == Keysym::Return {
         // run terminal
         Some(KeyAction::Run("xfce4-terminal".into()))
+    } else if modifiers.alt && keysym == Keysym::Tab {
+        Some(KeyAction::NextWindow)
+    } else if modifiers.alt && keysym == Keysym::ISO_Left_Tab {
+        // Shift turns Tab into ISO_Left_Tab
+        Some(KeyAction::PreviousWindow)
     } else if keysym == Keysym::Alt_R || keysym == Keysym::Menu {
         Some(KeyAction::Run("consolation-switcher".into()))
     } else if modifiers.logo && modifiers.shift && keysym == Keysym::T {
         Some(KeyAction::ToggleTint)
     } else if modifiers.logo && modifiers.shift && keysym == Keysym::P {
         Some(KeyAction::NextMode)
+    } else if modifiers.logo && modifiers.shift && keysym == Keysym::C {
+        Some(KeyAction::CalibrateTouch)
     } else {
         None
     }
@@ -0,0 +1,114 @@
+use tracing::{info, warn};
+use zbus::{blocking::Connection, fdo};
+
+use crate::{
+    dbus::Requests,
+    state::{AccelProfile, AnvilState, Backend},
+};
+
+const BUS_NAME: &str = "org.consolation.Input";
+const OBJECT_PATH: &str = "/org/consolation/Input";
+
+#[derive(Debug)]
+enum Request {
+    AccelProfile(AccelProfile),
+    AccelSpeed(f64),
+    NaturalScroll(bool),
+    TapToClick(bool),
+}
+
+/// `org.consolation.Input` object, changing the libinput settings of pointer
+/// devices until the next config reload
+struct InputServer {
+    requests: Requests<Request, ()>,
+}
+
+#[zbus::interface(name = "org.consolation.Input")]
+impl InputServer {
+    /// `flat` or `adaptive`
+    fn set_accel_profile(&self, profile: &str) -> fdo::Result<()> {
+        let profile = match profile.to_ascii_lowercase().as_str() {
+            "flat" => AccelProfile::Flat,
+            "adaptive" => AccelProfile::Adaptive,
+            _ => {
+                return Err(fdo::Error::InvalidArgs(format!(
+                    "Unknown accel profile {profile}"
+                )))
+            }
+        };
+        self.requests.send(Request::AccelProfile(profile));
+        Ok(())
+    }
+
+    /// Between -1.0 (slowest) and 1.0 (fastest)
+    fn set_accel_speed(&self, speed: f64) -> fdo::Result<()> {
+        if !(-1.0..=1.0).contains(&speed) {
+            return Err(fdo::Error::InvalidArgs(format!(
+                "Accel speed {speed} is not between -1 and 1"
+            )));
+        }
+        self.requests.send(Request::AccelSpeed(speed));
+        Ok(())
+    }
+
+    fn set_natural_scroll(&self, enabled: bool) {
+        self.requests.send(Request::NaturalScroll(enabled));
+    }
+
+    fn set_tap_to_click(&self, enabled: bool) {
+        self.requests.send(Request::TapToClick(enabled));
+    }
+}
+
+#[zbus::proxy(
+    interface = "org.consolation.Input",
+    default_service = "org.consolation.Input",
+    default_path = "/org/consolation/Input"
+)]
+trait ConsolationInput {
+    fn set_accel_profile(&self, profile: &str) -> zbus::Result<()>;
+    fn set_accel_speed(&self, speed: f64) -> zbus::Result<()>;
+    fn set_natural_scroll(&self, enabled: bool) -> zbus::Result<()>;
+    fn set_tap_to_click(&self, enabled: bool) -> zbus::Result<()>;
+}
+
+/// Change `setting` of the pointer devices of the running compositor, one of
+/// `accel-profile`, `accel-speed`, `natural-scroll` or `tap-to-click`
+pub fn request_setting(setting: &str, value: &str) -> zbus::Result<()> {
+    let invalid = || zbus::Error::Failure(format!("Invalid value {value} for {setting}"));
+    let connection = Connection::session()?;
+    let proxy = ConsolationInputProxyBlocking::new(&connection)?;
+    match setting {
+        "accel-profile" => proxy.set_accel_profile(value),
+        "accel-speed" => proxy.set_accel_speed(value.parse().map_err(|_| invalid())?),
+        "natural-scroll" => proxy.set_natural_scroll(value.parse().map_err(|_| invalid())?),
+        "tap-to-click" => proxy.set_tap_to_click(value.parse().map_err(|_| invalid())?),
+        _ => Err(zbus::Error::Failure(format!("Unknown setting {setting}"))),
+    }
+}
+
+impl<BackendData: Backend> AnvilState<BackendData> {
+    /// Serve `org.consolation.Input`, so `consolation --input` changes pointer
+    /// settings without editing the config file
+    pub fn start_input_settings(&mut self) {
+        let Some(requests) = self.insert_requests(|data, request| {
+            info!(?request, "Input setting changed");
+            let config = &mut data.config.input;
+            match request {
+                Request::AccelProfile(profile) => config.accel_profile = Some(profile),
+                Request::AccelSpeed(speed) => config.accel_speed = Some(speed),
+                Request::NaturalScroll(enabled) => config.natural_scroll = Some(enabled),
+                Request::TapToClick(enabled) => config.tap_to_click = Some(enabled),
+            }
+            BackendData::input_config_changed(data);
+        }) else {
+            return;
+        };
+        let server = InputServer { requests };
+        match self.dbus.serve(BUS_NAME, OBJECT_PATH, server, false) {
+            Ok(true) => {}
+            Ok(false) => warn!("Input bus name is already taken"),
+            Err(err) => warn!(?err, "Unable to serve input settings"),
+        }
+    }
+}
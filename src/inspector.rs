@@ -0,0 +1,150 @@
+use std::collections::BTreeMap;
+
+use smithay::{
+    backend::renderer::{
+        buffer_type,
+        utils::{with_renderer_surface_state, Buffer},
+        BufferType,
+    },
+    desktop::Window,
+    reexports::wayland_server::{Client, Resource},
+    wayland::{dmabuf::get_dmabuf, seat::WaylandFocus, shm::with_buffer_contents},
+};
+use tracing::{info, warn};
+use zbus::blocking::Connection;
+
+use crate::{
+    dbus::Requests,
+    policy::client_executable,
+    shell::{window_app_id, window_name},
+    state::{client_credentials, AnvilState, Backend, ClientState},
+};
+
+const BUS_NAME: &str = "org.consolation.Inspector";
+const OBJECT_PATH: &str = "/org/consolation/Inspector";
+
+/// `org.consolation.Inspector` object, asking the event loop about its clients
+struct InspectorServer {
+    requests: Requests<(), Vec<String>>,
+}
+
+#[zbus::interface(name = "org.consolation.Inspector")]
+impl InspectorServer {
+    /// A report of the connected clients, their windows and the objects they use
+    async fn clients(&self) -> Vec<String> {
+        self.requests.call(()).await.unwrap_or_default()
+    }
+}
+
+#[zbus::proxy(
+    interface = "org.consolation.Inspector",
+    default_service = "org.consolation.Inspector",
+    default_path = "/org/consolation/Inspector"
+)]
+trait ConsolationInspector {
+    fn clients(&self) -> zbus::Result<Vec<String>>;
+}
+
+/// The client report of the running compositor, like `wayland-info` for all of them
+pub fn request_clients() -> zbus::Result<Vec<String>> {
+    let connection = Connection::session()?;
+    ConsolationInspectorProxyBlocking::new(&connection)?.clients()
+}
+
+/// Format and kind of the buffer shown by the toplevel surface of `window`
+fn buffer_format(window: &Window) -> Option<String> {
+    let surface = window.wl_surface()?;
+    let buffer: Buffer = with_renderer_surface_state(&surface, |state| state.buffer().cloned())??;
+    match buffer_type(&buffer)? {
+        BufferType::Shm => {
+            with_buffer_contents(&buffer, |_, _, data| format!("shm {:?}", data.format)).ok()
+        }
+        BufferType::Dma => get_dmabuf(&buffer).ok().map(|dmabuf| {
+            format!(
+                "dmabuf {:?} {:?}",
+                dmabuf.format().code,
+                dmabuf.format().modifier
+            )
+        }),
+        other => Some(format!("{other:?}")),
+    }
+}
+
+impl<BackendData: Backend> AnvilState<BackendData> {
+    /// Serve `org.consolation.Inspector`, so `consolation --clients` prints what
+    /// clients are connected and what they use
+    pub fn start_inspector(&mut self) {
+        let Some(requests) = self.insert_requests(|data, ()| data.client_report()) else {
+            return;
+        };
+        let server = InspectorServer { requests };
+        match self.dbus.serve(BUS_NAME, OBJECT_PATH, server, false) {
+            Ok(true) => info!("Serving client inspector"),
+            Ok(false) => warn!("Inspector bus name is already taken"),
+            Err(err) => warn!(?err, "Unable to serve client inspector"),
+        }
+    }
+
+    /// Per client its process, sandbox, windows with their buffers, and the
+    /// objects it holds by interface, globals bound among them
+    fn client_report(&self) -> Vec<String> {
+        let handle = self.display_handle.backend_handle();
+        let mut lines = Vec::new();
+        for id in handle.all_clients() {
+            let Ok(client) = Client::from_id(&self.display_handle, id.clone()) else {
+                continue;
+            };
+            let pid = client_credentials(&client, &self.display_handle).map_or_else(
+                || "?".to_string(),
+                |credentials| credentials.pid.to_string(),
+            );
+            let executable = client_executable(&client, &self.display_handle)
+                .map_or_else(|| "?".into(), |path| path.display().to_string());
+            let mut header = format!("{id:?} pid {pid} {executable}");
+            if let Some(state) = client.get_data::<ClientState>() {
+                if let Some(context) = state.security_context.as_ref() {
+                    header += &format!(
+                        " sandboxed by {}",
+                        context.sandbox_engine.as_deref().unwrap_or("?")
+                    );
+                    if let Some(app_id) = context.app_id.as_deref() {
+                        header += &format!(" as {app_id}");
+                    }
+                }
+                if state.privileged {
+                    header += " privileged";
+                }
+            }
+            lines.push(header);
+
+            for window in self.elements.iter().filter(|window| {
+                window.wl_surface().and_then(|surface| surface.client()) == Some(client.clone())
+            }) {
+                lines.push(format!(
+                    "  window \"{}\" app id {} size {}x{} buffer {}",
+                    window_name(window),
+                    window_app_id(window).unwrap_or_else(|| "?".into()),
+                    window.geometry().size.w,
+                    window.geometry().size.h,
+                    buffer_format(window).unwrap_or_else(|| "none".into()),
+                ));
+            }
+
+            let mut interfaces: BTreeMap<&str, (usize, u32)> = BTreeMap::new();
+            if let Ok(objects) = handle.all_objects_for(&id) {
+                for object in objects {
+                    let version = handle
+                        .object_info(object.clone())
+                        .map_or(0, |info| info.version);
+                    let entry = interfaces.entry(object.interface().name).or_default();
+                    entry.0 += 1;
+                    entry.1 = entry.1.max(version);
+                }
+            }
+            for (interface, (count, version)) in interfaces {
+                lines.push(format!("  {interface} v{version} x{count}"));
+            }
+        }
+        lines
+    }
+}
@@ -0,0 +1,78 @@
+use std::{fmt, rc::Rc};
+
+use smithay::input::keyboard::{Keysym, ModifiersState};
+use tracing::{debug, warn};
+
+use crate::{
+    input_handler::KeyAction,
+    state::{AnvilState, Backend},
+};
+
+type KeyMatcher = Box<dyn Fn(ModifiersState, Keysym) -> bool>;
+type KeyHandler<BackendData> = Rc<dyn Fn(&mut AnvilState<BackendData>)>;
+
+/// Key binding registered by an embedding shell, like for a hardware button of a device
+pub struct CustomKeyAction<BackendData: Backend + 'static> {
+    name: String,
+    matches: KeyMatcher,
+    handler: KeyHandler<BackendData>,
+}
+
+impl<BackendData: Backend> fmt::Debug for CustomKeyAction<BackendData> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("CustomKeyAction")
+            .field("name", &self.name)
+            .finish_non_exhaustive()
+    }
+}
+
+impl<BackendData: Backend> AnvilState<BackendData> {
+    /// Run `handler` when a key for which `matches` returns true is pressed.
+    ///
+    /// Custom actions are checked before the built-in shortcuts, so they can rebind
+    /// keys. The key is not forwarded to clients. Registering a `name` again
+    /// replaces the previous action
+    pub fn register_key_action(
+        &mut self,
+        name: impl Into<String>,
+        matches: impl Fn(ModifiersState, Keysym) -> bool + 'static,
+        handler: impl Fn(&mut AnvilState<BackendData>) + 'static,
+    ) {
+        let name = name.into();
+        self.unregister_key_action(&name);
+        self.custom_key_actions.push(CustomKeyAction {
+            name,
+            matches: Box::new(matches),
+            handler: Rc::new(handler),
+        });
+    }
+
+    pub fn unregister_key_action(&mut self, name: &str) {
+        self.custom_key_actions.retain(|action| action.name != name);
+    }
+
+    pub(crate) fn custom_key_action(
+        &self,
+        modifiers: ModifiersState,
+        keysym: Keysym,
+    ) -> Option<KeyAction> {
+        self.custom_key_actions
+            .iter()
+            .find(|action| (action.matches)(modifiers, keysym))
+            .map(|action| KeyAction::Custom(action.name.clone()))
+    }
+
+    pub(crate) fn run_custom_key_action(&mut self, name: &str) {
+        let Some(action) = self
+            .custom_key_actions
+            .iter()
+            .find(|action| action.name == name)
+        else {
+            warn!(name, "Custom key action was unregistered");
+            return;
+        };
+        debug!(name, "Running custom key action");
+        let handler = action.handler.clone();
+        handler(self);
+    }
+}
@@ -2,23 +2,77 @@
 // If no backend is enabled, a large portion of the codebase is unused.
 // So silence this useless warning for the CI.
 #![cfg_attr(
-    not(any(feature = "winit", feature = "x11", feature = "udev")),
+    not(any(
+        feature = "winit",
+        feature = "x11",
+        feature = "udev",
+        feature = "headless"
+    )),
     allow(dead_code, unused_imports)
 )]
 
+pub mod animation;
+pub mod backlight;
+#[cfg(feature = "udev")]
+pub mod calibration;
+pub mod clipboard;
+pub mod color_filter;
 #[cfg(any(feature = "udev", feature = "xwayland"))]
 pub mod cursor;
+#[cfg(feature = "udev")]
+pub mod cursor_visibility;
+pub mod dbus;
+pub mod dock;
 pub mod drawing;
 pub mod focus;
+pub mod gestures;
+#[cfg(feature = "headless")]
+pub mod headless;
+#[cfg(feature = "udev")]
+pub mod hibernation;
 pub mod input_handler;
+pub mod input_settings;
+pub mod inspector;
+pub mod key_actions;
+pub mod log_shipping;
+pub mod logging;
+pub mod metrics;
+pub mod notifications;
+pub mod output_scale;
+pub mod picture_in_picture;
+pub mod policy;
+#[cfg(feature = "udev")]
+pub mod portal;
+pub mod progress;
+pub mod protocol_trace;
+pub mod reload;
+#[cfg(feature = "udev")]
+pub mod remote_desktop;
 pub mod render;
+pub mod screenshot;
+pub mod services;
+pub mod shaders;
 pub mod shell;
+#[cfg(feature = "udev")]
+pub mod sleep;
+pub mod split_screen;
+pub mod startup;
 pub mod state;
+pub mod status;
+pub mod systemd;
+pub mod text;
+pub mod text_entry;
+pub mod time_settings;
 #[cfg(feature = "udev")]
 pub mod udev;
+pub mod watchdog;
+pub mod window_cycling;
 #[cfg(feature = "winit")]
 pub mod winit;
 #[cfg(feature = "x11")]
 pub mod x11;
+#[cfg(feature = "xwayland")]
+pub mod xwayland_on_demand;
+pub mod zoom;
 
 pub use state::{AnvilState, ClientState};
@@ -0,0 +1,189 @@
+use std::{
+    fmt::Write as _,
+    io::Write,
+    net::{TcpStream, UdpSocket},
+    sync::{
+        mpsc::{sync_channel, SyncSender},
+        Mutex,
+    },
+    time::{Duration, Instant},
+};
+
+use tracing::{
+    field::{Field, Visit},
+    Event, Level, Subscriber,
+};
+use tracing_subscriber::{layer::Context, Layer};
+
+use crate::state::LogShippingConfiguration;
+
+/// Fields that may carry secrets are never shipped
+const REDACTED_FIELDS: &[&str] = &["token", "password", "secret", "key", "cookie"];
+/// Lines waiting for the sender thread, anything beyond is dropped
+const QUEUE_SIZE: usize = 64;
+
+/// Forwards warnings and errors to a syslog server or HTTP endpoint,
+/// for devices nobody reads the local logs of
+pub struct RemoteLogLayer {
+    sender: SyncSender<String>,
+    hostname: String,
+    home: Option<String>,
+    limiter: Mutex<RateLimiter>,
+}
+
+struct RateLimiter {
+    max_per_minute: u32,
+    window_start: Instant,
+    sent: u32,
+}
+
+impl RateLimiter {
+    fn allow(&mut self) -> bool {
+        if self.window_start.elapsed() >= Duration::from_secs(60) {
+            self.window_start = Instant::now();
+            self.sent = 0;
+        }
+        if self.sent >= self.max_per_minute {
+            return false;
+        }
+        self.sent += 1;
+        true
+    }
+}
+
+enum Target {
+    Syslog(String),
+    Http {
+        address: String,
+        host: String,
+        path: String,
+    },
+}
+
+impl RemoteLogLayer {
+    pub fn new(config: &LogShippingConfiguration) -> Option<Self> {
+        let target = if let Some(address) = config.syslog.clone() {
+            Target::Syslog(address)
+        } else {
+            let url = config.http.as_deref()?;
+            // Only plain http, TLS would need another dependency
+            let rest = url.strip_prefix("http://")?;
+            let (host, path) = match rest.find('/') {
+                Some(index) => (&rest[..index], &rest[index..]),
+                None => (rest, "/"),
+            };
+            let address = if host.contains(':') {
+                host.to_string()
+            } else {
+                format!("{host}:80")
+            };
+            Target::Http {
+                address,
+                host: host.to_string(),
+                path: path.to_string(),
+            }
+        };
+
+        let (sender, receiver) = sync_channel::<String>(QUEUE_SIZE);
+        std::thread::Builder::new()
+            .name("log-shipping".into())
+            .spawn(move || {
+                // Errors can't be logged here, they would come right back
+                let socket = UdpSocket::bind("0.0.0.0:0").ok();
+                for line in receiver {
+                    match &target {
+                        Target::Syslog(address) => {
+                            if let Some(socket) = socket.as_ref() {
+                                let _ = socket.send_to(line.as_bytes(), address);
+                            }
+                        }
+                        Target::Http {
+                            address,
+                            host,
+                            path,
+                        } => {
+                            let _ = post(address, host, path, &line);
+                        }
+                    }
+                }
+            })
+            .ok()?;
+
+        Some(Self {
+            sender,
+            hostname: std::fs::read_to_string("/etc/hostname")
+                .map(|name| name.trim().to_string())
+                .unwrap_or_else(|_| "consolation".into()),
+            home: std::env::var("HOME").ok().filter(|home| home.len() > 1),
+            limiter: Mutex::new(RateLimiter {
+                max_per_minute: config.max_per_minute,
+                window_start: Instant::now(),
+                sent: 0,
+            }),
+        })
+    }
+}
+
+fn post(address: &str, host: &str, path: &str, body: &str) -> std::io::Result<()> {
+    let mut stream = TcpStream::connect(address)?;
+    stream.set_write_timeout(Some(Duration::from_secs(5)))?;
+    write!(
+        stream,
+        "POST {path} HTTP/1.1\r\nHost: {host}\r\nContent-Type: text/plain\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{body}",
+        body.len()
+    )
+}
+
+/// The message of an event and its other fields, secrets left out
+#[derive(Default)]
+pub(crate) struct MessageVisitor {
+    pub message: String,
+    pub fields: String,
+}
+
+impl Visit for MessageVisitor {
+    fn record_debug(&mut self, field: &Field, value: &dyn std::fmt::Debug) {
+        if field.name() == "message" {
+            let _ = write!(self.message, "{value:?}");
+        } else if REDACTED_FIELDS
+            .iter()
+            .any(|name| field.name().contains(name))
+        {
+            let _ = write!(self.fields, " {}=<redacted>", field.name());
+        } else {
+            let _ = write!(self.fields, " {}={value:?}", field.name());
+        }
+    }
+}
+
+impl<S: Subscriber> Layer<S> for RemoteLogLayer {
+    fn on_event(&self, event: &Event<'_>, _ctx: Context<'_, S>) {
+        let metadata = event.metadata();
+        let severity = match *metadata.level() {
+            Level::ERROR => 3,
+            Level::WARN => 4,
+            _ => return,
+        };
+        if !self.limiter.lock().unwrap().allow() {
+            return;
+        }
+
+        let mut visitor = MessageVisitor::default();
+        event.record(&mut visitor);
+        let mut line = format!(
+            "<{}>1 - {} consolation - - - {}: {}{}",
+            8 + severity, // facility "user"
+            self.hostname,
+            metadata.target(),
+            visitor.message,
+            visitor.fields
+        );
+        // Paths in the home directory give away the user name
+        if let Some(home) = self.home.as_deref() {
+            line = line.replace(home, "~");
+        }
+
+        // Dropped if the endpoint can't keep up
+        let _ = self.sender.try_send(line);
+    }
+}
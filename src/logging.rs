@@ -0,0 +1,162 @@
+use std::{
+    collections::VecDeque,
+    os::unix::net::UnixDatagram,
+    sync::{Arc, Mutex, OnceLock},
+};
+
+use tracing::{info, warn, Event, Level, Subscriber};
+use tracing_subscriber::{layer::Context, Layer};
+use zbus::blocking::Connection;
+
+use crate::{
+    log_shipping::MessageVisitor,
+    state::{AnvilState, Backend},
+};
+
+const BUS_NAME: &str = "org.consolation.Logs";
+const OBJECT_PATH: &str = "/org/consolation/Logs";
+const JOURNAL_SOCKET: &str = "/run/systemd/journal/socket";
+
+type Lines = Arc<Mutex<VecDeque<String>>>;
+
+/// Lines kept by the [`RecentLogLayer`], for serving them over the bus
+static RECENT_LINES: OnceLock<Lines> = OnceLock::new();
+
+/// Keeps the latest lines in memory, so they can be read without a terminal attached
+pub struct RecentLogLayer {
+    lines: Lines,
+    capacity: usize,
+}
+
+impl RecentLogLayer {
+    pub fn new(capacity: usize) -> Option<Self> {
+        if capacity == 0 {
+            return None;
+        }
+        let lines = RECENT_LINES
+            .get_or_init(|| Arc::new(Mutex::new(VecDeque::with_capacity(capacity))))
+            .clone();
+        Some(Self { lines, capacity })
+    }
+}
+
+impl<S: Subscriber> Layer<S> for RecentLogLayer {
+    fn on_event(&self, event: &Event<'_>, _ctx: Context<'_, S>) {
+        let metadata = event.metadata();
+        let mut visitor = MessageVisitor::default();
+        event.record(&mut visitor);
+        let line = format!(
+            "{} {:>5} {}: {}{}",
+            chrono::Local::now().format("%Y-%m-%d %H:%M:%S%.3f"),
+            metadata.level(),
+            metadata.target(),
+            visitor.message,
+            visitor.fields
+        );
+        let mut lines = self.lines.lock().unwrap();
+        if lines.len() >= self.capacity {
+            lines.pop_front();
+        }
+        lines.push_back(line);
+    }
+}
+
+/// Writes events to the systemd journal with their priority, for sessions not
+/// started as a systemd service, whose output goes nowhere
+pub struct JournaldLayer {
+    socket: UnixDatagram,
+}
+
+impl JournaldLayer {
+    pub fn new() -> Option<Self> {
+        let socket = UnixDatagram::unbound().ok()?;
+        socket.connect(JOURNAL_SOCKET).ok()?;
+        Some(Self { socket })
+    }
+}
+
+/// A field of the journal export format, the length prefixed form when the
+/// value spans lines
+fn journal_field(entry: &mut Vec<u8>, name: &str, value: &str) {
+    entry.extend_from_slice(name.as_bytes());
+    if value.contains('\n') {
+        entry.push(b'\n');
+        entry.extend_from_slice(&(value.len() as u64).to_le_bytes());
+    } else {
+        entry.push(b'=');
+    }
+    entry.extend_from_slice(value.as_bytes());
+    entry.push(b'\n');
+}
+
+impl<S: Subscriber> Layer<S> for JournaldLayer {
+    fn on_event(&self, event: &Event<'_>, _ctx: Context<'_, S>) {
+        let metadata = event.metadata();
+        let priority = match *metadata.level() {
+            Level::ERROR => "3",
+            Level::WARN => "4",
+            Level::INFO => "6",
+            _ => "7",
+        };
+        let mut visitor = MessageVisitor::default();
+        event.record(&mut visitor);
+
+        let mut entry = Vec::new();
+        journal_field(&mut entry, "PRIORITY", priority);
+        journal_field(&mut entry, "SYSLOG_IDENTIFIER", "consolation");
+        journal_field(&mut entry, "TARGET", metadata.target());
+        journal_field(
+            &mut entry,
+            "MESSAGE",
+            &format!("{}{}", visitor.message, visitor.fields),
+        );
+        // Errors can't be logged here, they would come right back. Entries too
+        // large for a datagram are dropped
+        let _ = self.socket.send(&entry);
+    }
+}
+
+/// `org.consolation.Logs` object, reading the lines kept in memory
+struct LogServer {
+    lines: Lines,
+}
+
+#[zbus::interface(name = "org.consolation.Logs")]
+impl LogServer {
+    /// The latest lines, oldest first
+    fn recent(&self) -> Vec<String> {
+        self.lines.lock().unwrap().iter().cloned().collect()
+    }
+}
+
+#[zbus::proxy(
+    interface = "org.consolation.Logs",
+    default_service = "org.consolation.Logs",
+    default_path = "/org/consolation/Logs"
+)]
+trait ConsolationLogs {
+    fn recent(&self) -> zbus::Result<Vec<String>>;
+}
+
+/// The latest lines of the running compositor
+pub fn request_logs() -> zbus::Result<Vec<String>> {
+    let connection = Connection::session()?;
+    ConsolationLogsProxyBlocking::new(&connection)?.recent()
+}
+
+impl<BackendData: Backend> AnvilState<BackendData> {
+    /// Serve the lines kept in memory, so `consolation --logs` prints them
+    pub fn start_log_retrieval(&mut self) {
+        let Some(lines) = RECENT_LINES.get().cloned() else {
+            return;
+        };
+        match self
+            .dbus
+            .serve(BUS_NAME, OBJECT_PATH, LogServer { lines }, false)
+        {
+            Ok(true) => info!("Serving recent logs"),
+            Ok(false) => warn!("Logs bus name is already taken"),
+            Err(err) => warn!(?err, "Unable to serve recent logs"),
+        }
+    }
+}
@@ -1,17 +1,254 @@
+use std::path::PathBuf;
+
+use clap::{ArgAction, Parser, ValueEnum};
+use tracing::info;
+use tracing_subscriber::{filter::LevelFilter, prelude::*, EnvFilter};
+
 #[cfg(feature = "profile-with-tracy-mem")]
 #[global_allocator]
 static GLOBAL: profiling::tracy_client::ProfiledAllocator<std::alloc::System> =
     profiling::tracy_client::ProfiledAllocator::new(std::alloc::System, 10);
 
-fn main() {
-    if let Ok(env_filter) = tracing_subscriber::EnvFilter::try_from_default_env() {
-        tracing_subscriber::fmt()
-            .compact()
-            .with_env_filter(env_filter)
-            .init();
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+enum BackendChoice {
+    /// Nested in the X server of a desktop when started from one, udev otherwise
+    Auto,
+    /// DRM and libinput, from a TTY or a login manager
+    Udev,
+    /// Nested in a window of the X server of `DISPLAY`
+    X11,
+    /// Offscreen without DRM or input devices, for tests and benchmarks
+    Headless,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+enum PipAction {
+    /// Show the most recently used other window, or hide the one shown
+    Toggle,
+    Hide,
+    /// Move to the next corner clockwise
+    Next,
+    TopLeft,
+    TopRight,
+    BottomLeft,
+    BottomRight,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+enum SplitAction {
+    /// Split with the most recently used other window, or end the split
+    Toggle,
+    End,
+}
+
+/// A Wayland compositor showing one window at a time, for game consoles and handhelds
+#[derive(Debug, Parser)]
+#[command(version, about)]
+struct Args {
+    #[arg(long, value_enum, default_value_t = BackendChoice::Auto)]
+    backend: BackendChoice,
+    /// Configuration file to use instead of the one in the config directory
+    #[arg(long, value_name = "FILE")]
+    config: Option<PathBuf>,
+    /// Name of the wayland socket given to apps as `WAYLAND_DISPLAY`
+    #[arg(long, value_name = "NAME")]
+    socket: Option<String>,
+    /// Least severe messages logged, like `debug`. `RUST_LOG` takes precedence
+    #[arg(long, value_name = "LEVEL", conflicts_with_all = ["verbose", "quiet"])]
+    log_level: Option<LevelFilter>,
+    /// Log debug messages, and trace messages when given twice
+    #[arg(short, long, action = ArgAction::Count)]
+    verbose: u8,
+    /// Log warnings and errors only
+    #[arg(short, long, conflicts_with = "verbose")]
+    quiet: bool,
+    /// Make the running compositor read its configuration again
+    #[arg(long, group = "request")]
+    reload: bool,
+    /// Print the latest log lines of the running compositor
+    #[arg(long, group = "request")]
+    logs: bool,
+    /// Print the clients connected to the running compositor and what they use
+    #[arg(long, group = "request")]
+    clients: bool,
+    /// Write the protocol messages of clients of EXECUTABLE connecting from now on
+    /// to FILE, of the given interfaces only if any
+    #[arg(
+        long,
+        group = "request",
+        num_args = 2..,
+        value_names = ["EXECUTABLE", "FILE", "INTERFACE"]
+    )]
+    trace: Option<Vec<String>>,
+    /// Stop writing protocol messages
+    #[arg(long, group = "request")]
+    untrace: bool,
+    /// Change a libinput setting of pointer devices until the next reload, one of
+    /// accel-profile, accel-speed, natural-scroll or tap-to-click
+    #[arg(
+        long,
+        group = "request",
+        num_args = 2,
+        value_names = ["SETTING", "VALUE"]
+    )]
+    input: Option<Vec<String>>,
+    /// Show, hide or move the window drawn small above the shown one
+    #[arg(long, group = "request", value_name = "ACTION")]
+    pip: Option<PipAction>,
+    /// Show the most recently used window of APP_ID in picture-in-picture
+    #[arg(long, group = "request", value_name = "APP_ID")]
+    pip_show: Option<String>,
+    /// Share the screen between the shown window and another one, for couch co-op
+    #[arg(long, group = "request", value_name = "ACTION")]
+    split: Option<SplitAction>,
+    /// Split the screen with the most recently used window of APP_ID
+    #[arg(long, group = "request", value_name = "APP_ID")]
+    split_with: Option<String>,
+    /// Keep the command following it running as the primary application,
+    /// restarting it when it exits. Takes the remaining arguments
+    #[arg(long)]
+    app: bool,
+}
+
+impl Args {
+    fn log_level(&self) -> LevelFilter {
+        match (self.log_level, self.verbose, self.quiet) {
+            (Some(level), ..) => level,
+            (None, _, true) => LevelFilter::WARN,
+            (None, 0, false) => LevelFilter::INFO,
+            (None, 1, false) => LevelFilter::DEBUG,
+            (None, _, false) => LevelFilter::TRACE,
+        }
+    }
+
+    /// Run the request to the running compositor given, if any, and exit
+    fn run_request(&self) {
+        let result = if self.reload {
+            consolation::reload::request_reload()
+                .map_err(|err| format!("Unable to reload the configuration: {err}"))
+        } else if self.logs {
+            consolation::logging::request_logs()
+                .map(print_lines)
+                .map_err(|err| format!("Unable to read the logs: {err}"))
+        } else if self.clients {
+            consolation::inspector::request_clients()
+                .map(print_lines)
+                .map_err(|err| format!("Unable to inspect the clients: {err}"))
+        } else if let Some([executable, file, interfaces @ ..]) = self.trace.as_deref() {
+            // The compositor may run in another directory
+            let file = std::env::current_dir()
+                .map(|dir| dir.join(file))
+                .unwrap_or_else(|_| file.into());
+            consolation::protocol_trace::request_trace(
+                executable,
+                &file.to_string_lossy(),
+                interfaces,
+            )
+            .map_err(|err| format!("Unable to start tracing: {err}"))
+        } else if self.untrace {
+            consolation::protocol_trace::request_untrace()
+                .map_err(|err| format!("Unable to stop tracing: {err}"))
+        } else if let Some([setting, value]) = self.input.as_deref() {
+            consolation::input_settings::request_setting(setting, value)
+                .map_err(|err| format!("Unable to change {setting}: {err}"))
+        } else if let Some(action) = self.pip {
+            use consolation::{picture_in_picture as pip, state::Corner};
+            match action {
+                PipAction::Toggle => pip::request_toggle(),
+                PipAction::Hide => pip::request_hide(),
+                PipAction::Next => pip::request_move(None),
+                PipAction::TopLeft => pip::request_move(Some(Corner::TopLeft)),
+                PipAction::TopRight => pip::request_move(Some(Corner::TopRight)),
+                PipAction::BottomLeft => pip::request_move(Some(Corner::BottomLeft)),
+                PipAction::BottomRight => pip::request_move(Some(Corner::BottomRight)),
+            }
+            .map_err(|err| format!("Unable to change picture-in-picture: {err}"))
+        } else if let Some(app_id) = self.pip_show.as_deref() {
+            consolation::picture_in_picture::request_show(app_id)
+                .map_err(|err| format!("Unable to show {app_id} in picture-in-picture: {err}"))
+        } else if let Some(action) = self.split {
+            match action {
+                SplitAction::Toggle => consolation::split_screen::request_toggle(),
+                SplitAction::End => consolation::split_screen::request_end(),
+            }
+            .map_err(|err| format!("Unable to change the split screen: {err}"))
+        } else if let Some(app_id) = self.split_with.as_deref() {
+            consolation::split_screen::request_show(app_id)
+                .map_err(|err| format!("Unable to split the screen with {app_id}: {err}"))
+        } else {
+            return;
+        };
+        match result {
+            Ok(()) => std::process::exit(0),
+            Err(err) => {
+                eprintln!("{err}");
+                std::process::exit(1);
+            }
+        }
+    }
+}
+
+fn print_lines(lines: Vec<String>) {
+    for line in lines {
+        println!("{line}");
+    }
+}
+
+/// Nested when started from a graphical session, which sets these, unless logind
+/// says it's a TTY. Only backends that were built are picked, a desktop without
+/// an X server, or a build without the x11 backend, gets the headless one
+fn auto_backend() -> BackendChoice {
+    let tty = std::env::var("XDG_SESSION_TYPE").is_ok_and(|session| session == "tty");
+    let x11 = std::env::var_os("DISPLAY").is_some();
+    let desktop = x11 || std::env::var_os("WAYLAND_DISPLAY").is_some();
+    if cfg!(feature = "x11") && x11 && !tty {
+        BackendChoice::X11
+    } else if cfg!(feature = "udev") && (tty || !desktop) {
+        BackendChoice::Udev
+    } else if cfg!(feature = "headless") {
+        BackendChoice::Headless
     } else {
-        tracing_subscriber::fmt().compact().init();
+        BackendChoice::Udev
     }
+}
+
+fn main() {
+    // `--app <command> [args...]` takes the remaining arguments, which may look
+    // like options of their own
+    let mut args = std::env::args().collect::<Vec<_>>();
+    let app = args
+        .iter()
+        .position(|arg| arg == "--app")
+        .map(|index| args.split_off(index + 1));
+    let args = Args::parse_from(args);
+    args.run_request();
+    if let Some(path) = args.config.clone() {
+        consolation::state::set_config_path(path);
+    }
+
+    let env_filter = EnvFilter::try_from_default_env()
+        .unwrap_or_else(|_| EnvFilter::default().add_directive(args.log_level().into()));
+    let config = consolation::state::load_config().ok();
+    let remote_log = config
+        .as_ref()
+        .and_then(|config| config.log_shipping.as_ref())
+        .and_then(consolation::log_shipping::RemoteLogLayer::new);
+    let logging = config.map(|config| config.logging).unwrap_or_default();
+    let recent_log = consolation::logging::RecentLogLayer::new(logging.recent_lines);
+    let journald = logging
+        .journald
+        .then(consolation::logging::JournaldLayer::new)
+        .flatten();
+    tracing_subscriber::registry()
+        .with(
+            tracing_subscriber::fmt::layer()
+                .compact()
+                .with_filter(env_filter),
+        )
+        .with(remote_log.with_filter(LevelFilter::WARN))
+        .with(recent_log.with_filter(LevelFilter::INFO))
+        .with(journald.with_filter(LevelFilter::INFO))
+        .init();
 
     #[cfg(feature = "profile-with-tracy")]
     profiling::tracy_client::Client::start();
@@ -24,5 +261,29 @@ fn main() {
     #[cfg(feature = "profile-with-puffin")]
     profiling::puffin::set_scopes_on(true);
 
-    consolation::udev::run_udev();
+    let backend = match args.backend {
+        BackendChoice::Auto => {
+            let backend = auto_backend();
+            info!(?backend, "Picked backend");
+            backend
+        }
+        backend => backend,
+    };
+    let app = app.filter(|app| !app.is_empty());
+    let socket = args.socket;
+    match backend {
+        #[cfg(feature = "udev")]
+        BackendChoice::Udev => consolation::udev::run_udev(app, socket),
+        #[cfg(feature = "x11")]
+        BackendChoice::X11 => consolation::x11::run_x11(app, socket),
+        #[cfg(feature = "headless")]
+        BackendChoice::Headless => consolation::headless::run_headless(app, socket),
+        other => {
+            eprintln!(
+                "Consolation was built without the {} backend",
+                other.to_possible_value().unwrap().get_name()
+            );
+            std::process::exit(1);
+        }
+    }
 }
@@ -0,0 +1,239 @@
+use std::{
+    fmt::Write as _,
+    io::{Read, Write},
+    net::{Ipv4Addr, TcpListener},
+    sync::atomic::{AtomicUsize, Ordering},
+    time::Duration,
+};
+
+use smithay::reexports::calloop::{generic::Generic, Interest, Mode, PostAction};
+use tracing::{debug, info, warn};
+
+use crate::state::{AnvilState, Backend};
+
+/// Connected Wayland clients, kept up to date by `ClientState`
+pub static CONNECTED_CLIENTS: AtomicUsize = AtomicUsize::new(0);
+
+/// Bucket bounds in milliseconds
+const FRAME_TIME_BUCKETS: &[f64] = &[1.0, 2.0, 4.0, 8.0, 12.0, 16.0, 24.0, 33.0, 50.0, 100.0];
+const INPUT_LATENCY_BUCKETS: &[f64] = &[0.5, 1.0, 2.0, 4.0, 8.0, 16.0, 32.0, 64.0];
+
+#[derive(Debug)]
+pub struct Histogram {
+    buckets: &'static [f64],
+    counts: Vec<u64>,
+    sum: f64,
+    count: u64,
+}
+
+impl Histogram {
+    fn new(buckets: &'static [f64]) -> Self {
+        Self {
+            buckets,
+            counts: vec![0; buckets.len()],
+            sum: 0.0,
+            count: 0,
+        }
+    }
+
+    pub fn observe(&mut self, duration: Duration) {
+        let value = duration.as_secs_f64() * 1000.0;
+        for (bound, count) in self.buckets.iter().zip(self.counts.iter_mut()) {
+            if value <= *bound {
+                *count += 1;
+            }
+        }
+        self.sum += value;
+        self.count += 1;
+    }
+
+    fn write(&self, out: &mut String, name: &str, help: &str) {
+        let _ = writeln!(out, "# HELP {name} {help}");
+        let _ = writeln!(out, "# TYPE {name} histogram");
+        for (bound, count) in self.buckets.iter().zip(self.counts.iter()) {
+            let _ = writeln!(out, "{name}_bucket{{le=\"{bound}\"}} {count}");
+        }
+        let _ = writeln!(out, "{name}_bucket{{le=\"+Inf\"}} {}", self.count);
+        let _ = writeln!(out, "{name}_sum {}", self.sum);
+        let _ = writeln!(out, "{name}_count {}", self.count);
+    }
+}
+
+/// Statistics for fleet monitoring, served in the Prometheus text format
+#[derive(Debug)]
+pub struct Metrics {
+    pub frame_time: Histogram,
+    pub input_latency: Histogram,
+    pub dropped_frames: u64,
+}
+
+impl Default for Metrics {
+    fn default() -> Self {
+        Self {
+            frame_time: Histogram::new(FRAME_TIME_BUCKETS),
+            input_latency: Histogram::new(INPUT_LATENCY_BUCKETS),
+            dropped_frames: 0,
+        }
+    }
+}
+
+/// Video memory in use per DRM card, only known for some drivers (amdgpu)
+fn gpu_memory() -> Vec<(String, u64)> {
+    let Ok(cards) = std::fs::read_dir("/sys/class/drm") else {
+        return Vec::new();
+    };
+    cards
+        .flatten()
+        .filter_map(|card| {
+            let name = card.file_name().into_string().ok()?;
+            if !name.starts_with("card") || name.contains('-') {
+                return None;
+            }
+            let used =
+                std::fs::read_to_string(card.path().join("device/mem_info_vram_used")).ok()?;
+            Some((name, used.trim().parse().ok()?))
+        })
+        .collect()
+}
+
+/// `value` escaped to be quoted as a label value in the text format
+fn escape_label(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
+
+impl<BackendData: Backend> AnvilState<BackendData> {
+    fn render_metrics(&self) -> String {
+        let mut out = String::new();
+        let metrics = &self.metrics;
+        metrics.frame_time.write(
+            &mut out,
+            "consolation_frame_time_milliseconds",
+            "Time spent rendering a frame",
+        );
+        metrics.input_latency.write(
+            &mut out,
+            "consolation_input_latency_milliseconds",
+            "Time from the kernel timestamp of an input event until it is handled",
+        );
+        let _ = writeln!(
+            out,
+            "# HELP consolation_dropped_frames_total Frames that failed to render"
+        );
+        let _ = writeln!(out, "# TYPE consolation_dropped_frames_total counter");
+        let _ = writeln!(
+            out,
+            "consolation_dropped_frames_total {}",
+            metrics.dropped_frames
+        );
+        let _ = writeln!(out, "# HELP consolation_clients Connected Wayland clients");
+        let _ = writeln!(out, "# TYPE consolation_clients gauge");
+        let _ = writeln!(
+            out,
+            "consolation_clients {}",
+            CONNECTED_CLIENTS.load(Ordering::Relaxed)
+        );
+        let _ = writeln!(out, "# HELP consolation_windows Mapped windows");
+        let _ = writeln!(out, "# TYPE consolation_windows gauge");
+        let _ = writeln!(out, "consolation_windows {}", self.elements.len());
+        let _ = writeln!(
+            out,
+            "# HELP consolation_tracked_entries Entries kept per window and process map"
+        );
+        let _ = writeln!(out, "# TYPE consolation_tracked_entries gauge");
+        for (map, len) in [
+            ("windows", self.elements.len()),
+            ("foreign_toplevels", self.toplevel_manager.tracked()),
+            ("launched_commands", self.launched.count()),
+        ] {
+            let _ = writeln!(out, "consolation_tracked_entries{{map=\"{map}\"}} {len}");
+        }
+        let _ = writeln!(
+            out,
+            "# HELP consolation_gpu_memory_bytes Video memory in use"
+        );
+        let _ = writeln!(out, "# TYPE consolation_gpu_memory_bytes gauge");
+        for (card, used) in gpu_memory() {
+            let _ = writeln!(
+                out,
+                "consolation_gpu_memory_bytes{{card=\"{card}\"}} {used}"
+            );
+        }
+        let _ = writeln!(
+            out,
+            "# HELP consolation_service_up Whether a configured service is running"
+        );
+        let _ = writeln!(out, "# TYPE consolation_service_up gauge");
+        for (name, running, _) in self.services.status() {
+            let _ = writeln!(
+                out,
+                "consolation_service_up{{name=\"{}\"}} {}",
+                escape_label(name),
+                running as u8
+            );
+        }
+        let _ = writeln!(
+            out,
+            "# HELP consolation_service_restarts_total Times a configured service was restarted"
+        );
+        let _ = writeln!(out, "# TYPE consolation_service_restarts_total counter");
+        for (name, _, restarts) in self.services.status() {
+            let _ = writeln!(
+                out,
+                "consolation_service_restarts_total{{name=\"{}\"}} {restarts}",
+                escape_label(name)
+            );
+        }
+        out
+    }
+
+    /// Serve the metrics on localhost if enabled in the configuration
+    pub fn start_metrics_server(&mut self) {
+        let Some(port) = self.config.metrics_port else {
+            return;
+        };
+        let listener = match TcpListener::bind((Ipv4Addr::LOCALHOST, port)) {
+            Ok(listener) => listener,
+            Err(err) => {
+                warn!(?err, port, "Unable to start metrics server");
+                return;
+            }
+        };
+        if let Err(err) = listener.set_nonblocking(true) {
+            warn!(?err, "Unable to start metrics server");
+            return;
+        }
+
+        let res = self.handle.insert_source(
+            Generic::new(listener, Interest::READ, Mode::Level),
+            |_, listener, data| {
+                while let Ok((stream, _)) = listener.accept() {
+                    let body = data.render_metrics();
+                    // Don't let slow scrapers block the event loop
+                    std::thread::spawn(move || {
+                        let mut stream = stream;
+                        let _ = stream.set_nonblocking(false);
+                        let _ = stream.set_read_timeout(Some(Duration::from_secs(1)));
+                        let mut request = [0u8; 1024];
+                        let _ = stream.read(&mut request);
+                        let response = format!(
+                            "HTTP/1.1 200 OK\r\nContent-Type: text/plain; version=0.0.4\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+                            body.len(),
+                            body
+                        );
+                        if let Err(err) = stream.write_all(response.as_bytes()) {
+                            debug!(?err, "Failed to send metrics");
+                        }
+                    });
+                }
+                Ok(PostAction::Continue)
+            },
+        );
+        match res {
+            Ok(_) => info!(port, "Serving metrics on localhost"),
+            Err(err) => warn!(?err, "Unable to insert metrics source"),
+        }
+    }
+}
@@ -0,0 +1,383 @@
+use std::{
+    collections::HashMap,
+    fmt,
+    time::{Duration, Instant},
+};
+
+use rusttype::Font;
+use smithay::{
+    backend::{
+        allocator::Fourcc,
+        renderer::{
+            element::{
+                memory::{MemoryRenderBuffer, MemoryRenderBufferRenderElement},
+                Kind,
+            },
+            ImportMem, Renderer,
+        },
+    },
+    input::keyboard::Keysym,
+    output::Output,
+    reexports::calloop::timer::{TimeoutAction, Timer},
+    utils::{Physical, Point, Size, Transform},
+};
+use tracing::{debug, info, warn};
+use xkbcommon::xkb;
+use zbus::zvariant::OwnedValue;
+
+use crate::{
+    dbus::Requests,
+    input_handler::KeyAction,
+    state::{AnvilState, Backend},
+    text::{draw_text, load_font},
+};
+
+const BUS_NAME: &str = "org.freedesktop.Notifications";
+const OBJECT_PATH: &str = "/org/freedesktop/Notifications";
+/// At most this many toasts are stacked, the newest on top
+const MAX_SHOWN: usize = 3;
+const BODY_LINES: usize = 3;
+const TOAST_WIDTH: i32 = 360;
+const TOAST_MARGIN: i32 = 16;
+const TOAST_PADDING: i32 = 12;
+const LINE_HEIGHT: i32 = 22;
+const FONT_SIZE: f32 = 16.0;
+/// Premultiplied BGRA
+const BACKDROP_COLOR: [u8; 4] = [20, 20, 20, 230];
+
+/// Reasons of the `NotificationClosed` signal
+const CLOSED_EXPIRED: u32 = 1;
+const CLOSED_DISMISSED: u32 = 2;
+const CLOSED_BY_CALL: u32 = 3;
+
+#[derive(Debug)]
+enum Request {
+    Notify {
+        id: u32,
+        app_name: String,
+        summary: String,
+        body: String,
+        timeout: Option<Duration>,
+    },
+    Close(u32),
+}
+
+/// `org.freedesktop.Notifications` object, forwarding calls to the event loop
+struct NotificationServer {
+    requests: Requests<Request, ()>,
+    next_id: u32,
+    default_timeout: Duration,
+}
+
+#[zbus::interface(name = "org.freedesktop.Notifications")]
+impl NotificationServer {
+    #[allow(clippy::too_many_arguments)]
+    fn notify(
+        &mut self,
+        app_name: String,
+        replaces_id: u32,
+        _app_icon: String,
+        summary: String,
+        body: String,
+        _actions: Vec<String>,
+        _hints: HashMap<String, OwnedValue>,
+        expire_timeout: i32,
+    ) -> u32 {
+        let id = if replaces_id != 0 {
+            replaces_id
+        } else {
+            self.next_id += 1;
+            self.next_id
+        };
+        let timeout = match expire_timeout {
+            0 => None,
+            ms if ms < 0 => Some(self.default_timeout),
+            ms => Some(Duration::from_millis(ms as u64)),
+        };
+        self.requests.send(Request::Notify {
+            id,
+            app_name,
+            summary,
+            body,
+            timeout,
+        });
+        id
+    }
+
+    fn close_notification(&self, id: u32) {
+        self.requests.send(Request::Close(id));
+    }
+
+    fn get_capabilities(&self) -> Vec<String> {
+        vec!["body".to_string()]
+    }
+
+    fn get_server_information(&self) -> (String, String, String, String) {
+        (
+            "consolation".to_string(),
+            "consolation".to_string(),
+            env!("CARGO_PKG_VERSION").to_string(),
+            "1.2".to_string(),
+        )
+    }
+}
+
+#[derive(Debug)]
+struct Toast {
+    id: u32,
+    summary: String,
+    body: String,
+    until: Option<Instant>,
+    /// Drawn toast, its size and the output scale it was drawn for
+    buffer: Option<(f64, MemoryRenderBuffer, Size<i32, Physical>)>,
+}
+
+/// Built-in notification daemon, only running while no other one owns the bus name
+#[derive(Default)]
+pub struct Notifications {
+    /// Whether the bus name is ours
+    serving: bool,
+    font: Option<Font<'static>>,
+    /// Ids of toasts of the compositor count down, away from the ones of the bus
+    next_own_id: u32,
+    toasts: Vec<Toast>,
+}
+
+impl fmt::Debug for Notifications {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Notifications")
+            .field("serving", &self.serving)
+            .field("font", &self.font.is_some())
+            .field("next_own_id", &self.next_own_id)
+            .field("toasts", &self.toasts)
+            .finish()
+    }
+}
+
+/// Toast backdrop with the summary and first body lines, in physical pixels
+fn draw_toast(
+    font: Option<&Font<'static>>,
+    toast: &Toast,
+    scale: f64,
+) -> (MemoryRenderBuffer, Size<i32, Physical>) {
+    let scaled = |value: i32| (value as f64 * scale).round() as i32;
+    let lines: Vec<&str> = std::iter::once(toast.summary.as_str())
+        .chain(toast.body.lines().take(BODY_LINES))
+        .filter(|line| !line.trim().is_empty())
+        .collect();
+    let width = scaled(TOAST_WIDTH);
+    let padding = scaled(TOAST_PADDING);
+    let line_height = scaled(LINE_HEIGHT);
+    let height = padding * 2 + line_height * lines.len().max(1) as i32;
+
+    let mut pixels = BACKDROP_COLOR.repeat((width * height) as usize);
+    if let Some(font) = font {
+        for (row, line) in lines.iter().enumerate() {
+            let origin = (padding as f32, (padding + line_height * row as i32) as f32);
+            let size = FONT_SIZE * scale as f32;
+            draw_text(
+                &mut pixels,
+                width,
+                font,
+                line,
+                size,
+                origin,
+                width - padding,
+            );
+        }
+    }
+
+    let buffer = MemoryRenderBuffer::from_slice(
+        &pixels,
+        Fourcc::Argb8888,
+        (width, height),
+        1,
+        Transform::Normal,
+        None,
+    );
+    (buffer, Size::from((width, height)))
+}
+
+impl Notifications {
+    /// Toasts stacked in the top right corner of `output`
+    pub fn render_elements<R>(
+        &mut self,
+        renderer: &mut R,
+        output: &Output,
+    ) -> Vec<MemoryRenderBufferRenderElement<R>>
+    where
+        R: Renderer + ImportMem,
+        <R as Renderer>::TextureId: Send + Clone + 'static,
+    {
+        if self.toasts.is_empty() {
+            return Vec::new();
+        }
+        let Some(mode) = output.current_mode() else {
+            return Vec::new();
+        };
+        let size: Size<i32, Physical> = output.current_transform().transform_size(mode.size);
+        let scale = output.current_scale().fractional_scale();
+        let margin = (TOAST_MARGIN as f64 * scale).round() as i32;
+
+        let mut elements = Vec::new();
+        let mut y = margin;
+        for toast in self.toasts.iter_mut().rev().take(MAX_SHOWN) {
+            if toast.buffer.as_ref().map(|(drawn, ..)| *drawn) != Some(scale) {
+                let (buffer, size) = draw_toast(self.font.as_ref(), toast, scale);
+                toast.buffer = Some((scale, buffer, size));
+            }
+            let (_, buffer, toast_size) = toast.buffer.as_ref().unwrap();
+            let location = Point::<i32, Physical>::from((size.w - margin - toast_size.w, y));
+            match MemoryRenderBufferRenderElement::from_buffer(
+                renderer,
+                location.to_f64(),
+                buffer,
+                None,
+                None,
+                None,
+                Kind::Unspecified,
+            ) {
+                Ok(element) => elements.push(element),
+                Err(err) => warn!(?err, "Unable to upload notification"),
+            }
+            y += toast_size.h + margin;
+        }
+        elements
+    }
+}
+
+impl<BackendData: Backend> AnvilState<BackendData> {
+    /// Serve `org.freedesktop.Notifications` if enabled and no other daemon runs
+    pub fn start_notifications(&mut self) {
+        let Some(config) = self.config.notifications.clone() else {
+            return;
+        };
+        let Some(requests) =
+            self.insert_requests(|data, request| data.handle_notification_request(request))
+        else {
+            return;
+        };
+
+        let server = NotificationServer {
+            requests,
+            next_id: 0,
+            default_timeout: Duration::from_secs_f64(config.timeout_secs.max(0.0)),
+        };
+        // Own the bus name unless another daemon does, letting one started later
+        // replace us
+        match self.dbus.serve(BUS_NAME, OBJECT_PATH, server, true) {
+            Ok(true) => {
+                info!("Serving notifications");
+                self.notifications.font = load_font(config.font.as_deref());
+                self.notifications.serving = true;
+            }
+            Ok(false) => info!("Another notification daemon runs"),
+            Err(err) => warn!(?err, "Unable to serve notifications"),
+        }
+    }
+
+    /// Show a toast of the compositor itself, even while another daemon serves the
+    /// notifications of apps
+    pub fn show_toast(&mut self, summary: impl Into<String>, body: impl Into<String>) {
+        let config = self.config.notifications.clone().unwrap_or_default();
+        if self.notifications.font.is_none() {
+            self.notifications.font = load_font(config.font.as_deref());
+        }
+        self.notifications.next_own_id = self.notifications.next_own_id.wrapping_sub(1);
+        self.handle_notification_request(Request::Notify {
+            id: self.notifications.next_own_id,
+            app_name: "consolation".to_string(),
+            summary: summary.into(),
+            body: body.into(),
+            timeout: Some(Duration::from_secs_f64(config.timeout_secs.max(0.0))),
+        });
+    }
+
+    fn handle_notification_request(&mut self, request: Request) {
+        match request {
+            Request::Notify {
+                id,
+                app_name,
+                summary,
+                body,
+                timeout,
+            } => {
+                debug!(id, %app_name, %summary, "Notification");
+                let until = timeout.map(|timeout| Instant::now() + timeout);
+                let toast = Toast {
+                    id,
+                    summary,
+                    body,
+                    until,
+                    buffer: None,
+                };
+                let toasts = &mut self.notifications.toasts;
+                match toasts.iter_mut().find(|toast| toast.id == id) {
+                    Some(replaced) => *replaced = toast,
+                    None => toasts.push(toast),
+                }
+                if let Some(timeout) = timeout {
+                    self.expire_notification(id, until, timeout);
+                }
+            }
+            Request::Close(id) => self.close_notification(id, CLOSED_BY_CALL),
+        }
+    }
+
+    fn expire_notification(&mut self, id: u32, until: Option<Instant>, timeout: Duration) {
+        let res = self
+            .handle
+            .insert_source(Timer::from_duration(timeout), move |_, _, data| {
+                // Replacing the notification started a new timer
+                if data
+                    .notifications
+                    .toasts
+                    .iter()
+                    .any(|toast| toast.id == id && toast.until == until)
+                {
+                    data.close_notification(id, CLOSED_EXPIRED);
+                }
+                TimeoutAction::Drop
+            });
+        if let Err(err) = res {
+            warn!(?err, "Unable to insert notification timer");
+        }
+    }
+
+    fn close_notification(&mut self, id: u32, reason: u32) {
+        let toasts = &mut self.notifications.toasts;
+        let Some(index) = toasts.iter().position(|toast| toast.id == id) else {
+            return;
+        };
+        toasts.remove(index);
+        if !self.notifications.serving {
+            return;
+        }
+        let res = self.dbus.connection().and_then(|connection| {
+            connection.emit_signal(
+                None::<&str>,
+                OBJECT_PATH,
+                BUS_NAME,
+                "NotificationClosed",
+                &(id, reason),
+            )
+        });
+        if let Err(err) = res {
+            warn!(?err, "Unable to signal closed notification");
+        }
+    }
+
+    /// Check if `keysym` dismisses the newest notification
+    pub fn notification_key_action(&self, keysym: Keysym) -> Option<KeyAction> {
+        let config = self.config.notifications.as_ref()?;
+        self.notifications.toasts.last()?;
+        let dismiss_key = xkb::keysym_from_name(&config.dismiss_key, xkb::KEYSYM_CASE_INSENSITIVE);
+        (dismiss_key.raw() == keysym.raw()).then_some(KeyAction::DismissNotification)
+    }
+
+    pub fn dismiss_notification(&mut self) {
+        if let Some(id) = self.notifications.toasts.last().map(|toast| toast.id) {
+            self.close_notification(id, CLOSED_DISMISSED);
+        }
+    }
+}
@@ -0,0 +1,71 @@
+use smithay::utils::{Physical, Size};
+use tracing::info;
+
+use crate::state::OutputScaleConfiguration;
+
+/// Distance in centimeters a 96 DPI monitor is viewed from at scale 1
+const REFERENCE_DISTANCE: f64 = 60.0;
+const REFERENCE_DPI: f64 = 96.0;
+/// Suggested scales are multiples of this
+const SCALE_STEP: f64 = 0.25;
+const MAX_SCALE: f64 = 3.0;
+/// Some TVs report their aspect ratio in the EDID instead of a size
+const ASPECT_ONLY_SIZES: &[(u32, u32)] = &[(160, 90), (160, 100), (1600, 900), (1600, 1000)];
+
+/// Viewing distance in centimeters for a screen with a `diagonal` in inches
+fn guess_distance(diagonal: f64) -> f64 {
+    if diagonal < 10.0 {
+        // Handhelds
+        30.0
+    } else if diagonal < 40.0 {
+        // Monitors and laptops
+        60.0
+    } else {
+        // TVs across the room
+        300.0
+    }
+}
+
+/// Scale at which content looks as large as on a 96 DPI monitor viewed from 60cm
+fn suggested_scale(
+    size_mm: (u32, u32),
+    mode: Size<i32, Physical>,
+    distance: Option<f64>,
+) -> Option<f64> {
+    if size_mm.0 == 0 || size_mm.1 == 0 || ASPECT_ONLY_SIZES.contains(&size_mm) || mode.w <= 0 {
+        return None;
+    }
+    let width = size_mm.0 as f64 / 25.4;
+    let diagonal = (size_mm.0 as f64).hypot(size_mm.1 as f64) / 25.4;
+    let distance = distance.unwrap_or_else(|| guess_distance(diagonal));
+    // Pixels per degree of view, relative to the reference
+    let density = mode.w as f64 / width * distance;
+    let reference = REFERENCE_DPI * REFERENCE_DISTANCE;
+    let scale = (density / reference / SCALE_STEP).round() * SCALE_STEP;
+    Some(scale.clamp(1.0, MAX_SCALE))
+}
+
+/// Scale for the output `name` when it connects, if there is one besides 1
+pub fn output_scale(
+    config: &OutputScaleConfiguration,
+    name: &str,
+    size_mm: Option<(u32, u32)>,
+    mode: Size<i32, Physical>,
+) -> Option<f64> {
+    if let Some(scale) = config.scales.get(name) {
+        return Some(*scale);
+    }
+    if !config.suggest {
+        return None;
+    }
+    let distance = config.viewing_distances.get(name).copied();
+    let scale = suggested_scale(size_mm?, mode, distance)?;
+    info!(
+        output = name,
+        ?size_mm,
+        ?distance,
+        scale,
+        "Suggested output scale"
+    );
+    Some(scale)
+}
@@ -0,0 +1,247 @@
+use smithay::{desktop::Window, input::keyboard::Keysym};
+use tracing::{debug, info, warn};
+use xkbcommon::xkb;
+use zbus::{blocking::Connection, fdo};
+
+use crate::{
+    dbus::Requests,
+    input_handler::KeyAction,
+    render::InsetWindow,
+    shell::{is_hidden, is_popup, peek::PEEK_SIZE, window_app_id},
+    state::{AnvilState, Backend, Corner},
+};
+
+const BUS_NAME: &str = "org.consolation.PictureInPicture";
+const OBJECT_PATH: &str = "/org/consolation/PictureInPicture";
+
+/// A background window drawn small above the shown one
+#[derive(Debug, Clone)]
+pub struct PictureInPicture {
+    pub window: Window,
+    pub corner: Corner,
+}
+
+#[derive(Debug)]
+enum Request {
+    Toggle,
+    Show(String),
+    Hide,
+    Move(Option<Corner>),
+}
+
+/// `org.consolation.PictureInPicture` object, forwarding calls to the event loop
+struct PictureInPictureServer {
+    requests: Requests<Request, Result<(), String>>,
+}
+
+#[zbus::interface(name = "org.consolation.PictureInPicture")]
+impl PictureInPictureServer {
+    /// Show the most recently used other window, or hide the one shown
+    async fn toggle(&self) -> fdo::Result<()> {
+        self.requests.try_call(Request::Toggle).await
+    }
+
+    /// Show the most recently used window of `app_id`
+    async fn show(&self, app_id: String) -> fdo::Result<()> {
+        self.requests.try_call(Request::Show(app_id)).await
+    }
+
+    async fn hide(&self) -> fdo::Result<()> {
+        self.requests.try_call(Request::Hide).await
+    }
+
+    /// Move to "TopLeft", "TopRight", "BottomLeft" or "BottomRight", or to the
+    /// next corner clockwise when empty
+    async fn move_to(&self, corner: String) -> fdo::Result<()> {
+        let corner = match corner.as_str() {
+            "" => None,
+            "TopLeft" => Some(Corner::TopLeft),
+            "TopRight" => Some(Corner::TopRight),
+            "BottomLeft" => Some(Corner::BottomLeft),
+            "BottomRight" => Some(Corner::BottomRight),
+            other => {
+                return Err(fdo::Error::InvalidArgs(format!("No corner {other}")));
+            }
+        };
+        self.requests.try_call(Request::Move(corner)).await
+    }
+}
+
+#[zbus::proxy(
+    interface = "org.consolation.PictureInPicture",
+    default_service = "org.consolation.PictureInPicture",
+    default_path = "/org/consolation/PictureInPicture"
+)]
+trait ConsolationPictureInPicture {
+    fn toggle(&self) -> zbus::Result<()>;
+    fn show(&self, app_id: &str) -> zbus::Result<()>;
+    fn hide(&self) -> zbus::Result<()>;
+    fn move_to(&self, corner: &str) -> zbus::Result<()>;
+}
+
+fn proxy() -> zbus::Result<ConsolationPictureInPictureProxyBlocking<'static>> {
+    let connection = Connection::session()?;
+    ConsolationPictureInPictureProxyBlocking::new(&connection)
+}
+
+/// Ask the running compositor to show or hide picture-in-picture
+pub fn request_toggle() -> zbus::Result<()> {
+    proxy()?.toggle()
+}
+
+/// Ask the running compositor to show a window of `app_id` in picture-in-picture
+pub fn request_show(app_id: &str) -> zbus::Result<()> {
+    proxy()?.show(app_id)
+}
+
+pub fn request_hide() -> zbus::Result<()> {
+    proxy()?.hide()
+}
+
+/// Ask the running compositor to move picture-in-picture to `corner`, or to the
+/// next one when unset
+pub fn request_move(corner: Option<Corner>) -> zbus::Result<()> {
+    let corner = match corner {
+        None => "",
+        Some(Corner::TopLeft) => "TopLeft",
+        Some(Corner::TopRight) => "TopRight",
+        Some(Corner::BottomLeft) => "BottomLeft",
+        Some(Corner::BottomRight) => "BottomRight",
+    };
+    proxy()?.move_to(corner)
+}
+
+impl<BackendData: Backend> AnvilState<BackendData> {
+    /// Serve `org.consolation.PictureInPicture`, so `consolation --pip` and
+    /// launchers can show windows in it
+    pub fn start_picture_in_picture(&mut self) {
+        let Some(requests) = self.insert_requests(|data, request| match request {
+            Request::Toggle => {
+                data.toggle_picture_in_picture();
+                Ok(())
+            }
+            Request::Show(app_id) => data.show_app_in_picture(&app_id),
+            Request::Hide => {
+                data.picture_in_picture = None;
+                Ok(())
+            }
+            Request::Move(corner) => data.move_picture_in_picture(corner),
+        }) else {
+            return;
+        };
+        let server = PictureInPictureServer { requests };
+        match self.dbus.serve(BUS_NAME, OBJECT_PATH, server, false) {
+            Ok(true) => info!("Serving picture-in-picture"),
+            Ok(false) => warn!("Picture-in-picture bus name is already taken"),
+            Err(err) => warn!(?err, "Unable to serve picture-in-picture"),
+        }
+    }
+
+    /// Windows that can go into picture-in-picture, most recently used first
+    fn picture_candidates(&self) -> impl Iterator<Item = &Window> {
+        let current = self.current_window().0;
+        let split = self.split_screen.as_ref().map(|split| &split.window);
+        self.elements.iter().filter(move |window| {
+            Some(*window) != current.as_ref()
+                && Some(*window) != split
+                && !is_popup(window)
+                && !is_hidden(window)
+        })
+    }
+
+    fn show_in_picture(&mut self, window: Window) {
+        let corner = self
+            .picture_in_picture
+            .as_ref()
+            .map_or(self.config.picture_in_picture.corner, |pip| pip.corner);
+        debug!(?corner, "Showing window in picture-in-picture");
+        if self
+            .window_peek
+            .as_ref()
+            .is_some_and(|peek| peek.window == window)
+        {
+            self.window_peek = None;
+        }
+        self.picture_in_picture = Some(PictureInPicture { window, corner });
+    }
+
+    pub fn toggle_picture_in_picture(&mut self) {
+        if self.picture_in_picture.take().is_some() {
+            debug!("Hid picture-in-picture");
+            return;
+        }
+        if let Some(window) = self.picture_candidates().next().cloned() {
+            self.show_in_picture(window);
+        }
+    }
+
+    fn show_app_in_picture(&mut self, app_id: &str) -> Result<(), String> {
+        let window = self
+            .picture_candidates()
+            .find(|window| window_app_id(window).as_deref() == Some(app_id))
+            .cloned()
+            .ok_or_else(|| format!("No window of {app_id} in the background"))?;
+        self.show_in_picture(window);
+        Ok(())
+    }
+
+    /// Move picture-in-picture to `corner`, or to the next one clockwise
+    fn move_picture_in_picture(&mut self, corner: Option<Corner>) -> Result<(), String> {
+        let pip = self
+            .picture_in_picture
+            .as_mut()
+            .ok_or_else(|| "Picture-in-picture is hidden".to_string())?;
+        pip.corner = corner.unwrap_or_else(|| pip.corner.clockwise());
+        Ok(())
+    }
+
+    /// Check if `keysym` shows, hides or moves picture-in-picture
+    pub fn picture_in_picture_key_action(&self, keysym: Keysym) -> Option<KeyAction> {
+        let config = &self.config.picture_in_picture;
+        let matches = |key: &Option<String>| {
+            key.as_ref().is_some_and(|key| {
+                xkb::keysym_from_name(key, xkb::KEYSYM_CASE_INSENSITIVE).raw() == keysym.raw()
+            })
+        };
+        if matches(&config.toggle_key) {
+            Some(KeyAction::TogglePictureInPicture)
+        } else if matches(&config.move_key) && self.picture_in_picture.is_some() {
+            Some(KeyAction::MovePictureInPicture)
+        } else {
+            None
+        }
+    }
+
+    pub fn move_picture_in_picture_clockwise(&mut self) {
+        let _ = self.move_picture_in_picture(None);
+    }
+
+    /// End picture-in-picture once its window is unmapped, minimized or became the
+    /// active one
+    pub fn refresh_picture_in_picture(&mut self) {
+        if let Some(pip) = self.picture_in_picture.as_ref() {
+            if !self.elements.contains(&pip.window)
+                || is_hidden(&pip.window)
+                || self.current_window().0.as_ref() == Some(&pip.window)
+            {
+                self.picture_in_picture = None;
+            }
+        }
+    }
+
+    /// Windows drawn small above the shown one, the attention peek above
+    /// picture-in-picture
+    pub fn inset_windows(&self) -> Vec<InsetWindow> {
+        let peek = self.window_peek.as_ref().map(|peek| InsetWindow {
+            window: peek.window.clone(),
+            corner: self.peek_corner(),
+            size: PEEK_SIZE,
+        });
+        let pip = self.picture_in_picture.as_ref().map(|pip| InsetWindow {
+            window: pip.window.clone(),
+            corner: pip.corner,
+            size: self.config.picture_in_picture.size.clamp(0.05, 1.0),
+        });
+        peek.into_iter().chain(pip).collect()
+    }
+}
@@ -0,0 +1,96 @@
+use std::{
+    path::{Path, PathBuf},
+    sync::{Arc, RwLock},
+};
+
+use smithay::reexports::wayland_server::{Client, DisplayHandle};
+use tracing::debug;
+
+use crate::state::{client_credentials, unsandboxed, ClientState, ProtocolPolicyConfiguration};
+
+/// Globals letting clients control the compositor or see other clients
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PrivilegedProtocol {
+    OutputManagement,
+    ForeignToplevel,
+    VirtualPointer,
+}
+
+impl PrivilegedProtocol {
+    fn allowed(self, config: &ProtocolPolicyConfiguration) -> &[String] {
+        match self {
+            PrivilegedProtocol::OutputManagement => &config.output_management,
+            PrivilegedProtocol::ForeignToplevel => &config.foreign_toplevel,
+            PrivilegedProtocol::VirtualPointer => &config.virtual_pointer,
+        }
+    }
+}
+
+/// Executable of the process behind `client`
+pub fn client_executable(client: &Client, display: &DisplayHandle) -> Option<PathBuf> {
+    process_executable(client_credentials(client, display)?.pid)
+}
+
+/// Executable of the process `pid`, a canonical path
+pub fn process_executable(pid: i32) -> Option<PathBuf> {
+    std::fs::read_link(format!("/proc/{pid}/exe")).ok()
+}
+
+/// Whether `executable` is one of the absolute paths in `allowed`. Names alone
+/// match nothing, so a binary can't pass as another by its name
+pub fn executable_allowed(executable: &Path, allowed: &[String]) -> bool {
+    allowed.iter().map(Path::new).any(|path| {
+        path.is_absolute() && std::fs::canonicalize(path).is_ok_and(|path| path == executable)
+    })
+}
+
+/// Shared with the filters of privileged globals, which can't reach the state
+#[derive(Debug, Clone)]
+pub struct ProtocolPolicy {
+    display: DisplayHandle,
+    config: Arc<RwLock<Option<ProtocolPolicyConfiguration>>>,
+}
+
+impl ProtocolPolicy {
+    pub fn new(display: DisplayHandle) -> Self {
+        Self {
+            display,
+            config: Arc::default(),
+        }
+    }
+
+    /// Applies to clients binding the globals from now on
+    pub fn update(&self, config: Option<ProtocolPolicyConfiguration>) {
+        *self.config.write().unwrap() = config;
+    }
+
+    /// Global filter letting unsandboxed clients use `protocol` if the policy allows
+    pub fn filter(
+        &self,
+        protocol: PrivilegedProtocol,
+    ) -> impl for<'c> Fn(&'c Client) -> bool + Send + Sync + 'static {
+        let policy = self.clone();
+        move |client| unsandboxed(client) && policy.allows(client, protocol)
+    }
+
+    fn allows(&self, client: &Client, protocol: PrivilegedProtocol) -> bool {
+        let config = self.config.read().unwrap();
+        let Some(config) = config.as_ref() else {
+            return true;
+        };
+        if client
+            .get_data::<ClientState>()
+            .is_some_and(|client_state| client_state.privileged)
+        {
+            return true;
+        }
+        let executable = client_executable(client, &self.display);
+        let allowed = executable
+            .as_deref()
+            .is_some_and(|path| executable_allowed(path, protocol.allowed(config)));
+        if !allowed {
+            debug!(?protocol, ?executable, "Hiding privileged global");
+        }
+        allowed
+    }
+}
@@ -0,0 +1,277 @@
+use std::{
+    collections::HashMap,
+    os::unix::ffi::OsStrExt,
+    path::{Path, PathBuf},
+};
+
+use tracing::{debug, info, warn};
+use zbus::{
+    zvariant::{OwnedObjectPath, OwnedValue, Value},
+    SignalContext,
+};
+
+use crate::{
+    dbus::Requests,
+    screenshot::{save_picture, screenshot_directory, Picture},
+    state::{AnvilState, ColorScheme, PortalConfiguration},
+    udev::UdevData,
+};
+
+const BUS_NAME: &str = "org.freedesktop.impl.portal.desktop.consolation";
+const OBJECT_PATH: &str = "/org/freedesktop/portal/desktop";
+const APPEARANCE: &str = "org.freedesktop.appearance";
+
+/// Responses of portal requests
+const RESPONSE_SUCCESS: u32 = 0;
+const RESPONSE_OTHER: u32 = 2;
+
+#[derive(Debug, zbus::DBusError)]
+#[zbus(prefix = "org.freedesktop.portal.Error")]
+enum PortalError {
+    #[zbus(error)]
+    ZBus(zbus::Error),
+    NotFound(String),
+}
+
+/// `org.freedesktop.impl.portal.Screenshot`, saving the shown window as a png
+struct ScreenshotPortal {
+    pictures: Requests<(), Option<Picture>>,
+    colors: Requests<(), Option<[f64; 3]>>,
+    directory: PathBuf,
+}
+
+#[zbus::interface(name = "org.freedesktop.impl.portal.Screenshot")]
+impl ScreenshotPortal {
+    /// There is no dialog to confirm, a screenshot is taken right away
+    async fn screenshot(
+        &self,
+        _handle: OwnedObjectPath,
+        app_id: String,
+        _parent_window: String,
+        _options: HashMap<String, OwnedValue>,
+    ) -> (u32, HashMap<String, OwnedValue>) {
+        let Some(picture) = self.pictures.call(()).await.flatten() else {
+            return (RESPONSE_OTHER, HashMap::new());
+        };
+        let path = match save_picture(&self.directory, picture) {
+            Ok(path) => path,
+            Err(err) => {
+                warn!(%app_id, ?err, "Unable to save screenshot");
+                return (RESPONSE_OTHER, HashMap::new());
+            }
+        };
+        info!(%app_id, path = %path.display(), "Saved screenshot");
+        match OwnedValue::try_from(Value::from(file_uri(&path))) {
+            Ok(uri) => (RESPONSE_SUCCESS, HashMap::from([("uri".to_string(), uri)])),
+            Err(err) => {
+                warn!(?err, "Unable to encode screenshot uri");
+                (RESPONSE_OTHER, HashMap::new())
+            }
+        }
+    }
+
+    /// The color under the pointer, picked right away like screenshots are taken
+    async fn pick_color(
+        &self,
+        _handle: OwnedObjectPath,
+        app_id: String,
+        _parent_window: String,
+        _options: HashMap<String, OwnedValue>,
+    ) -> (u32, HashMap<String, OwnedValue>) {
+        let Some([r, g, b]) = self.colors.call(()).await.flatten() else {
+            return (RESPONSE_OTHER, HashMap::new());
+        };
+        debug!(%app_id, r, g, b, "Picked color");
+        match OwnedValue::try_from(Value::from((r, g, b))) {
+            Ok(color) => (
+                RESPONSE_SUCCESS,
+                HashMap::from([("color".to_string(), color)]),
+            ),
+            Err(err) => {
+                warn!(?err, "Unable to encode picked color");
+                (RESPONSE_OTHER, HashMap::new())
+            }
+        }
+    }
+
+    #[zbus(property, name = "version")]
+    fn version(&self) -> u32 {
+        2
+    }
+}
+
+/// `org.freedesktop.impl.portal.Settings`, giving the appearance of the configuration
+struct SettingsPortal {
+    config: PortalConfiguration,
+}
+
+#[zbus::interface(name = "org.freedesktop.impl.portal.Settings")]
+impl SettingsPortal {
+    fn read_all(&self, namespaces: Vec<String>) -> HashMap<String, HashMap<String, OwnedValue>> {
+        let requested = namespaces.is_empty()
+            || namespaces
+                .iter()
+                .any(|namespace| match namespace.strip_suffix('*') {
+                    Some(prefix) => APPEARANCE.starts_with(prefix),
+                    None => namespace == APPEARANCE,
+                });
+        if !requested {
+            return HashMap::new();
+        }
+        let values = appearance(&self.config)
+            .into_iter()
+            .map(|(key, value)| (key.to_string(), value))
+            .collect();
+        HashMap::from([(APPEARANCE.to_string(), values)])
+    }
+
+    fn read(&self, namespace: &str, key: &str) -> Result<OwnedValue, PortalError> {
+        if namespace != APPEARANCE {
+            return Err(PortalError::NotFound(format!(
+                "Unknown namespace {namespace}"
+            )));
+        }
+        appearance(&self.config)
+            .into_iter()
+            .find(|(name, _)| *name == key)
+            .map(|(_, value)| value)
+            .ok_or_else(|| PortalError::NotFound(format!("Unknown key {key}")))
+    }
+
+    #[zbus(signal)]
+    async fn setting_changed(
+        ctxt: &SignalContext<'_>,
+        namespace: &str,
+        key: &str,
+        value: Value<'_>,
+    ) -> zbus::Result<()>;
+
+    #[zbus(property, name = "version")]
+    fn version(&self) -> u32 {
+        2
+    }
+}
+
+/// Built-in xdg-desktop-portal backend, only running while no other one owns the
+/// bus name
+#[derive(Debug, Default)]
+pub struct Portal {
+    /// Whether the bus name is ours
+    serving: bool,
+}
+
+fn appearance(config: &PortalConfiguration) -> Vec<(&'static str, OwnedValue)> {
+    let color_scheme = match config.color_scheme {
+        ColorScheme::NoPreference => 0u32,
+        ColorScheme::Dark => 1,
+        ColorScheme::Light => 2,
+    };
+    let mut values = vec![
+        ("color-scheme", OwnedValue::from(color_scheme)),
+        ("contrast", OwnedValue::from(config.high_contrast as u32)),
+    ];
+    // Out of range components mean no preference
+    let [r, g, b] = config.accent_color.unwrap_or([-1.0; 3]);
+    match OwnedValue::try_from(Value::from((r, g, b))) {
+        Ok(accent_color) => values.push(("accent-color", accent_color)),
+        Err(err) => warn!(?err, "Unable to encode accent color"),
+    }
+    values
+}
+
+/// `file://` uri of an absolute path, percent-encoding all but unreserved bytes
+fn file_uri(path: &Path) -> String {
+    let mut uri = String::from("file://");
+    for &byte in path.as_os_str().as_bytes() {
+        if byte.is_ascii_alphanumeric() || b"/-._~".contains(&byte) {
+            uri.push(byte as char);
+        } else {
+            uri.push_str(&format!("%{byte:02X}"));
+        }
+    }
+    uri
+}
+
+impl AnvilState<UdevData> {
+    /// Serve the portal backend if configured
+    pub fn start_portal(&mut self) {
+        let Some(config) = self.config.portal.clone() else {
+            return;
+        };
+        let (Some(pictures), Some(colors)) = (
+            self.insert_requests(|data, ()| data.portal_picture()),
+            self.insert_requests(|data, ()| data.pick_color()),
+        ) else {
+            return;
+        };
+
+        let screenshot = ScreenshotPortal {
+            pictures,
+            colors,
+            directory: screenshot_directory(config.screenshot_dir.as_deref()),
+        };
+        let res = self
+            .dbus
+            .serve(BUS_NAME, OBJECT_PATH, screenshot, false)
+            .and_then(|serving| {
+                if serving {
+                    let settings = SettingsPortal { config };
+                    self.dbus
+                        .connection()?
+                        .object_server()
+                        .at(OBJECT_PATH, settings)?;
+                }
+                Ok(serving)
+            });
+        match res {
+            Ok(true) => {
+                info!("Serving portal backend");
+                self.portal.serving = true;
+            }
+            Ok(false) => info!("Another portal backend runs"),
+            Err(err) => warn!(?err, "Unable to serve portal backend"),
+        }
+    }
+
+    /// Tell apps about appearance changes of the configuration
+    pub fn reload_portal(&mut self) {
+        if !self.portal.serving {
+            return;
+        }
+        let config = self.config.portal.clone().unwrap_or_default();
+        let res = self.dbus.connection().and_then(|connection| {
+            let settings = connection
+                .object_server()
+                .interface::<_, SettingsPortal>(OBJECT_PATH)?;
+            if settings.get().config == config {
+                return Ok(());
+            }
+            debug!("Portal appearance changed");
+            settings.get_mut().config = config.clone();
+            for (key, value) in appearance(&config) {
+                zbus::block_on(SettingsPortal::setting_changed(
+                    settings.signal_context(),
+                    APPEARANCE,
+                    key,
+                    value.into(),
+                ))?;
+            }
+            Ok(())
+        });
+        if let Err(err) = res {
+            warn!(?err, "Unable to update portal settings");
+        }
+    }
+
+    /// The shown window at the size of the first output
+    fn portal_picture(&mut self) -> Option<Picture> {
+        let output = self.outputs.first()?;
+        let mode = output.current_mode()?;
+        let size = output.current_transform().transform_size(mode.size);
+        let (Some(window), _) = self.current_window() else {
+            return None;
+        };
+        let pixels = self.backend_data.sample_window(&window, size)?;
+        Some(Picture { size, pixels })
+    }
+}
@@ -0,0 +1,296 @@
+use std::fmt;
+
+use rusttype::Font;
+use smithay::{
+    backend::{
+        allocator::Fourcc,
+        renderer::{
+            element::{
+                memory::{MemoryRenderBuffer, MemoryRenderBufferRenderElement},
+                Kind,
+            },
+            ImportMem, Renderer,
+        },
+    },
+    output::Output,
+    utils::{Physical, Point, Size, Transform},
+};
+use tracing::{debug, info, warn};
+
+use crate::{
+    dbus::Requests,
+    state::{AnvilState, Backend},
+    text::{draw_text, fill_rect, load_font},
+};
+
+const BUS_NAME: &str = "org.consolation.Progress";
+const OBJECT_PATH: &str = "/org/consolation/Progress";
+/// At most this many bars are stacked, the newest at the bottom
+const MAX_SHOWN: usize = 3;
+const BOX_WIDTH: i32 = 420;
+const BOX_MARGIN: i32 = 32;
+const BOX_PADDING: i32 = 12;
+const LINE_HEIGHT: i32 = 22;
+const BAR_HEIGHT: i32 = 8;
+const FONT_SIZE: f32 = 16.0;
+/// Premultiplied BGRA
+const BACKDROP_COLOR: [u8; 4] = [20, 20, 20, 230];
+const TRACK_COLOR: [u8; 4] = [70, 70, 70, 255];
+const BAR_COLOR: [u8; 4] = [230, 170, 60, 255];
+/// Shown for the whole track while the progress is unknown
+const BUSY_COLOR: [u8; 4] = [140, 105, 40, 255];
+
+#[derive(Debug)]
+enum Request {
+    Start {
+        id: u32,
+        label: String,
+    },
+    Update {
+        id: u32,
+        label: String,
+        fraction: f64,
+    },
+    Finish(u32),
+}
+
+/// `org.consolation.Progress` object, forwarding calls of scripts to the event loop
+struct ProgressServer {
+    requests: Requests<Request, ()>,
+    next_id: u32,
+}
+
+#[zbus::interface(name = "org.consolation.Progress")]
+impl ProgressServer {
+    /// Show a bar labeled `label`, returning its id
+    fn start(&mut self, label: String) -> u32 {
+        // Ids from the bus count down, leaving the low ones to the compositor
+        self.next_id = self.next_id.wrapping_sub(1);
+        self.requests.send(Request::Start {
+            id: self.next_id,
+            label,
+        });
+        self.next_id
+    }
+
+    /// Set the `fraction` done, negative when unknown. An empty `label` keeps the last one
+    fn update(&self, id: u32, label: String, fraction: f64) {
+        self.requests.send(Request::Update {
+            id,
+            label,
+            fraction,
+        });
+    }
+
+    fn finish(&self, id: u32) {
+        self.requests.send(Request::Finish(id));
+    }
+}
+
+#[derive(Debug)]
+struct Bar {
+    id: u32,
+    label: String,
+    /// Between 0 and 1, `None` while unknown
+    fraction: Option<f64>,
+    /// Drawn bar, its size and the output scale it was drawn for
+    buffer: Option<(f64, MemoryRenderBuffer, Size<i32, Physical>)>,
+}
+
+/// Progress of long-running operations, shown as bars at the bottom of every output
+#[derive(Default)]
+pub struct ProgressReports {
+    font: Option<Font<'static>>,
+    next_id: u32,
+    bars: Vec<Bar>,
+}
+
+impl fmt::Debug for ProgressReports {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ProgressReports")
+            .field("font", &self.font.is_some())
+            .field("next_id", &self.next_id)
+            .field("bars", &self.bars)
+            .finish()
+    }
+}
+
+/// Backdrop with the label above the bar, in physical pixels
+fn draw_bar(
+    font: Option<&Font<'static>>,
+    bar: &Bar,
+    scale: f64,
+) -> (MemoryRenderBuffer, Size<i32, Physical>) {
+    let scaled = |value: i32| (value as f64 * scale).round() as i32;
+    let width = scaled(BOX_WIDTH);
+    let padding = scaled(BOX_PADDING);
+    let line_height = scaled(LINE_HEIGHT);
+    let bar_height = scaled(BAR_HEIGHT).max(1);
+    let height = padding * 3 + line_height + bar_height;
+
+    let mut pixels = BACKDROP_COLOR.repeat((width * height) as usize);
+    if let Some(font) = font {
+        let origin = (padding as f32, padding as f32);
+        let size = FONT_SIZE * scale as f32;
+        draw_text(
+            &mut pixels,
+            width,
+            font,
+            &bar.label,
+            size,
+            origin,
+            width - padding,
+        );
+    }
+    let track = (
+        padding,
+        padding * 2 + line_height,
+        width - padding * 2,
+        bar_height,
+    );
+    match bar.fraction {
+        Some(fraction) => {
+            fill_rect(&mut pixels, width, track, TRACK_COLOR);
+            let done = (track.2 as f64 * fraction).round() as i32;
+            fill_rect(
+                &mut pixels,
+                width,
+                (track.0, track.1, done, track.3),
+                BAR_COLOR,
+            );
+        }
+        None => fill_rect(&mut pixels, width, track, BUSY_COLOR),
+    }
+
+    let buffer = MemoryRenderBuffer::from_slice(
+        &pixels,
+        Fourcc::Argb8888,
+        (width, height),
+        1,
+        Transform::Normal,
+        None,
+    );
+    (buffer, Size::from((width, height)))
+}
+
+impl ProgressReports {
+    /// Bars stacked at the bottom center of `output`
+    pub fn render_elements<R>(
+        &mut self,
+        renderer: &mut R,
+        output: &Output,
+    ) -> Vec<MemoryRenderBufferRenderElement<R>>
+    where
+        R: Renderer + ImportMem,
+        <R as Renderer>::TextureId: Send + Clone + 'static,
+    {
+        if self.bars.is_empty() {
+            return Vec::new();
+        }
+        let Some(mode) = output.current_mode() else {
+            return Vec::new();
+        };
+        let size: Size<i32, Physical> = output.current_transform().transform_size(mode.size);
+        let scale = output.current_scale().fractional_scale();
+        let margin = (BOX_MARGIN as f64 * scale).round() as i32;
+
+        let mut elements = Vec::new();
+        let mut y = size.h - margin;
+        for bar in self.bars.iter_mut().rev().take(MAX_SHOWN) {
+            if bar.buffer.as_ref().map(|(drawn, ..)| *drawn) != Some(scale) {
+                let (buffer, size) = draw_bar(self.font.as_ref(), bar, scale);
+                bar.buffer = Some((scale, buffer, size));
+            }
+            let (_, buffer, bar_size) = bar.buffer.as_ref().unwrap();
+            y -= bar_size.h;
+            let location = Point::<i32, Physical>::from(((size.w - bar_size.w) / 2, y));
+            match MemoryRenderBufferRenderElement::from_buffer(
+                renderer,
+                location.to_f64(),
+                buffer,
+                None,
+                None,
+                None,
+                Kind::Unspecified,
+            ) {
+                Ok(element) => elements.push(element),
+                Err(err) => warn!(?err, "Unable to upload progress"),
+            }
+            y -= margin / 2;
+        }
+        elements
+    }
+}
+
+impl<BackendData: Backend> AnvilState<BackendData> {
+    /// Load the font and serve `org.consolation.Progress` if enabled
+    pub fn start_progress_reports(&mut self) {
+        let config = self.config.progress.clone();
+        self.progress.font = load_font(config.font.as_deref());
+        if !config.serve_dbus {
+            return;
+        }
+
+        let Some(requests) = self.insert_requests(|data, request| match request {
+            Request::Start { id, label } => data.insert_progress(id, label),
+            Request::Update {
+                id,
+                label,
+                fraction,
+            } => {
+                let label = (!label.is_empty()).then_some(label);
+                let fraction = (fraction >= 0.0).then_some(fraction);
+                data.update_progress(id, label, fraction);
+            }
+            Request::Finish(id) => data.finish_progress(id),
+        }) else {
+            return;
+        };
+
+        let server = ProgressServer {
+            requests,
+            next_id: 0,
+        };
+        match self.dbus.serve(BUS_NAME, OBJECT_PATH, server, false) {
+            Ok(true) => info!("Serving progress reports"),
+            Ok(false) => warn!("Progress bus name is already taken"),
+            Err(err) => warn!(?err, "Unable to serve progress reports"),
+        }
+    }
+
+    /// Show a bar labeled `label` with unknown progress until
+    /// [`finish_progress`](Self::finish_progress), returning its id
+    pub fn start_progress(&mut self, label: impl Into<String>) -> u32 {
+        self.progress.next_id += 1;
+        let id = self.progress.next_id;
+        self.insert_progress(id, label.into());
+        id
+    }
+
+    fn insert_progress(&mut self, id: u32, label: String) {
+        debug!(id, %label, "Progress started");
+        self.progress.bars.push(Bar {
+            id,
+            label,
+            fraction: None,
+            buffer: None,
+        });
+    }
+
+    /// Set the `fraction` done of a bar, `None` when unknown, and optionally relabel it
+    pub fn update_progress(&mut self, id: u32, label: Option<String>, fraction: Option<f64>) {
+        let Some(bar) = self.progress.bars.iter_mut().find(|bar| bar.id == id) else {
+            return;
+        };
+        if let Some(label) = label {
+            bar.label = label;
+        }
+        bar.fraction = fraction.map(|fraction| fraction.clamp(0.0, 1.0));
+        bar.buffer = None;
+    }
+
+    pub fn finish_progress(&mut self, id: u32) {
+        debug!(id, "Progress finished");
+        self.progress.bars.retain(|bar| bar.id != id);
+    }
+}
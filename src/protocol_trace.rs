@@ -0,0 +1,420 @@
+use std::{
+    fs::{File, OpenOptions},
+    io::{self, IoSlice, IoSliceMut, Write},
+    net::Shutdown,
+    os::{
+        fd::{AsFd, BorrowedFd, OwnedFd},
+        unix::net::UnixStream,
+    },
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, Mutex,
+    },
+    thread,
+    time::Instant,
+};
+
+use smithay::reexports::{
+    rustix::{
+        self,
+        io::Errno,
+        net::{
+            recvmsg, sendmsg, sockopt::get_socket_peercred, RecvAncillaryBuffer,
+            RecvAncillaryMessage, RecvFlags, SendAncillaryBuffer, SendAncillaryMessage, SendFlags,
+        },
+    },
+    wayland_server::backend::{
+        protocol::{ArgumentType, Interface},
+        ClientId, Credentials, Handle,
+    },
+};
+use tracing::{info, warn};
+use zbus::{blocking::Connection, fdo};
+
+use crate::{
+    dbus::Requests,
+    policy::process_executable,
+    state::{AnvilState, Backend, ClientState},
+};
+
+const BUS_NAME: &str = "org.consolation.Trace";
+const OBJECT_PATH: &str = "/org/consolation/Trace";
+/// Most file descriptors sent along one message by libwayland
+const MAX_FDS: usize = 28;
+
+/// Clients whose messages are written to a file, set at runtime over the bus
+#[derive(Debug, Default)]
+pub struct ProtocolTrace {
+    target: Option<TraceTarget>,
+}
+
+#[derive(Debug)]
+struct TraceTarget {
+    executable: String,
+    output: Arc<Mutex<File>>,
+    /// Interfaces of the messages written, all of them when empty
+    interfaces: Arc<Vec<String>>,
+    /// Cleared when tracing stops, the relays of traced clients keep going
+    active: Arc<AtomicBool>,
+}
+
+impl ProtocolTrace {
+    fn start(
+        &mut self,
+        executable: String,
+        path: &str,
+        interfaces: Vec<String>,
+    ) -> Result<(), String> {
+        let file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(path)
+            .map_err(|err| format!("Unable to open {path}: {err}"))?;
+        self.stop();
+        info!(executable, path, ?interfaces, "Tracing protocol messages");
+        self.target = Some(TraceTarget {
+            executable,
+            output: Arc::new(Mutex::new(file)),
+            interfaces: Arc::new(interfaces),
+            active: Arc::new(AtomicBool::new(true)),
+        });
+        Ok(())
+    }
+
+    fn stop(&mut self) {
+        if let Some(target) = self.target.take() {
+            target.active.store(false, Ordering::Relaxed);
+            info!(
+                executable = target.executable,
+                "Stopped tracing protocol messages"
+            );
+        }
+    }
+}
+
+/// Writes the messages passing through the relay of one client, like
+/// `WAYLAND_DEBUG` does on the client side
+struct Tracer {
+    client: ClientId,
+    handle: Handle,
+    output: Arc<Mutex<File>>,
+    interfaces: Arc<Vec<String>>,
+    active: Arc<AtomicBool>,
+    start: Instant,
+}
+
+impl Tracer {
+    /// The interface of the object `id` of the client, unknown until the
+    /// server handled the request creating it
+    fn interface_of(&self, id: u32) -> Option<&'static Interface> {
+        self.handle
+            .all_objects_for(&self.client)
+            .ok()?
+            .find(|object| object.protocol_id() == id)
+            .map(|object| object.interface())
+    }
+
+    /// Log the complete messages at the start of `pending` and drop them
+    fn log_messages(&self, pending: &mut Vec<u8>, events: bool) {
+        let word =
+            |bytes: &[u8], at: usize| u32::from_ne_bytes(bytes[at..at + 4].try_into().unwrap());
+        let mut offset = 0;
+        while pending.len() - offset >= 8 {
+            let object = word(pending, offset);
+            let size = (word(pending, offset + 4) >> 16) as usize;
+            let opcode = (word(pending, offset + 4) & 0xffff) as usize;
+            if size < 8 {
+                // Not a message, the connection is about to be closed
+                pending.clear();
+                return;
+            }
+            if pending.len() - offset < size {
+                break;
+            }
+            if self.active.load(Ordering::Relaxed) {
+                self.log(object, opcode, &pending[offset + 8..offset + size], events);
+            }
+            offset += size;
+        }
+        pending.drain(..offset);
+    }
+
+    fn log(&self, object: u32, opcode: usize, args: &[u8], events: bool) {
+        let interface = self.interface_of(object);
+        if !self.interfaces.is_empty()
+            && !interface
+                .is_some_and(|interface| self.interfaces.iter().any(|name| name == interface.name))
+        {
+            return;
+        }
+        let message = interface.and_then(|interface| {
+            let messages = if events {
+                interface.events
+            } else {
+                interface.requests
+            };
+            messages.get(opcode)
+        });
+        let line = match (interface, message) {
+            (Some(interface), Some(message)) => format!(
+                "{}@{object}.{}({})",
+                interface.name,
+                message.name,
+                decode_args(message.signature, args)
+            ),
+            _ => format!("?@{object}.{opcode}({} bytes)", args.len()),
+        };
+        let direction = if events { " -> " } else { "" };
+        let time = self.start.elapsed().as_secs_f64() * 1000.0;
+        let _ = writeln!(
+            self.output.lock().unwrap(),
+            "[{time:10.3}] {:?} {direction}{line}",
+            self.client
+        );
+    }
+}
+
+fn decode_args(signature: &[ArgumentType], mut args: &[u8]) -> String {
+    let mut decoded = Vec::new();
+    for arg in signature {
+        // File descriptors go along the message rather than in it
+        if matches!(arg, ArgumentType::Fd) {
+            decoded.push("fd".to_string());
+            continue;
+        }
+        if args.len() < 4 {
+            decoded.push("...".to_string());
+            break;
+        }
+        let word = u32::from_ne_bytes(args[..4].try_into().unwrap());
+        args = &args[4..];
+        decoded.push(match arg {
+            ArgumentType::Int => (word as i32).to_string(),
+            ArgumentType::Uint => word.to_string(),
+            ArgumentType::Fixed => (word as i32 as f64 / 256.0).to_string(),
+            ArgumentType::Object(_) if word == 0 => "nil".to_string(),
+            ArgumentType::Object(_) => format!("@{word}"),
+            ArgumentType::NewId => format!("new id @{word}"),
+            ArgumentType::Str(_) | ArgumentType::Array => {
+                let len = word as usize;
+                let bytes = &args[..len.min(args.len())];
+                args = &args[((len + 3) & !3).min(args.len())..];
+                match arg {
+                    ArgumentType::Str(_) if len == 0 => "nil".to_string(),
+                    ArgumentType::Str(_) => format!(
+                        "\"{}\"",
+                        String::from_utf8_lossy(bytes.strip_suffix(&[0]).unwrap_or(bytes))
+                    ),
+                    _ => format!("array[{len}]"),
+                }
+            }
+            _ => "?".to_string(),
+        });
+    }
+    decoded.join(", ")
+}
+
+fn send_all(to: &UnixStream, mut bytes: &[u8], fds: &[OwnedFd]) -> rustix::io::Result<()> {
+    let fds = fds.iter().map(AsFd::as_fd).collect::<Vec<BorrowedFd<'_>>>();
+    let mut space = [0; rustix::cmsg_space!(ScmRights(MAX_FDS))];
+    let mut ancillary = SendAncillaryBuffer::new(&mut space);
+    if !fds.is_empty() {
+        ancillary.push(SendAncillaryMessage::ScmRights(&fds));
+    }
+    while !bytes.is_empty() {
+        match sendmsg(
+            to,
+            &[IoSlice::new(bytes)],
+            &mut ancillary,
+            SendFlags::NOSIGNAL,
+        ) {
+            Ok(sent) => {
+                bytes = &bytes[sent..];
+                // The descriptors went with the first part
+                ancillary.clear();
+            }
+            Err(Errno::INTR) => {}
+            Err(err) => return Err(err),
+        }
+    }
+    Ok(())
+}
+
+/// Pass bytes and file descriptors from `from` to `to` until either closes,
+/// showing the bytes to `observe` on the way
+pub(crate) fn relay(from: UnixStream, to: UnixStream, mut observe: impl FnMut(&[u8])) {
+    let mut buffer = [0; 4096];
+    loop {
+        let mut space = [0; rustix::cmsg_space!(ScmRights(MAX_FDS))];
+        let mut ancillary = RecvAncillaryBuffer::new(&mut space);
+        let received = match recvmsg(
+            &from,
+            &mut [IoSliceMut::new(&mut buffer)],
+            &mut ancillary,
+            RecvFlags::CMSG_CLOEXEC,
+        ) {
+            Ok(msg) if msg.bytes > 0 => msg.bytes,
+            Err(Errno::INTR) => continue,
+            _ => break,
+        };
+        let fds = ancillary
+            .drain()
+            .flat_map(|message| match message {
+                RecvAncillaryMessage::ScmRights(fds) => fds.collect::<Vec<_>>(),
+                _ => Vec::new(),
+            })
+            .collect::<Vec<_>>();
+        if send_all(&to, &buffer[..received], &fds).is_err() {
+            break;
+        }
+        observe(&buffer[..received]);
+    }
+    let _ = from.shutdown(Shutdown::Both);
+    let _ = to.shutdown(Shutdown::Both);
+}
+
+enum Request {
+    Start {
+        executable: String,
+        path: String,
+        interfaces: Vec<String>,
+    },
+    Stop,
+}
+
+/// `org.consolation.Trace` object, forwarding the toggles to the event loop
+struct TraceServer {
+    requests: Requests<Request, Result<(), String>>,
+}
+
+#[zbus::interface(name = "org.consolation.Trace")]
+impl TraceServer {
+    /// Write the messages of clients of `executable` connecting from now on to
+    /// the file at `path`, of the `interfaces` only unless empty
+    async fn start(
+        &self,
+        executable: String,
+        path: String,
+        interfaces: Vec<String>,
+    ) -> fdo::Result<()> {
+        self.requests.try_call(Request::Start {
+            executable,
+            path,
+            interfaces,
+        })
+    }
+
+    async fn stop(&self) -> fdo::Result<()> {
+        self.requests.try_call(Request::Stop).await
+    }
+}
+
+#[zbus::proxy(
+    interface = "org.consolation.Trace",
+    default_service = "org.consolation.Trace",
+    default_path = "/org/consolation/Trace"
+)]
+trait ConsolationTrace {
+    fn start(&self, executable: &str, path: &str, interfaces: &[String]) -> zbus::Result<()>;
+    fn stop(&self) -> zbus::Result<()>;
+}
+
+/// Ask the running compositor to trace the clients of `executable` into `path`
+pub fn request_trace(executable: &str, path: &str, interfaces: &[String]) -> zbus::Result<()> {
+    let connection = Connection::session()?;
+    ConsolationTraceProxyBlocking::new(&connection)?.start(executable, path, interfaces)
+}
+
+/// Ask the running compositor to stop tracing
+pub fn request_untrace() -> zbus::Result<()> {
+    let connection = Connection::session()?;
+    ConsolationTraceProxyBlocking::new(&connection)?.stop()
+}
+
+impl<BackendData: Backend> AnvilState<BackendData> {
+    /// Serve `org.consolation.Trace`, so `consolation --trace` can pick clients
+    /// to trace
+    pub fn start_protocol_trace(&mut self) {
+        let Some(requests) = self.insert_requests(|data, request| match request {
+            Request::Start {
+                executable,
+                path,
+                interfaces,
+            } => data.protocol_trace.start(executable, &path, interfaces),
+            Request::Stop => {
+                data.protocol_trace.stop();
+                Ok(())
+            }
+        }) else {
+            return;
+        };
+        let server = TraceServer { requests };
+        match self.dbus.serve(BUS_NAME, OBJECT_PATH, server, false) {
+            Ok(true) => {}
+            Ok(false) => warn!("Trace bus name is already taken"),
+            Err(err) => warn!(?err, "Unable to serve protocol tracing"),
+        }
+    }
+
+    /// Add the client connected through `stream`, through a relay tracing its
+    /// messages if it's a client of the executable being traced
+    pub fn insert_client(
+        &mut self,
+        stream: UnixStream,
+        mut client_state: ClientState,
+    ) -> io::Result<()> {
+        let credentials = get_socket_peercred(&stream).ok().map(|ucred| Credentials {
+            pid: ucred.pid.as_raw_nonzero().get(),
+            uid: ucred.uid.as_raw(),
+            gid: ucred.gid.as_raw(),
+        });
+        let Some(target) = self.protocol_trace.target.as_ref().filter(|target| {
+            credentials
+                .and_then(|credentials| process_executable(credentials.pid))
+                .is_some_and(|executable| {
+                    executable.as_os_str() == target.executable.as_str()
+                        || executable
+                            .file_name()
+                            .is_some_and(|name| name == target.executable.as_str())
+                })
+        }) else {
+            self.display_handle
+                .insert_client(stream, Arc::new(client_state))?;
+            return Ok(());
+        };
+
+        // The relay connects from the compositor, policies look at the client
+        client_state.credentials = credentials;
+        let (server_end, relay_end) = UnixStream::pair()?;
+        stream.set_nonblocking(false)?;
+        let client = self
+            .display_handle
+            .insert_client(server_end, Arc::new(client_state))?;
+        info!(?client, executable = target.executable, "Tracing client");
+        let tracer = Arc::new(Tracer {
+            client: client.id(),
+            handle: self.display_handle.backend_handle(),
+            output: target.output.clone(),
+            interfaces: target.interfaces.clone(),
+            active: target.active.clone(),
+            start: Instant::now(),
+        });
+        let (client_end, events_end) = (stream.try_clone()?, relay_end.try_clone()?);
+        let requests_tracer = tracer.clone();
+        thread::spawn(move || {
+            let mut pending = Vec::new();
+            relay(client_end, relay_end, |bytes| {
+                pending.extend_from_slice(bytes);
+                requests_tracer.log_messages(&mut pending, false);
+            })
+        });
+        thread::spawn(move || {
+            let mut pending = Vec::new();
+            relay(events_end, stream, |bytes| {
+                pending.extend_from_slice(bytes);
+                tracer.log_messages(&mut pending, true);
+            })
+        });
+        Ok(())
+    }
+}
@@ -0,0 +1,64 @@
+use tracing::{info, warn};
+use zbus::{blocking::Connection, fdo};
+
+use crate::{
+    dbus::Requests,
+    state::{AnvilState, Backend},
+};
+
+const BUS_NAME: &str = "org.consolation.Config";
+const OBJECT_PATH: &str = "/org/consolation/Config";
+
+/// `org.consolation.Config` object, forwarding reloads to the event loop
+struct ConfigServer {
+    requests: Requests<(), Result<(), String>>,
+}
+
+#[zbus::interface(name = "org.consolation.Config")]
+impl ConfigServer {
+    /// Read the configuration file again and apply it, failing with what's wrong
+    /// with the file
+    async fn reload(&self) -> fdo::Result<()> {
+        self.requests.try_call(()).await
+    }
+}
+
+#[zbus::proxy(
+    interface = "org.consolation.Config",
+    default_service = "org.consolation.Config",
+    default_path = "/org/consolation/Config"
+)]
+trait ConsolationConfig {
+    fn reload(&self) -> zbus::Result<()>;
+}
+
+/// Ask the running compositor to reload its configuration
+pub fn request_reload() -> zbus::Result<()> {
+    let connection = Connection::session()?;
+    ConsolationConfigProxyBlocking::new(&connection)?.reload()
+}
+
+impl<BackendData: Backend + 'static> AnvilState<BackendData> {
+    /// Serve `org.consolation.Config`, so `consolation --reload` applies changes
+    /// right away and reports errors in the file
+    pub fn start_config_reload(&mut self) {
+        let Some(requests) = self.insert_requests(|data, ()| {
+            info!("Reload requested");
+            let result = data.reload_config();
+            if let Err(err) = &result {
+                warn!(err, "Unable to reload config");
+            }
+            result
+        }) else {
+            return;
+        };
+        match self
+            .dbus
+            .serve(BUS_NAME, OBJECT_PATH, ConfigServer { requests }, false)
+        {
+            Ok(true) => {}
+            Ok(false) => warn!("Config bus name is already taken"),
+            Err(err) => warn!(?err, "Unable to serve config reloads"),
+        }
+    }
+}
@@ -0,0 +1,729 @@
+use std::{
+    io::{self, Read, Write},
+    net::{Shutdown, SocketAddr, TcpListener, TcpStream},
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        mpsc::{self, RecvTimeoutError},
+        Arc, Mutex,
+    },
+    thread,
+    time::{Duration, Instant},
+};
+
+use des::{
+    cipher::{generic_array::GenericArray, BlockEncrypt, KeyInit},
+    Des,
+};
+use smithay::{
+    backend::input::{Axis, AxisSource, KeyState},
+    input::{
+        keyboard::{keysyms as xkb, FilterResult},
+        pointer::AxisFrame,
+    },
+    reexports::{
+        calloop::channel::{channel, Event, Sender},
+        wayland_server::protocol::wl_pointer,
+    },
+    utils::{Physical, Size, SERIAL_COUNTER as SCOUNTER},
+};
+use tracing::{debug, info, warn};
+
+use crate::{
+    shell::virtual_pointer::VirtualPointerHandler,
+    state::{AnvilState, Backend, RemoteDesktopConfiguration},
+    udev::UdevData,
+};
+
+const DESKTOP_NAME: &str = "Consolation";
+const BTN_LEFT: u32 = 0x110;
+const BTN_RIGHT: u32 = 0x111;
+const BTN_MIDDLE: u32 = 0x112;
+/// Time a client has to answer each step of the handshake
+const HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(10);
+/// Clients connected at once, others are turned away
+const MAX_CLIENTS: usize = 4;
+/// Time clients are turned away after a wrong password, doubled on each other one
+const AUTH_BACKOFF: Duration = Duration::from_secs(1);
+const MAX_AUTH_BACKOFF: Duration = Duration::from_secs(60);
+
+/// Frame sent to a client, RGBA
+#[derive(Clone)]
+struct Frame {
+    size: (u16, u16),
+    pixels: Arc<Vec<u8>>,
+}
+
+/// The last frame read back, shared by all clients asking within a frame interval
+struct SharedFrame {
+    interval: Duration,
+    last: Option<(Instant, Frame)>,
+}
+
+impl SharedFrame {
+    fn get(&mut self, read: impl FnOnce() -> Option<Frame>) -> Option<Frame> {
+        if let Some((_, frame)) = self
+            .last
+            .as_ref()
+            .filter(|(read_at, _)| read_at.elapsed() < self.interval)
+        {
+            return Some(frame.clone());
+        }
+        let frame = read();
+        self.last = frame.clone().map(|frame| (Instant::now(), frame));
+        frame
+    }
+}
+
+enum Request {
+    Frame(mpsc::Sender<Option<Frame>>),
+    /// Fraction of the width and height of the picture
+    Motion(f64, f64),
+    Button(u32, wl_pointer::ButtonState),
+    Scroll(Axis, i32),
+    Key {
+        keysym: u32,
+        down: bool,
+    },
+}
+
+/// From the reader of a client to its writer
+enum Update {
+    PixelFormat(PixelFormat),
+    Request { incremental: bool },
+}
+
+#[derive(Debug, Clone, Copy)]
+struct PixelFormat {
+    bits_per_pixel: u8,
+    depth: u8,
+    big_endian: bool,
+    true_color: bool,
+    max: [u16; 3],
+    shift: [u8; 3],
+}
+
+impl Default for PixelFormat {
+    /// RGBA as read back from the renderer
+    fn default() -> Self {
+        Self {
+            bits_per_pixel: 32,
+            depth: 24,
+            big_endian: false,
+            true_color: true,
+            max: [255; 3],
+            shift: [0, 8, 16],
+        }
+    }
+}
+
+impl PixelFormat {
+    fn from_bytes(bytes: &[u8; 16]) -> Self {
+        let max = |at: usize| u16::from_be_bytes([bytes[at], bytes[at + 1]]);
+        Self {
+            bits_per_pixel: bytes[0],
+            depth: bytes[1],
+            big_endian: bytes[2] != 0,
+            true_color: bytes[3] != 0,
+            max: [max(4), max(6), max(8)],
+            shift: [bytes[10], bytes[11], bytes[12]],
+        }
+    }
+
+    fn to_bytes(self) -> [u8; 16] {
+        let mut bytes = [0; 16];
+        bytes[0] = self.bits_per_pixel;
+        bytes[1] = self.depth;
+        bytes[2] = self.big_endian as u8;
+        bytes[3] = self.true_color as u8;
+        for (i, max) in self.max.iter().enumerate() {
+            bytes[4 + i * 2..6 + i * 2].copy_from_slice(&max.to_be_bytes());
+        }
+        bytes[10..13].copy_from_slice(&self.shift);
+        bytes
+    }
+
+    /// Color maps are not served, nor channels that don't fit the pixel
+    fn supported(&self) -> bool {
+        self.true_color
+            && matches!(self.bits_per_pixel, 8 | 16 | 32)
+            && self.max.iter().all(|max| *max > 0)
+            && self.shift.iter().all(|shift| *shift < self.bits_per_pixel)
+    }
+
+    fn encode(&self, rgba: &[u8], out: &mut Vec<u8>) {
+        let bytes = self.bits_per_pixel as usize / 8;
+        for pixel in rgba.chunks_exact(4) {
+            let value = (0..3).fold(0u32, |value, channel| {
+                let max = self.max[channel] as u32;
+                let component = (pixel[channel] as u32 * max + 127) / 255;
+                value | component << self.shift[channel]
+            });
+            if self.big_endian {
+                out.extend_from_slice(&value.to_be_bytes()[4 - bytes..]);
+            } else {
+                out.extend_from_slice(&value.to_le_bytes()[..bytes]);
+            }
+        }
+    }
+}
+
+/// Linux input code of the key typing `keysym` on a US layout
+fn keysym_keycode(keysym: u32) -> Option<u32> {
+    const LETTERS: [u32; 26] = [
+        30, 48, 46, 32, 18, 33, 34, 35, 23, 36, 37, 38, 50, 49, 24, 25, 16, 19, 31, 20, 22, 47, 17,
+        45, 21, 44,
+    ];
+    let code = match keysym {
+        0x61..=0x7a => LETTERS[(keysym - 0x61) as usize],
+        0x41..=0x5a => LETTERS[(keysym - 0x41) as usize],
+        0x31..=0x39 => keysym - 0x31 + 2,
+        0x30 => 11,
+        0x21 => 2,
+        0x40 => 3,
+        0x23 => 4,
+        0x24 => 5,
+        0x25 => 6,
+        0x5e => 7,
+        0x26 => 8,
+        0x2a => 9,
+        0x28 => 10,
+        0x29 => 11,
+        0x2d | 0x5f => 12,
+        0x3d | 0x2b => 13,
+        0x5b | 0x7b => 26,
+        0x5d | 0x7d => 27,
+        0x3b | 0x3a => 39,
+        0x27 | 0x22 => 40,
+        0x60 | 0x7e => 41,
+        0x5c | 0x7c => 43,
+        0x2c | 0x3c => 51,
+        0x2e | 0x3e => 52,
+        0x2f | 0x3f => 53,
+        0x20 => 57,
+        xkb::KEY_Escape => 1,
+        xkb::KEY_BackSpace => 14,
+        xkb::KEY_Tab => 15,
+        xkb::KEY_Return => 28,
+        xkb::KEY_Control_L => 29,
+        xkb::KEY_Shift_L => 42,
+        xkb::KEY_Shift_R => 54,
+        xkb::KEY_Alt_L => 56,
+        xkb::KEY_Caps_Lock => 58,
+        xkb::KEY_F1..=xkb::KEY_F10 => keysym - xkb::KEY_F1 + 59,
+        xkb::KEY_F11 => 87,
+        xkb::KEY_F12 => 88,
+        xkb::KEY_Control_R => 97,
+        xkb::KEY_Alt_R => 100,
+        xkb::KEY_Home => 102,
+        xkb::KEY_Up => 103,
+        xkb::KEY_Page_Up => 104,
+        xkb::KEY_Left => 105,
+        xkb::KEY_Right => 106,
+        xkb::KEY_End => 107,
+        xkb::KEY_Down => 108,
+        xkb::KEY_Page_Down => 109,
+        xkb::KEY_Insert => 110,
+        xkb::KEY_Delete => 111,
+        xkb::KEY_Super_L => 125,
+        xkb::KEY_Super_R => 126,
+        xkb::KEY_Menu => 127,
+        _ => return None,
+    };
+    Some(code)
+}
+
+fn request_frame(requests: &Sender<Request>) -> Option<Frame> {
+    let (reply, frame) = mpsc::channel();
+    requests.send(Request::Frame(reply)).ok()?;
+    frame.recv().ok().flatten()
+}
+
+/// DES encryption of the challenge, keyed with the password of which each byte is
+/// mirrored, as VNC clients do
+fn vnc_response(password: &str, challenge: &[u8; 16]) -> [u8; 16] {
+    let mut key = [0u8; 8];
+    for (key, byte) in key.iter_mut().zip(password.bytes()) {
+        *key = byte.reverse_bits();
+    }
+    let cipher = Des::new(GenericArray::from_slice(&key));
+    let mut response = *challenge;
+    for block in response.chunks_exact_mut(8) {
+        cipher.encrypt_block(GenericArray::from_mut_slice(block));
+    }
+    response
+}
+
+/// Agree on the protocol version and check the password if there is one
+fn handshake(stream: &mut TcpStream, password: Option<&str>) -> io::Result<()> {
+    stream.write_all(b"RFB 003.008\n")?;
+    let mut version = [0u8; 12];
+    stream.read_exact(&mut version)?;
+    if !version.starts_with(b"RFB 003.") {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            "Not an RFB client",
+        ));
+    }
+    let minor: u32 = std::str::from_utf8(&version[8..11])
+        .ok()
+        .and_then(|minor| minor.parse().ok())
+        .unwrap_or(3);
+
+    let security = if password.is_some() { 2u8 } else { 1 };
+    if minor >= 7 {
+        stream.write_all(&[1, security])?;
+        let mut chosen = [0u8];
+        stream.read_exact(&mut chosen)?;
+        if chosen[0] != security {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "Unsupported security type",
+            ));
+        }
+    } else {
+        stream.write_all(&(security as u32).to_be_bytes())?;
+    }
+
+    let Some(password) = password else {
+        if minor >= 8 {
+            stream.write_all(&0u32.to_be_bytes())?;
+        }
+        return Ok(());
+    };
+    let challenge: [u8; 16] = rand::random();
+    stream.write_all(&challenge)?;
+    let mut response = [0u8; 16];
+    stream.read_exact(&mut response)?;
+    if response == vnc_response(password, &challenge) {
+        stream.write_all(&0u32.to_be_bytes())?;
+        return Ok(());
+    }
+    stream.write_all(&1u32.to_be_bytes())?;
+    if minor >= 8 {
+        let reason = b"Wrong password";
+        stream.write_all(&(reason.len() as u32).to_be_bytes())?;
+        stream.write_all(reason)?;
+    }
+    Err(io::Error::new(
+        io::ErrorKind::PermissionDenied,
+        "Wrong password",
+    ))
+}
+
+/// Wrong passwords in a row and until when clients are turned away for them
+#[derive(Debug, Default)]
+struct AuthBackoff {
+    failures: u32,
+    until: Option<Instant>,
+}
+
+impl AuthBackoff {
+    fn blocked(&self) -> bool {
+        self.until.is_some_and(|until| Instant::now() < until)
+    }
+
+    fn failed(&mut self) {
+        let delay = AUTH_BACKOFF.saturating_mul(1 << self.failures.min(6));
+        self.failures = self.failures.saturating_add(1);
+        self.until = Some(Instant::now() + delay.min(MAX_AUTH_BACKOFF));
+    }
+}
+
+/// State shared by the threads of the clients
+#[derive(Debug, Default)]
+struct Clients {
+    connected: AtomicUsize,
+    backoff: Mutex<AuthBackoff>,
+}
+
+/// First and past the last row that differ
+fn changed_rows(previous: &[u8], current: &[u8], stride: usize) -> Option<(usize, usize)> {
+    let rows = current.len() / stride;
+    let differs = |(previous, current): (&[u8], &[u8])| previous != current;
+    let first = previous
+        .chunks_exact(stride)
+        .zip(current.chunks_exact(stride))
+        .position(differs)?;
+    let from_end = previous
+        .chunks_exact(stride)
+        .rev()
+        .zip(current.chunks_exact(stride).rev())
+        .position(differs)
+        .unwrap_or(0);
+    Some((first, rows - from_end))
+}
+
+/// Send the changed rows whenever the client asks, at most `interval` apart
+fn send_updates(
+    stream: &mut TcpStream,
+    requests: Sender<Request>,
+    size: (u16, u16),
+    updates: mpsc::Receiver<Update>,
+    interval: Duration,
+) -> io::Result<()> {
+    let stride = size.0 as usize * 4;
+    let mut format = PixelFormat::default();
+    let mut previous: Option<Arc<Vec<u8>>> = None;
+    let mut last_sent = Instant::now() - interval;
+    // Whether the client waits for an update, and if it needs all of it
+    let mut pending: Option<bool> = None;
+    loop {
+        let update = match pending {
+            Some(_) => updates.recv_timeout(interval.saturating_sub(last_sent.elapsed())),
+            None => updates.recv().map_err(|_| RecvTimeoutError::Disconnected),
+        };
+        match update {
+            Ok(Update::PixelFormat(new)) => {
+                format = new;
+                previous = None;
+                continue;
+            }
+            Ok(Update::Request { incremental }) => {
+                pending = Some(pending.unwrap_or(false) || !incremental);
+                continue;
+            }
+            Err(RecvTimeoutError::Timeout) => {}
+            Err(RecvTimeoutError::Disconnected) => return Ok(()),
+        }
+
+        let full = pending.take().unwrap_or(false);
+        last_sent = Instant::now();
+        let frame = request_frame(&requests).ok_or_else(|| io::Error::other("Nothing to share"))?;
+        if frame.size != size {
+            return Err(io::Error::other("Picture size changed"));
+        }
+        let rows = match previous.as_deref() {
+            Some(previous) if !full => changed_rows(previous, &frame.pixels, stride),
+            _ => Some((0, size.1 as usize)),
+        };
+        let Some((first, last)) = rows else {
+            // Unchanged, try again on the next frame
+            pending = Some(false);
+            continue;
+        };
+
+        let mut message = vec![0, 0];
+        message.extend_from_slice(&1u16.to_be_bytes());
+        message.extend_from_slice(&0u16.to_be_bytes());
+        message.extend_from_slice(&(first as u16).to_be_bytes());
+        message.extend_from_slice(&size.0.to_be_bytes());
+        message.extend_from_slice(&((last - first) as u16).to_be_bytes());
+        // Raw encoding
+        message.extend_from_slice(&0i32.to_be_bytes());
+        format.encode(&frame.pixels[first * stride..last * stride], &mut message);
+        stream.write_all(&message)?;
+        previous = Some(frame.pixels);
+    }
+}
+
+/// Forward the input of the client, and its update requests to the writer
+fn read_messages(
+    mut stream: TcpStream,
+    requests: Sender<Request>,
+    size: (u16, u16),
+    updates: mpsc::Sender<Update>,
+) -> io::Result<()> {
+    let mut buttons = 0u8;
+    loop {
+        let mut kind = [0u8];
+        stream.read_exact(&mut kind)?;
+        match kind[0] {
+            // SetPixelFormat
+            0 => {
+                let mut message = [0u8; 19];
+                stream.read_exact(&mut message)?;
+                let format = PixelFormat::from_bytes(message[3..].try_into().unwrap());
+                if !format.supported() {
+                    warn!(?format, "Unsupported remote desktop pixel format");
+                } else if updates.send(Update::PixelFormat(format)).is_err() {
+                    return Ok(());
+                }
+            }
+            // SetEncodings, only raw is sent
+            2 => {
+                let mut header = [0u8; 3];
+                stream.read_exact(&mut header)?;
+                let count = u16::from_be_bytes([header[1], header[2]]);
+                let mut encodings = vec![0u8; count as usize * 4];
+                stream.read_exact(&mut encodings)?;
+            }
+            // FramebufferUpdateRequest, always for the whole picture
+            3 => {
+                let mut message = [0u8; 9];
+                stream.read_exact(&mut message)?;
+                let incremental = message[0] != 0;
+                if updates.send(Update::Request { incremental }).is_err() {
+                    return Ok(());
+                }
+            }
+            // KeyEvent
+            4 => {
+                let mut message = [0u8; 7];
+                stream.read_exact(&mut message)?;
+                let _ = requests.send(Request::Key {
+                    keysym: u32::from_be_bytes(message[3..].try_into().unwrap()),
+                    down: message[0] != 0,
+                });
+            }
+            // PointerEvent
+            5 => {
+                let mut message = [0u8; 5];
+                stream.read_exact(&mut message)?;
+                let mask = message[0];
+                let x = u16::from_be_bytes([message[1], message[2]]);
+                let y = u16::from_be_bytes([message[3], message[4]]);
+                let _ = requests.send(Request::Motion(
+                    x as f64 / size.0 as f64,
+                    y as f64 / size.1 as f64,
+                ));
+                for (bit, button) in [(0, BTN_LEFT), (1, BTN_MIDDLE), (2, BTN_RIGHT)] {
+                    let pressed = mask & 1 << bit != 0;
+                    if pressed != (buttons & 1 << bit != 0) {
+                        let state = if pressed {
+                            wl_pointer::ButtonState::Pressed
+                        } else {
+                            wl_pointer::ButtonState::Released
+                        };
+                        let _ = requests.send(Request::Button(button, state));
+                    }
+                }
+                // The wheel presses and releases a button per step
+                let wheel = [
+                    (3, Axis::Vertical, -1),
+                    (4, Axis::Vertical, 1),
+                    (5, Axis::Horizontal, -1),
+                    (6, Axis::Horizontal, 1),
+                ];
+                for (bit, axis, steps) in wheel {
+                    if mask & 1 << bit != 0 && buttons & 1 << bit == 0 {
+                        let _ = requests.send(Request::Scroll(axis, steps));
+                    }
+                }
+                buttons = mask;
+            }
+            // ClientCutText, the clipboard is not shared
+            6 => {
+                let mut header = [0u8; 7];
+                stream.read_exact(&mut header)?;
+                let length = u32::from_be_bytes(header[3..].try_into().unwrap());
+                io::copy(&mut (&mut stream).take(length as u64), &mut io::sink())?;
+            }
+            kind => {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("Unknown client message {kind}"),
+                ))
+            }
+        }
+    }
+}
+
+fn serve_client(
+    mut stream: TcpStream,
+    requests: Sender<Request>,
+    config: &RemoteDesktopConfiguration,
+    clients: &Clients,
+) -> io::Result<()> {
+    stream.set_nodelay(true)?;
+    stream.set_read_timeout(Some(HANDSHAKE_TIMEOUT))?;
+    let res = handshake(&mut stream, config.password.as_deref());
+    if let Ok(mut backoff) = clients.backoff.lock() {
+        match &res {
+            Ok(()) => *backoff = AuthBackoff::default(),
+            Err(err) if err.kind() == io::ErrorKind::PermissionDenied => backoff.failed(),
+            Err(_) => {}
+        }
+    }
+    res?;
+    // Other clients stay connected whether this one asks to share or not
+    let mut shared = [0u8];
+    stream.read_exact(&mut shared)?;
+    stream.set_read_timeout(None)?;
+
+    let frame = request_frame(&requests).ok_or_else(|| io::Error::other("Nothing to share"))?;
+    let size = frame.size;
+    let mut init = Vec::new();
+    init.extend_from_slice(&size.0.to_be_bytes());
+    init.extend_from_slice(&size.1.to_be_bytes());
+    init.extend_from_slice(&PixelFormat::default().to_bytes());
+    init.extend_from_slice(&(DESKTOP_NAME.len() as u32).to_be_bytes());
+    init.extend_from_slice(DESKTOP_NAME.as_bytes());
+    stream.write_all(&init)?;
+
+    let (updates, update_requests) = mpsc::channel();
+    let mut writer = stream.try_clone()?;
+    let frame_requests = requests.clone();
+    let interval = Duration::from_secs_f64(1.0 / config.frame_rate.max(1.0));
+    thread::spawn(move || {
+        let res = send_updates(&mut writer, frame_requests, size, update_requests, interval);
+        if let Err(err) = res {
+            debug!(?err, "Stopped sending remote desktop updates");
+            // Unblocks the reader
+            let _ = writer.shutdown(Shutdown::Both);
+        }
+    });
+    let res = read_messages(stream.try_clone()?, requests, size, updates);
+    let _ = stream.shutdown(Shutdown::Both);
+    res
+}
+
+fn accept_clients(
+    listener: TcpListener,
+    requests: Sender<Request>,
+    config: RemoteDesktopConfiguration,
+) {
+    let clients = Arc::new(Clients::default());
+    for stream in listener.incoming() {
+        let stream = match stream {
+            Ok(stream) => stream,
+            Err(err) => {
+                warn!(?err, "Unable to accept remote desktop client");
+                continue;
+            }
+        };
+        let peer = stream.peer_addr().ok();
+        if clients
+            .backoff
+            .lock()
+            .is_ok_and(|backoff| backoff.blocked())
+        {
+            debug!(
+                ?peer,
+                "Wrong password lately, turning away remote desktop client"
+            );
+            continue;
+        }
+        if clients.connected.load(Ordering::Relaxed) >= MAX_CLIENTS {
+            warn!(?peer, "Too many remote desktop clients");
+            continue;
+        }
+        clients.connected.fetch_add(1, Ordering::Relaxed);
+        let requests = requests.clone();
+        let config = config.clone();
+        let clients = clients.clone();
+        thread::spawn(move || {
+            info!(?peer, "Remote desktop client connected");
+            if let Err(err) = serve_client(stream, requests, &config, &clients) {
+                debug!(?err, "Remote desktop client failed");
+            }
+            clients.connected.fetch_sub(1, Ordering::Relaxed);
+            info!(?peer, "Remote desktop client disconnected");
+        });
+    }
+}
+
+impl AnvilState<UdevData> {
+    /// Serve the shown window over VNC if configured
+    pub fn start_remote_desktop(&mut self) {
+        let Some(config) = self.config.remote_desktop.clone() else {
+            return;
+        };
+
+        let (sender, requests) = channel();
+        let mut frames = SharedFrame {
+            interval: Duration::from_secs_f64(1.0 / config.frame_rate.max(1.0)),
+            last: None,
+        };
+        let res = self.handle.insert_source(requests, move |event, _, data| {
+            if let Event::Msg(request) = event {
+                data.remote_desktop_request(request, &mut frames);
+            }
+        });
+        if let Err(err) = res {
+            warn!(?err, "Unable to insert remote desktop source");
+            return;
+        }
+
+        let listener = match TcpListener::bind(&config.listen) {
+            Ok(listener) => listener,
+            Err(err) => {
+                warn!(listen = %config.listen, ?err, "Unable to serve remote desktop");
+                return;
+            }
+        };
+        let local = config
+            .listen
+            .parse::<SocketAddr>()
+            .is_ok_and(|address| address.ip().is_loopback());
+        if config.password.is_none() && !local {
+            warn!(listen = %config.listen, "Remote desktop is served without a password");
+        }
+        info!(listen = %config.listen, "Serving remote desktop");
+        let res = thread::Builder::new()
+            .name("remote-desktop".into())
+            .spawn(move || accept_clients(listener, sender, config));
+        if let Err(err) = res {
+            warn!(?err, "Unable to start remote desktop thread");
+        }
+    }
+
+    fn remote_desktop_request(&mut self, request: Request, frames: &mut SharedFrame) {
+        let time = Duration::from(self.clock.now()).as_millis() as u32;
+        match request {
+            Request::Frame(reply) => {
+                let _ = reply.send(frames.get(|| self.remote_desktop_frame()));
+            }
+            Request::Motion(x, y) => self.virtual_pointer_motion_absolute((x, y), time),
+            Request::Button(button, state) => self.virtual_pointer_button(button, state, time),
+            Request::Scroll(axis, steps) => {
+                let frame = AxisFrame::new(time)
+                    .source(AxisSource::Wheel)
+                    .value(axis, steps as f64 * 15.0)
+                    .v120(axis, steps * 120);
+                self.virtual_pointer_axis(frame);
+            }
+            Request::Key { keysym, down } => self.remote_desktop_key(keysym, down, time),
+        }
+    }
+
+    /// The shown window stretched to the picture size, black without one
+    fn remote_desktop_frame(&mut self) -> Option<Frame> {
+        let size: Size<i32, Physical> = match self
+            .config
+            .remote_desktop
+            .as_ref()
+            .and_then(|config| config.size)
+        {
+            Some((w, h)) => Size::from((w as i32, h as i32)),
+            None => {
+                let output = self.outputs.first()?;
+                let mode = output.current_mode()?;
+                output.current_transform().transform_size(mode.size)
+            }
+        };
+        let frame_size = (u16::try_from(size.w).ok()?, u16::try_from(size.h).ok()?);
+        let pixels = match self.current_window() {
+            (Some(window), _) => self.backend_data.sample_window(&window, size),
+            (None, _) => None,
+        }
+        .unwrap_or_else(|| vec![0; size.w as usize * size.h as usize * 4]);
+        Some(Frame {
+            size: frame_size,
+            pixels: Arc::new(pixels),
+        })
+    }
+
+    /// Keys go to the focused window, past the compositor shortcuts
+    fn remote_desktop_key(&mut self, keysym: u32, down: bool, time: u32) {
+        let Some(code) = keysym_keycode(keysym) else {
+            debug!(keysym, "Remote desktop key has no key code");
+            return;
+        };
+        self.virtual_input_activity();
+        let state = if down {
+            KeyState::Pressed
+        } else {
+            KeyState::Released
+        };
+        let keyboard = self.seat.get_keyboard().unwrap();
+        // xkb key codes are offset from the input ones
+        keyboard.input(
+            self,
+            code + 8,
+            state,
+            SCOUNTER.next_serial(),
+            time,
+            |_, _, _| FilterResult::Forward::<bool>,
+        );
+    }
+}
@@ -1,32 +1,132 @@
+use std::{cell::RefCell, collections::HashMap};
+
 use smithay::{
-    backend::renderer::{
-        damage::{Error as OutputDamageTrackerError, OutputDamageTracker, RenderOutputResult},
-        element::{
-            surface::WaylandSurfaceRenderElement,
-            utils::{
-                constrain_as_render_elements, ConstrainAlign, ConstrainScaleBehavior,
-                CropRenderElement, RelocateRenderElement, RescaleRenderElement,
+    backend::{
+        allocator::Fourcc,
+        renderer::{
+            buffer_dimensions,
+            damage::{Error as OutputDamageTrackerError, OutputDamageTracker, RenderOutputResult},
+            element::{
+                memory::{MemoryRenderBuffer, MemoryRenderBufferRenderElement},
+                solid::{SolidColorBuffer, SolidColorRenderElement},
+                surface::WaylandSurfaceRenderElement,
+                utils::{
+                    constrain_as_render_elements, ConstrainAlign, ConstrainScaleBehavior,
+                    CropRenderElement, RelocateRenderElement, RescaleRenderElement,
+                },
+                AsRenderElements, Element, Id, Kind, RenderElement, UnderlyingStorage, Wrap,
             },
-            AsRenderElements, RenderElement, Wrap,
+            gles::{GlesTexProgram, GlesTexture},
+            utils::RendererSurfaceStateUserData,
+            Bind, ImportAll, ImportMem, Offscreen, Renderer,
         },
-        ImportAll, ImportMem, Renderer,
     },
     desktop::{
         space::{ConstrainBehavior, ConstrainReference, SpaceRenderElements},
         LayerSurface, Window,
     },
     output::Output,
-    utils::{Logical, Point, Rectangle, Scale},
-    wayland::shell::wlr_layer::Layer,
+    utils::{Logical, Physical, Point, Rectangle, Scale, Size, Transform},
+    wayland::{
+        compositor::{get_children, with_states},
+        shell::wlr_layer::Layer,
+        single_pixel_buffer::get_single_pixel_buffer,
+    },
 };
+use tracing::warn;
 
 #[cfg(feature = "debug")]
 use crate::drawing::FpsElement;
 use crate::{
-    drawing::{BackgroundElement, PointerRenderElement, CLEAR_COLOR},
-    shell::{WindowElement, WindowRenderElement},
+    color_filter::ColorFilterPass,
+    drawing::{BackgroundElement, PointerRenderElement},
+    shaders::{letterbox_blur_shader, scaling_shader, GlesAccess, ShadedRenderElement},
+    shell::{
+        is_popup,
+        letterbox::window_content_geometry,
+        scaling::{window_scaling, WindowScaling},
+        shown_window,
+        transition::WindowTransition,
+        WindowElement, WindowRenderElement,
+    },
+    state::{
+        hex_color, ColorFilter, Configuration, Corner, LetterboxFill, ScalingMode,
+        SplitOrientation, TransitionStyle,
+    },
 };
 
+const INSET_MARGIN: i32 = 16;
+
+/// A window drawn small into a corner above the shown one
+#[derive(Debug, Clone)]
+pub struct InsetWindow {
+    pub window: Window,
+    pub corner: Corner,
+    /// Width and height as a fraction of the usable area
+    pub size: f64,
+}
+
+/// Windows drawn besides the shown one
+#[derive(Debug, Default)]
+pub struct WindowLayout {
+    pub insets: Vec<InsetWindow>,
+    /// Window of the second half of a split screen, the shown one takes the first
+    pub split: Option<(Window, SplitOrientation)>,
+}
+
+/// The halves of `zone` the shown window and the second window of a split screen
+/// are drawn into
+pub fn split_zones(
+    zone: Rectangle<i32, Logical>,
+    orientation: SplitOrientation,
+) -> (Rectangle<i32, Logical>, Rectangle<i32, Logical>) {
+    match orientation {
+        SplitOrientation::SideBySide => {
+            let half = zone.size.w / 2;
+            (
+                Rectangle::from_loc_and_size(zone.loc, (half, zone.size.h)),
+                Rectangle::from_loc_and_size(
+                    (zone.loc.x + half, zone.loc.y),
+                    (zone.size.w - half, zone.size.h),
+                ),
+            )
+        }
+        SplitOrientation::TopBottom => {
+            let half = zone.size.h / 2;
+            (
+                Rectangle::from_loc_and_size(zone.loc, (zone.size.w, half)),
+                Rectangle::from_loc_and_size(
+                    (zone.loc.x, zone.loc.y + half),
+                    (zone.size.w, zone.size.h - half),
+                ),
+            )
+        }
+    }
+}
+
+/// The part of `zone` an inset window in `corner` is drawn into
+pub fn inset_zone(
+    zone: Rectangle<i32, Logical>,
+    corner: Corner,
+    size: f64,
+) -> Rectangle<i32, Logical> {
+    let size = (
+        (zone.size.w as f64 * size).round() as i32,
+        (zone.size.h as f64 * size).round() as i32,
+    );
+    let left = zone.loc.x + INSET_MARGIN;
+    let right = zone.loc.x + zone.size.w - size.0 - INSET_MARGIN;
+    let top = zone.loc.y + INSET_MARGIN;
+    let bottom = zone.loc.y + zone.size.h - size.1 - INSET_MARGIN;
+    let loc = match corner {
+        Corner::TopLeft => (left, top),
+        Corner::TopRight => (right, top),
+        Corner::BottomLeft => (left, bottom),
+        Corner::BottomRight => (right, bottom),
+    };
+    Rectangle::from_loc_and_size(loc, size)
+}
+
 smithay::backend::renderer::element::render_elements! {
     pub CustomRenderElements<R> where
         R: ImportAll + ImportMem;
@@ -39,6 +139,8 @@ smithay::backend::renderer::element::render_elements! {
     // in the declaration.
     Fps=FpsElement<<R as Renderer>::TextureId>,
     Background=BackgroundElement<<R as Renderer>::TextureId>,
+    Solid=SolidColorRenderElement,
+    Memory=MemoryRenderBufferRenderElement<R>,
 }
 
 impl<R: Renderer> std::fmt::Debug for CustomRenderElements<R> {
@@ -49,20 +151,27 @@ impl<R: Renderer> std::fmt::Debug for CustomRenderElements<R> {
             #[cfg(feature = "debug")]
             Self::Fps(arg0) => f.debug_tuple("Fps").field(arg0).finish(),
             Self::Background(arg0) => f.debug_tuple("Background").field(arg0).finish(),
+            Self::Solid(arg0) => f.debug_tuple("Solid").field(arg0).finish(),
+            Self::Memory(arg0) => f.debug_tuple("Memory").field(arg0).finish(),
             Self::_GenericCatcher(arg0) => f.debug_tuple("_GenericCatcher").field(arg0).finish(),
         }
     }
 }
 
 smithay::backend::renderer::element::render_elements! {
-    pub OutputRenderElements<R, E> where R: ImportAll + ImportMem;
+    pub OutputRenderElements<R, E> where R: ImportAll + ImportMem + GlesAccess;
     Space=SpaceRenderElements<R, E>,
     Window=Wrap<E>,
     Custom=CustomRenderElements<R>,
-    Preview=CropRenderElement<RelocateRenderElement<RescaleRenderElement<WindowRenderElement<R>>>>,
+    Preview=ScaledWindowElement<R>,
+    Shaded=ShadedRenderElement<ScaledWindowElement<R>>,
 }
 
-impl<R: Renderer + ImportAll + ImportMem, E: RenderElement<R> + std::fmt::Debug> std::fmt::Debug
+/// A window scaled into its zone, cropped to it
+pub type ScaledWindowElement<R> =
+    CropRenderElement<RelocateRenderElement<RescaleRenderElement<WindowRenderElement<R>>>>;
+
+impl<R: ImportAll + ImportMem + GlesAccess, E: RenderElement<R> + std::fmt::Debug> std::fmt::Debug
     for OutputRenderElements<R, E>
 {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
@@ -71,14 +180,69 @@ impl<R: Renderer + ImportAll + ImportMem, E: RenderElement<R> + std::fmt::Debug>
             Self::Window(arg0) => f.debug_tuple("Window").field(arg0).finish(),
             Self::Custom(arg0) => f.debug_tuple("Custom").field(arg0).finish(),
             Self::Preview(arg0) => f.debug_tuple("Preview").field(arg0).finish(),
+            Self::Shaded(arg0) => f.debug_tuple("Shaded").field(arg0).finish(),
             Self::_GenericCatcher(arg0) => f.debug_tuple("_GenericCatcher").field(arg0).finish(),
         }
     }
 }
 
+smithay::backend::renderer::element::render_elements! {
+    pub ZoomRenderElements<R, E> where R: ImportAll + ImportMem + GlesAccess;
+    Output=OutputRenderElements<R, E>,
+    Zoomed=RescaleRenderElement<OutputRenderElements<R, E>>,
+}
+
+impl<R: ImportAll + ImportMem + GlesAccess, E: RenderElement<R> + std::fmt::Debug> std::fmt::Debug
+    for ZoomRenderElements<R, E>
+{
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Output(arg0) => f.debug_tuple("Output").field(arg0).finish(),
+            Self::Zoomed(arg0) => f.debug_tuple("Zoomed").field(arg0).finish(),
+            Self::_GenericCatcher(arg0) => f.debug_tuple("_GenericCatcher").field(arg0).finish(),
+        }
+    }
+}
+
+/// Magnification of a whole output around a point of it
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Zoom {
+    pub level: f64,
+    pub center: Point<i32, Physical>,
+}
+
+/// Scale the composited `elements` by the zoom level around its center, which
+/// stays in place
+pub fn zoom_elements<R, E>(
+    elements: Vec<OutputRenderElements<R, E>>,
+    zoom: Option<Zoom>,
+) -> Vec<ZoomRenderElements<R, E>>
+where
+    R: ImportAll + ImportMem + GlesAccess,
+    E: RenderElement<R>,
+{
+    match zoom {
+        Some(zoom) => elements
+            .into_iter()
+            .map(|element| {
+                ZoomRenderElements::Zoomed(RescaleRenderElement::from_element(
+                    element,
+                    zoom.center,
+                    zoom.level,
+                ))
+            })
+            .collect(),
+        None => elements
+            .into_iter()
+            .map(ZoomRenderElements::Output)
+            .collect(),
+    }
+}
+
 pub fn get_window_scales(
     window: Window,
     zone: Rectangle<i32, smithay::utils::Logical>,
+    scaling: &WindowScaling,
 ) -> (
     Rectangle<i32, Logical>,
     Point<i32, Logical>,
@@ -87,18 +251,77 @@ pub fn get_window_scales(
 ) {
     let behavior = ConstrainBehavior {
         reference: ConstrainReference::BoundingBox,
-        behavior: ConstrainScaleBehavior::Fit,
+        behavior: match scaling.mode {
+            // Cropped windows scale their visible part to the zone, see below
+            ScalingMode::Stretch | ScalingMode::Crop => ConstrainScaleBehavior::Stretch,
+            ScalingMode::Fit | ScalingMode::Integer => ConstrainScaleBehavior::Fit,
+        },
         align: ConstrainAlign::CENTER,
     };
 
-    let constrain = zone;
+    // Scale the xdg geometry (or X11 geometry) into place, and crop away what lies
+    // outside of it, like client side shadows and off-surface subsurfaces
+    let (screen_geometry, scale) = window_screen_geometry(&window, zone, scaling);
+    let geometry = window_content_geometry(&window);
+    let (constrain, scale_reference) = match scaling.mode {
+        // Only the part of the window that ends up inside the zone is scaled into it
+        ScalingMode::Crop => {
+            let visible: Rectangle<f64, Logical> = Rectangle::from_loc_and_size(
+                geometry.loc.to_f64() + (zone.loc - screen_geometry.loc).to_f64().downscale(scale),
+                zone.size.to_f64().downscale(scale),
+            );
+            (zone, visible.to_i32_round())
+        }
+        _ => (screen_geometry, geometry),
+    };
 
     let location = zone.loc;
 
-    let scale_reference = window.bbox();
     (constrain, location, scale_reference, behavior)
 }
 
+/// Where the geometry of `window` ends up inside `zone` once scaled,
+/// and the scale applied to it
+pub fn window_screen_geometry(
+    window: &Window,
+    zone: Rectangle<i32, Logical>,
+    scaling: &WindowScaling,
+) -> (Rectangle<i32, Logical>, Scale<f64>) {
+    let reference = window_content_geometry(window).size.to_f64();
+    if reference.w <= 0.0 || reference.h <= 0.0 {
+        return (zone, Scale::from(1.0));
+    }
+    let fit = f64::min(
+        zone.size.w as f64 / reference.w,
+        zone.size.h as f64 / reference.h,
+    );
+    let scale = match scaling.mode {
+        ScalingMode::Fit => Scale::from(fit),
+        // Windows larger than the zone still have to shrink
+        ScalingMode::Integer if fit >= 1.0 => Scale::from(fit.floor()),
+        ScalingMode::Integer => Scale::from(fit),
+        ScalingMode::Stretch => Scale::from((
+            zone.size.w as f64 / reference.w,
+            zone.size.h as f64 / reference.h,
+        )),
+        ScalingMode::Crop => Scale::from(f64::max(
+            zone.size.w as f64 / reference.w,
+            zone.size.h as f64 / reference.h,
+        )),
+    };
+    let size: Size<i32, Logical> = reference.upscale(scale).to_i32_round();
+    // Centered, unless the offset of a cropped window moves it
+    let (offset_x, offset_y) = match scaling.mode {
+        ScalingMode::Crop => scaling.crop_offset,
+        _ => (0.0, 0.0),
+    };
+    let offset: Point<i32, Logical> = Point::from((
+        ((zone.size.w - size.w) as f64 * (1.0 + offset_x) / 2.0).round() as i32,
+        ((zone.size.h - size.h) as f64 * (1.0 + offset_y) / 2.0).round() as i32,
+    ));
+    (Rectangle::from_loc_and_size(zone.loc + offset, size), scale)
+}
+
 pub fn render_window<'a, R, C>(
     renderer: &'a mut R,
     window: Window,
@@ -106,22 +329,22 @@ pub fn render_window<'a, R, C>(
     location: Point<i32, Logical>,
     mut scale_reference: Rectangle<i32, Logical>,
     behavior: ConstrainBehavior,
+    alpha: f32,
 ) -> impl Iterator<Item = C> + 'a
 where
     R: Renderer + ImportAll + ImportMem,
     R::TextureId: Clone + 'static,
-    C: From<CropRenderElement<RelocateRenderElement<RescaleRenderElement<WindowRenderElement<R>>>>>
-        + 'a,
+    C: From<ScaledWindowElement<R>> + 'a,
 {
     let wele = WindowElement(window.clone());
-    if window.is_x11() && window.x11_surface().unwrap().is_override_redirect() {
+    if is_popup(&window) {
         let geo = window.x11_surface().unwrap().geometry();
         scale_reference.loc -= geo.loc;
         constrain_as_render_elements(
             &wele,
             renderer,
             (location - scale_reference.loc).to_physical_precise_round(1.0),
-            1.0,
+            alpha,
             constrain.to_physical_precise_round(1.0),
             scale_reference.to_physical_precise_round(1.0),
             behavior.behavior,
@@ -134,7 +357,7 @@ where
             &wele,
             renderer,
             (location - scale_reference.loc).to_physical_precise_round(1.0),
-            1.0,
+            alpha,
             constrain.to_physical_precise_round(1.0),
             scale_reference.to_physical_precise_round(1.0),
             behavior.behavior,
@@ -145,10 +368,320 @@ where
     }
 }
 
+/// Window elements drawn through `shader` if there is one, for filters like
+/// scanlines on games
+fn shade_window<R>(
+    renderer: &mut R,
+    elements: Vec<ScaledWindowElement<R>>,
+    shader: Option<GlesTexProgram>,
+) -> Vec<OutputRenderElements<R, WindowRenderElement<R>>>
+where
+    R: ImportAll + ImportMem + GlesAccess,
+    R::TextureId: Clone + 'static,
+{
+    elements
+        .into_iter()
+        .map(|element| match shader.as_ref() {
+            Some(program) => {
+                // Shaders sampling whole pixels need the size of the buffer
+                let texture_size = match element.underlying_storage(renderer) {
+                    Some(UnderlyingStorage::Wayland(buffer)) => buffer_dimensions(&buffer),
+                    _ => None,
+                };
+                OutputRenderElements::Shaded(ShadedRenderElement::new(
+                    element,
+                    program.clone(),
+                    texture_size,
+                ))
+            }
+            None => OutputRenderElements::Preview(element),
+        })
+        .collect()
+}
+
+/// Image of `LetterboxFill::Image`, kept with the output
+#[derive(Debug, Default)]
+struct LetterboxImage {
+    /// Path loaded, with the image and its size or `None` if it couldn't be loaded
+    loaded: Option<(String, Option<(MemoryRenderBuffer, Size<i32, Logical>)>)>,
+}
+
+/// Color of `LetterboxFill::Color`, kept with the window so damage tracking sees
+/// the same element every frame
+#[derive(Debug, Default)]
+struct LetterboxColor(RefCell<SolidColorBuffer>);
+
+/// Ids of the elements of the blurred copy of a window, by the ids of the
+/// elements of the window
+#[derive(Debug, Default)]
+struct LetterboxIds(RefCell<HashMap<Id, Id>>);
+
+fn letterbox_image(
+    output: &Output,
+    path: &str,
+) -> Option<(MemoryRenderBuffer, Size<i32, Logical>)> {
+    output
+        .user_data()
+        .insert_if_missing(|| RefCell::new(LetterboxImage::default()));
+    let mut image = output
+        .user_data()
+        .get::<RefCell<LetterboxImage>>()
+        .unwrap()
+        .borrow_mut();
+    if image.loaded.as_ref().map(|(loaded, _)| loaded.as_str()) != Some(path) {
+        let buffer = match image::open(path) {
+            Ok(image) => {
+                let size = Size::from((image.width() as i32, image.height() as i32));
+                let buffer = MemoryRenderBuffer::from_slice(
+                    &image.to_rgba8(),
+                    Fourcc::Abgr8888,
+                    (size.w, size.h),
+                    1,
+                    Transform::Normal,
+                    None,
+                );
+                Some((buffer, size))
+            }
+            Err(err) => {
+                warn!(path, ?err, "Unable to load letterbox image");
+                None
+            }
+        };
+        image.loaded = Some((path.to_owned(), buffer));
+    }
+    image.loaded.as_ref()?.1.clone()
+}
+
+/// The fill of the bars `window` leaves in `zone`, drawn below it
+#[allow(clippy::too_many_arguments)]
+fn letterbox_elements<R>(
+    renderer: &mut R,
+    output: &Output,
+    window: &Window,
+    zone: Rectangle<i32, Logical>,
+    scaling: &WindowScaling,
+    config: &Configuration,
+    alpha: f32,
+) -> Vec<OutputRenderElements<R, WindowRenderElement<R>>>
+where
+    R: ImportAll + ImportMem + GlesAccess,
+    R::TextureId: Send + Clone + 'static,
+{
+    let fill = config
+        .window_rule_for(window)
+        .and_then(|rule| rule.letterbox_fill)
+        .unwrap_or(config.letterbox.fill);
+    let (geometry, _) = window_screen_geometry(window, zone, scaling);
+    if geometry.contains_rect(zone) {
+        return Vec::new();
+    }
+    let location = zone.loc.to_physical_precise_round(1.0);
+    match fill {
+        LetterboxFill::Background => Vec::new(),
+        LetterboxFill::Color => {
+            let Some(color) = config.letterbox.color.as_deref().and_then(hex_color) else {
+                return Vec::new();
+            };
+            let user_data = window.user_data();
+            user_data.insert_if_missing(LetterboxColor::default);
+            let mut buffer = user_data.get::<LetterboxColor>().unwrap().0.borrow_mut();
+            buffer.update(zone.size, color);
+            let element = SolidColorRenderElement::from_buffer(
+                &buffer,
+                location,
+                1.0,
+                alpha,
+                Kind::Unspecified,
+            );
+            vec![OutputRenderElements::Custom(CustomRenderElements::Solid(
+                element,
+            ))]
+        }
+        LetterboxFill::Image => {
+            let Some((buffer, size)) = config
+                .letterbox
+                .image
+                .as_deref()
+                .and_then(|path| letterbox_image(output, path))
+            else {
+                return Vec::new();
+            };
+            // The part of the image covering the zone
+            let scale = f64::max(
+                zone.size.w as f64 / size.w as f64,
+                zone.size.h as f64 / size.h as f64,
+            );
+            let src_size = zone.size.to_f64().downscale(scale);
+            let src = Rectangle::from_loc_and_size(
+                (
+                    (size.w as f64 - src_size.w) / 2.0,
+                    (size.h as f64 - src_size.h) / 2.0,
+                ),
+                src_size,
+            );
+            match MemoryRenderBufferRenderElement::from_buffer(
+                renderer,
+                location.to_f64(),
+                &buffer,
+                Some(alpha),
+                Some(src),
+                Some(zone.size),
+                Kind::Unspecified,
+            ) {
+                Ok(element) => vec![OutputRenderElements::Custom(CustomRenderElements::Memory(
+                    element,
+                ))],
+                Err(err) => {
+                    warn!(?err, "Unable to upload letterbox image");
+                    Vec::new()
+                }
+            }
+        }
+        LetterboxFill::Blur => {
+            let Some(shader) = letterbox_blur_shader(renderer) else {
+                return Vec::new();
+            };
+            let stretch = WindowScaling {
+                mode: ScalingMode::Stretch,
+                ..WindowScaling::default()
+            };
+            let (constrain, location, scale_reference, behavior) =
+                get_window_scales(window.clone(), zone, &stretch);
+            let window_elements: Vec<ScaledWindowElement<R>> = render_window(
+                renderer,
+                window.clone(),
+                constrain,
+                location,
+                scale_reference,
+                behavior,
+                alpha,
+            )
+            .collect();
+            // The copy is drawn in the same frame as the window, under ids of its own
+            let user_data = window.user_data();
+            user_data.insert_if_missing(LetterboxIds::default);
+            let mut ids = user_data.get::<LetterboxIds>().unwrap().0.borrow_mut();
+            let previous = std::mem::take(&mut *ids);
+            window_elements
+                .into_iter()
+                .map(|element| {
+                    let id = previous.get(element.id()).cloned().unwrap_or_else(Id::new);
+                    ids.insert(element.id().clone(), id.clone());
+                    OutputRenderElements::Shaded(
+                        ShadedRenderElement::new(element, shader.clone(), None).with_id(id),
+                    )
+                })
+                .collect()
+        }
+    }
+}
+
+/// Where the pointer shows on `output`, and the scale it is drawn at. The pointer
+/// moves in the coordinates of the shown window, scaled into the usable area
+pub fn pointer_position(
+    output: &Output,
+    window: Option<&Window>,
+    pointer_location: Point<f64, Logical>,
+    config: &Configuration,
+) -> (Scale<f64>, Point<i32, Physical>) {
+    let (scale, offset) = if let Some(window) = window {
+        let layer_map = smithay::desktop::layer_map_for_output(output);
+        let zone = layer_map.non_exclusive_zone();
+        let scaling = window_scaling(window, config);
+        let (screen_geometry, mouse_scale) = window_screen_geometry(window, zone, &scaling);
+
+        // The pointer is in surface coordinates, which start outside of the geometry
+        let offset: Point<f64, Physical> =
+            (screen_geometry.loc - zone.loc).to_f64().to_physical(1.0)
+                - window_content_geometry(window)
+                    .loc
+                    .to_f64()
+                    .to_physical(mouse_scale);
+        (mouse_scale, offset)
+    } else {
+        let scale = Scale::from(output.current_scale().fractional_scale());
+        let offset: Point<f64, Physical> = Point::from((0 as f64, 0 as f64));
+        (scale, offset)
+    };
+
+    let cursor_pos_scaled = pointer_location.to_physical(scale);
+    (scale, (cursor_pos_scaled + offset).to_i32_round())
+}
+
+/// Color of a layer surface only showing a single-pixel buffer, like a fade to black
+/// or the background of a bar, and its size
+fn single_pixel_color(layer: &LayerSurface) -> Option<([f32; 4], Size<i32, Logical>)> {
+    let surface = layer.wl_surface();
+    if !get_children(surface).is_empty() {
+        return None;
+    }
+    with_states(surface, |states| {
+        let state = states
+            .data_map
+            .get::<RendererSurfaceStateUserData>()?
+            .lock()
+            .unwrap();
+        let pixel = get_single_pixel_buffer(state.buffer()?).ok()?;
+        // Single-pixel buffers are premultiplied already
+        let channel = |value: u32| (value as f64 / u32::MAX as f64) as f32;
+        let color = [
+            channel(pixel.r),
+            channel(pixel.g),
+            channel(pixel.b),
+            channel(pixel.a),
+        ];
+        Some((color, state.view()?.dst))
+    })
+}
+
+/// Single-pixel layer surfaces become solid colors instead of stretched textures
+fn layer_elements<R>(
+    renderer: &mut R,
+    layer: &LayerSurface,
+    location: Point<i32, Logical>,
+    output_scale: f64,
+) -> Vec<OutputRenderElements<R, WindowRenderElement<R>>>
+where
+    R: ImportAll + ImportMem + GlesAccess,
+    R::TextureId: Clone + 'static,
+{
+    let location = location.to_physical_precise_round(output_scale);
+    let scale = Scale::from(output_scale);
+    if let Some((color, size)) = single_pixel_color(layer) {
+        // Kept with the surface so damage tracking sees the same element every frame
+        let element = with_states(layer.wl_surface(), |states| {
+            states
+                .data_map
+                .insert_if_missing(|| RefCell::new(SolidColorBuffer::default()));
+            let mut buffer = states
+                .data_map
+                .get::<RefCell<SolidColorBuffer>>()
+                .unwrap()
+                .borrow_mut();
+            buffer.update(size, color);
+            SolidColorRenderElement::from_buffer(&buffer, location, scale, 1.0, Kind::Unspecified)
+        });
+        return vec![OutputRenderElements::Custom(CustomRenderElements::Solid(
+            element,
+        ))];
+    }
+    AsRenderElements::<R>::render_elements::<WaylandSurfaceRenderElement<R>>(
+        layer, renderer, location, scale, 1.0,
+    )
+    .into_iter()
+    .map(SpaceRenderElements::Surface)
+    .map(OutputRenderElements::Space)
+    .collect()
+}
+
+#[allow(clippy::too_many_arguments)]
 #[profiling::function]
 pub fn output_elements<R>(
     output: &Output,
     elements: &Vec<Window>,
+    layout: &WindowLayout,
+    transition: Option<&WindowTransition>,
+    config: &Configuration,
     custom_elements: impl IntoIterator<Item = CustomRenderElements<R>>,
     background_element: Option<CustomRenderElements<R>>,
     renderer: &mut R,
@@ -157,8 +690,8 @@ pub fn output_elements<R>(
     [f32; 4],
 )
 where
-    R: Renderer + ImportAll + ImportMem,
-    R::TextureId: Clone + 'static,
+    R: ImportAll + ImportMem + GlesAccess,
+    R::TextureId: Send + Clone + 'static,
 {
     let mut render_elements = vec![];
 
@@ -173,6 +706,24 @@ where
     let layer_map = smithay::desktop::layer_map_for_output(output);
     let non_exclusion_zone = layer_map.non_exclusive_zone();
 
+    // Picture-in-picture and windows asking for attention, above everything else
+    for inset in &layout.insets {
+        let (constrain, location, scale_reference, behavior) = get_window_scales(
+            inset.window.clone(),
+            inset_zone(non_exclusion_zone, inset.corner, inset.size),
+            &WindowScaling::default(),
+        );
+        render_elements.extend(render_window(
+            renderer,
+            inset.window.clone(),
+            constrain,
+            location,
+            scale_reference,
+            behavior,
+            1.0,
+        ));
+    }
+
     // Render Overlay and Top LayerShells
     let lower = {
         let (lower, upper): (Vec<&LayerSurface>, Vec<&LayerSurface>) = layer_map
@@ -188,19 +739,7 @@ where
                         .layer_geometry(surface)
                         .map(|geo| (geo.loc, surface))
                 })
-                .flat_map(|(loc, surface)| {
-                    AsRenderElements::<R>::render_elements::<WaylandSurfaceRenderElement<R>>(
-                        surface,
-                        renderer,
-                        loc.to_physical_precise_round(output_scale),
-                        Scale::from(output_scale),
-                        1.0,
-                    )
-                    .into_iter()
-                    .map(SpaceRenderElements::Surface)
-                    .into_iter()
-                    .map(OutputRenderElements::Space)
-                }),
+                .flat_map(|(loc, surface)| layer_elements(renderer, surface, loc, output_scale)),
         );
 
         lower
@@ -211,48 +750,122 @@ where
     // For wayland applications, this should only result in 0th
     // For X11 applications, this will result in popups first then the actual application
 
-    let mut popups = vec![];
-    let mut window = None;
-    for element in elements {
-        if element.is_wayland() {
-            window = Some(element.clone());
-            break;
-        } else {
-            match element.x11_surface() {
-                Some(x11surface) => {
-                    if x11surface.is_override_redirect() {
-                        popups.push(element.clone());
-                    } else {
-                        window = Some(element.clone());
-                        break;
-                    }
-                }
-                None => {}
-            }
+    // The shown window takes the first half of a split screen
+    let window_zone = match layout.split.as_ref() {
+        Some((second, orientation)) => {
+            let (first_zone, second_zone) = split_zones(non_exclusion_zone, *orientation);
+            let scaling = window_scaling(second, config);
+            let (constrain, location, scale_reference, behavior) =
+                get_window_scales(second.clone(), second_zone, &scaling);
+            let shader = scaling_shader(renderer, config, &scaling);
+            let window_elements = render_window(
+                renderer,
+                second.clone(),
+                constrain,
+                location,
+                scale_reference,
+                behavior,
+                1.0,
+            )
+            .collect();
+            render_elements.extend(shade_window(renderer, window_elements, shader));
+            render_elements.extend(letterbox_elements(
+                renderer,
+                output,
+                second,
+                second_zone,
+                &scaling,
+                config,
+                1.0,
+            ));
+            first_zone
         }
-    }
+        None => non_exclusion_zone,
+    };
+
+    let (window, popups) = shown_window(elements);
     if let Some(window) = window {
-        let (constrain, location, scale_reference, behavior) =
-            get_window_scales(window.clone(), non_exclusion_zone);
+        // The window that was active before stays visible while the new one comes in
+        let (alpha, slide_in, slide_out) = match transition {
+            Some(transition) => {
+                let progress = transition.progress();
+                let width = window_zone.size.w as f64;
+                match transition.style {
+                    // The new window pushes the old one out to the left, unless they
+                    // would cover the other half of a split screen
+                    TransitionStyle::Slide if layout.split.is_none() => (
+                        1.0,
+                        ((1.0 - progress) * width).round() as i32,
+                        (-progress * width).round() as i32,
+                    ),
+                    _ => (progress as f32, 0, 0),
+                }
+            }
+            None => (1.0, 0, 0),
+        };
+        let slide = Point::from((slide_in, 0));
+
+        let scaling = window_scaling(&window, config);
+        let (mut constrain, mut location, scale_reference, behavior) =
+            get_window_scales(window.clone(), window_zone, &scaling);
+        constrain.loc += slide;
+        location += slide;
 
+        // Popups may extend past the window, only keep them inside the usable area
         for popup in popups {
             render_elements.extend(render_window(
                 renderer,
                 popup,
-                constrain,
+                Rectangle::from_loc_and_size(window_zone.loc + slide, window_zone.size),
                 location,
                 scale_reference,
                 behavior,
+                alpha,
             ));
         }
-        render_elements.extend(render_window(
+        let letterbox = letterbox_elements(
+            renderer,
+            output,
+            &window,
+            Rectangle::from_loc_and_size(window_zone.loc + slide, window_zone.size),
+            &scaling,
+            config,
+            alpha,
+        );
+        let shader = scaling_shader(renderer, config, &scaling);
+        let window_elements = render_window(
             renderer,
             window,
             constrain,
             location,
             scale_reference,
             behavior,
-        ));
+            alpha,
+        )
+        .collect();
+        render_elements.extend(shade_window(renderer, window_elements, shader));
+        render_elements.extend(letterbox);
+
+        if let Some(transition) = transition {
+            let scaling = window_scaling(&transition.from, config);
+            let (mut constrain, mut location, scale_reference, behavior) =
+                get_window_scales(transition.from.clone(), window_zone, &scaling);
+            let slide = Point::from((slide_out, 0));
+            constrain.loc += slide;
+            location += slide;
+            let shader = scaling_shader(renderer, config, &scaling);
+            let window_elements = render_window(
+                renderer,
+                transition.from.clone(),
+                constrain,
+                location,
+                scale_reference,
+                behavior,
+                1.0,
+            )
+            .collect();
+            render_elements.extend(shade_window(renderer, window_elements, shader));
+        }
     }
 
     // Render Bottom and Background LayerShells
@@ -264,49 +877,62 @@ where
                     .layer_geometry(surface)
                     .map(|geo| (geo.loc, surface))
             })
-            .flat_map(|(loc, surface)| {
-                AsRenderElements::<R>::render_elements::<WaylandSurfaceRenderElement<R>>(
-                    surface,
-                    renderer,
-                    loc.to_physical_precise_round(output_scale),
-                    Scale::from(output_scale),
-                    1.0,
-                )
-                .into_iter()
-                .map(SpaceRenderElements::Surface)
-                .into_iter()
-                .map(OutputRenderElements::Space)
-            }),
+            .flat_map(|(loc, surface)| layer_elements(renderer, surface, loc, output_scale)),
     );
 
     if let Some(background_element) = background_element {
         render_elements.push(OutputRenderElements::from(background_element));
     }
 
-    (render_elements, CLEAR_COLOR)
+    (render_elements, config.background.clear_color())
 }
 
+/// Render the elements of `output` into `target`, through the color filter of
+/// the pass given if any
 #[allow(clippy::too_many_arguments)]
-pub fn render_output<'a, 'd, R>(
+pub fn render_output<'a, 'd, R, T>(
     output: &'a Output,
     elements: &Vec<Window>,
+    layout: &WindowLayout,
+    transition: Option<&WindowTransition>,
+    config: &Configuration,
     custom_elements: impl IntoIterator<Item = CustomRenderElements<R>>,
     background_element: Option<CustomRenderElements<R>>,
     renderer: &'a mut R,
+    target: T,
     damage_tracker: &'d mut OutputDamageTracker,
     age: usize,
+    zoom: Option<Zoom>,
+    color_filter: Option<(ColorFilter, &mut ColorFilterPass)>,
 ) -> Result<RenderOutputResult<'d>, OutputDamageTrackerError<R>>
 where
-    R: Renderer + ImportAll + ImportMem,
-    R::TextureId: Clone + 'static,
+    R: ImportAll + ImportMem + GlesAccess + Bind<T> + Bind<GlesTexture> + Offscreen<GlesTexture>,
+    R::TextureId: Send + Clone + 'static,
 {
     let (elements, clear_color) = output_elements(
         output,
         elements,
+        layout,
+        transition,
+        config,
         custom_elements,
         background_element,
         renderer,
     );
 
-    damage_tracker.render_output(renderer, age, &elements, clear_color)
+    let elements = zoom_elements(elements, zoom);
+    let filtered = color_filter
+        .and_then(|(filter, pass)| pass.render(renderer, output, &elements, clear_color, filter));
+    renderer
+        .bind(target)
+        .map_err(OutputDamageTrackerError::Rendering)?;
+    match filtered {
+        Some((element, states)) => damage_tracker
+            .render_output(renderer, age, &[element], clear_color)
+            .map(|mut result| {
+                result.states = states;
+                result
+            }),
+        None => damage_tracker.render_output(renderer, age, &elements, clear_color),
+    }
 }
@@ -0,0 +1,349 @@
+use std::{
+    env,
+    fs::{File, OpenOptions},
+    io::{self, BufWriter},
+    path::{Path, PathBuf},
+    thread,
+};
+
+use smithay::{
+    backend::renderer::{
+        element::{
+            solid::{SolidColorBuffer, SolidColorRenderElement},
+            Kind,
+        },
+        utils::RendererSurfaceStateUserData,
+    },
+    desktop::Window,
+    input::keyboard::Keysym,
+    output::Output,
+    reexports::calloop::channel::{channel, Event, Sender},
+    utils::{Physical, Point, Rectangle, Scale, Size},
+    wayland::compositor::with_states,
+};
+use tracing::{debug, info, warn};
+use xkbcommon::xkb;
+
+#[cfg(feature = "udev")]
+use crate::udev::UdevData;
+use crate::{
+    input_handler::KeyAction,
+    render::pointer_position,
+    shell::window_geometry,
+    state::{AnvilState, Backend},
+};
+
+/// Arrow keys move the corner of the region by this many pixels
+const NUDGE_STEP: i32 = 16;
+/// Premultiplied
+const REGION_COLOR: [f32; 4] = [0.1, 0.2, 0.4, 0.4];
+
+/// Picture of an output or window, RGBA
+pub(crate) struct Picture {
+    pub size: Size<i32, Physical>,
+    pub pixels: Vec<u8>,
+}
+
+/// What a screenshot shows
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ScreenshotKind {
+    /// The first output as composited
+    Output,
+    /// The shown window at the resolution it renders at, before scaling
+    Window,
+    /// A region of the first output, selected first
+    Region,
+}
+
+/// Region of a screenshot being selected, from where it started to the pointer
+#[derive(Debug)]
+struct RegionSelection {
+    anchor: Point<i32, Physical>,
+    /// Added to the pointer position by the arrow keys
+    nudge: Point<i32, Physical>,
+}
+
+#[derive(Debug)]
+pub struct ScreenshotState {
+    region: Option<RegionSelection>,
+    highlight: SolidColorBuffer,
+    /// Where the threads saving screenshots report back, set up by the first one
+    saved: Option<Sender<image::ImageResult<PathBuf>>>,
+}
+
+impl Default for ScreenshotState {
+    fn default() -> Self {
+        Self {
+            region: None,
+            highlight: SolidColorBuffer::new((0, 0), REGION_COLOR),
+            saved: None,
+        }
+    }
+}
+
+impl ScreenshotState {
+    /// The highlight of `region` if it is being selected on `output`
+    pub fn render_elements(
+        &mut self,
+        output: &Output,
+        region: Option<&(Output, Rectangle<i32, Physical>)>,
+    ) -> Vec<SolidColorRenderElement> {
+        let Some((_, region)) = region.filter(|(selected, _)| selected == output) else {
+            return Vec::new();
+        };
+        self.highlight.resize((region.size.w, region.size.h));
+        vec![SolidColorRenderElement::from_buffer(
+            &self.highlight,
+            region.loc,
+            Scale::from(1.0),
+            1.0,
+            Kind::Unspecified,
+        )]
+    }
+}
+
+/// Screenshots go to the configured directory, else the pictures directory
+pub(crate) fn screenshot_directory(configured: Option<&Path>) -> PathBuf {
+    configured
+        .map(Path::to_path_buf)
+        .or_else(|| env::var_os("XDG_PICTURES_DIR").map(PathBuf::from))
+        .or_else(|| env::var_os("HOME").map(|home| Path::new(&home).join("Pictures")))
+        .unwrap_or_else(env::temp_dir)
+}
+
+/// A new file named after the time, numbered if another one has the same name
+fn create_screenshot_file(directory: &Path) -> io::Result<(PathBuf, File)> {
+    let name = chrono::Local::now().format("Screenshot_%Y-%m-%d_%H-%M-%S%.3f");
+    for number in 0.. {
+        let path = match number {
+            0 => directory.join(format!("{name}.png")),
+            number => directory.join(format!("{name}_{number}.png")),
+        };
+        match OpenOptions::new().write(true).create_new(true).open(&path) {
+            Ok(file) => return Ok((path, file)),
+            Err(err) if err.kind() == io::ErrorKind::AlreadyExists => continue,
+            Err(err) => return Err(err),
+        }
+    }
+    unreachable!()
+}
+
+/// Encodes and writes the file, which takes long for whole outputs. Called off the
+/// event loop
+pub(crate) fn save_picture(directory: &Path, picture: Picture) -> image::ImageResult<PathBuf> {
+    std::fs::create_dir_all(directory)?;
+    let image =
+        image::RgbaImage::from_raw(picture.size.w as u32, picture.size.h as u32, picture.pixels)
+            .ok_or_else(|| {
+                image::ImageError::Parameter(image::error::ParameterError::from_kind(
+                    image::error::ParameterErrorKind::DimensionMismatch,
+                ))
+            })?;
+    let (path, file) = create_screenshot_file(directory)?;
+    let res = image.write_to(&mut BufWriter::new(file), image::ImageFormat::Png);
+    if let Err(err) = res {
+        let _ = std::fs::remove_file(&path);
+        return Err(err);
+    }
+    Ok(path)
+}
+
+/// Size of `window` in the pixels of its buffer, before it is scaled to the output
+fn native_window_size(window: &Window) -> Option<Size<i32, Physical>> {
+    let surface = window.wl_surface()?;
+    let factor = with_states(&surface, |states| {
+        let state = states
+            .data_map
+            .get::<RendererSurfaceStateUserData>()?
+            .lock()
+            .unwrap();
+        let view = state.view()?;
+        (view.dst.w > 0).then(|| view.src.size.w / view.dst.w as f64)
+    })?;
+    let size = window_geometry(window)
+        .size
+        .to_f64()
+        .to_physical(factor)
+        .to_i32_round();
+    (size.w > 0 && size.h > 0).then_some(size)
+}
+
+impl<BackendData: Backend> AnvilState<BackendData> {
+    /// Keys resizing, saving and cancelling the region while one is selected, or
+    /// the configured screenshot keys
+    pub fn screenshot_key_action(&self, keysym: Keysym) -> Option<KeyAction> {
+        if self.screenshot.region.is_some() {
+            let action = match keysym {
+                Keysym::Up | Keysym::KP_Up => Some(KeyAction::MoveScreenshotRegion(0, -1)),
+                Keysym::Down | Keysym::KP_Down => Some(KeyAction::MoveScreenshotRegion(0, 1)),
+                Keysym::Left | Keysym::KP_Left => Some(KeyAction::MoveScreenshotRegion(-1, 0)),
+                Keysym::Right | Keysym::KP_Right => Some(KeyAction::MoveScreenshotRegion(1, 0)),
+                Keysym::Return | Keysym::KP_Enter => {
+                    Some(KeyAction::Screenshot(ScreenshotKind::Region))
+                }
+                Keysym::Escape | Keysym::BackSpace => Some(KeyAction::CancelScreenshot),
+                _ => None,
+            };
+            if action.is_some() {
+                return action;
+            }
+        }
+        let config = &self.config.screenshot;
+        [
+            (&config.output_key, ScreenshotKind::Output),
+            (&config.window_key, ScreenshotKind::Window),
+            (&config.region_key, ScreenshotKind::Region),
+        ]
+        .into_iter()
+        .find_map(|(key, kind)| {
+            let key = xkb::keysym_from_name(key.as_ref()?, xkb::KEYSYM_CASE_INSENSITIVE);
+            (key.raw() == keysym.raw()).then_some(KeyAction::Screenshot(kind))
+        })
+    }
+
+    /// The first output and where the pointer shows on it
+    fn pointer_on_first_output(&self) -> Option<(Output, Point<i32, Physical>)> {
+        let output = self.outputs.first()?.clone();
+        let (window, _) = self.current_window();
+        let (_, position) = pointer_position(
+            &output,
+            window.as_ref(),
+            self.pointer.current_location(),
+            &self.config,
+        );
+        Some((output, position))
+    }
+
+    /// The region being selected and the output it is on
+    pub fn screenshot_region(&self) -> Option<(Output, Rectangle<i32, Physical>)> {
+        let selection = self.screenshot.region.as_ref()?;
+        let (output, position) = self.pointer_on_first_output()?;
+        let mode = output.current_mode()?;
+        let size = output.current_transform().transform_size(mode.size);
+        let corner = position + selection.nudge;
+        let corner = Point::from((corner.x.clamp(0, size.w), corner.y.clamp(0, size.h)));
+        let anchor = selection.anchor;
+        let loc = Point::from((anchor.x.min(corner.x), anchor.y.min(corner.y)));
+        let region = Rectangle::from_loc_and_size(
+            loc,
+            (
+                (anchor.x - corner.x).abs().max(1),
+                (anchor.y - corner.y).abs().max(1),
+            ),
+        );
+        Some((output, region))
+    }
+
+    pub fn move_screenshot_region(&mut self, dx: i32, dy: i32) {
+        if let Some(selection) = self.screenshot.region.as_mut() {
+            selection.nudge += Point::from((dx * NUDGE_STEP, dy * NUDGE_STEP));
+        }
+    }
+
+    pub fn cancel_screenshot_region(&mut self) {
+        if self.screenshot.region.take().is_some() {
+            debug!("Cancelled screenshot region");
+        }
+    }
+}
+
+#[cfg(feature = "udev")]
+impl AnvilState<UdevData> {
+    /// Save a screenshot of `kind`. The first of a region starts selecting it, the
+    /// next saves it
+    pub fn take_screenshot(&mut self, kind: ScreenshotKind) {
+        let picture = match kind {
+            ScreenshotKind::Output => self.output_picture(None),
+            ScreenshotKind::Window => self.window_picture(),
+            ScreenshotKind::Region => {
+                if self.screenshot.region.is_none() {
+                    self.start_screenshot_region();
+                    return;
+                }
+                let region = self.screenshot_region();
+                self.screenshot.region = None;
+                region.and_then(|(_, region)| self.output_picture(Some(region)))
+            }
+        };
+        self.save_screenshot(picture);
+    }
+
+    fn start_screenshot_region(&mut self) {
+        let Some((_, anchor)) = self.pointer_on_first_output() else {
+            return;
+        };
+        debug!(?anchor, "Selecting screenshot region");
+        self.screenshot.region = Some(RegionSelection {
+            anchor,
+            nudge: Point::default(),
+        });
+    }
+
+    /// Save `picture` on a thread, then tell where with a toast
+    fn save_screenshot(&mut self, picture: Option<Picture>) {
+        let Some(picture) = picture else {
+            warn!("Unable to take screenshot");
+            self.show_toast("Screenshot failed", "");
+            return;
+        };
+        let saved = match self.screenshot.saved.clone() {
+            Some(saved) => saved,
+            None => {
+                let (sender, results) = channel();
+                let res = self.handle.insert_source(results, |event, _, data| {
+                    if let Event::Msg(result) = event {
+                        data.screenshot_saved(result);
+                    }
+                });
+                if let Err(err) = res {
+                    warn!(?err, "Unable to insert screenshot source");
+                    return;
+                }
+                self.screenshot.saved.insert(sender).clone()
+            }
+        };
+        let directory = screenshot_directory(self.config.screenshot.directory.as_deref());
+        thread::spawn(move || {
+            let _ = saved.send(save_picture(&directory, picture));
+        });
+    }
+
+    fn screenshot_saved(&mut self, result: image::ImageResult<PathBuf>) {
+        match result {
+            Ok(path) => {
+                info!(path = %path.display(), "Saved screenshot");
+                self.show_toast("Screenshot saved", path.display().to_string());
+            }
+            Err(err) => {
+                warn!(?err, "Unable to save screenshot");
+                self.show_toast("Screenshot failed", err.to_string());
+            }
+        }
+    }
+
+    /// `region` of the first output, or all of it
+    fn output_picture(&mut self, region: Option<Rectangle<i32, Physical>>) -> Option<Picture> {
+        let region = match region {
+            Some(region) => region,
+            None => {
+                let mode = self.outputs.first()?.current_mode()?;
+                let transform = self.outputs.first()?.current_transform();
+                Rectangle::from_loc_and_size((0, 0), transform.transform_size(mode.size))
+            }
+        };
+        let pixels = self.read_output_region(region)?;
+        Some(Picture {
+            size: region.size,
+            pixels,
+        })
+    }
+
+    fn window_picture(&mut self) -> Option<Picture> {
+        let (Some(window), _) = self.current_window() else {
+            return None;
+        };
+        let size = native_window_size(&window)?;
+        let pixels = self.backend_data.sample_window(&window, size)?;
+        Some(Picture { size, pixels })
+    }
+}
@@ -0,0 +1,173 @@
+use std::{
+    process::{Child, Command},
+    time::{Duration, Instant},
+};
+
+use smithay::reexports::calloop::timer::{TimeoutAction, Timer};
+use tracing::{info, warn};
+
+use crate::state::{AnvilState, Backend, ServiceConfiguration};
+
+const POLL_INTERVAL: Duration = Duration::from_secs(1);
+const MIN_BACKOFF: Duration = Duration::from_secs(1);
+const MAX_BACKOFF: Duration = Duration::from_secs(60);
+/// A service running at least this long is considered healthy again
+const HEALTHY_RUNTIME: Duration = Duration::from_secs(60);
+
+#[derive(Debug)]
+struct Service {
+    config: ServiceConfiguration,
+    child: Option<Child>,
+    started: Instant,
+    backoff: Duration,
+    next_start: Instant,
+    restarts: u64,
+}
+
+impl Service {
+    fn new(config: ServiceConfiguration) -> Self {
+        Self {
+            config,
+            child: None,
+            started: Instant::now(),
+            backoff: MIN_BACKOFF,
+            next_start: Instant::now(),
+            restarts: 0,
+        }
+    }
+
+    fn start(&mut self, env: &[(&'static str, String)]) {
+        match Command::new(&self.config.command)
+            .args(&self.config.args)
+            .envs(env.iter().cloned())
+            .spawn()
+        {
+            Ok(child) => {
+                info!(name = self.config.name, pid = child.id(), "Started service");
+                self.child = Some(child);
+                self.started = Instant::now();
+            }
+            Err(err) => {
+                warn!(name = self.config.name, ?err, "Unable to start service");
+                self.schedule_restart();
+            }
+        }
+    }
+
+    fn schedule_restart(&mut self) {
+        if self.started.elapsed() >= HEALTHY_RUNTIME {
+            self.backoff = MIN_BACKOFF;
+        } else {
+            self.backoff = (self.backoff * 2).min(MAX_BACKOFF);
+        }
+        self.next_start = Instant::now() + self.backoff;
+        self.restarts += 1;
+    }
+
+    fn poll(&mut self, env: &[(&'static str, String)]) {
+        if let Some(child) = self.child.as_mut() {
+            match child.try_wait() {
+                Ok(None) => return,
+                Ok(Some(status)) => {
+                    warn!(name = self.config.name, %status, "Service exited");
+                }
+                Err(err) => {
+                    warn!(name = self.config.name, ?err, "Unable to check service");
+                }
+            }
+            self.child = None;
+            self.schedule_restart();
+        }
+        if Instant::now() >= self.next_start {
+            self.start(env);
+        }
+    }
+}
+
+impl Drop for Service {
+    fn drop(&mut self) {
+        if let Some(mut child) = self.child.take() {
+            info!(name = self.config.name, "Stopping service");
+            let _ = child.kill();
+            let _ = child.wait();
+        }
+    }
+}
+
+/// Keeps the services declared in the configuration running,
+/// restarting them with an increasing delay when they keep failing
+#[derive(Debug, Default)]
+pub struct ServiceSupervisor {
+    services: Vec<Service>,
+    /// Critical session applications, kept across configuration reloads
+    session: Vec<Service>,
+    polling: bool,
+}
+
+impl ServiceSupervisor {
+    /// Name, whether it is running, and how often it was restarted, for every service
+    pub fn status(&self) -> impl Iterator<Item = (&str, bool, u64)> {
+        self.services.iter().chain(&self.session).map(|service| {
+            (
+                service.config.name.as_str(),
+                service.child.is_some(),
+                service.restarts,
+            )
+        })
+    }
+}
+
+impl<BackendData: Backend> AnvilState<BackendData> {
+    /// Start services added to the configuration and stop removed or changed ones
+    pub fn sync_services(&mut self) {
+        let configs = self.config.services.clone();
+        let supervisor = &mut self.services;
+        supervisor
+            .services
+            .retain(|service| configs.contains(&service.config));
+        for config in configs {
+            if !supervisor
+                .services
+                .iter()
+                .any(|service| service.config == config)
+            {
+                supervisor.services.push(Service::new(config));
+            }
+        }
+
+        if !supervisor.services.is_empty() {
+            self.start_service_polling();
+        }
+    }
+
+    /// Keep a critical session application running until the compositor exits
+    pub fn supervise_session_application(&mut self, config: ServiceConfiguration) {
+        self.services.session.push(Service::new(config));
+        self.start_service_polling();
+    }
+
+    fn start_service_polling(&mut self) {
+        if !self.services.polling {
+            let res = self.handle.insert_source(Timer::immediate(), |_, _, data| {
+                data.poll_services();
+                TimeoutAction::ToDuration(POLL_INTERVAL)
+            });
+            match res {
+                Ok(_) => self.services.polling = true,
+                Err(err) => warn!(?err, "Unable to insert service timer"),
+            }
+        }
+    }
+
+    fn poll_services(&mut self) {
+        let env = self.client_environment();
+        let supervisor = &mut self.services;
+        for service in supervisor
+            .services
+            .iter_mut()
+            .chain(&mut supervisor.session)
+        {
+            service.poll(&env);
+        }
+    }
+}
@@ -0,0 +1,306 @@
+use std::{
+    cell::RefCell,
+    collections::HashMap,
+    path::{Path, PathBuf},
+    rc::Rc,
+    time::{Duration, Instant, SystemTime},
+};
+
+use smithay::{
+    backend::renderer::{
+        element::{Element, Id, Kind, RenderElement},
+        gles::{
+            GlesError, GlesFrame, GlesRenderer, GlesTexProgram, Uniform, UniformName, UniformType,
+            UniformValue,
+        },
+        utils::CommitCounter,
+        Renderer,
+    },
+    utils::{Buffer, Physical, Point, Rectangle, Scale, Size, Transform},
+};
+use tracing::{debug, warn};
+
+use crate::{
+    shell::scaling::WindowScaling,
+    state::{Configuration, Sampling},
+};
+
+static TEMPLATE: &str = include_str!("../resources/window_shader.frag");
+static NEAREST: &str = include_str!("../resources/nearest.frag");
+static LETTERBOX_BLUR: &str = include_str!("../resources/letterbox_blur.frag");
+
+/// Shader files are checked for changes at most this often
+const RELOAD_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Renderers drawing with GLES, which custom shaders need
+pub trait GlesAccess: Renderer {
+    fn gles_renderer(&mut self) -> &mut GlesRenderer;
+
+    fn gles_frame<'a, 'frame>(frame: &'a mut Self::Frame<'frame>) -> &'a mut GlesFrame<'frame>
+    where
+        Self: 'frame;
+
+    fn gles_error(err: GlesError) -> Self::Error;
+}
+
+impl GlesAccess for GlesRenderer {
+    fn gles_renderer(&mut self) -> &mut GlesRenderer {
+        self
+    }
+
+    fn gles_frame<'a, 'frame>(frame: &'a mut GlesFrame<'frame>) -> &'a mut GlesFrame<'frame>
+    where
+        Self: 'frame,
+    {
+        frame
+    }
+
+    fn gles_error(err: GlesError) -> GlesError {
+        err
+    }
+}
+
+/// Shader of a file, compiled for one GL context
+#[derive(Debug)]
+struct CachedShader {
+    /// `None` when it failed to compile
+    program: Option<GlesTexProgram>,
+    modified: Option<SystemTime>,
+    checked: Instant,
+}
+
+/// Shaders compiled for a GL context, kept in its user data
+#[derive(Debug, Default)]
+struct ShaderCache {
+    shaders: HashMap<PathBuf, CachedShader>,
+    /// Built-in shaders by name, compiled when first used
+    builtin: HashMap<&'static str, Option<GlesTexProgram>>,
+}
+
+fn shader_cache(renderer: &GlesRenderer) -> Rc<RefCell<ShaderCache>> {
+    let user_data = renderer.egl_context().user_data();
+    user_data.insert_if_missing(|| Rc::new(RefCell::new(ShaderCache::default())));
+    user_data.get::<Rc<RefCell<ShaderCache>>>().unwrap().clone()
+}
+
+fn modified(path: &Path) -> Option<SystemTime> {
+    std::fs::metadata(path)
+        .and_then(|meta| meta.modified())
+        .ok()
+}
+
+/// `shader` put into the window shader template
+fn compile_source(renderer: &mut GlesRenderer, shader: &str) -> Result<GlesTexProgram, GlesError> {
+    let source = TEMPLATE.replace("//_SHADER_", shader);
+    let uniforms = [
+        UniformName::new("source_size", UniformType::_2f),
+        UniformName::new("output_size", UniformType::_2f),
+        UniformName::new("texture_size", UniformType::_2f),
+    ];
+    renderer.compile_custom_texture_shader(source, &uniforms)
+}
+
+/// The shader of the file at `path`
+fn compile(renderer: &mut GlesRenderer, path: &Path) -> Option<GlesTexProgram> {
+    let shader = match std::fs::read_to_string(path) {
+        Ok(shader) => shader,
+        Err(err) => {
+            warn!(path = %path.display(), ?err, "Unable to read shader");
+            return None;
+        }
+    };
+    match compile_source(renderer, &shader) {
+        Ok(program) => {
+            debug!(path = %path.display(), "Compiled shader");
+            Some(program)
+        }
+        Err(err) => {
+            warn!(path = %path.display(), ?err, "Unable to compile shader");
+            None
+        }
+    }
+}
+
+/// The compiled shader of `preset`, read again when its file changed. `None`
+/// without a preset, or if it isn't configured or doesn't compile
+pub fn window_shader<R: GlesAccess>(
+    renderer: &mut R,
+    config: &Configuration,
+    preset: Option<&str>,
+) -> Option<GlesTexProgram> {
+    let path = PathBuf::from(config.shader_presets.get(preset?)?);
+    let renderer = renderer.gles_renderer();
+    let cache = shader_cache(renderer);
+    let mut cache = cache.borrow_mut();
+
+    if let Some(shader) = cache.shaders.get_mut(&path) {
+        if shader.checked.elapsed() < RELOAD_INTERVAL {
+            return shader.program.clone();
+        }
+        shader.checked = Instant::now();
+        let modified = modified(&path);
+        if modified == shader.modified {
+            return shader.program.clone();
+        }
+        shader.modified = modified;
+        shader.program = compile(renderer, &path);
+        return shader.program.clone();
+    }
+
+    let shader = CachedShader {
+        modified: modified(&path),
+        program: compile(renderer, &path),
+        checked: Instant::now(),
+    };
+    let program = shader.program.clone();
+    cache.shaders.insert(path, shader);
+    program
+}
+
+/// The shader drawing a window scaled by `scaling`: its shader preset, or the
+/// built-in one of nearest sampling. `None` to draw it with the default shader
+pub fn scaling_shader<R: GlesAccess>(
+    renderer: &mut R,
+    config: &Configuration,
+    scaling: &WindowScaling,
+) -> Option<GlesTexProgram> {
+    if let Some(program) = window_shader(renderer, config, scaling.shader_preset.as_deref()) {
+        return Some(program);
+    }
+    if scaling.sampling != Sampling::Nearest {
+        return None;
+    }
+    builtin_shader(renderer, "nearest", NEAREST)
+}
+
+/// The shader blurring windows into their letterbox bars
+pub fn letterbox_blur_shader<R: GlesAccess>(renderer: &mut R) -> Option<GlesTexProgram> {
+    builtin_shader(renderer, "letterbox_blur", LETTERBOX_BLUR)
+}
+
+fn builtin_shader<R: GlesAccess>(
+    renderer: &mut R,
+    name: &'static str,
+    shader: &str,
+) -> Option<GlesTexProgram> {
+    let renderer = renderer.gles_renderer();
+    let cache = shader_cache(renderer);
+    let mut cache = cache.borrow_mut();
+    cache
+        .builtin
+        .entry(name)
+        .or_insert_with(|| match compile_source(renderer, shader) {
+            Ok(program) => Some(program),
+            Err(err) => {
+                warn!(name, ?err, "Unable to compile built-in shader");
+                None
+            }
+        })
+        .clone()
+}
+
+/// Window content drawn through a user shader instead of the default one
+#[derive(Debug)]
+pub struct ShadedRenderElement<E> {
+    element: E,
+    /// Replaces the id of the element, for copies drawn along with it
+    id: Option<Id>,
+    program: GlesTexProgram,
+    /// Size of the buffer drawn, the size of the part drawn when unknown
+    texture_size: Option<Size<i32, Buffer>>,
+}
+
+impl<E> ShadedRenderElement<E> {
+    pub fn new(
+        element: E,
+        program: GlesTexProgram,
+        texture_size: Option<Size<i32, Buffer>>,
+    ) -> Self {
+        Self {
+            element,
+            id: None,
+            program,
+            texture_size,
+        }
+    }
+
+    pub fn with_id(self, id: Id) -> Self {
+        Self {
+            id: Some(id),
+            ..self
+        }
+    }
+}
+
+impl<E: Element> Element for ShadedRenderElement<E> {
+    fn id(&self) -> &Id {
+        self.id.as_ref().unwrap_or_else(|| self.element.id())
+    }
+
+    fn current_commit(&self) -> CommitCounter {
+        self.element.current_commit()
+    }
+
+    fn location(&self, scale: Scale<f64>) -> Point<i32, Physical> {
+        self.element.location(scale)
+    }
+
+    fn src(&self) -> Rectangle<f64, Buffer> {
+        self.element.src()
+    }
+
+    fn transform(&self) -> Transform {
+        self.element.transform()
+    }
+
+    fn geometry(&self, scale: Scale<f64>) -> Rectangle<i32, Physical> {
+        self.element.geometry(scale)
+    }
+
+    fn alpha(&self) -> f32 {
+        self.element.alpha()
+    }
+
+    fn kind(&self) -> Kind {
+        self.element.kind()
+    }
+}
+
+// No underlying storage, as scanning the buffer out directly would skip the shader
+impl<R, E> RenderElement<R> for ShadedRenderElement<E>
+where
+    R: GlesAccess,
+    E: RenderElement<R>,
+{
+    fn draw(
+        &self,
+        frame: &mut R::Frame<'_>,
+        src: Rectangle<f64, Buffer>,
+        dst: Rectangle<i32, Physical>,
+        damage: &[Rectangle<i32, Physical>],
+        opaque_regions: &[Rectangle<i32, Physical>],
+    ) -> Result<(), R::Error> {
+        let texture_size = self
+            .texture_size
+            .map(|size| size.to_f64())
+            .unwrap_or(src.size);
+        let uniforms = vec![
+            Uniform::new(
+                "source_size",
+                UniformValue::_2f(src.size.w as f32, src.size.h as f32),
+            ),
+            Uniform::new(
+                "output_size",
+                UniformValue::_2f(dst.size.w as f32, dst.size.h as f32),
+            ),
+            Uniform::new(
+                "texture_size",
+                UniformValue::_2f(texture_size.w as f32, texture_size.h as f32),
+            ),
+        ];
+        R::gles_frame(frame).override_default_tex_program(self.program.clone(), uniforms);
+        let result = self.element.draw(frame, src, dst, damage, opaque_regions);
+        R::gles_frame(frame).clear_tex_program_override();
+        result
+    }
+}
@@ -0,0 +1,141 @@
+use std::cell::Cell;
+
+use smithay::desktop::Window;
+use tracing::debug;
+
+use crate::{
+    shell::{is_denied, launch_groups::is_descendant, set_denied, window_app_id},
+    state::{AnvilState, Backend, KioskConfiguration, KioskOtherWindows, WindowAccess},
+    watchdog::AppWatchdog,
+};
+
+/// The `--app` process a window was checked against, and whether it descends from it
+#[derive(Debug, Default)]
+struct AppDescendant(Cell<Option<(u32, bool)>>);
+
+impl<BackendData: Backend> AnvilState<BackendData> {
+    pub fn kiosk_enabled(&self) -> bool {
+        self.config.kiosk.is_some()
+    }
+
+    /// Whether window rules or kiosk mode keep `window` from becoming active
+    pub fn window_blocked(&self, window: &Window) -> bool {
+        self.window_access(window)
+            .is_some_and(|access| access != WindowAccess::Normal)
+    }
+
+    /// How `window` may show, `None` while it is too early to tell
+    fn window_access(&self, window: &Window) -> Option<WindowAccess> {
+        let app_id = window_app_id(window);
+        let rule_access = self
+            .config
+            .window_rule_for(window)
+            .map(|rule| rule.access)
+            .unwrap_or_default();
+        if rule_access != WindowAccess::Normal {
+            return Some(rule_access);
+        }
+        let Some(config) = self.config.kiosk.as_ref() else {
+            return Some(WindowAccess::Normal);
+        };
+        let allowed = self.kiosk_allows(config, window, app_id.as_deref())?;
+        Some(match (allowed, config.other_windows) {
+            (true, _) => WindowAccess::Normal,
+            (false, KioskOtherWindows::Hide) => WindowAccess::Deny,
+            (false, KioskOtherWindows::Background) => WindowAccess::Background,
+        })
+    }
+
+    /// Whether kiosk mode lets `window` show, `None` while it is too early to tell
+    fn kiosk_allows(
+        &self,
+        config: &KioskConfiguration,
+        window: &Window,
+        app_id: Option<&str>,
+    ) -> Option<bool> {
+        if app_id.is_some_and(|id| config.app_ids.iter().any(|allowed| allowed == id)) {
+            return Some(true);
+        }
+        let app_pid = self.app_watchdog.as_ref().and_then(AppWatchdog::pid);
+        if let Some(app_pid) = app_pid.filter(|_| config.app_command) {
+            window.user_data().insert_if_missing(AppDescendant::default);
+            let cache = &window.user_data().get::<AppDescendant>().unwrap().0;
+            let descends = match cache.get() {
+                Some((pid, descends)) if pid == app_pid => descends,
+                _ => {
+                    let descends = self
+                        .window_pid(window)
+                        .is_some_and(|pid| is_descendant(pid, app_pid));
+                    cache.set(Some((app_pid, descends)));
+                    descends
+                }
+            };
+            if descends {
+                return Some(true);
+            }
+        }
+        // Clients set the app id after creating the toplevel, wait for the first buffer
+        (app_id.is_some() || !window.bbox().is_empty()).then_some(false)
+    }
+
+    /// Hide denied windows and keep background ones below the others. Denied
+    /// windows stay tracked, at the bottom, to show again once the rules allow
+    pub fn refresh_window_access(&mut self) {
+        if self.config.window_rules.is_empty()
+            && self.config.kiosk.is_none()
+            && !self.elements.iter().any(is_denied)
+        {
+            return;
+        }
+        let access: Vec<_> = self
+            .elements
+            .iter()
+            .map(|window| self.window_access(window))
+            .collect();
+        if self
+            .window_peek
+            .as_ref()
+            .is_some_and(|peek| self.window_blocked(&peek.window))
+        {
+            self.window_peek = None;
+        }
+        if self
+            .picture_in_picture
+            .as_ref()
+            .is_some_and(|pip| self.window_blocked(&pip.window))
+        {
+            self.picture_in_picture = None;
+        }
+        if self
+            .split_screen
+            .as_ref()
+            .is_some_and(|split| self.window_blocked(&split.window))
+        {
+            self.end_split_screen();
+        }
+
+        let mut elements = Vec::with_capacity(self.elements.len());
+        let mut background = Vec::new();
+        let mut denied = Vec::new();
+        let mut changed = false;
+        for (window, access) in self.elements.iter().zip(access) {
+            let deny = access == Some(WindowAccess::Deny);
+            if deny != is_denied(window) {
+                debug!(app_id = ?window_app_id(window), deny, "Changed window access");
+                set_denied(window, deny);
+                changed = true;
+            }
+            match access {
+                Some(WindowAccess::Deny) => denied.push(window.clone()),
+                Some(WindowAccess::Background) => background.push(window.clone()),
+                Some(WindowAccess::Normal) | None => elements.push(window.clone()),
+            }
+        }
+        elements.extend(background);
+        elements.extend(denied);
+        if changed || elements != self.elements {
+            self.elements = elements;
+            self.update_keyboard_focus();
+        }
+    }
+}
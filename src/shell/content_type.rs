@@ -0,0 +1,78 @@
+use smithay::{
+    desktop::Window,
+    reexports::wayland_protocols::wp::content_type::v1::server::wp_content_type_v1::Type as ContentType,
+    wayland::{compositor::with_states, content_type::ContentTypeSurfaceCachedState},
+};
+
+use super::tearing::TearingControlCachedState;
+
+/// How the output showing a window is driven, depending on the content of the window
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ContentPolicy {
+    pub vrr: bool,
+    pub direct_scanout: bool,
+    /// Follow `experimental_frame_divisor` of the window rules
+    pub pace_frames: bool,
+}
+
+impl From<ContentType> for ContentPolicy {
+    fn from(content_type: ContentType) -> Self {
+        match content_type {
+            // Games run at their own rate and want the lowest latency
+            ContentType::Game => Self {
+                vrr: true,
+                direct_scanout: true,
+                pace_frames: false,
+            },
+            // Videos have a fixed rate VRR can match
+            ContentType::Video => Self {
+                vrr: true,
+                direct_scanout: true,
+                pace_frames: true,
+            },
+            // Photos are composited, so they go through the same pipeline as the rest
+            ContentType::Photo => Self {
+                vrr: false,
+                direct_scanout: false,
+                pace_frames: true,
+            },
+            _ => Self {
+                vrr: false,
+                direct_scanout: true,
+                pace_frames: true,
+            },
+        }
+    }
+}
+
+/// The content type `window` declared. Windows asking for tearing count as games.
+fn window_content_type(window: &Window) -> ContentType {
+    let Some(toplevel) = window.toplevel() else {
+        return ContentType::None;
+    };
+    with_states(toplevel.wl_surface(), |states| {
+        if states
+            .cached_state
+            .get::<TearingControlCachedState>()
+            .current()
+            .allow_tearing
+        {
+            return ContentType::Game;
+        }
+        *states
+            .cached_state
+            .get::<ContentTypeSurfaceCachedState>()
+            .current()
+            .content_type()
+    })
+}
+
+pub fn window_content_policy(window: &Window) -> ContentPolicy {
+    window_content_type(window).into()
+}
+
+impl Default for ContentPolicy {
+    fn default() -> Self {
+        ContentType::None.into()
+    }
+}
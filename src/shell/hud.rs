@@ -0,0 +1,307 @@
+use std::os::unix::io::AsFd;
+
+use smithay::backend::allocator::{dmabuf::Dmabuf, Buffer};
+use smithay::output::Output;
+use smithay::reexports::wayland_protocols_wlr::foreign_toplevel::v1::server::zwlr_foreign_toplevel_handle_v1::ZwlrForeignToplevelHandleV1;
+use smithay::reexports::wayland_server::backend::ClientId;
+use smithay::reexports::wayland_server::{
+    Client, DataInit, Dispatch, DisplayHandle, GlobalDispatch, New, Resource,
+};
+use smithay::utils::{Logical, Rectangle};
+
+use crate::render::{inset_zone, window_screen_geometry};
+use crate::shell::{
+    peek::PEEK_SIZE,
+    scaling::{window_scaling, WindowScaling},
+    window_app_id,
+};
+use crate::state::{AnvilState, Backend};
+
+#[allow(non_upper_case_globals, non_camel_case_types, clippy::all)]
+pub mod protocol {
+    use smithay::reexports::wayland_protocols_wlr::foreign_toplevel::v1::server::*;
+    use smithay::reexports::wayland_server;
+
+    pub mod __interfaces {
+        use smithay::reexports::wayland_protocols_wlr::foreign_toplevel::v1::server::__interfaces::*;
+        use smithay::reexports::wayland_server::backend as wayland_backend;
+        wayland_scanner::generate_interfaces!("resources/protocols/consolation-hud-v1.xml");
+    }
+    use self::__interfaces::*;
+
+    wayland_scanner::generate_server_code!("resources/protocols/consolation-hud-v1.xml");
+}
+
+use protocol::consolation_hud_manager_v1::{self, ConsolationHudManagerV1};
+use protocol::consolation_toplevel_capture_v1::{self, ConsolationToplevelCaptureV1};
+
+const VERSION: u32 = 3;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct WindowPlacement {
+    geometry: Rectangle<i32, Logical>,
+    scale: f64,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+struct PeekPlacement {
+    geometry: Rectangle<i32, Logical>,
+    app_id: String,
+    switch_key: String,
+}
+
+#[derive(Debug)]
+pub struct HudManagerState {
+    instances: Vec<ConsolationHudManagerV1>,
+    placement: Option<WindowPlacement>,
+    peek: Option<PeekPlacement>,
+}
+
+pub trait HudHandler {
+    fn hud_manager_state(&mut self) -> &mut HudManagerState;
+    /// Copy the buffer of `toplevel` for `client`, if it may see it. Scaled down to fit
+    /// `max_size` for thumbnails
+    fn capture_toplevel(
+        &mut self,
+        client: &Client,
+        toplevel: &ZwlrForeignToplevelHandleV1,
+        max_size: Option<u32>,
+    ) -> Option<Dmabuf>;
+}
+
+pub struct HudGlobalData {
+    filter: Box<dyn for<'c> Fn(&'c Client) -> bool + Send + Sync>,
+}
+
+impl HudManagerState {
+    pub fn new<D, F>(display: &DisplayHandle, filter: F) -> Self
+    where
+        D: GlobalDispatch<ConsolationHudManagerV1, HudGlobalData>,
+        D: Dispatch<ConsolationHudManagerV1, ()>,
+        D: Dispatch<ConsolationToplevelCaptureV1, ()>,
+        D: 'static,
+        F: for<'c> Fn(&'c Client) -> bool + Send + Sync + 'static,
+    {
+        let global_data = HudGlobalData {
+            filter: Box::new(filter),
+        };
+        display.create_global::<D, ConsolationHudManagerV1, _>(VERSION, global_data);
+        Self {
+            instances: Vec::new(),
+            placement: None,
+            peek: None,
+        }
+    }
+}
+
+fn send_placement(instance: &ConsolationHudManagerV1, placement: Option<WindowPlacement>) {
+    match placement {
+        Some(placement) => instance.window_geometry(
+            placement.geometry.loc.x,
+            placement.geometry.loc.y,
+            placement.geometry.size.w,
+            placement.geometry.size.h,
+            placement.scale,
+        ),
+        None => instance.no_window(),
+    }
+}
+
+fn send_peek(instance: &ConsolationHudManagerV1, peek: Option<&PeekPlacement>) {
+    match peek {
+        Some(peek) => instance.attention_peek(
+            peek.geometry.loc.x,
+            peek.geometry.loc.y,
+            peek.geometry.size.w,
+            peek.geometry.size.h,
+            peek.app_id.clone(),
+            peek.switch_key.clone(),
+        ),
+        None => instance.attention_peek_done(),
+    }
+}
+
+fn send_capture(capture: &ConsolationToplevelCaptureV1, dmabuf: Option<&Dmabuf>) {
+    let Some(dmabuf) = dmabuf else {
+        capture.failed();
+        return;
+    };
+    let planes = dmabuf.handles().zip(dmabuf.offsets()).zip(dmabuf.strides());
+    for (index, ((fd, offset), stride)) in planes.enumerate() {
+        capture.plane(fd.as_fd(), index as u32, offset, stride);
+    }
+    let format = dmabuf.format();
+    let modifier = u64::from(format.modifier);
+    capture.ready(
+        dmabuf.width(),
+        dmabuf.height(),
+        format.code as u32,
+        (modifier >> 32) as u32,
+        modifier as u32,
+    );
+}
+
+/// Tell HUD clients if the active window moved, resized or changed scale
+pub fn refresh<D>(state: &mut AnvilState<D>)
+where
+    D: Backend + 'static,
+{
+    let placement = match (state.current_window(), state.outputs.first()) {
+        ((Some(window), _), Some(output)) => {
+            let zone = output_zone(output);
+            let scaling = window_scaling(&window, &state.config);
+            let (geometry, scale) = window_screen_geometry(&window, zone, &scaling);
+            Some(WindowPlacement {
+                geometry,
+                // Stretched windows are scaled less along one axis
+                scale: f64::min(scale.x, scale.y),
+            })
+        }
+        _ => None,
+    };
+    let peek = match (state.window_peek.as_ref(), state.outputs.first()) {
+        (Some(peek), Some(output)) => {
+            let zone = inset_zone(output_zone(output), state.peek_corner(), PEEK_SIZE);
+            let (geometry, _) =
+                window_screen_geometry(&peek.window, zone, &WindowScaling::default());
+            Some(PeekPlacement {
+                geometry,
+                app_id: window_app_id(&peek.window).unwrap_or_default(),
+                switch_key: state
+                    .config
+                    .attention_peek
+                    .as_ref()
+                    .map(|config| config.switch_key.clone())
+                    .unwrap_or_default(),
+            })
+        }
+        _ => None,
+    };
+
+    let protocol_state = &mut state.hud_manager_state;
+    if protocol_state.placement != placement {
+        protocol_state.placement = placement;
+        for instance in &protocol_state.instances {
+            send_placement(instance, placement);
+        }
+    }
+    if protocol_state.peek != peek {
+        for instance in &protocol_state.instances {
+            send_peek(instance, peek.as_ref());
+        }
+        protocol_state.peek = peek;
+    }
+}
+
+fn output_zone(output: &Output) -> Rectangle<i32, Logical> {
+    smithay::desktop::layer_map_for_output(output).non_exclusive_zone()
+}
+
+impl<D> GlobalDispatch<ConsolationHudManagerV1, HudGlobalData, D> for HudManagerState
+where
+    D: GlobalDispatch<ConsolationHudManagerV1, HudGlobalData>,
+    D: Dispatch<ConsolationHudManagerV1, ()>,
+    D: Dispatch<ConsolationToplevelCaptureV1, ()>,
+    D: HudHandler,
+{
+    fn bind(
+        state: &mut D,
+        _handle: &DisplayHandle,
+        _client: &Client,
+        resource: New<ConsolationHudManagerV1>,
+        _global_data: &HudGlobalData,
+        data_init: &mut DataInit<'_, D>,
+    ) {
+        let manager = data_init.init(resource, ());
+
+        let state = state.hud_manager_state();
+        send_placement(&manager, state.placement);
+        if state.peek.is_some() {
+            send_peek(&manager, state.peek.as_ref());
+        }
+        state.instances.push(manager);
+    }
+
+    fn can_view(client: Client, global_data: &HudGlobalData) -> bool {
+        (global_data.filter)(&client)
+    }
+}
+
+impl<D> Dispatch<ConsolationHudManagerV1, (), D> for HudManagerState
+where
+    D: Dispatch<ConsolationHudManagerV1, ()>,
+    D: Dispatch<ConsolationToplevelCaptureV1, ()>,
+    D: HudHandler,
+{
+    fn request(
+        state: &mut D,
+        client: &Client,
+        resource: &ConsolationHudManagerV1,
+        request: <ConsolationHudManagerV1 as Resource>::Request,
+        _data: &(),
+        _dhandle: &DisplayHandle,
+        data_init: &mut DataInit<'_, D>,
+    ) {
+        match request {
+            consolation_hud_manager_v1::Request::CaptureToplevel { capture, toplevel } => {
+                let capture = data_init.init(capture, ());
+                let dmabuf = state.capture_toplevel(client, &toplevel, None);
+                send_capture(&capture, dmabuf.as_ref());
+            }
+            consolation_hud_manager_v1::Request::CaptureThumbnail {
+                capture,
+                toplevel,
+                max_size,
+            } => {
+                let capture = data_init.init(capture, ());
+                let dmabuf = state.capture_toplevel(client, &toplevel, Some(max_size));
+                send_capture(&capture, dmabuf.as_ref());
+            }
+            consolation_hud_manager_v1::Request::Destroy => {
+                let state = state.hud_manager_state();
+                state.instances.retain(|x| x != resource);
+            }
+            _ => unreachable!(),
+        }
+    }
+
+    fn destroyed(state: &mut D, _client: ClientId, resource: &ConsolationHudManagerV1, _data: &()) {
+        let state = state.hud_manager_state();
+        state.instances.retain(|x| x != resource);
+    }
+}
+
+impl<D> Dispatch<ConsolationToplevelCaptureV1, (), D> for HudManagerState
+where
+    D: Dispatch<ConsolationToplevelCaptureV1, ()>,
+{
+    fn request(
+        _state: &mut D,
+        _client: &Client,
+        _resource: &ConsolationToplevelCaptureV1,
+        request: <ConsolationToplevelCaptureV1 as Resource>::Request,
+        _data: &(),
+        _dhandle: &DisplayHandle,
+        _data_init: &mut DataInit<'_, D>,
+    ) {
+        match request {
+            consolation_toplevel_capture_v1::Request::Destroy => {}
+            _ => unreachable!(),
+        }
+    }
+}
+
+#[macro_export]
+macro_rules! delegate_hud {
+    ($(@<$( $lt:tt $( : $clt:tt $(+ $dlt:tt )* )? ),+>)? $ty: ty) => {
+        smithay::reexports::wayland_server::delegate_global_dispatch!($(@< $( $lt $( : $clt $(+ $dlt )* )? ),+ >)? $ty: [
+            $crate::shell::hud::protocol::consolation_hud_manager_v1::ConsolationHudManagerV1: $crate::shell::hud::HudGlobalData
+        ] => $crate::shell::hud::HudManagerState);
+        smithay::reexports::wayland_server::delegate_dispatch!($(@< $( $lt $( : $clt $(+ $dlt )* )? ),+ >)? $ty: [
+            $crate::shell::hud::protocol::consolation_hud_manager_v1::ConsolationHudManagerV1: ()
+        ] => $crate::shell::hud::HudManagerState);
+        smithay::reexports::wayland_server::delegate_dispatch!($(@< $( $lt $( : $clt $(+ $dlt )* )? ),+ >)? $ty: [
+            $crate::shell::hud::protocol::consolation_toplevel_capture_v1::ConsolationToplevelCaptureV1: ()
+        ] => $crate::shell::hud::HudManagerState);
+    };
+}
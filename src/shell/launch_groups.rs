@@ -0,0 +1,127 @@
+use std::cell::Cell;
+
+use smithay::{desktop::Window, reexports::wayland_server::Resource, wayland::seat::WaylandFocus};
+
+use crate::state::{client_credentials, AnvilState, Backend};
+
+/// Process chains longer than this are not followed
+const MAX_DEPTH: usize = 64;
+
+/// Commands started by the compositor, whose windows and the windows of all
+/// their descendant processes form one launch group.
+/// A launcher and the game it spawns show up as a single window in window lists.
+#[derive(Debug, Default)]
+pub struct LaunchedCommands {
+    pids: Vec<u32>,
+    next_order: Cell<u64>,
+}
+
+impl LaunchedCommands {
+    pub fn insert(&mut self, pid: u32) {
+        self.reap();
+        self.pids.push(pid);
+    }
+
+    /// Forget commands that exited, their pid might be reused
+    pub fn reap(&mut self) {
+        self.pids
+            .retain(|pid| std::path::Path::new(&format!("/proc/{pid}")).exists());
+    }
+
+    /// Commands still tracked
+    pub fn count(&self) -> usize {
+        self.pids.len()
+    }
+
+    /// The launched command `pid` descends from
+    fn root_of(&self, mut pid: u32) -> Option<u32> {
+        for _ in 0..MAX_DEPTH {
+            if self.pids.contains(&pid) {
+                return Some(pid);
+            }
+            pid = parent_pid(pid).filter(|parent| *parent > 1)?;
+        }
+        None
+    }
+}
+
+/// Launch group of a window, computed once it is first listed
+#[derive(Debug)]
+struct LaunchGroup {
+    root: Option<u32>,
+    /// Windows listed earlier stand for their group
+    order: u64,
+}
+
+fn parent_pid(pid: u32) -> Option<u32> {
+    let stat = std::fs::read_to_string(format!("/proc/{pid}/stat")).ok()?;
+    // The command name in parentheses may contain spaces
+    let rest = &stat[stat.rfind(')')? + 1..];
+    rest.split_whitespace().nth(1)?.parse().ok()
+}
+
+/// Whether `pid` is `ancestor` or one of its descendants
+pub fn is_descendant(mut pid: u32, ancestor: u32) -> bool {
+    for _ in 0..MAX_DEPTH {
+        if pid == ancestor {
+            return true;
+        }
+        match parent_pid(pid) {
+            Some(parent) if parent > 1 => pid = parent,
+            _ => return false,
+        }
+    }
+    false
+}
+
+impl<BackendData: Backend> AnvilState<BackendData> {
+    pub fn window_pid(&self, window: &Window) -> Option<u32> {
+        #[cfg(feature = "xwayland")]
+        if let Some(x11surface) = window.x11_surface() {
+            return x11surface.pid();
+        }
+        let client = window.wl_surface()?.client()?;
+        let credentials = client_credentials(&client, &self.display_handle)?;
+        u32::try_from(credentials.pid).ok()
+    }
+
+    fn launch_group<'a>(&self, window: &'a Window) -> &'a LaunchGroup {
+        window.user_data().insert_if_missing(|| {
+            let order = self.launched.next_order.get();
+            self.launched.next_order.set(order + 1);
+            LaunchGroup {
+                root: self
+                    .window_pid(window)
+                    .and_then(|pid| self.launched.root_of(pid)),
+                order,
+            }
+        });
+        window.user_data().get::<LaunchGroup>().unwrap()
+    }
+
+    /// The oldest window of the launch group of `window`, listed for the whole group
+    pub fn launch_group_leader(&self, window: &Window) -> Window {
+        let group = self.launch_group(window);
+        let Some(root) = group.root else {
+            return window.clone();
+        };
+        self.elements
+            .iter()
+            .filter(|other| self.launch_group(other).root == Some(root))
+            .min_by_key(|other| self.launch_group(other).order)
+            .cloned()
+            .unwrap_or_else(|| window.clone())
+    }
+
+    /// The window of the launch group of `window` that was active most recently
+    pub fn launch_group_window(&self, window: &Window) -> Window {
+        let Some(root) = self.launch_group(window).root else {
+            return window.clone();
+        };
+        self.elements
+            .iter()
+            .find(|other| self.launch_group(other).root == Some(root))
+            .cloned()
+            .unwrap_or_else(|| window.clone())
+    }
+}
@@ -0,0 +1,156 @@
+use std::{cell::RefCell, time::Duration};
+
+use smithay::{
+    desktop::Window,
+    reexports::calloop::timer::{TimeoutAction, Timer},
+    utils::{Logical, Physical, Rectangle, Size},
+};
+use tracing::{debug, warn};
+
+use crate::{
+    shell::window_geometry,
+    state::{AnvilState, Backend},
+};
+
+/// Windows are sampled at this size, bars are found in steps of one sample pixel
+const SAMPLE_SIZE: (i32, i32) = (160, 120);
+const SAMPLE_INTERVAL: Duration = Duration::from_millis(500);
+/// Brightest channel value still counting as black
+const BLACK_THRESHOLD: u8 = 24;
+/// A row or column with less than 1/50 of its pixels lit is part of a bar
+const LIT_FRACTION: i32 = 50;
+/// Samples in a row that have to agree before the zoom changes
+const STABLE_SAMPLES: u32 = 4;
+/// Bars thinner than this are left alone
+const MIN_BAR: i32 = 3;
+/// Baked-in bars are symmetric, dark scenes rarely are
+const MAX_ASYMMETRY: i32 = 2;
+
+/// Content area found in the frames of a window, in sample pixels
+#[derive(Debug, Default)]
+struct Letterbox {
+    applied: Option<Rectangle<i32, Physical>>,
+    candidate: Option<Rectangle<i32, Physical>>,
+    count: u32,
+}
+
+#[derive(Debug, Default)]
+struct LetterboxState(RefCell<Letterbox>);
+
+/// The geometry of `window` without the black bars detected in its frames
+pub fn window_content_geometry(window: &Window) -> Rectangle<i32, Logical> {
+    let geometry = window_geometry(window);
+    let Some(content) = window
+        .user_data()
+        .get::<LetterboxState>()
+        .and_then(|state| state.0.borrow().applied)
+    else {
+        return geometry;
+    };
+    let x = |value: i32| geometry.size.w * value / SAMPLE_SIZE.0;
+    let y = |value: i32| geometry.size.h * value / SAMPLE_SIZE.1;
+    Rectangle::from_loc_and_size(
+        (
+            geometry.loc.x + x(content.loc.x),
+            geometry.loc.y + y(content.loc.y),
+        ),
+        (x(content.size.w), y(content.size.h)),
+    )
+}
+
+/// Bars on both sides of an axis, or none if they don't look baked in
+fn bars(start: i32, end: i32) -> (i32, i32) {
+    if start.min(end) < MIN_BAR || (start - end).abs() > MAX_ASYMMETRY {
+        (0, 0)
+    } else {
+        (start, end)
+    }
+}
+
+/// The area of a sample that isn't black bars, if anything is lit at all
+fn detect_content(pixels: &[u8]) -> Option<Rectangle<i32, Physical>> {
+    let (width, height) = SAMPLE_SIZE;
+    if pixels.len() < (width * height * 4) as usize {
+        return None;
+    }
+    let lit = |x: i32, y: i32| {
+        let index = ((y * width + x) * 4) as usize;
+        pixels[index..index + 3]
+            .iter()
+            .any(|channel| *channel > BLACK_THRESHOLD)
+    };
+    let row_lit =
+        |y: &i32| (0..width).filter(|x| lit(*x, *y)).count() as i32 * LIT_FRACTION > width;
+    let column_lit =
+        |x: &i32| (0..height).filter(|y| lit(*x, *y)).count() as i32 * LIT_FRACTION > height;
+
+    let (top, bottom) = bars(
+        (0..height).find(row_lit)?,
+        height - 1 - (0..height).rev().find(row_lit)?,
+    );
+    let (left, right) = bars(
+        (0..width).find(column_lit)?,
+        width - 1 - (0..width).rev().find(column_lit)?,
+    );
+    Some(Rectangle::from_loc_and_size(
+        (left, top),
+        (width - left - right, height - top - bottom),
+    ))
+}
+
+impl<BackendData: Backend + 'static> AnvilState<BackendData> {
+    /// Periodically look for black bars in the active window
+    pub fn start_letterbox_detection(&mut self) {
+        let res = self
+            .handle
+            .insert_source(Timer::from_duration(SAMPLE_INTERVAL), |_, _, data| {
+                data.refresh_letterbox();
+                TimeoutAction::ToDuration(SAMPLE_INTERVAL)
+            });
+        if let Err(err) = res {
+            warn!(?err, "Unable to insert letterbox timer");
+        }
+    }
+
+    fn refresh_letterbox(&mut self) {
+        let (Some(window), _) = self.current_window() else {
+            return;
+        };
+        window
+            .user_data()
+            .insert_if_missing(LetterboxState::default);
+        let state = window.user_data().get::<LetterboxState>().unwrap();
+
+        let enabled = self
+            .config
+            .window_rule_for(&window)
+            .is_some_and(|rule| rule.detect_letterbox);
+        if !enabled {
+            *state.0.borrow_mut() = Letterbox::default();
+            return;
+        }
+
+        let size = Size::from(SAMPLE_SIZE);
+        let Some(content) = self
+            .backend_data
+            .sample_window(&window, size)
+            .as_deref()
+            .and_then(detect_content)
+        else {
+            return;
+        };
+
+        let mut letterbox = state.0.borrow_mut();
+        if letterbox.candidate == Some(content) {
+            letterbox.count += 1;
+        } else {
+            letterbox.candidate = Some(content);
+            letterbox.count = 1;
+        }
+        let applied = (content.size != size).then_some(content);
+        if letterbox.count >= STABLE_SAMPLES && letterbox.applied != applied {
+            debug!(?applied, "Letterbox changed");
+            letterbox.applied = applied;
+        }
+    }
+}
@@ -1,7 +1,7 @@
-use std::cell::RefCell;
+use std::cell::{Cell, RefCell};
 
 #[cfg(feature = "xwayland")]
-use smithay::xwayland::XWaylandClientData;
+use smithay::{wayland::viewporter::ViewportCachedState, xwayland::XWaylandClientData};
 
 #[cfg(feature = "udev")]
 use smithay::wayland::drm_syncobj::DrmSyncobjCachedState;
@@ -39,24 +39,67 @@ use smithay::{
     },
 };
 
+use tracing::warn;
+
 use crate::{
     state::{AnvilState, Backend},
     ClientState,
 };
 
+pub(crate) mod access;
+pub(crate) mod content_type;
 mod element;
+pub(crate) mod hud;
+pub(crate) mod launch_groups;
+pub(crate) mod letterbox;
+pub(crate) mod peek;
 pub(crate) mod output_manager;
+pub(crate) mod placement;
+pub(crate) mod scaling;
+pub(crate) mod tearing;
+#[cfg(test)]
+mod testing;
 pub(crate) mod toplevel_manager;
+pub(crate) mod transition;
+pub(crate) mod virtual_pointer;
 #[cfg(feature = "xwayland")]
 mod x11;
 pub mod xdg;
 
 pub use self::element::*;
+#[cfg(feature = "xwayland")]
+pub use self::x11::is_x11_popup;
+
+/// Why a shell request could not be fulfilled. The window or its client may be gone
+/// by the time a request is handled
+#[derive(Debug, thiserror::Error)]
+pub enum ShellError {
+    #[error("No window for the surface")]
+    UnknownWindow,
+    #[error("The client of the surface is gone")]
+    UnknownClient,
+    #[error("No output")]
+    NoOutput,
+    #[error("The output has no mode")]
+    NoMode,
+    #[error("Failed to map layer surface: {0}")]
+    Layer(#[from] smithay::desktop::LayerError),
+    #[cfg(feature = "xwayland")]
+    #[error("Failed to talk to the X11 window: {0}")]
+    X11(#[from] x11rb::errors::ConnectionError),
+}
 
-fn fullscreen_output_geometry(outputs: &Vec<Output>) -> Option<Rectangle<i32, Logical>> {
-    let output = outputs.last().expect("No output while fullscreening");
-    let geometry = output.current_mode().unwrap();
-    Some(Rectangle {
+/// Size of the output fullscreen windows cover, the docked one while docked and
+/// else the last one connected
+fn fullscreen_output_geometry<BackendData: Backend>(
+    state: &AnvilState<BackendData>,
+) -> Result<Rectangle<i32, Logical>, ShellError> {
+    let output = state
+        .docked_output()
+        .or_else(|| state.outputs.last())
+        .ok_or(ShellError::NoOutput)?;
+    let geometry = output.current_mode().ok_or(ShellError::NoMode)?;
+    Ok(Rectangle {
         loc: Default::default(),
         size: geometry.size.to_logical(1),
     })
@@ -175,6 +218,22 @@ impl<BackendData: Backend> CompositorHandler for AnvilState<BackendData> {
         }
         self.popups.commit(surface);
 
+        if let Some(dnd_icon) = self.dnd_icon.as_mut() {
+            if &dnd_icon.surface == surface {
+                let buffer_delta = with_states(surface, |states| {
+                    states
+                        .cached_state
+                        .get::<SurfaceAttributes>()
+                        .current()
+                        .buffer_delta
+                        .take()
+                });
+                if let Some(buffer_delta) = buffer_delta {
+                    dnd_icon.offset += buffer_delta;
+                }
+            }
+        }
+
         ensure_initial_configure(surface, &self.elements, &self.outputs, &mut self.popups)
     }
 }
@@ -191,13 +250,10 @@ impl<BackendData: Backend> WlrLayerShellHandler for AnvilState<BackendData> {
         _layer: Layer,
         namespace: String,
     ) {
-        let output = wl_output
-            .as_ref()
-            .and_then(Output::from_resource)
-            .unwrap_or_else(|| self.outputs.iter().next().unwrap().clone());
-        let mut map = layer_map_for_output(&output);
-        map.map_layer(&LayerSurface::new(surface, namespace))
-            .unwrap();
+        if let Err(err) = self.map_layer_surface(surface.clone(), wl_output, namespace) {
+            warn!(?err, "Unable to map layer surface");
+            surface.send_close();
+        }
     }
 
     fn layer_destroyed(&mut self, surface: WlrLayerSurface) {
@@ -215,6 +271,22 @@ impl<BackendData: Backend> WlrLayerShellHandler for AnvilState<BackendData> {
 }
 
 impl<BackendData: Backend> AnvilState<BackendData> {
+    fn map_layer_surface(
+        &mut self,
+        surface: WlrLayerSurface,
+        wl_output: Option<wl_output::WlOutput>,
+        namespace: String,
+    ) -> Result<(), ShellError> {
+        let output = wl_output
+            .as_ref()
+            .and_then(Output::from_resource)
+            .or_else(|| self.outputs.first().cloned())
+            .ok_or(ShellError::NoOutput)?;
+        let mut map = layer_map_for_output(&output);
+        map.map_layer(&LayerSurface::new(surface, namespace))?;
+        Ok(())
+    }
+
     pub fn window_for_surface(&self, surface: &WlSurface) -> Option<Window> {
         self.elements
             .iter()
@@ -223,6 +295,169 @@ impl<BackendData: Backend> AnvilState<BackendData> {
     }
 }
 
+/// The app_id of a wayland toplevel, or the WM_CLASS of an X11 window
+pub fn window_app_id(window: &Window) -> Option<String> {
+    #[cfg(feature = "xwayland")]
+    if let Some(x11surface) = window.x11_surface() {
+        return Some(x11surface.class());
+    }
+    let toplevel = window.toplevel()?;
+    with_states(toplevel.wl_surface(), |states| {
+        states
+            .data_map
+            .get::<XdgToplevelSurfaceData>()?
+            .lock()
+            .unwrap()
+            .app_id
+            .clone()
+    })
+}
+
+pub fn window_title(window: &Window) -> Option<String> {
+    #[cfg(feature = "xwayland")]
+    if let Some(x11surface) = window.x11_surface() {
+        return Some(x11surface.title());
+    }
+    let toplevel = window.toplevel()?;
+    with_states(toplevel.wl_surface(), |states| {
+        states
+            .data_map
+            .get::<XdgToplevelSurfaceData>()?
+            .lock()
+            .unwrap()
+            .title
+            .clone()
+    })
+}
+
+/// The title, else the app id of windows without one
+pub fn window_name(window: &Window) -> String {
+    window_title(window)
+        .filter(|title| !title.trim().is_empty())
+        .or_else(|| window_app_id(window))
+        .unwrap_or_default()
+}
+
+/// The geometry of `window` on screen. X11 games that changed the resolution
+/// through RandR keep that size in X11, while Xwayland stretches their surface to
+/// the output with a viewport
+pub fn window_geometry(window: &Window) -> Rectangle<i32, Logical> {
+    let geometry = window.geometry();
+    #[cfg(feature = "xwayland")]
+    if window.x11_surface().is_some() {
+        let destination = window.wl_surface().and_then(|surface| {
+            with_states(&surface, |states| {
+                states
+                    .cached_state
+                    .get::<ViewportCachedState>()
+                    .current()
+                    .dst
+            })
+        });
+        if let Some(size) = destination {
+            return Rectangle::from_loc_and_size(geometry.loc, size);
+        }
+    }
+    geometry
+}
+
+/// Set on windows minimized through foreign-toplevel or `AnvilState::minimize_window`
+#[derive(Debug, Default)]
+struct MinimizedState(Cell<bool>);
+
+/// Minimized windows are left out of the shown window and window switching, and
+/// get no frame callbacks until raised again
+pub fn is_minimized(window: &Window) -> bool {
+    window
+        .user_data()
+        .get::<MinimizedState>()
+        .is_some_and(|state| state.0.get())
+}
+
+pub fn set_minimized(window: &Window, minimized: bool) {
+    window
+        .user_data()
+        .insert_if_missing(MinimizedState::default);
+    window
+        .user_data()
+        .get::<MinimizedState>()
+        .unwrap()
+        .0
+        .set(minimized);
+}
+
+/// Set on windows window rules or kiosk mode deny, which stay mapped in case the
+/// rules change
+#[derive(Debug, Default)]
+struct DeniedState(Cell<bool>);
+
+pub fn is_denied(window: &Window) -> bool {
+    window
+        .user_data()
+        .get::<DeniedState>()
+        .is_some_and(|state| state.0.get())
+}
+
+pub fn set_denied(window: &Window, denied: bool) {
+    window.user_data().insert_if_missing(DeniedState::default);
+    window
+        .user_data()
+        .get::<DeniedState>()
+        .unwrap()
+        .0
+        .set(denied);
+}
+
+/// Minimized or denied windows aren't shown, focused or switched to
+pub fn is_hidden(window: &Window) -> bool {
+    is_minimized(window) || is_denied(window)
+}
+
+/// Whether `window` shows over the main window, instead of being scaled to the
+/// output itself
+pub fn is_popup(window: &Window) -> bool {
+    #[cfg(feature = "xwayland")]
+    if let Some(x11surface) = window.x11_surface() {
+        return is_x11_popup(&x11surface);
+    }
+    false
+}
+
+/// The window scaled to the output and the X11 popups over it, topmost first.
+/// Without a main window, the topmost managed popup like a standalone dialog is
+/// shown as one
+pub fn shown_window(elements: &[Window]) -> (Option<Window>, Vec<Window>) {
+    let mut popups = vec![];
+    for element in elements.iter().filter(|element| !is_hidden(element)) {
+        if !is_popup(element) {
+            return (Some(element.clone()), popups);
+        }
+        popups.push(element.clone());
+    }
+    #[cfg(feature = "xwayland")]
+    if let Some(index) = popups.iter().position(|popup| {
+        popup
+            .x11_surface()
+            .is_some_and(|surface| !surface.is_override_redirect())
+    }) {
+        let window = popups.remove(index);
+        popups.truncate(index);
+        return (Some(window), popups);
+    }
+    (None, vec![])
+}
+
+/// App id of the sandbox, like the Flatpak app id, `window` was created in
+pub fn window_sandbox_app_id(window: &Window) -> Option<String> {
+    let client = window.wl_surface()?.client()?;
+    client
+        .get_data::<ClientState>()?
+        .security_context
+        .as_ref()?
+        .app_id
+        .clone()
+}
+
 #[derive(Default)]
 pub struct SurfaceData {
     pub geometry: Option<Rectangle<i32, Logical>>,
@@ -330,5 +565,6 @@ fn ensure_initial_configure(
 }
 
 fn place_new_window(elements: &mut Vec<Window>, window: &Window) {
+    placement::mark_new_window(window);
     elements.insert(0, window.clone());
 }
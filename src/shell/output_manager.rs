@@ -14,6 +14,7 @@ use smithay::reexports::wayland_server::{
     Client, DataInit, Dispatch, DisplayHandle, GlobalDispatch, New, Resource, WEnum,
 };
 use smithay::utils::Transform;
+use tracing::warn;
 use zwlr_output_configuration_head_v1::ZwlrOutputConfigurationHeadV1;
 use zwlr_output_configuration_v1::ZwlrOutputConfigurationV1;
 use zwlr_output_head_v1::{AdaptiveSyncState, ZwlrOutputHeadV1};
@@ -191,7 +192,7 @@ impl OutputManagementManagerState {
                 if modes_changed {
                     changed = true;
                     if old.modes.len() != conf.modes.len() {
-                        println!("output's old mode count doesn't match new modes");
+                        warn!("output's old mode count doesn't match new modes");
                     } else {
                         for client in self.clients.values() {
                             if let Some((_, modes)) = client.heads.get(output) {
@@ -217,7 +218,7 @@ impl OutputManagementManagerState {
                                     if let Some(new_mode) = modes.get(new_index) {
                                         head.current_mode(new_mode);
                                     } else {
-                                        println!(
+                                        warn!(
                                             "output new mode doesnt exist for the client's output"
                                         );
                                     }
@@ -242,7 +243,7 @@ impl OutputManagementManagerState {
                                     if let Some(mode) = modes.get(new_index) {
                                         head.current_mode(mode);
                                     } else {
-                                        println!(
+                                        warn!(
                                             "output new mode doesnt exist for the client's output"
                                         );
                                     }
@@ -383,7 +384,7 @@ where
                     let state = OutputConfigurationState::Ongoing(HashMap::new());
                     client_data.confs.insert(conf, state);
                 } else {
-                    println!("CreateConfiguration: missing client data");
+                    warn!("CreateConfiguration: missing client data");
                 }
             }
             zwlr_output_manager_v1::Request::Stop => {
@@ -422,7 +423,7 @@ where
         let g_state = state.output_management_state();
         let outdated = *serial != g_state.serial;
         if outdated {
-            println!("OutputConfiguration: request from an outdated configuration");
+            warn!("OutputConfiguration: request from an outdated configuration");
         }
 
         let new_config = g_state
@@ -430,13 +431,13 @@ where
             .get_mut(&client.id())
             .and_then(|data| data.confs.get_mut(conf));
         if new_config.is_none() {
-            println!("OutputConfiguration: request from unknown configuration object");
+            warn!("OutputConfiguration: request from unknown configuration object");
         }
 
         match request {
             zwlr_output_configuration_v1::Request::EnableHead { id, head } => {
                 let Some(output) = head.data::<OutputId>() else {
-                    println!("EnableHead: Missing attached output");
+                    warn!("EnableHead: Missing attached output");
                     let _fail = data_init.init(id, OutputConfigurationHeadState::Cancelled);
                     return;
                 };
@@ -460,7 +461,7 @@ where
                 };
 
                 let Some(current_config) = g_state.current_state.get(output) else {
-                    println!("EnableHead: output missing from current config");
+                    warn!("EnableHead: output missing from current config");
                     let _fail = data_init.init(id, OutputConfigurationHeadState::Cancelled);
                     return;
                 };
@@ -495,7 +496,7 @@ where
                     return;
                 }
                 let Some(output) = head.data::<OutputId>() else {
-                    println!("DisableHead: missing attached output head name");
+                    warn!("DisableHead: missing attached output head name");
                     return;
                 };
 
@@ -512,7 +513,7 @@ where
                 };
 
                 let Some(current_config) = g_state.current_state.get(output) else {
-                    println!("EnableHead: output missing from current config");
+                    warn!("EnableHead: output missing from current config");
                     return;
                 };
 
@@ -629,23 +630,23 @@ where
     ) {
         let g_state = state.output_management_state();
         let Some(client_data) = g_state.clients.get_mut(&client.id()) else {
-            println!("ConfigurationHead: missing client data");
+            warn!("ConfigurationHead: missing client data");
             return;
         };
         let OutputConfigurationHeadState::Ok(output_id, conf) = data else {
-            println!("ConfigurationHead: request sent to a cancelled head");
+            warn!("ConfigurationHead: request sent to a cancelled head");
             return;
         };
         let Some(serial) = conf.data::<u32>() else {
-            println!("ConfigurationHead: missing serial");
+            warn!("ConfigurationHead: missing serial");
             return;
         };
         if *serial != g_state.serial {
-            println!("ConfigurationHead: request sent to an outdated");
+            warn!("ConfigurationHead: request sent to an outdated");
             return;
         }
         let Some(new_config) = client_data.confs.get_mut(conf) else {
-            println!("ConfigurationHead: unknown configuration");
+            warn!("ConfigurationHead: unknown configuration");
             return;
         };
         let OutputConfigurationState::Ongoing(new_config) = new_config else {
@@ -656,7 +657,7 @@ where
             return;
         };
         let Some(new_config) = new_config.get_mut(output_id) else {
-            println!("ConfigurationHead: config missing from enabled heads");
+            warn!("ConfigurationHead: config missing from enabled heads");
             return;
         };
 
@@ -669,7 +670,7 @@ where
                 {
                     Some(Some(index)) => index,
                     _ => {
-                        println!("SetMode: failed to find requested mode");
+                        warn!("SetMode: failed to find requested mode");
                         conf_head.post_error(
                             zwlr_output_configuration_head_v1::Error::InvalidMode,
                             "failed to find requested mode",
@@ -679,12 +680,12 @@ where
                 };
 
                 let Some(current_config) = g_state.current_state.get(output_id) else {
-                    println!("SetMode: output missing from the current config");
+                    warn!("SetMode: output missing from the current config");
                     return;
                 };
 
                 let Some(mode) = current_config.modes.get(index) else {
-                    println!("SetMode: requested mode is out of range");
+                    warn!("SetMode: requested mode is out of range");
                     return;
                 };
 
@@ -700,13 +701,13 @@ where
                     match (width.try_into(), height.try_into(), refresh.try_into()) {
                         (Ok(width), Ok(height), Ok(refresh)) => (width, height, refresh),
                         _ => {
-                            println!("SetCustomMode: invalid input data");
+                            warn!("SetCustomMode: invalid input data");
                             return;
                         }
                     };
 
                 let Some(current_config) = g_state.current_state.get(output_id) else {
-                    println!("SetMode: output missing from the current config");
+                    warn!("SetMode: output missing from the current config");
                     return;
                 };
 
@@ -715,7 +716,7 @@ where
                         && m.height == height
                         && (refresh == 0 || m.refresh_rate == refresh)
                 }) else {
-                    println!("SetCustomMode: no matching mode");
+                    warn!("SetCustomMode: no matching mode");
                     return;
                 };
 
@@ -753,7 +754,7 @@ where
                     WEnum::Value(AdaptiveSyncState::Enabled) => true,
                     WEnum::Value(AdaptiveSyncState::Disabled) => false,
                     _ => {
-                        println!("SetAdaptativeSync: unknown requested adaptative sync");
+                        warn!("SetAdaptativeSync: unknown requested adaptative sync");
                         conf_head.post_error(
                             zwlr_output_configuration_head_v1::Error::InvalidAdaptiveSyncState,
                             "unknown adaptive sync value",
@@ -0,0 +1,126 @@
+use std::{
+    cell::RefCell,
+    time::{Duration, Instant},
+};
+
+use smithay::{
+    desktop::Window,
+    input::keyboard::Keysym,
+    reexports::calloop::timer::{TimeoutAction, Timer},
+};
+use tracing::{debug, warn};
+use xkbcommon::xkb;
+
+use crate::{
+    input_handler::KeyAction,
+    state::{AnvilState, Backend, Corner},
+};
+
+/// How many attention requests it takes until a window gets peeked at
+const REPEATED_REQUESTS: usize = 2;
+/// Requests older than this are forgotten
+const REQUEST_WINDOW: Duration = Duration::from_secs(30);
+/// Width and height of the peek as a fraction of the usable area
+pub const PEEK_SIZE: f64 = 0.25;
+
+/// Recent attention requests of a window
+#[derive(Debug, Default)]
+struct AttentionRequests(RefCell<Vec<Instant>>);
+
+/// Picture-in-picture view of a background window that wants attention
+#[derive(Debug, Clone)]
+pub struct WindowPeek {
+    pub window: Window,
+    pub until: Instant,
+}
+
+impl<BackendData: Backend> AnvilState<BackendData> {
+    /// A window asked for attention without being allowed to take the focus.
+    /// Peek at it if it keeps asking.
+    pub fn request_attention(&mut self, window: &Window) {
+        let Some(config) = self.config.attention_peek.clone() else {
+            return;
+        };
+        if self.current_window().0.as_ref() == Some(window)
+            || self
+                .picture_in_picture
+                .as_ref()
+                .is_some_and(|pip| &pip.window == window)
+            || self
+                .split_screen
+                .as_ref()
+                .is_some_and(|split| &split.window == window)
+        {
+            return;
+        }
+
+        window
+            .user_data()
+            .insert_if_missing(AttentionRequests::default);
+        let requests = window.user_data().get::<AttentionRequests>().unwrap();
+        let now = Instant::now();
+        let mut requests = requests.0.borrow_mut();
+        requests.retain(|time| now.duration_since(*time) < REQUEST_WINDOW);
+        requests.push(now);
+        if requests.len() < REPEATED_REQUESTS {
+            return;
+        }
+        requests.clear();
+
+        debug!("Peeking at window requesting attention");
+        let duration = Duration::from_secs_f64(config.duration_secs.max(0.0));
+        let until = now + duration;
+        self.window_peek = Some(WindowPeek {
+            window: window.clone(),
+            until,
+        });
+        let res = self
+            .handle
+            .insert_source(Timer::from_duration(duration), move |_, _, data| {
+                if data
+                    .window_peek
+                    .as_ref()
+                    .is_some_and(|peek| peek.until == until)
+                {
+                    data.window_peek = None;
+                }
+                TimeoutAction::Drop
+            });
+        if let Err(err) = res {
+            warn!(?err, "Unable to insert peek timer");
+        }
+    }
+
+    /// The bottom right corner, unless picture-in-picture is shown there
+    pub fn peek_corner(&self) -> Corner {
+        match self.picture_in_picture.as_ref() {
+            Some(pip) if pip.corner == Corner::BottomRight => Corner::TopRight,
+            _ => Corner::BottomRight,
+        }
+    }
+
+    /// Check if `keysym` switches to the window currently peeked at
+    pub fn peek_key_action(&self, keysym: Keysym) -> Option<KeyAction> {
+        let config = self.config.attention_peek.as_ref()?;
+        self.window_peek.as_ref()?;
+        let switch_key = xkb::keysym_from_name(&config.switch_key, xkb::KEYSYM_CASE_INSENSITIVE);
+        (switch_key.raw() == keysym.raw()).then_some(KeyAction::SwitchToPeek)
+    }
+
+    pub fn switch_to_peek(&mut self) {
+        if let Some(peek) = self.window_peek.take() {
+            self.raise_window(&peek.window);
+        }
+    }
+
+    /// Forget the peek once its window is unmapped or became the active one
+    pub fn refresh_peek(&mut self) {
+        if let Some(peek) = self.window_peek.as_ref() {
+            if !self.elements.contains(&peek.window)
+                || self.current_window().0.as_ref() == Some(&peek.window)
+            {
+                self.window_peek = None;
+            }
+        }
+    }
+}
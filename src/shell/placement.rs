@@ -0,0 +1,83 @@
+use std::cell::Cell;
+
+use smithay::desktop::Window;
+use tracing::debug;
+
+use crate::{
+    shell::{is_hidden, is_popup, window_app_id, window_name},
+    state::{AnvilState, Backend, NewWindowPlacement},
+};
+
+/// Set on new windows until their placement is applied
+#[derive(Debug, Default)]
+struct PendingPlacement(Cell<bool>);
+
+pub fn mark_new_window(window: &Window) {
+    window
+        .user_data()
+        .insert_if_missing(|| PendingPlacement(Cell::new(true)));
+}
+
+fn take_pending(window: &Window) -> bool {
+    window
+        .user_data()
+        .get::<PendingPlacement>()
+        .is_some_and(|pending| pending.0.replace(false))
+}
+
+impl<BackendData: Backend> AnvilState<BackendData> {
+    /// Move new windows behind the shown one if their app opens in the background
+    pub fn refresh_window_placement(&mut self) {
+        let new_windows: Vec<Window> = self
+            .elements
+            .iter()
+            .filter(|window| {
+                window
+                    .user_data()
+                    .get::<PendingPlacement>()
+                    .is_some_and(|pending| pending.0.get())
+            })
+            .cloned()
+            .collect();
+        for window in new_windows {
+            // Clients set the app id after creating the toplevel, wait for the first buffer
+            if window_app_id(&window).is_none() && window.bbox().is_empty() {
+                continue;
+            }
+            if !take_pending(&window) || is_popup(&window) {
+                continue;
+            }
+            let placement = self
+                .config
+                .window_rule_for(&window)
+                .and_then(|rule| rule.new_window)
+                .unwrap_or(self.config.new_windows);
+            if placement == NewWindowPlacement::Focus {
+                continue;
+            }
+
+            let Some(index) = self.elements.iter().position(|other| *other == window) else {
+                continue;
+            };
+            let shown = self
+                .elements
+                .iter()
+                .position(|other| *other != window && !is_popup(other) && !is_hidden(other));
+            // Nothing else shows, or the window already is behind
+            let Some(shown) = shown.filter(|shown| *shown > index) else {
+                continue;
+            };
+            debug!(app_id = ?window_app_id(&window), ?placement, "Opening window behind");
+            self.elements.remove(index);
+            self.elements.insert(shown, window.clone());
+            self.update_keyboard_focus();
+
+            if placement == NewWindowPlacement::Prompt {
+                self.show_toast(
+                    format!("{} opened", window_name(&window)),
+                    "Press Alt+Tab to switch to it",
+                );
+            }
+        }
+    }
+}
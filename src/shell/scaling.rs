@@ -0,0 +1,328 @@
+use std::sync::Mutex;
+
+use smithay::desktop::Window;
+use smithay::reexports::wayland_server::backend::ClientId;
+use smithay::reexports::wayland_server::protocol::wl_surface::WlSurface;
+use smithay::reexports::wayland_server::{
+    Client, DataInit, Dispatch, DisplayHandle, GlobalDispatch, New, Resource, WEnum,
+};
+use smithay::wayland::compositor::with_states;
+
+use crate::state::{AnvilState, Backend, Configuration, Sampling, ScalingMode};
+
+#[allow(non_upper_case_globals, non_camel_case_types, clippy::all)]
+pub mod protocol {
+    use smithay::reexports::wayland_server;
+    use smithay::reexports::wayland_server::protocol::*;
+
+    pub mod __interfaces {
+        use smithay::reexports::wayland_server::backend as wayland_backend;
+        use smithay::reexports::wayland_server::protocol::__interfaces::*;
+        wayland_scanner::generate_interfaces!("resources/protocols/consolation-scaling-v1.xml");
+    }
+    use self::__interfaces::*;
+
+    wayland_scanner::generate_server_code!("resources/protocols/consolation-scaling-v1.xml");
+}
+
+use protocol::consolation_scaling_manager_v1::{self, ConsolationScalingManagerV1};
+use protocol::consolation_window_scaling_v1::{self, ConsolationWindowScalingV1};
+
+const VERSION: u32 = 2;
+
+/// How a window ends up being scaled
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct WindowScaling {
+    pub mode: ScalingMode,
+    pub shader_preset: Option<String>,
+    /// Part of the window kept by `ScalingMode::Crop`, (0, 0) is the center
+    pub crop_offset: (f64, f64),
+    pub sampling: Sampling,
+}
+
+/// What the client asked for
+#[derive(Debug, Clone, Default)]
+struct Preference {
+    /// `None` until the client picks a mode
+    mode: Option<ScalingMode>,
+    shader_preset: Option<String>,
+    crop_offset: (f64, f64),
+}
+
+/// Kept in the data map of the surface of the client
+#[derive(Debug, Default)]
+struct ScalingPreference(Mutex<Preference>);
+
+/// Between -1 and 1, an invalid offset centers the window
+fn clamp_offset(offset: f64) -> f64 {
+    if offset.is_finite() {
+        offset.clamp(-1.0, 1.0)
+    } else {
+        0.0
+    }
+}
+
+/// The client preferences of `window`, with window rules taking precedence.
+/// Low resolution windows default to integer scaling
+pub fn window_scaling(window: &Window, config: &Configuration) -> WindowScaling {
+    let rule = config.window_rule_for(window);
+    let preference = window
+        .wl_surface()
+        .and_then(|surface| {
+            with_states(&surface, |states| {
+                states
+                    .data_map
+                    .get::<ScalingPreference>()
+                    .map(|preference| preference.0.lock().unwrap().clone())
+            })
+        })
+        .unwrap_or_default();
+    let height = window.geometry().size.h;
+    let low_resolution = config
+        .low_resolution_height
+        .is_some_and(|max_height| (1..=max_height).contains(&height));
+    let mode = rule
+        .and_then(|rule| rule.scaling_mode)
+        .or(preference.mode)
+        .unwrap_or(if low_resolution {
+            ScalingMode::Integer
+        } else {
+            ScalingMode::Fit
+        });
+    WindowScaling {
+        mode,
+        shader_preset: rule
+            .and_then(|rule| rule.shader_preset.clone())
+            .or(preference.shader_preset),
+        // Rules are clamped to the range clients are held to
+        crop_offset: rule
+            .and_then(|rule| rule.crop_offset)
+            .map(|(x, y)| (clamp_offset(x), clamp_offset(y)))
+            .unwrap_or(preference.crop_offset),
+        sampling: rule.and_then(|rule| rule.sampling).unwrap_or(match mode {
+            ScalingMode::Integer => Sampling::Nearest,
+            _ => Sampling::Linear,
+        }),
+    }
+}
+
+impl From<ScalingMode> for consolation_window_scaling_v1::Mode {
+    fn from(mode: ScalingMode) -> Self {
+        match mode {
+            ScalingMode::Fit => Self::Fit,
+            ScalingMode::Integer => Self::Integer,
+            ScalingMode::Stretch => Self::Stretch,
+            ScalingMode::Crop => Self::Crop,
+        }
+    }
+}
+
+impl From<consolation_window_scaling_v1::Mode> for ScalingMode {
+    fn from(mode: consolation_window_scaling_v1::Mode) -> Self {
+        match mode {
+            consolation_window_scaling_v1::Mode::Fit => Self::Fit,
+            consolation_window_scaling_v1::Mode::Integer => Self::Integer,
+            consolation_window_scaling_v1::Mode::Stretch => Self::Stretch,
+            consolation_window_scaling_v1::Mode::Crop => Self::Crop,
+            _ => Self::default(),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct ScalingManagerState {
+    instances: Vec<ConsolationWindowScalingV1>,
+}
+
+pub trait ScalingHandler {
+    fn scaling_manager_state(&mut self) -> &mut ScalingManagerState;
+}
+
+pub struct WindowScalingData {
+    surface: WlSurface,
+    /// Last values sent to the client
+    sent: Mutex<Option<WindowScaling>>,
+}
+
+impl ScalingManagerState {
+    pub fn new<D>(display: &DisplayHandle) -> Self
+    where
+        D: GlobalDispatch<ConsolationScalingManagerV1, ()>,
+        D: Dispatch<ConsolationScalingManagerV1, ()>,
+        D: Dispatch<ConsolationWindowScalingV1, WindowScalingData>,
+        D: 'static,
+    {
+        display.create_global::<D, ConsolationScalingManagerV1, _>(VERSION, ());
+        Self {
+            instances: Vec::new(),
+        }
+    }
+}
+
+fn update_preference(surface: &WlSurface, update: impl FnOnce(&mut Preference)) {
+    if !surface.is_alive() {
+        return;
+    }
+    with_states(surface, |states| {
+        states
+            .data_map
+            .insert_if_missing_threadsafe(ScalingPreference::default);
+        let preference = states.data_map.get::<ScalingPreference>().unwrap();
+        update(&mut preference.0.lock().unwrap());
+    });
+}
+
+/// Tell clients how their windows are scaled, once they asked or the config changed
+pub fn refresh<D>(state: &mut AnvilState<D>)
+where
+    D: Backend + 'static,
+{
+    for instance in &state.scaling_manager_state.instances {
+        let data = instance.data::<WindowScalingData>().unwrap();
+        let Some(window) = state.window_for_surface(&data.surface) else {
+            continue;
+        };
+        let scaling = window_scaling(&window, &state.config);
+        let mut sent = data.sent.lock().unwrap();
+        if sent.as_ref() != Some(&scaling) {
+            if instance.version() >= 2 {
+                instance.mode(scaling.mode.into());
+                instance.crop_offset(scaling.crop_offset.0, scaling.crop_offset.1);
+            } else if scaling.mode == ScalingMode::Crop {
+                // Unknown to version 1
+                instance.mode(ScalingMode::Fit.into());
+            } else {
+                instance.mode(scaling.mode.into());
+            }
+            instance.shader_preset(scaling.shader_preset.clone());
+            instance.done();
+            *sent = Some(scaling);
+        }
+    }
+}
+
+impl<D> GlobalDispatch<ConsolationScalingManagerV1, (), D> for ScalingManagerState
+where
+    D: GlobalDispatch<ConsolationScalingManagerV1, ()>,
+    D: Dispatch<ConsolationScalingManagerV1, ()>,
+    D: Dispatch<ConsolationWindowScalingV1, WindowScalingData>,
+    D: ScalingHandler,
+{
+    fn bind(
+        _state: &mut D,
+        _handle: &DisplayHandle,
+        _client: &Client,
+        resource: New<ConsolationScalingManagerV1>,
+        _global_data: &(),
+        data_init: &mut DataInit<'_, D>,
+    ) {
+        data_init.init(resource, ());
+    }
+}
+
+impl<D> Dispatch<ConsolationScalingManagerV1, (), D> for ScalingManagerState
+where
+    D: Dispatch<ConsolationScalingManagerV1, ()>,
+    D: Dispatch<ConsolationWindowScalingV1, WindowScalingData>,
+    D: ScalingHandler,
+{
+    fn request(
+        state: &mut D,
+        _client: &Client,
+        _resource: &ConsolationScalingManagerV1,
+        request: <ConsolationScalingManagerV1 as Resource>::Request,
+        _data: &(),
+        _dhandle: &DisplayHandle,
+        data_init: &mut DataInit<'_, D>,
+    ) {
+        match request {
+            consolation_scaling_manager_v1::Request::GetWindowScaling { id, surface } => {
+                let instance = data_init.init(
+                    id,
+                    WindowScalingData {
+                        surface,
+                        sent: Mutex::new(None),
+                    },
+                );
+                // The current values are sent on the next refresh
+                state.scaling_manager_state().instances.push(instance);
+            }
+            consolation_scaling_manager_v1::Request::Destroy => {}
+            _ => unreachable!(),
+        }
+    }
+}
+
+impl<D> Dispatch<ConsolationWindowScalingV1, WindowScalingData, D> for ScalingManagerState
+where
+    D: Dispatch<ConsolationWindowScalingV1, WindowScalingData>,
+    D: ScalingHandler,
+{
+    fn request(
+        _state: &mut D,
+        _client: &Client,
+        _resource: &ConsolationWindowScalingV1,
+        request: <ConsolationWindowScalingV1 as Resource>::Request,
+        data: &WindowScalingData,
+        _dhandle: &DisplayHandle,
+        _data_init: &mut DataInit<'_, D>,
+    ) {
+        match request {
+            consolation_window_scaling_v1::Request::SetMode { mode } => {
+                if let WEnum::Value(mode) = mode {
+                    update_preference(&data.surface, |preference| {
+                        preference.mode = Some(mode.into())
+                    });
+                }
+            }
+            consolation_window_scaling_v1::Request::SetShaderPreset { preset } => {
+                update_preference(&data.surface, |preference| {
+                    preference.shader_preset = preset
+                });
+            }
+            consolation_window_scaling_v1::Request::SetCropOffset { x, y } => {
+                update_preference(&data.surface, |preference| {
+                    preference.crop_offset = (clamp_offset(x), clamp_offset(y))
+                });
+            }
+            consolation_window_scaling_v1::Request::Destroy => {}
+            _ => unreachable!(),
+        }
+    }
+
+    fn destroyed(
+        state: &mut D,
+        _client: ClientId,
+        resource: &ConsolationWindowScalingV1,
+        data: &WindowScalingData,
+    ) {
+        let state = state.scaling_manager_state();
+        state.instances.retain(|x| x != resource);
+        // Another object of the surface still holds the preference
+        let shared = state.instances.iter().any(|instance| {
+            instance
+                .data::<WindowScalingData>()
+                .is_some_and(|other| other.surface == data.surface)
+        });
+        if !shared {
+            update_preference(&data.surface, |preference| {
+                *preference = Preference::default()
+            });
+        }
+    }
+}
+
+#[macro_export]
+macro_rules! delegate_scaling {
+    ($(@<$( $lt:tt $( : $clt:tt $(+ $dlt:tt )* )? ),+>)? $ty: ty) => {
+        smithay::reexports::wayland_server::delegate_global_dispatch!($(@< $( $lt $( : $clt $(+ $dlt )* )? ),+ >)? $ty: [
+            $crate::shell::scaling::protocol::consolation_scaling_manager_v1::ConsolationScalingManagerV1: ()
+        ] => $crate::shell::scaling::ScalingManagerState);
+        smithay::reexports::wayland_server::delegate_dispatch!($(@< $( $lt $( : $clt $(+ $dlt )* )? ),+ >)? $ty: [
+            $crate::shell::scaling::protocol::consolation_scaling_manager_v1::ConsolationScalingManagerV1: ()
+        ] => $crate::shell::scaling::ScalingManagerState);
+        smithay::reexports::wayland_server::delegate_dispatch!($(@< $( $lt $( : $clt $(+ $dlt )* )? ),+ >)? $ty: [
+            $crate::shell::scaling::protocol::consolation_window_scaling_v1::ConsolationWindowScalingV1: $crate::shell::scaling::WindowScalingData
+        ] => $crate::shell::scaling::ScalingManagerState);
+    };
+}
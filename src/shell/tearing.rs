@@ -0,0 +1,183 @@
+use std::sync::atomic::{AtomicBool, Ordering};
+
+use smithay::reexports::wayland_protocols::wp::tearing_control::v1::server::{
+    wp_tearing_control_manager_v1::{self, WpTearingControlManagerV1},
+    wp_tearing_control_v1::{self, WpTearingControlV1},
+};
+use smithay::reexports::wayland_server::backend::ClientId;
+use smithay::reexports::wayland_server::protocol::wl_surface::WlSurface;
+use smithay::reexports::wayland_server::{
+    Client, DataInit, Dispatch, DisplayHandle, GlobalDispatch, New, Resource, WEnum,
+};
+use smithay::wayland::compositor::{with_states, Cacheable};
+
+const VERSION: u32 = 1;
+
+/// Double-buffered presentation hint of a surface, making the window count as a
+/// game
+#[derive(Debug, Clone, Copy, Default)]
+pub struct TearingControlCachedState {
+    pub allow_tearing: bool,
+}
+
+impl Cacheable for TearingControlCachedState {
+    fn commit(&mut self, _dh: &DisplayHandle) -> Self {
+        *self
+    }
+
+    fn merge_into(self, into: &mut Self, _dh: &DisplayHandle) {
+        *into = self;
+    }
+}
+
+/// Marks surfaces that already have a wp_tearing_control_v1
+#[derive(Debug, Default)]
+struct TearingControlExists(AtomicBool);
+
+fn set_allow_tearing(surface: &WlSurface, allow_tearing: bool) {
+    if !surface.is_alive() {
+        return;
+    }
+    with_states(surface, |states| {
+        states
+            .cached_state
+            .get::<TearingControlCachedState>()
+            .pending()
+            .allow_tearing = allow_tearing;
+    });
+}
+
+#[derive(Debug)]
+pub struct TearingControlState;
+
+impl TearingControlState {
+    pub fn new<D>(display: &DisplayHandle) -> Self
+    where
+        D: GlobalDispatch<WpTearingControlManagerV1, ()>,
+        D: Dispatch<WpTearingControlManagerV1, ()>,
+        D: Dispatch<WpTearingControlV1, WlSurface>,
+        D: 'static,
+    {
+        display.create_global::<D, WpTearingControlManagerV1, _>(VERSION, ());
+        Self
+    }
+}
+
+impl<D> GlobalDispatch<WpTearingControlManagerV1, (), D> for TearingControlState
+where
+    D: GlobalDispatch<WpTearingControlManagerV1, ()>,
+    D: Dispatch<WpTearingControlManagerV1, ()>,
+    D: Dispatch<WpTearingControlV1, WlSurface>,
+{
+    fn bind(
+        _state: &mut D,
+        _handle: &DisplayHandle,
+        _client: &Client,
+        resource: New<WpTearingControlManagerV1>,
+        _global_data: &(),
+        data_init: &mut DataInit<'_, D>,
+    ) {
+        data_init.init(resource, ());
+    }
+}
+
+impl<D> Dispatch<WpTearingControlManagerV1, (), D> for TearingControlState
+where
+    D: Dispatch<WpTearingControlManagerV1, ()>,
+    D: Dispatch<WpTearingControlV1, WlSurface>,
+{
+    fn request(
+        _state: &mut D,
+        _client: &Client,
+        resource: &WpTearingControlManagerV1,
+        request: <WpTearingControlManagerV1 as Resource>::Request,
+        _data: &(),
+        _dhandle: &DisplayHandle,
+        data_init: &mut DataInit<'_, D>,
+    ) {
+        match request {
+            wp_tearing_control_manager_v1::Request::GetTearingControl { id, surface } => {
+                let exists = with_states(&surface, |states| {
+                    states
+                        .data_map
+                        .insert_if_missing_threadsafe(TearingControlExists::default);
+                    states
+                        .data_map
+                        .get::<TearingControlExists>()
+                        .unwrap()
+                        .0
+                        .swap(true, Ordering::SeqCst)
+                });
+                if exists {
+                    resource.post_error(
+                        wp_tearing_control_manager_v1::Error::TearingControlExists,
+                        "surface already has a tearing control object",
+                    );
+                    return;
+                }
+                data_init.init(id, surface);
+            }
+            wp_tearing_control_manager_v1::Request::Destroy => {}
+            _ => unreachable!(),
+        }
+    }
+}
+
+impl<D> Dispatch<WpTearingControlV1, WlSurface, D> for TearingControlState
+where
+    D: Dispatch<WpTearingControlV1, WlSurface>,
+{
+    fn request(
+        _state: &mut D,
+        _client: &Client,
+        _resource: &WpTearingControlV1,
+        request: <WpTearingControlV1 as Resource>::Request,
+        surface: &WlSurface,
+        _dhandle: &DisplayHandle,
+        _data_init: &mut DataInit<'_, D>,
+    ) {
+        match request {
+            wp_tearing_control_v1::Request::SetPresentationHint { hint } => {
+                let allow_tearing = matches!(
+                    hint,
+                    WEnum::Value(wp_tearing_control_v1::PresentationHint::Async)
+                );
+                set_allow_tearing(surface, allow_tearing);
+            }
+            wp_tearing_control_v1::Request::Destroy => {}
+            _ => unreachable!(),
+        }
+    }
+
+    fn destroyed(
+        _state: &mut D,
+        _client: ClientId,
+        _resource: &WpTearingControlV1,
+        surface: &WlSurface,
+    ) {
+        // Back to vsync with the next commit
+        set_allow_tearing(surface, false);
+        if surface.is_alive() {
+            with_states(surface, |states| {
+                if let Some(exists) = states.data_map.get::<TearingControlExists>() {
+                    exists.0.store(false, Ordering::SeqCst);
+                }
+            });
+        }
+    }
+}
+
+#[macro_export]
+macro_rules! delegate_tearing_control {
+    ($(@<$( $lt:tt $( : $clt:tt $(+ $dlt:tt )* )? ),+>)? $ty: ty) => {
+        smithay::reexports::wayland_server::delegate_global_dispatch!($(@< $( $lt $( : $clt $(+ $dlt )* )? ),+ >)? $ty: [
+            smithay::reexports::wayland_protocols::wp::tearing_control::v1::server::wp_tearing_control_manager_v1::WpTearingControlManagerV1: ()
+        ] => $crate::shell::tearing::TearingControlState);
+        smithay::reexports::wayland_server::delegate_dispatch!($(@< $( $lt $( : $clt $(+ $dlt )* )? ),+ >)? $ty: [
+            smithay::reexports::wayland_protocols::wp::tearing_control::v1::server::wp_tearing_control_manager_v1::WpTearingControlManagerV1: ()
+        ] => $crate::shell::tearing::TearingControlState);
+        smithay::reexports::wayland_server::delegate_dispatch!($(@< $( $lt $( : $clt $(+ $dlt )* )? ),+ >)? $ty: [
+            smithay::reexports::wayland_protocols::wp::tearing_control::v1::server::wp_tearing_control_v1::WpTearingControlV1: smithay::reexports::wayland_server::protocol::wl_surface::WlSurface
+        ] => $crate::shell::tearing::TearingControlState);
+    };
+}
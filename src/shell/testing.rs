@@ -0,0 +1,234 @@
+//! A compositor without a backend, and a client talking to it over a socket pair,
+//! to drive shell requests from tests
+
+use std::{os::unix::net::UnixStream, time::Duration};
+
+use smithay::{
+    backend::{allocator::dmabuf::Dmabuf, input::LedState},
+    desktop::Window,
+    output::{Mode as WlMode, Output, PhysicalProperties, Subpixel},
+    reexports::{
+        calloop::EventLoop,
+        wayland_server::{protocol::wl_surface::WlSurface, Display},
+    },
+    utils::{Physical, Size},
+};
+use wayland_client::{
+    delegate_noop,
+    protocol::{wl_compositor, wl_registry, wl_surface},
+    Connection, Dispatch, EventQueue, QueueHandle,
+};
+use wayland_protocols::xdg::shell::client::{xdg_surface, xdg_toplevel, xdg_wm_base};
+
+use crate::{
+    state::{set_config_path, AnvilState, Backend},
+    ClientState,
+};
+
+#[derive(Debug)]
+pub struct TestBackend;
+
+impl Backend for TestBackend {
+    fn seat_name(&self) -> String {
+        String::from("test")
+    }
+
+    fn reset_buffers(&mut self, _output: &Output) {}
+
+    fn early_import(&mut self, _surface: &WlSurface) {}
+
+    fn update_led_state(&mut self, _led_state: LedState) {}
+
+    fn capture_window(&mut self, _window: &Window, _size: Size<i32, Physical>) -> Option<Dmabuf> {
+        None
+    }
+
+    fn sample_window(&mut self, _window: &Window, _size: Size<i32, Physical>) -> Option<Vec<u8>> {
+        None
+    }
+}
+
+pub struct TestCompositor {
+    event_loop: EventLoop<'static, AnvilState<TestBackend>>,
+    pub state: AnvilState<TestBackend>,
+}
+
+impl TestCompositor {
+    /// A compositor with the default config and one 1280x800 output
+    pub fn new() -> TestCompositor {
+        set_config_path(
+            std::env::temp_dir().join(format!("consolation-test-{}.toml", std::process::id())),
+        );
+        let event_loop = EventLoop::try_new().unwrap();
+        let display = Display::new().unwrap();
+        let mut state = AnvilState::init(display, event_loop.handle(), TestBackend, false, None);
+
+        let output = Output::new(
+            String::from("TEST-1"),
+            PhysicalProperties {
+                size: (0, 0).into(),
+                subpixel: Subpixel::Unknown,
+                make: "Consolation".into(),
+                model: "Test".into(),
+            },
+        );
+        let mode = WlMode {
+            size: (1280, 800).into(),
+            refresh: 60_000,
+        };
+        output.add_mode(mode);
+        output.set_preferred(mode);
+        output.change_current_state(Some(mode), None, None, Some((0, 0).into()));
+        state.outputs.push(output);
+
+        TestCompositor { event_loop, state }
+    }
+
+    pub fn connect(&mut self) -> TestClient {
+        let (server, client) = UnixStream::pair().unwrap();
+        self.state
+            .insert_client(server, ClientState::default())
+            .unwrap();
+        let connection = Connection::from_socket(client).unwrap();
+        let queue = connection.new_event_queue();
+        connection.display().get_registry(&queue.handle(), ());
+        let mut client = TestClient {
+            connection,
+            queue,
+            data: ClientData::default(),
+        };
+        self.roundtrip(&mut client);
+        client
+    }
+
+    /// Handle what clients sent, like the main loop does
+    pub fn dispatch(&mut self) {
+        self.event_loop
+            .dispatch(Some(Duration::ZERO), &mut self.state)
+            .unwrap();
+        self.state.refresh_windows();
+        self.state.display_handle.flush_clients().unwrap();
+    }
+
+    /// Handle what `client` sent, then what the compositor sent back
+    pub fn roundtrip(&mut self, client: &mut TestClient) {
+        client.connection.flush().unwrap();
+        self.dispatch();
+        if let Some(guard) = client.queue.prepare_read() {
+            let _ = guard.read();
+        }
+        client.queue.dispatch_pending(&mut client.data).unwrap();
+    }
+}
+
+pub struct TestClient {
+    connection: Connection,
+    queue: EventQueue<ClientData>,
+    data: ClientData,
+}
+
+impl TestClient {
+    /// Create a toplevel and commit it, so the compositor maps it
+    pub fn create_toplevel(&mut self) -> TestToplevel {
+        let qh = self.queue.handle();
+        let surface = self
+            .data
+            .compositor
+            .as_ref()
+            .expect("No wl_compositor")
+            .create_surface(&qh, ());
+        let xdg_surface = self
+            .data
+            .wm_base
+            .as_ref()
+            .expect("No xdg_wm_base")
+            .get_xdg_surface(&surface, &qh, ());
+        let toplevel = xdg_surface.get_toplevel(&qh, ());
+        surface.commit();
+        TestToplevel {
+            surface,
+            xdg_surface,
+            toplevel,
+        }
+    }
+}
+
+pub struct TestToplevel {
+    surface: wl_surface::WlSurface,
+    xdg_surface: xdg_surface::XdgSurface,
+    toplevel: xdg_toplevel::XdgToplevel,
+}
+
+impl TestToplevel {
+    pub fn destroy(self) {
+        self.toplevel.destroy();
+        self.xdg_surface.destroy();
+        self.surface.destroy();
+    }
+}
+
+#[derive(Default)]
+struct ClientData {
+    compositor: Option<wl_compositor::WlCompositor>,
+    wm_base: Option<xdg_wm_base::XdgWmBase>,
+}
+
+impl Dispatch<wl_registry::WlRegistry, ()> for ClientData {
+    fn event(
+        data: &mut Self,
+        registry: &wl_registry::WlRegistry,
+        event: wl_registry::Event,
+        _: &(),
+        _: &Connection,
+        qh: &QueueHandle<Self>,
+    ) {
+        if let wl_registry::Event::Global {
+            name,
+            interface,
+            version,
+        } = event
+        {
+            match interface.as_str() {
+                "wl_compositor" => {
+                    data.compositor = Some(registry.bind(name, version.min(5), qh, ()));
+                }
+                "xdg_wm_base" => data.wm_base = Some(registry.bind(name, 1, qh, ())),
+                _ => {}
+            }
+        }
+    }
+}
+
+impl Dispatch<xdg_wm_base::XdgWmBase, ()> for ClientData {
+    fn event(
+        _: &mut Self,
+        wm_base: &xdg_wm_base::XdgWmBase,
+        event: xdg_wm_base::Event,
+        _: &(),
+        _: &Connection,
+        _: &QueueHandle<Self>,
+    ) {
+        if let xdg_wm_base::Event::Ping { serial } = event {
+            wm_base.pong(serial);
+        }
+    }
+}
+
+impl Dispatch<xdg_surface::XdgSurface, ()> for ClientData {
+    fn event(
+        _: &mut Self,
+        xdg_surface: &xdg_surface::XdgSurface,
+        event: xdg_surface::Event,
+        _: &(),
+        _: &Connection,
+        _: &QueueHandle<Self>,
+    ) {
+        if let xdg_surface::Event::Configure { serial } = event {
+            xdg_surface.ack_configure(serial);
+        }
+    }
+}
+
+delegate_noop!(ClientData: ignore wl_compositor::WlCompositor);
+delegate_noop!(ClientData: ignore wl_surface::WlSurface);
+delegate_noop!(ClientData: ignore xdg_toplevel::XdgToplevel);
@@ -1,4 +1,5 @@
 use arrayvec::ArrayVec;
+use smithay::desktop::Window;
 use smithay::output::Output;
 use smithay::reexports::wayland_protocols::xdg::shell::server::xdg_toplevel;
 use smithay::reexports::wayland_protocols_wlr;
@@ -21,7 +22,10 @@ use wayland_protocols_wlr::foreign_toplevel::v1::server::{
 use zwlr_foreign_toplevel_handle_v1::ZwlrForeignToplevelHandleV1;
 use zwlr_foreign_toplevel_manager_v1::ZwlrForeignToplevelManagerV1;
 
-use crate::state::{AnvilState, Backend};
+use crate::{
+    shell::{is_denied, is_minimized},
+    state::{AnvilState, Backend},
+};
 
 const VERSION: u32 = 3;
 
@@ -48,7 +52,7 @@ pub trait ForeignToplevelHandler {
 struct ToplevelData {
     title: Option<String>,
     app_id: Option<String>,
-    states: ArrayVec<u32, 3>,
+    states: ArrayVec<u32, 4>,
     output: Option<Output>,
     instances: HashMap<ZwlrForeignToplevelHandleV1, Vec<WlOutput>>,
 }
@@ -75,18 +79,39 @@ impl ForeignToplevelManagerState {
             toplevels: HashMap::new(),
         }
     }
+
+    /// Toplevels currently announced to clients
+    pub fn tracked(&self) -> usize {
+        self.toplevels.len()
+    }
+
+    /// The surface of the toplevel `handle` stands for
+    pub fn surface_for_handle(&self, handle: &ZwlrForeignToplevelHandleV1) -> Option<WlSurface> {
+        self.toplevels
+            .iter()
+            .find(|(_, data)| data.instances.contains_key(handle))
+            .map(|(surface, _)| surface.clone())
+    }
 }
 
 pub fn refresh<D>(state: &mut AnvilState<D>)
 where
     D: Backend + 'static,
 {
+    // Launch groups are listed once, in the place of their most recent window
+    let mut windows: Vec<Window> = Vec::new();
+    for window in state.elements.iter().filter(|window| !is_denied(window)) {
+        let leader = state.launch_group_leader(window);
+        if !windows.contains(&leader) {
+            windows.push(leader);
+        }
+    }
+
     let protocol_state = &mut state.toplevel_manager;
 
     // Handle closed windows.
     protocol_state.toplevels.retain(|surface, data| {
-        if state
-            .elements
+        if windows
             .iter()
             .find(|window| match window.wl_surface() {
                 Some(window_surface) => window_surface.id() == surface.id(),
@@ -106,7 +131,7 @@ where
     });
 
     let mut focus = true;
-    state.elements.iter().for_each(|mapped| {
+    windows.iter().for_each(|mapped| {
         if let Some(wl_surface) = mapped.wl_surface() {
             let wl_surface = wl_surface.into_owned();
 
@@ -115,7 +140,7 @@ where
                 let title = Some(xwindow.title());
                 let app_id = xwindow.startup_id();
                 let maximized = xwindow.is_maximized();
-                let minimized = xwindow.is_minimized();
+                let minimized = xwindow.is_minimized() || is_minimized(mapped);
                 let fullscreen = xwindow.is_fullscreen();
                 let output = state.outputs.get(0);
                 refresh_toplevel_x11::<D>(
@@ -138,8 +163,14 @@ where
                         .lock()
                         .unwrap();
                     let output = state.outputs.get(0);
-                    focus =
-                        refresh_toplevel::<D>(protocol_state, &wl_surface, &role, output, focus);
+                    focus = refresh_toplevel::<D>(
+                        protocol_state,
+                        &wl_surface,
+                        &role,
+                        is_minimized(mapped),
+                        output,
+                        focus,
+                    );
                 });
             }
         }
@@ -160,7 +191,7 @@ fn refresh_toplevel_x11<D>(
 ) where
     D: Backend + 'static,
 {
-    let mut states: ArrayVec<u32, 3> = ArrayVec::new();
+    let mut states: ArrayVec<u32, 4> = ArrayVec::new();
     if maximized {
         states.push(zwlr_foreign_toplevel_handle_v1::State::Maximized as u32);
     }
@@ -173,7 +204,6 @@ fn refresh_toplevel_x11<D>(
     if fullscreen {
         states.push(zwlr_foreign_toplevel_handle_v1::State::Fullscreen as u32);
     }
-    let states = ArrayVec::new();
     match protocol_state.toplevels.entry(wl_surface.clone()) {
         Entry::Occupied(entry) => {
             let data = entry.into_mut();
@@ -253,6 +283,7 @@ fn refresh_toplevel<D>(
     protocol_state: &mut ForeignToplevelManagerState,
     wl_surface: &WlSurface,
     role: &XdgToplevelSurfaceRoleAttributes,
+    minimized: bool,
     output: Option<&Output>,
     has_focus: bool,
 ) -> bool
@@ -261,7 +292,7 @@ where
 {
     let mut has_focus = has_focus;
 
-    let states = to_state_vec(&role.current.states, has_focus);
+    let states = to_state_vec(&role.current.states, minimized, has_focus);
     if role.title.is_none() || role.title.clone().unwrap() != "nil" {
         has_focus = false;
     }
@@ -480,16 +511,12 @@ where
         _dhandle: &DisplayHandle,
         _data_init: &mut DataInit<'_, D>,
     ) {
-        let protocol_state = state.foreign_toplevel_manager_state();
-
-        let Some((surface, _)) = protocol_state
-            .toplevels
-            .iter()
-            .find(|(_, data)| data.instances.contains_key(resource))
+        let Some(surface) = state
+            .foreign_toplevel_manager_state()
+            .surface_for_handle(resource)
         else {
             return;
         };
-        let surface = surface.clone();
 
         match request {
             zwlr_foreign_toplevel_handle_v1::Request::SetMaximized => {
@@ -535,11 +562,14 @@ where
     }
 }
 
-fn to_state_vec(states: &ToplevelStateSet, has_focus: bool) -> ArrayVec<u32, 3> {
+fn to_state_vec(states: &ToplevelStateSet, minimized: bool, has_focus: bool) -> ArrayVec<u32, 4> {
     let mut rv = ArrayVec::new();
     if states.contains(xdg_toplevel::State::Maximized) {
         rv.push(zwlr_foreign_toplevel_handle_v1::State::Maximized as u32);
     }
+    if minimized {
+        rv.push(zwlr_foreign_toplevel_handle_v1::State::Minimized as u32);
+    }
     if states.contains(xdg_toplevel::State::Fullscreen) {
         rv.push(zwlr_foreign_toplevel_handle_v1::State::Fullscreen as u32);
     }
@@ -0,0 +1,79 @@
+use std::time::Duration;
+
+use smithay::desktop::Window;
+
+use crate::{
+    animation::AnimationClock,
+    state::{AnvilState, Backend, TransitionStyle},
+};
+
+/// Animation of the active window changing
+#[derive(Debug, Clone)]
+pub struct WindowTransition {
+    /// The window that was active before, drawn below the new one
+    pub from: Window,
+    pub style: TransitionStyle,
+    clock: AnimationClock,
+    started: Duration,
+    duration: Duration,
+}
+
+impl WindowTransition {
+    /// From 0 when the switch happened to 1 once it is done, easing out
+    pub fn progress(&self) -> f64 {
+        let t = self.elapsed().as_secs_f64() / self.duration.as_secs_f64();
+        1.0 - (1.0 - t.min(1.0)).powi(3)
+    }
+
+    fn elapsed(&self) -> Duration {
+        self.clock.now().saturating_sub(self.started)
+    }
+
+    fn finished(&self) -> bool {
+        self.elapsed() >= self.duration
+    }
+
+    /// Time until it is done and dropped on the next refresh
+    pub fn remaining(&self) -> Duration {
+        self.duration.saturating_sub(self.elapsed())
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct WindowTransitions {
+    /// The active window on the last refresh
+    last: Option<Window>,
+    pub current: Option<WindowTransition>,
+}
+
+impl<BackendData: Backend> AnvilState<BackendData> {
+    /// Start a transition when the active window changed, and drop finished ones
+    pub fn refresh_transition(&mut self) {
+        let active = self.current_window().0;
+        let transitions = &mut self.window_transitions;
+        if transitions.current.as_ref().is_some_and(|transition| {
+            transition.finished() || !self.elements.contains(&transition.from)
+        }) {
+            transitions.current = None;
+        }
+        if active == transitions.last {
+            return;
+        }
+
+        let last = std::mem::replace(&mut transitions.last, active.clone());
+        transitions.current = match (&self.config.window_transition, last, active) {
+            (Some(config), Some(from), Some(_))
+                if config.duration_ms > 0 && self.elements.contains(&from) =>
+            {
+                Some(WindowTransition {
+                    from,
+                    style: config.style,
+                    clock: self.animation_clock,
+                    started: self.animation_clock.now(),
+                    duration: Duration::from_millis(config.duration_ms),
+                })
+            }
+            _ => None,
+        };
+    }
+}
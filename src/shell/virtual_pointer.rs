@@ -0,0 +1,241 @@
+use std::sync::Mutex;
+
+use smithay::backend::input::{Axis, AxisSource};
+use smithay::input::pointer::AxisFrame;
+use smithay::reexports::wayland_protocols_wlr::virtual_pointer::v1::server::{
+    zwlr_virtual_pointer_manager_v1, zwlr_virtual_pointer_v1,
+};
+use smithay::reexports::wayland_server::protocol::wl_pointer;
+use smithay::reexports::wayland_server::{
+    Client, DataInit, Dispatch, DisplayHandle, GlobalDispatch, New, Resource, WEnum,
+};
+use smithay::utils::{Logical, Point};
+use zwlr_virtual_pointer_manager_v1::ZwlrVirtualPointerManagerV1;
+use zwlr_virtual_pointer_v1::ZwlrVirtualPointerV1;
+
+const VERSION: u32 = 2;
+
+/// `zwlr_virtual_pointer_manager_v1` global, letting remote controls move the pointer
+#[derive(Debug)]
+pub struct VirtualPointerManagerState;
+
+/// Receives the input of all virtual pointers, as if it came from a device of the seat
+pub trait VirtualPointerHandler {
+    fn virtual_pointer_motion(&mut self, delta: Point<f64, Logical>, time: u32);
+    /// Motion to a fraction of the width and height of the shown window
+    fn virtual_pointer_motion_absolute(&mut self, position: (f64, f64), time: u32);
+    fn virtual_pointer_button(&mut self, button: u32, state: wl_pointer::ButtonState, time: u32);
+    fn virtual_pointer_axis(&mut self, frame: AxisFrame);
+}
+
+pub struct VirtualPointerGlobalData {
+    filter: Box<dyn for<'c> Fn(&'c Client) -> bool + Send + Sync>,
+}
+
+/// Axis events are sent together on the next frame request
+#[derive(Debug, Default)]
+pub struct VirtualPointerData {
+    axis: Mutex<Option<AxisFrame>>,
+}
+
+impl VirtualPointerData {
+    fn update_axis(&self, time: u32, update: impl FnOnce(AxisFrame) -> AxisFrame) {
+        let mut axis = self.axis.lock().unwrap();
+        let frame = axis.take().unwrap_or_else(|| AxisFrame::new(time));
+        *axis = Some(update(frame));
+    }
+}
+
+impl VirtualPointerManagerState {
+    pub fn new<D, F>(display: &DisplayHandle, filter: F) -> Self
+    where
+        D: GlobalDispatch<ZwlrVirtualPointerManagerV1, VirtualPointerGlobalData>,
+        D: Dispatch<ZwlrVirtualPointerManagerV1, ()>,
+        D: Dispatch<ZwlrVirtualPointerV1, VirtualPointerData>,
+        D: 'static,
+        F: for<'c> Fn(&'c Client) -> bool + Send + Sync + 'static,
+    {
+        let global_data = VirtualPointerGlobalData {
+            filter: Box::new(filter),
+        };
+        display.create_global::<D, ZwlrVirtualPointerManagerV1, _>(VERSION, global_data);
+        Self
+    }
+}
+
+fn axis(axis: WEnum<wl_pointer::Axis>) -> Option<Axis> {
+    match axis {
+        WEnum::Value(wl_pointer::Axis::HorizontalScroll) => Some(Axis::Horizontal),
+        WEnum::Value(wl_pointer::Axis::VerticalScroll) => Some(Axis::Vertical),
+        _ => None,
+    }
+}
+
+fn axis_source(source: WEnum<wl_pointer::AxisSource>) -> Option<AxisSource> {
+    match source {
+        WEnum::Value(wl_pointer::AxisSource::Wheel) => Some(AxisSource::Wheel),
+        WEnum::Value(wl_pointer::AxisSource::Finger) => Some(AxisSource::Finger),
+        WEnum::Value(wl_pointer::AxisSource::Continuous) => Some(AxisSource::Continuous),
+        WEnum::Value(wl_pointer::AxisSource::WheelTilt) => Some(AxisSource::WheelTilt),
+        _ => None,
+    }
+}
+
+impl<D> GlobalDispatch<ZwlrVirtualPointerManagerV1, VirtualPointerGlobalData, D>
+    for VirtualPointerManagerState
+where
+    D: GlobalDispatch<ZwlrVirtualPointerManagerV1, VirtualPointerGlobalData>,
+    D: Dispatch<ZwlrVirtualPointerManagerV1, ()>,
+    D: Dispatch<ZwlrVirtualPointerV1, VirtualPointerData>,
+    D: VirtualPointerHandler,
+{
+    fn bind(
+        _state: &mut D,
+        _handle: &DisplayHandle,
+        _client: &Client,
+        resource: New<ZwlrVirtualPointerManagerV1>,
+        _global_data: &VirtualPointerGlobalData,
+        data_init: &mut DataInit<'_, D>,
+    ) {
+        data_init.init(resource, ());
+    }
+
+    fn can_view(client: Client, global_data: &VirtualPointerGlobalData) -> bool {
+        (global_data.filter)(&client)
+    }
+}
+
+impl<D> Dispatch<ZwlrVirtualPointerManagerV1, (), D> for VirtualPointerManagerState
+where
+    D: Dispatch<ZwlrVirtualPointerManagerV1, ()>,
+    D: Dispatch<ZwlrVirtualPointerV1, VirtualPointerData>,
+    D: VirtualPointerHandler,
+{
+    fn request(
+        _state: &mut D,
+        _client: &Client,
+        _resource: &ZwlrVirtualPointerManagerV1,
+        request: <ZwlrVirtualPointerManagerV1 as Resource>::Request,
+        _data: &(),
+        _dhandle: &DisplayHandle,
+        data_init: &mut DataInit<'_, D>,
+    ) {
+        // There is a single seat, and absolute motion maps to the shown window
+        // whatever the output
+        match request {
+            zwlr_virtual_pointer_manager_v1::Request::CreateVirtualPointer { id, .. }
+            | zwlr_virtual_pointer_manager_v1::Request::CreateVirtualPointerWithOutput {
+                id, ..
+            } => {
+                data_init.init(id, VirtualPointerData::default());
+            }
+            zwlr_virtual_pointer_manager_v1::Request::Destroy => (),
+            _ => unreachable!(),
+        }
+    }
+}
+
+impl<D> Dispatch<ZwlrVirtualPointerV1, VirtualPointerData, D> for VirtualPointerManagerState
+where
+    D: Dispatch<ZwlrVirtualPointerV1, VirtualPointerData>,
+    D: VirtualPointerHandler,
+{
+    fn request(
+        state: &mut D,
+        _client: &Client,
+        _resource: &ZwlrVirtualPointerV1,
+        request: <ZwlrVirtualPointerV1 as Resource>::Request,
+        data: &VirtualPointerData,
+        _dhandle: &DisplayHandle,
+        _data_init: &mut DataInit<'_, D>,
+    ) {
+        match request {
+            zwlr_virtual_pointer_v1::Request::Motion { time, dx, dy } => {
+                state.virtual_pointer_motion(Point::from((dx, dy)), time);
+            }
+            zwlr_virtual_pointer_v1::Request::MotionAbsolute {
+                time,
+                x,
+                y,
+                x_extent,
+                y_extent,
+            } => {
+                if x_extent == 0 || y_extent == 0 {
+                    return;
+                }
+                let position = (
+                    x.min(x_extent) as f64 / x_extent as f64,
+                    y.min(y_extent) as f64 / y_extent as f64,
+                );
+                state.virtual_pointer_motion_absolute(position, time);
+            }
+            zwlr_virtual_pointer_v1::Request::Button {
+                time,
+                button,
+                state: WEnum::Value(button_state),
+            } => {
+                state.virtual_pointer_button(button, button_state, time);
+            }
+            zwlr_virtual_pointer_v1::Request::Button { .. } => (),
+            zwlr_virtual_pointer_v1::Request::Axis {
+                time,
+                axis: wl_axis,
+                value,
+            } => {
+                if let Some(axis) = axis(wl_axis) {
+                    data.update_axis(time, |frame| frame.value(axis, value));
+                }
+            }
+            zwlr_virtual_pointer_v1::Request::AxisDiscrete {
+                time,
+                axis: wl_axis,
+                value,
+                discrete,
+            } => {
+                if let Some(axis) = axis(wl_axis) {
+                    data.update_axis(time, |frame| {
+                        frame.value(axis, value).v120(axis, discrete * 120)
+                    });
+                }
+            }
+            zwlr_virtual_pointer_v1::Request::AxisSource {
+                axis_source: source,
+            } => {
+                if let Some(source) = axis_source(source) {
+                    data.update_axis(0, |frame| frame.source(source));
+                }
+            }
+            zwlr_virtual_pointer_v1::Request::AxisStop {
+                time,
+                axis: wl_axis,
+            } => {
+                if let Some(axis) = axis(wl_axis) {
+                    data.update_axis(time, |frame| frame.stop(axis));
+                }
+            }
+            zwlr_virtual_pointer_v1::Request::Frame => {
+                let frame = data.axis.lock().unwrap().take();
+                if let Some(frame) = frame {
+                    state.virtual_pointer_axis(frame);
+                }
+            }
+            zwlr_virtual_pointer_v1::Request::Destroy => (),
+            _ => unreachable!(),
+        }
+    }
+}
+
+#[macro_export]
+macro_rules! delegate_virtual_pointer {
+    ($(@<$( $lt:tt $( : $clt:tt $(+ $dlt:tt )* )? ),+>)? $ty: ty) => {
+        smithay::reexports::wayland_server::delegate_global_dispatch!($(@< $( $lt $( : $clt $(+ $dlt )* )? ),+ >)? $ty: [
+            smithay::reexports::wayland_protocols_wlr::virtual_pointer::v1::server::zwlr_virtual_pointer_manager_v1::ZwlrVirtualPointerManagerV1: $crate::shell::virtual_pointer::VirtualPointerGlobalData
+        ] => $crate::shell::virtual_pointer::VirtualPointerManagerState);
+        smithay::reexports::wayland_server::delegate_dispatch!($(@< $( $lt $( : $clt $(+ $dlt )* )? ),+ >)? $ty: [
+            smithay::reexports::wayland_protocols_wlr::virtual_pointer::v1::server::zwlr_virtual_pointer_manager_v1::ZwlrVirtualPointerManagerV1: ()
+        ] => $crate::shell::virtual_pointer::VirtualPointerManagerState);
+        smithay::reexports::wayland_server::delegate_dispatch!($(@< $( $lt $( : $clt $(+ $dlt )* )? ),+ >)? $ty: [
+            smithay::reexports::wayland_protocols_wlr::virtual_pointer::v1::server::zwlr_virtual_pointer_v1::ZwlrVirtualPointerV1: $crate::shell::virtual_pointer::VirtualPointerData
+        ] => $crate::shell::virtual_pointer::VirtualPointerManagerState);
+    };
+}
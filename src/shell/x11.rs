@@ -2,7 +2,7 @@ use std::{cell::RefCell, os::unix::io::OwnedFd};
 
 use smithay::{
     desktop::{space::SpaceElement, Window},
-    utils::{Logical, Rectangle},
+    utils::{Logical, Point, Rectangle},
     wayland::{
         selection::{
             data_device::{
@@ -18,15 +18,20 @@ use smithay::{
         xwayland_shell::{XWaylandShellHandler, XWaylandShellState},
     },
     xwayland::{
-        xwm::{Reorder, ResizeEdge as X11ResizeEdge, XwmId},
+        xwm::{Reorder, ResizeEdge as X11ResizeEdge, WmWindowType, XwmId},
         X11Surface, X11Wm, XwmHandler,
     },
 };
-use tracing::{error, trace};
+use tracing::{error, trace, warn};
 
-use crate::{focus::KeyboardFocusTarget, state::Backend, AnvilState};
+use crate::{
+    clipboard::{send_cached_selection, SelectionOrigin},
+    focus::KeyboardFocusTarget,
+    state::Backend,
+    AnvilState,
+};
 
-use super::{fullscreen_output_geometry, place_new_window};
+use super::{fullscreen_output_geometry, is_hidden, place_new_window, ShellError};
 
 #[derive(Debug, Default)]
 struct OldGeometry(RefCell<Option<Rectangle<i32, Logical>>>);
@@ -40,6 +45,40 @@ impl OldGeometry {
     }
 }
 
+fn save_old_geometry(window: &X11Surface, geo: Rectangle<i32, Logical>) {
+    window.user_data().insert_if_missing(OldGeometry::default);
+    window.user_data().get::<OldGeometry>().unwrap().save(geo);
+}
+
+fn restore_old_geometry(window: &X11Surface) -> Option<Rectangle<i32, Logical>> {
+    window
+        .user_data()
+        .get::<OldGeometry>()
+        .and_then(|data| data.restore())
+}
+
+/// Override-redirect windows, menus, tooltips, notifications and tool windows, and
+/// windows transient for another one like dialogs. They show over the main window
+/// at their own position
+pub fn is_x11_popup(window: &X11Surface) -> bool {
+    if window.is_override_redirect() || window.is_transient_for().is_some() {
+        return true;
+    }
+    matches!(
+        window.window_type(),
+        Some(
+            WmWindowType::DropdownMenu
+                | WmWindowType::PopupMenu
+                | WmWindowType::Menu
+                | WmWindowType::Tooltip
+                | WmWindowType::Notification
+                | WmWindowType::Dialog
+                | WmWindowType::Utility
+                | WmWindowType::Toolbar
+        )
+    )
+}
+
 impl<BackendData: Backend> XWaylandShellHandler for AnvilState<BackendData> {
     fn xwayland_shell_state(&mut self) -> &mut XWaylandShellState {
         &mut self.xwayland_shell_state
@@ -55,16 +94,9 @@ impl<BackendData: Backend> XwmHandler for AnvilState<BackendData> {
     fn new_override_redirect_window(&mut self, _xwm: XwmId, _window: X11Surface) {}
 
     fn map_window_request(&mut self, _xwm: XwmId, window: X11Surface) {
-        window.set_mapped(true).unwrap();
-        let window = Window::new_x11_window(window);
-        place_new_window(&mut self.elements, &window);
-        let bbox = window.bbox();
-        let Some(xsurface) = window.x11_surface() else {
-            unreachable!()
-        };
-        xsurface.configure(Some(bbox)).unwrap();
-        self.update_keyboard_focus();
-        //window.set_ssd(!xsurface.is_decorated());
+        if let Err(err) = self.map_x11_window(window) {
+            warn!(?err, "Unable to map X11 window");
+        }
     }
 
     fn mapped_override_redirect_window(&mut self, _xwm: XwmId, window: X11Surface) {
@@ -83,24 +115,46 @@ impl<BackendData: Backend> XwmHandler for AnvilState<BackendData> {
             self.unmap_window(&elem)
         }
         if !window.is_override_redirect() {
-            window.set_mapped(false).unwrap();
+            if let Err(err) = window.set_mapped(false) {
+                warn!(?err, "Unable to unmap X11 window");
+            }
         }
     }
 
-    fn destroyed_window(&mut self, _xwm: XwmId, _window: X11Surface) {}
+    fn destroyed_window(&mut self, _xwm: XwmId, window: X11Surface) {
+        // Windows destroyed while mapped don't always get unmapped first
+        let maybe = self
+            .elements
+            .iter()
+            .find(|e| matches!(e.x11_surface(), Some(w) if w == &window))
+            .cloned();
+        if let Some(elem) = maybe {
+            self.unmap_window(&elem);
+            self.update_keyboard_focus();
+        }
+    }
 
     fn configure_request(
         &mut self,
         _xwm: XwmId,
         window: X11Surface,
-        _x: Option<i32>,
-        _y: Option<i32>,
+        x: Option<i32>,
+        y: Option<i32>,
         w: Option<u32>,
         h: Option<u32>,
         _reorder: Option<Reorder>,
     ) {
-        // we just set the new size, but don't let windows move themselves around freely
+        // we just set the new size, but don't let windows move themselves around freely.
+        // Popups place themselves over the main window
         let mut geo = window.geometry();
+        if is_x11_popup(&window) {
+            if let Some(x) = x {
+                geo.loc.x = x;
+            }
+            if let Some(y) = y {
+                geo.loc.y = y;
+            }
+        }
         if let Some(w) = w {
             geo.size.w = w as i32;
         }
@@ -133,67 +187,20 @@ impl<BackendData: Backend> XwmHandler for AnvilState<BackendData> {
     }
 
     fn unmaximize_request(&mut self, _xwm: XwmId, window: X11Surface) {
-        let Some(elem) = self
-            .elements
-            .iter()
-            .find(|e| matches!(e.x11_surface(), Some(w) if w == &window))
-            .cloned()
-        else {
-            return;
-        };
-
-        window.set_maximized(false).unwrap();
-        if let Some(old_geo) = window
-            .user_data()
-            .get::<OldGeometry>()
-            .and_then(|data| data.restore())
-        {
-            window.configure(old_geo).unwrap();
-            self.map_window(&elem);
+        if let Err(err) = self.unmaximize_x11(&window) {
+            warn!(?err, "Unable to unmaximize X11 window");
         }
     }
 
     fn fullscreen_request(&mut self, _xwm: XwmId, window: X11Surface) {
-        let mut saved_elem = None;
-        if let Some(elem) = self
-            .elements
-            .iter()
-            .find(|e| matches!(e.x11_surface(), Some(w) if w == &window))
-        {
-            saved_elem = Some(elem.clone());
-        }
-        if let Some(elem) = saved_elem {
-            let old_geo = elem.bbox();
-
-            let geometry = fullscreen_output_geometry(&self.outputs);
-            window.set_fullscreen(true).unwrap();
-            window.configure(geometry).unwrap();
-
-            window.user_data().insert_if_missing(OldGeometry::default);
-            window
-                .user_data()
-                .get::<OldGeometry>()
-                .unwrap()
-                .save(old_geo);
-            self.map_window(&elem);
+        if let Err(err) = self.fullscreen_x11(&window) {
+            warn!(?err, "Unable to fullscreen X11 window");
         }
     }
 
     fn unfullscreen_request(&mut self, _xwm: XwmId, window: X11Surface) {
-        if let Some(_lem) = self
-            .elements
-            .iter()
-            .find(|e| matches!(e.x11_surface(), Some(w) if w == &window))
-        {
-            let _attempt_fs = window.set_fullscreen(false);
-
-            if let Some(old_geo) = window
-                .user_data()
-                .get::<OldGeometry>()
-                .and_then(|data| data.restore())
-            {
-                window.configure(old_geo).unwrap();
-            }
+        if let Err(err) = self.unfullscreen_x11(&window) {
+            warn!(?err, "Unable to unfullscreen X11 window");
         }
     }
 
@@ -255,7 +262,7 @@ impl<BackendData: Backend> XwmHandler for AnvilState<BackendData> {
             // check that an X11 window is focused
             if let Some(KeyboardFocusTarget::Window(w)) = keyboard.current_focus() {
                 if let Some(surface) = w.x11_surface() {
-                    if surface.xwm_id().unwrap() == xwm {
+                    if surface.xwm_id() == Some(xwm) {
                         return true;
                     }
                 }
@@ -264,6 +271,8 @@ impl<BackendData: Backend> XwmHandler for AnvilState<BackendData> {
         false
     }
 
+    /// Only hands over `fd`. The window manager splits large transfers into INCR
+    /// chunks for the X11 client, and reassembles the ones it reads from X11.
     fn send_selection(
         &mut self,
         _xwm: XwmId,
@@ -273,7 +282,13 @@ impl<BackendData: Backend> XwmHandler for AnvilState<BackendData> {
     ) {
         match selection {
             SelectionTarget::Clipboard => {
-                if let Err(err) = request_data_device_client_selection(&self.seat, mime_type, fd) {
+                if let Some(SelectionOrigin::Cached(contents)) =
+                    current_data_device_selection_userdata(&self.seat)
+                {
+                    send_cached_selection(&contents, &mime_type, fd);
+                } else if let Err(err) =
+                    request_data_device_client_selection(&self.seat, mime_type, fd)
+                {
                     error!(
                         ?err,
                         "Failed to request current wayland clipboard for Xwayland",
@@ -296,23 +311,37 @@ impl<BackendData: Backend> XwmHandler for AnvilState<BackendData> {
         // TODO check, that focused windows is X11 window before doing this
         match selection {
             SelectionTarget::Clipboard => {
-                set_data_device_selection(&self.display_handle, &self.seat, mime_types, ())
-            }
-            SelectionTarget::Primary => {
-                set_primary_selection(&self.display_handle, &self.seat, mime_types, ())
+                set_data_device_selection(
+                    &self.display_handle,
+                    &self.seat,
+                    mime_types,
+                    SelectionOrigin::Xwayland,
+                )
             }
+            SelectionTarget::Primary => set_primary_selection(
+                &self.display_handle,
+                &self.seat,
+                mime_types,
+                SelectionOrigin::Xwayland,
+            ),
         }
     }
 
     fn cleared_selection(&mut self, _xwm: XwmId, selection: SelectionTarget) {
         match selection {
             SelectionTarget::Clipboard => {
-                if current_data_device_selection_userdata(&self.seat).is_some() {
+                if matches!(
+                    current_data_device_selection_userdata(&self.seat),
+                    Some(SelectionOrigin::Xwayland)
+                ) {
                     clear_data_device_selection(&self.display_handle, &self.seat)
                 }
             }
             SelectionTarget::Primary => {
-                if current_primary_selection_userdata(&self.seat).is_some() {
+                if matches!(
+                    current_primary_selection_userdata(&self.seat),
+                    Some(SelectionOrigin::Xwayland)
+                ) {
                     clear_primary_selection(&self.display_handle, &self.seat)
                 }
             }
@@ -322,30 +351,206 @@ impl<BackendData: Backend> XwmHandler for AnvilState<BackendData> {
 
 impl<BackendData: Backend> AnvilState<BackendData> {
     pub fn maximize_request_x11(&mut self, window: &X11Surface) {
-        let Some(elem) = self
-            .elements
+        if let Err(err) = self.maximize_x11(window) {
+            warn!(?err, "Unable to maximize X11 window");
+        }
+    }
+
+    fn x11_element(&self, window: &X11Surface) -> Result<Window, ShellError> {
+        self.elements
             .iter()
             .find(|e| matches!(e.x11_surface(), Some(w) if w == window))
             .cloned()
-        else {
-            return;
-        };
+            .ok_or(ShellError::UnknownWindow)
+    }
+
+    fn map_x11_window(&mut self, window: X11Surface) -> Result<(), ShellError> {
+        window.set_mapped(true)?;
+        let elem = Window::new_x11_window(window.clone());
+        let geometry = self.x11_transient_geometry(&window);
+        place_new_window(&mut self.elements, &elem);
+        self.update_keyboard_focus();
+        window.configure(geometry.unwrap_or_else(|| elem.bbox()))?;
+        // Games often set their _NET_WM_STATE before mapping instead of asking
+        if window.is_fullscreen() {
+            self.fullscreen_x11(&window)?;
+        } else if window.is_maximized() {
+            self.maximize_x11(&window)?;
+        }
+        Ok(())
+    }
+
+    /// Give X11 windows the focused state of the window with the keyboard, and the
+    /// X11 stacking order of the windows. The window manager keeps
+    /// `_NET_CLIENT_LIST` and `_NET_ACTIVE_WINDOW` from them
+    pub fn update_x11_window_states(&mut self) {
+        let focused = self.elements.iter().find(|window| !is_hidden(window));
+        for window in &self.elements {
+            let Some(surface) = window.x11_surface() else {
+                continue;
+            };
+            if surface.is_override_redirect() {
+                continue;
+            }
+            if let Err(err) = surface.set_activated(Some(window) == focused) {
+                warn!(?err, "Unable to set the focused state of X11 window");
+            }
+        }
+        if let Some(xwm) = self.xwm.as_mut() {
+            xwm.update_stacking_order_downwards(self.elements.iter());
+        }
+    }
+
+    /// Transient windows left at the origin, like most dialogs, are centered over
+    /// their parent
+    fn x11_transient_geometry(&self, window: &X11Surface) -> Option<Rectangle<i32, Logical>> {
+        let parent_id = window.is_transient_for()?;
+        let mut geometry = window.geometry();
+        if geometry.loc != Point::from((0, 0)) {
+            return None;
+        }
+        let parent = self.elements.iter().find_map(|element| {
+            element
+                .x11_surface()
+                .filter(|surface| surface.window_id() == parent_id)
+                .map(|surface| surface.geometry())
+        })?;
+        geometry.loc = parent.loc
+            + Point::from((
+                (parent.size.w - geometry.size.w) / 2,
+                (parent.size.h - geometry.size.h) / 2,
+            ));
+        Some(geometry)
+    }
 
+    /// Size of a fullscreen or maximized window: the output size, the one the window
+    /// asked for, or the output size scaled down by its window rule
+    fn x11_fullscreen_geometry(
+        &self,
+        window: &X11Surface,
+        elem: &Window,
+    ) -> Result<Rectangle<i32, Logical>, ShellError> {
+        let output_geometry = fullscreen_output_geometry(self)?;
+        let scale = self
+            .config
+            .window_rule_for(elem)
+            .and_then(|rule| rule.x11_scale)
+            .filter(|scale| *scale > 0.0);
+        if let Some(scale) = scale {
+            let size = output_geometry
+                .size
+                .to_f64()
+                .downscale(scale)
+                .to_i32_round();
+            return Ok(Rectangle::from_loc_and_size(output_geometry.loc, size));
+        }
+        let requested = window.geometry().size;
+        if self.config.xwayland.keep_requested_size && requested.w > 0 && requested.h > 0 {
+            return Ok(Rectangle::from_loc_and_size(output_geometry.loc, requested));
+        }
+        Ok(output_geometry)
+    }
+
+    fn maximize_x11(&mut self, window: &X11Surface) -> Result<(), ShellError> {
+        let elem = self.x11_element(window)?;
         let old_geo = window.bbox();
+        let geometry = self.x11_fullscreen_geometry(window, &elem)?;
+        window.set_maximized(true)?;
+        window.configure(geometry)?;
+        save_old_geometry(window, old_geo);
+        self.map_window(&elem);
+        Ok(())
+    }
 
-        let geometry = fullscreen_output_geometry(&self.outputs);
-        window.set_maximized(true).unwrap();
-        window.configure(geometry).unwrap();
+    fn unmaximize_x11(&mut self, window: &X11Surface) -> Result<(), ShellError> {
+        let elem = self.x11_element(window)?;
+        window.set_maximized(false)?;
+        if let Some(old_geo) = restore_old_geometry(window) {
+            window.configure(old_geo)?;
+            self.map_window(&elem);
+        }
+        Ok(())
+    }
 
-        window.user_data().insert_if_missing(OldGeometry::default);
-        window
-            .user_data()
-            .get::<OldGeometry>()
-            .unwrap()
-            .save(old_geo);
+    fn fullscreen_x11(&mut self, window: &X11Surface) -> Result<(), ShellError> {
+        let elem = self.x11_element(window)?;
+        let old_geo = elem.bbox();
+        let geometry = self.x11_fullscreen_geometry(window, &elem)?;
+        window.set_fullscreen(true)?;
+        window.configure(geometry)?;
+        save_old_geometry(window, old_geo);
         self.map_window(&elem);
+        Ok(())
+    }
+
+    fn unfullscreen_x11(&mut self, window: &X11Surface) -> Result<(), ShellError> {
+        self.x11_element(window)?;
+        window.set_fullscreen(false)?;
+        if let Some(old_geo) = restore_old_geometry(window) {
+            window.configure(old_geo)?;
+        }
+        Ok(())
     }
 
     /* Move window by D&D. We do not support it as window positions do not exist */
     pub fn move_request_x11(&mut self, _window: &X11Surface) {}
 }
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Weak;
+
+    use smithay::xwayland::xwm::Atoms;
+
+    use crate::shell::testing::TestCompositor;
+
+    use super::*;
+
+    /// An X11 window of an Xwayland that's gone, as seen by requests still queued
+    fn orphaned_window(id: u32) -> X11Surface {
+        // Safety: the atoms are plain ids, and nothing is sent without a connection
+        let atoms: Atoms = unsafe { std::mem::zeroed() };
+        X11Surface::new(
+            None,
+            id,
+            false,
+            Weak::new(),
+            atoms,
+            Rectangle::from_loc_and_size((0, 0), (640, 480)),
+        )
+    }
+
+    #[test]
+    fn requests_for_unmapped_x11_window_fail() {
+        let mut compositor = TestCompositor::new();
+        let window = orphaned_window(1);
+        let state = &mut compositor.state;
+        assert!(matches!(
+            state.fullscreen_x11(&window),
+            Err(ShellError::UnknownWindow)
+        ));
+        assert!(matches!(
+            state.maximize_x11(&window),
+            Err(ShellError::UnknownWindow)
+        ));
+        assert!(matches!(
+            state.unfullscreen_x11(&window),
+            Err(ShellError::UnknownWindow)
+        ));
+        assert!(matches!(
+            state.unmaximize_x11(&window),
+            Err(ShellError::UnknownWindow)
+        ));
+        state.maximize_request_x11(&window);
+    }
+
+    #[test]
+    fn mapping_x11_window_of_gone_xwayland_fails() {
+        let mut compositor = TestCompositor::new();
+        let window = orphaned_window(1);
+        match compositor.state.map_x11_window(window) {
+            Ok(()) | Err(ShellError::X11(_)) => {}
+            Err(err) => panic!("Unexpected error mapping X11 window: {err}"),
+        }
+    }
+}
@@ -15,13 +15,13 @@ use smithay::{
             Resource,
         },
     },
-    utils::{Logical, Point, Serial},
+    utils::{Logical, Point, Serial, Size},
     wayland::{
         compositor::{self, with_states},
         seat::WaylandFocus,
         shell::xdg::{
-            Configure, PopupSurface, PositionerState, ToplevelSurface, XdgShellHandler,
-            XdgShellState,
+            Configure, PopupSurface, PositionerState, SurfaceCachedState, ToplevelSurface,
+            XdgShellHandler, XdgShellState,
         },
     },
 };
@@ -29,7 +29,9 @@ use tracing::{trace, warn};
 
 use crate::state::{AnvilState, Backend};
 
-use super::{fullscreen_output_geometry, place_new_window, FullscreenSurface, SurfaceData};
+use super::{
+    fullscreen_output_geometry, place_new_window, FullscreenSurface, ShellError, SurfaceData,
+};
 
 impl<BackendData: Backend> XdgShellHandler for AnvilState<BackendData> {
     fn xdg_shell_state(&mut self) -> &mut XdgShellState {
@@ -269,46 +271,15 @@ impl<BackendData: Backend> XdgShellHandler for AnvilState<BackendData> {
     fn fullscreen_request(
         &mut self,
         surface: ToplevelSurface,
-        mut wl_output: Option<wl_output::WlOutput>,
+        wl_output: Option<wl_output::WlOutput>,
     ) {
         if surface
             .current_state()
             .capabilities
             .contains(xdg_toplevel::WmCapabilities::Fullscreen)
         {
-            let wl_surface = surface.wl_surface();
-
-            let output_geometry = fullscreen_output_geometry(&self.outputs);
-
-            if let Some(geometry) = output_geometry {
-                let output = wl_output
-                    .as_ref()
-                    .and_then(Output::from_resource)
-                    .unwrap_or_else(|| self.outputs.iter().next().unwrap().clone());
-                let client = match self.display_handle.get_client(wl_surface.id()) {
-                    Ok(client) => client,
-                    Err(_) => return,
-                };
-                for output in output.client_outputs(&client) {
-                    wl_output = Some(output);
-                }
-                let window = self
-                    .elements
-                    .iter()
-                    .find(|window| {
-                        window
-                            .wl_surface()
-                            .map(|s| &*s == wl_surface)
-                            .unwrap_or(false)
-                    })
-                    .unwrap();
-
-                surface.with_pending_state(|state| {
-                    state.states.set(xdg_toplevel::State::Fullscreen);
-                    state.size = Some(geometry.size);
-                    state.fullscreen_output = wl_output;
-                });
-                trace!("Fullscreening: {:?}", window);
+            if let Err(err) = self.fullscreen_toplevel(&surface, wl_output) {
+                warn!(?err, "Unable to fullscreen window");
             }
         }
 
@@ -331,8 +302,7 @@ impl<BackendData: Backend> XdgShellHandler for AnvilState<BackendData> {
             state.size = None;
             state.fullscreen_output.take()
         });
-        if let Some(output) = ret {
-            let output = Output::from_resource(&output).unwrap();
+        if let Some(output) = ret.as_ref().and_then(Output::from_resource) {
             if let Some(fullscreen) = output.user_data().get::<FullscreenSurface>() {
                 trace!("Unfullscreening: {:?}", fullscreen.get());
                 fullscreen.clear();
@@ -349,16 +319,9 @@ impl<BackendData: Backend> XdgShellHandler for AnvilState<BackendData> {
             .capabilities
             .contains(xdg_toplevel::WmCapabilities::Maximize)
         {
-            let _window = self.window_for_surface(surface.wl_surface()).unwrap();
-
-            let geometry = fullscreen_output_geometry(&self.outputs).unwrap();
-
-            surface.with_pending_state(|state| {
-                state.states.set(xdg_toplevel::State::Maximized);
-                state.size = Some(geometry.size);
-            });
-
-            //self.space.map_element(window, geometry.loc, true);
+            if let Err(err) = self.maximize_toplevel(&surface) {
+                warn!(?err, "Unable to maximize window");
+            }
         }
 
         // The protocol demands us to always reply with a configure,
@@ -388,16 +351,9 @@ impl<BackendData: Backend> XdgShellHandler for AnvilState<BackendData> {
             .capabilities
             .contains(xdg_toplevel::WmCapabilities::Maximize)
         {
-            let _window = self.window_for_surface(surface.wl_surface()).unwrap();
-
-            let geometry = fullscreen_output_geometry(&self.outputs).unwrap();
-
-            surface.with_pending_state(|state| {
-                state.states.set(xdg_toplevel::State::Maximized);
-                state.size = Some(geometry.size);
-            });
-
-            //self.space.map_element(window, geometry.loc, true);
+            if let Err(err) = self.maximize_toplevel(&surface) {
+                warn!(?err, "Unable to maximize window");
+            }
         }
 
         // The protocol demands us to always reply with a configure,
@@ -479,6 +435,66 @@ impl<BackendData: Backend> AnvilState<BackendData> {
             state.geometry = state.positioner.get_unconstrained_geometry(target);
         });
     }
+
+    fn fullscreen_toplevel(
+        &mut self,
+        surface: &ToplevelSurface,
+        mut wl_output: Option<wl_output::WlOutput>,
+    ) -> Result<(), ShellError> {
+        let wl_surface = surface.wl_surface();
+        let geometry = fullscreen_output_geometry(self)?;
+        let output = wl_output
+            .as_ref()
+            .and_then(Output::from_resource)
+            .or_else(|| self.outputs.first().cloned())
+            .ok_or(ShellError::NoOutput)?;
+        let client = self
+            .display_handle
+            .get_client(wl_surface.id())
+            .map_err(|_| ShellError::UnknownClient)?;
+        for output in output.client_outputs(&client) {
+            wl_output = Some(output);
+        }
+        let window = self
+            .window_for_surface(wl_surface)
+            .ok_or(ShellError::UnknownWindow)?;
+
+        surface.with_pending_state(|state| {
+            state.states.set(xdg_toplevel::State::Fullscreen);
+            state.size = Some(bounded_size(surface, geometry.size));
+            state.fullscreen_output = wl_output;
+        });
+        trace!("Fullscreening: {:?}", window);
+        Ok(())
+    }
+
+    fn maximize_toplevel(&mut self, surface: &ToplevelSurface) -> Result<(), ShellError> {
+        self.window_for_surface(surface.wl_surface())
+            .ok_or(ShellError::UnknownWindow)?;
+        let geometry = fullscreen_output_geometry(self)?;
+
+        surface.with_pending_state(|state| {
+            state.states.set(xdg_toplevel::State::Maximized);
+            state.size = Some(bounded_size(surface, geometry.size));
+        });
+        Ok(())
+    }
+}
+
+/// `size` within the minimum and maximum size the toplevel advertises, zero
+/// meaning unbounded. Fixed-size windows keep their size, and get letterboxed
+/// by the scaler instead
+fn bounded_size(surface: &ToplevelSurface, size: Size<i32, Logical>) -> Size<i32, Logical> {
+    let (min, max) = with_states(surface.wl_surface(), |states| {
+        let mut cached = states.cached_state.get::<SurfaceCachedState>();
+        let current = cached.current();
+        (current.min_size, current.max_size)
+    });
+    let bound = |size: i32, min: i32, max: i32| {
+        let size = if max > 0 { size.min(max) } else { size };
+        size.max(min)
+    };
+    Size::from((bound(size.w, min.w, max.w), bound(size.h, min.h, max.h)))
 }
 
 /// Should be called on `WlSurface::commit` of xdg toplevel
@@ -500,3 +516,60 @@ fn handle_toplevel_commit(elements: &Vec<Window>, surface: &WlSurface) -> Option
 
     Some(())
 }
+
+#[cfg(test)]
+mod tests {
+    use crate::shell::testing::TestCompositor;
+
+    use super::*;
+
+    #[test]
+    fn requests_for_destroyed_toplevel_fail() {
+        let mut compositor = TestCompositor::new();
+        let mut client = compositor.connect();
+        let toplevel = client.create_toplevel();
+        compositor.roundtrip(&mut client);
+        let surface = compositor.state.elements[0].toplevel().unwrap().clone();
+
+        toplevel.destroy();
+        compositor.roundtrip(&mut client);
+        assert!(compositor.state.elements.is_empty());
+
+        let state = &mut compositor.state;
+        assert!(matches!(
+            state.fullscreen_toplevel(&surface, None),
+            Err(ShellError::UnknownWindow | ShellError::UnknownClient)
+        ));
+        assert!(matches!(
+            state.maximize_toplevel(&surface),
+            Err(ShellError::UnknownWindow)
+        ));
+        state.fullscreen_request(surface.clone(), None);
+        state.maximize_request(surface);
+    }
+
+    #[test]
+    fn requests_after_client_hung_up_fail() {
+        let mut compositor = TestCompositor::new();
+        let mut client = compositor.connect();
+        let _toplevel = client.create_toplevel();
+        compositor.roundtrip(&mut client);
+        let surface = compositor.state.elements[0].toplevel().unwrap().clone();
+
+        drop(client);
+        compositor.dispatch();
+        assert!(compositor.state.elements.is_empty());
+
+        let state = &mut compositor.state;
+        assert!(matches!(
+            state.fullscreen_toplevel(&surface, None),
+            Err(ShellError::UnknownClient)
+        ));
+        assert!(matches!(
+            state.maximize_toplevel(&surface),
+            Err(ShellError::UnknownWindow)
+        ));
+        state.fullscreen_request(surface.clone(), None);
+        state.maximize_request(surface);
+    }
+}
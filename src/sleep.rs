@@ -0,0 +1,97 @@
+use std::{sync::mpsc, thread, time::Duration};
+
+use smithay::reexports::calloop::channel::{channel, Event, Sender};
+use tracing::{debug, info, warn};
+use zbus::{blocking::Connection, zvariant::OwnedFd};
+
+use crate::{state::AnvilState, udev::UdevData};
+
+/// How long sleep is held up for the outputs to pause
+const PAUSE_TIMEOUT: Duration = Duration::from_secs(2);
+
+#[zbus::proxy(
+    interface = "org.freedesktop.login1.Manager",
+    default_service = "org.freedesktop.login1",
+    default_path = "/org/freedesktop/login1"
+)]
+trait LoginManager {
+    fn inhibit(&self, what: &str, who: &str, why: &str, mode: &str) -> zbus::Result<OwnedFd>;
+
+    #[zbus(signal)]
+    fn prepare_for_sleep(&self, start: bool) -> zbus::Result<()>;
+}
+
+/// Delays sleep until dropped, so the outputs pause first
+fn delay_lock(manager: &LoginManagerProxyBlocking<'_>) -> Option<OwnedFd> {
+    manager
+        .inhibit("sleep", "consolation", "Pausing the outputs", "delay")
+        .map_err(|err| warn!(?err, "Unable to delay sleep"))
+        .ok()
+}
+
+/// Forward `PrepareForSleep` of logind to the event loop, releasing the delay
+/// lock once it paused
+fn watch_sleep(sleeping: Sender<bool>, paused: mpsc::Receiver<()>) {
+    let manager = match Connection::system()
+        .and_then(|connection| LoginManagerProxyBlocking::new(&connection))
+    {
+        Ok(manager) => manager,
+        Err(err) => {
+            warn!(?err, "Unable to connect to logind");
+            return;
+        }
+    };
+    let signals = match manager.receive_prepare_for_sleep() {
+        Ok(signals) => signals,
+        Err(err) => {
+            warn!(?err, "Unable to watch for sleep");
+            return;
+        }
+    };
+    let mut lock = delay_lock(&manager);
+    for signal in signals {
+        let start = match signal.args() {
+            Ok(args) => *args.start(),
+            Err(err) => {
+                warn!(?err, "Invalid sleep signal");
+                continue;
+            }
+        };
+        if sleeping.send(start).is_err() {
+            break;
+        }
+        if start {
+            if paused.recv_timeout(PAUSE_TIMEOUT).is_err() {
+                warn!("Outputs didn't pause in time for sleep");
+            }
+            lock = None;
+        } else if lock.is_none() {
+            lock = delay_lock(&manager);
+        }
+    }
+}
+
+impl AnvilState<UdevData> {
+    /// Pause the outputs before the system sleeps, and set them up anew after
+    pub fn start_sleep_watch(&mut self) {
+        let (sender, sleeping) = channel();
+        let (paused_sender, paused) = mpsc::channel();
+        let res = self.handle.insert_source(sleeping, move |event, _, data| {
+            if let Event::Msg(start) = event {
+                debug!(start, "Prepare for sleep");
+                if start {
+                    data.pause_for_sleep();
+                    let _ = paused_sender.send(());
+                } else {
+                    data.resume_from_sleep();
+                }
+            }
+        });
+        if let Err(err) = res {
+            warn!(?err, "Unable to insert sleep source");
+            return;
+        }
+        thread::spawn(move || watch_sleep(sender, paused));
+        info!("Watching for system sleep");
+    }
+}
@@ -0,0 +1,432 @@
+use std::time::Duration;
+
+use smithay::{
+    backend::input::{
+        Device, Event, InputBackend, InputEvent, KeyboardKeyEvent, PointerButtonEvent,
+        PointerMotionEvent,
+    },
+    desktop::{layer_map_for_output, Window},
+    input::{
+        keyboard::{FilterResult, Keysym, XkbConfig},
+        pointer::{ButtonEvent, MotionEvent, RelativeMotionEvent},
+        Seat,
+    },
+    utils::{Logical, Point, Rectangle, Size, SERIAL_COUNTER as SCOUNTER},
+    wayland::seat::WaylandFocus,
+};
+use tracing::{debug, info, warn};
+use xkbcommon::xkb;
+use zbus::{blocking::Connection, fdo};
+
+use crate::{
+    dbus::Requests,
+    focus::{KeyboardFocusTarget, PointerFocusTarget},
+    input_handler::KeyAction,
+    render::{split_zones, WindowLayout},
+    shell::{is_hidden, is_popup, window_app_id, window_geometry},
+    state::{AnvilState, Backend},
+};
+
+const BUS_NAME: &str = "org.consolation.SplitScreen";
+const OBJECT_PATH: &str = "/org/consolation/SplitScreen";
+
+/// The shown window and a second one sharing the outputs, for couch co-op
+#[derive(Debug, Clone)]
+pub struct SplitScreen {
+    /// Window of the second seat
+    pub window: Window,
+    /// The shown window when last checked, sized to the first half
+    first: Window,
+}
+
+#[derive(Debug)]
+enum Request {
+    Toggle,
+    Show(String),
+    End,
+}
+
+/// `org.consolation.SplitScreen` object, forwarding calls to the event loop
+struct SplitScreenServer {
+    requests: Requests<Request, Result<(), String>>,
+}
+
+#[zbus::interface(name = "org.consolation.SplitScreen")]
+impl SplitScreenServer {
+    /// Split with the most recently used other window, or end the split
+    async fn toggle(&self) -> fdo::Result<()> {
+        self.requests.try_call(Request::Toggle).await
+    }
+
+    /// Split with the most recently used window of `app_id`
+    async fn show(&self, app_id: String) -> fdo::Result<()> {
+        self.requests.try_call(Request::Show(app_id)).await
+    }
+
+    async fn end(&self) -> fdo::Result<()> {
+        self.requests.try_call(Request::End).await
+    }
+}
+
+#[zbus::proxy(
+    interface = "org.consolation.SplitScreen",
+    default_service = "org.consolation.SplitScreen",
+    default_path = "/org/consolation/SplitScreen"
+)]
+trait ConsolationSplitScreen {
+    fn toggle(&self) -> zbus::Result<()>;
+    fn show(&self, app_id: &str) -> zbus::Result<()>;
+    fn end(&self) -> zbus::Result<()>;
+}
+
+fn proxy() -> zbus::Result<ConsolationSplitScreenProxyBlocking<'static>> {
+    let connection = Connection::session()?;
+    ConsolationSplitScreenProxyBlocking::new(&connection)
+}
+
+/// Ask the running compositor to split the screen or end the split
+pub fn request_toggle() -> zbus::Result<()> {
+    proxy()?.toggle()
+}
+
+/// Ask the running compositor to split the screen with a window of `app_id`
+pub fn request_show(app_id: &str) -> zbus::Result<()> {
+    proxy()?.show(app_id)
+}
+
+pub fn request_end() -> zbus::Result<()> {
+    proxy()?.end()
+}
+
+/// Ask `window` to take `size`, like fullscreen windows are given the output size
+fn configure_size(window: &Window, size: Size<i32, Logical>) {
+    if let Some(toplevel) = window.toplevel() {
+        toplevel.with_pending_state(|state| state.size = Some(size));
+        toplevel.send_pending_configure();
+    }
+    #[cfg(feature = "xwayland")]
+    if let Some(surface) = window.x11_surface() {
+        let mut geometry = surface.geometry();
+        geometry.size = size;
+        if let Err(err) = surface.configure(geometry) {
+            warn!(?err, "Unable to resize X11 window");
+        }
+    }
+}
+
+impl<BackendData: Backend> AnvilState<BackendData> {
+    /// Serve `org.consolation.SplitScreen`, so `consolation --split` and launchers
+    /// can start two player sessions
+    pub fn start_split_screen(&mut self) {
+        let Some(requests) = self.insert_requests(|data, request| match request {
+            Request::Toggle => {
+                data.toggle_split_screen();
+                Ok(())
+            }
+            Request::Show(app_id) => data.split_with_app(&app_id),
+            Request::End => {
+                data.end_split_screen();
+                Ok(())
+            }
+        }) else {
+            return;
+        };
+        let server = SplitScreenServer { requests };
+        match self.dbus.serve(BUS_NAME, OBJECT_PATH, server, false) {
+            Ok(true) => info!("Serving split screen"),
+            Ok(false) => warn!("Split screen bus name is already taken"),
+            Err(err) => warn!(?err, "Unable to serve split screen"),
+        }
+    }
+
+    fn usable_zone(&self) -> Option<Rectangle<i32, Logical>> {
+        let output = self.outputs.first()?;
+        Some(layer_map_for_output(output).non_exclusive_zone())
+    }
+
+    /// The seat of the second window, added to the globals on first use
+    fn second_seat(&mut self) -> Seat<Self> {
+        if let Some(seat) = self.second_seat.clone() {
+            return seat;
+        }
+        let mut seat = self
+            .seat_state
+            .new_wl_seat(&self.display_handle, format!("{}-2", self.seat_name));
+        seat.add_pointer();
+        if let Err(err) = seat.add_keyboard(XkbConfig::default(), 200, 25) {
+            warn!(?err, "Unable to add a keyboard to the second seat");
+        }
+        self.second_seat = Some(seat.clone());
+        seat
+    }
+
+    fn focus_second_seat(&mut self, window: Option<Window>) {
+        let Some(seat) = self.second_seat.clone() else {
+            return;
+        };
+        if let Some(keyboard) = seat.get_keyboard() {
+            keyboard.set_focus(
+                self,
+                window.clone().map(KeyboardFocusTarget::from),
+                SCOUNTER.next_serial(),
+            );
+        }
+        if let Some(pointer) = seat.get_pointer() {
+            let focus = window
+                .as_ref()
+                .and_then(|window| window.wl_surface())
+                .map(|surface| {
+                    (
+                        PointerFocusTarget::from(surface.into_owned()),
+                        Point::from((0.0, 0.0)),
+                    )
+                });
+            pointer.motion(
+                self,
+                focus,
+                &MotionEvent {
+                    location: pointer.current_location(),
+                    serial: SCOUNTER.next_serial(),
+                    time: Duration::from(self.clock.now()).as_millis() as u32,
+                },
+            );
+            pointer.frame(self);
+        }
+    }
+
+    /// Windows the screen can be split with, most recently used first
+    fn split_candidates(&self) -> impl Iterator<Item = &Window> {
+        let current = self.current_window().0;
+        self.elements.iter().filter(move |window| {
+            Some(*window) != current.as_ref() && !is_popup(window) && !is_hidden(window)
+        })
+    }
+
+    fn split_with(&mut self, window: Window) {
+        let (Some(first), Some(zone)) = (self.current_window().0, self.usable_zone()) else {
+            return;
+        };
+        debug!(app_id = ?window_app_id(&window), "Splitting the screen");
+        if self
+            .picture_in_picture
+            .as_ref()
+            .is_some_and(|pip| pip.window == window)
+        {
+            self.picture_in_picture = None;
+        }
+        if self
+            .window_peek
+            .as_ref()
+            .is_some_and(|peek| peek.window == window)
+        {
+            self.window_peek = None;
+        }
+        if let Some(split) = self.split_screen.take() {
+            if let Some(zone) = self.usable_zone() {
+                configure_size(&split.window, zone.size);
+            }
+        }
+
+        let (first_zone, second_zone) = split_zones(zone, self.config.split_screen.orientation);
+        configure_size(&first, first_zone.size);
+        configure_size(&window, second_zone.size);
+        self.split_screen = Some(SplitScreen {
+            window: window.clone(),
+            first,
+        });
+        self.second_seat();
+        self.focus_second_seat(Some(window));
+    }
+
+    pub fn toggle_split_screen(&mut self) {
+        if self.split_screen.is_some() {
+            self.end_split_screen();
+            return;
+        }
+        if let Some(window) = self.split_candidates().next().cloned() {
+            self.split_with(window);
+        }
+    }
+
+    fn split_with_app(&mut self, app_id: &str) -> Result<(), String> {
+        let window = self
+            .split_candidates()
+            .find(|window| window_app_id(window).as_deref() == Some(app_id))
+            .cloned()
+            .ok_or_else(|| format!("No window of {app_id} in the background"))?;
+        if self.current_window().0.is_none() {
+            return Err("No window is shown".into());
+        }
+        self.split_with(window);
+        Ok(())
+    }
+
+    /// Give both windows the whole usable area again
+    pub fn end_split_screen(&mut self) {
+        let Some(split) = self.split_screen.take() else {
+            return;
+        };
+        debug!("Ending split screen");
+        if let Some(zone) = self.usable_zone() {
+            for window in [&split.first, &split.window] {
+                if self.elements.contains(window) {
+                    configure_size(window, zone.size);
+                }
+            }
+        }
+        self.focus_second_seat(None);
+    }
+
+    /// Check if `keysym` splits the screen or ends the split
+    pub fn split_screen_key_action(&self, keysym: Keysym) -> Option<KeyAction> {
+        let toggle_key = self.config.split_screen.toggle_key.as_ref()?;
+        let toggle_key = xkb::keysym_from_name(toggle_key, xkb::KEYSYM_CASE_INSENSITIVE);
+        (toggle_key.raw() == keysym.raw()).then_some(KeyAction::ToggleSplitScreen)
+    }
+
+    /// End the split once the second window is gone or became the shown one, and
+    /// give the first half to the window shown instead of the first
+    pub fn refresh_split_screen(&mut self) {
+        let Some(split) = self.split_screen.as_ref() else {
+            return;
+        };
+        let current = self.current_window().0;
+        let Some(current) = current.filter(|current| {
+            *current != split.window
+                && self.elements.contains(&split.window)
+                && !is_hidden(&split.window)
+        }) else {
+            self.end_split_screen();
+            return;
+        };
+        if current == split.first {
+            return;
+        }
+        let previous = split.first.clone();
+        if let Some(zone) = self.usable_zone() {
+            let (first_zone, _) = split_zones(zone, self.config.split_screen.orientation);
+            configure_size(&current, first_zone.size);
+            if self.elements.contains(&previous) {
+                configure_size(&previous, zone.size);
+            }
+        }
+        if let Some(split) = self.split_screen.as_mut() {
+            split.first = current;
+        }
+    }
+
+    /// Handle `event` with the second seat when it comes from one of its devices
+    /// during a split, else give it back
+    pub fn split_screen_input<B: InputBackend>(
+        &mut self,
+        event: InputEvent<B>,
+    ) -> Option<InputEvent<B>> {
+        let (Some(split), Some(seat)) = (self.split_screen.as_ref(), self.second_seat.clone())
+        else {
+            return Some(event);
+        };
+        let device = match &event {
+            InputEvent::Keyboard { event, .. } => event.device(),
+            InputEvent::PointerMotion { event, .. } => event.device(),
+            InputEvent::PointerButton { event, .. } => event.device(),
+            InputEvent::PointerAxis { event, .. } => event.device(),
+            _ => return Some(event),
+        };
+        if !self.config.split_screen.devices.contains(&device.name()) {
+            return Some(event);
+        }
+        let window = split.window.clone();
+        let focus = window.wl_surface().map(|surface| {
+            (
+                PointerFocusTarget::from(surface.into_owned()),
+                Point::from((0.0, 0.0)),
+            )
+        });
+
+        match event {
+            InputEvent::Keyboard { event, .. } => {
+                if let Some(keyboard) = seat.get_keyboard() {
+                    keyboard.input(
+                        self,
+                        event.key_code(),
+                        event.state(),
+                        SCOUNTER.next_serial(),
+                        event.time_msec(),
+                        |_, _, _| FilterResult::Forward::<()>,
+                    );
+                }
+            }
+            InputEvent::PointerMotion { event, .. } => {
+                let Some(pointer) = seat.get_pointer() else {
+                    return None;
+                };
+                pointer.relative_motion(
+                    self,
+                    focus.clone(),
+                    &RelativeMotionEvent {
+                        delta: event.delta(),
+                        delta_unaccel: event.delta_unaccel(),
+                        utime: event.time(),
+                    },
+                );
+                // Like the first pointer, this one stays within its window
+                let geometry = window_geometry(&window).to_f64();
+                let location = pointer.current_location() + event.delta();
+                let location = Point::from((
+                    location
+                        .x
+                        .clamp(geometry.loc.x, geometry.loc.x + geometry.size.w),
+                    location
+                        .y
+                        .clamp(geometry.loc.y, geometry.loc.y + geometry.size.h),
+                ));
+                pointer.motion(
+                    self,
+                    focus,
+                    &MotionEvent {
+                        location,
+                        serial: SCOUNTER.next_serial(),
+                        time: event.time_msec(),
+                    },
+                );
+                pointer.frame(self);
+            }
+            InputEvent::PointerButton { event, .. } => {
+                let Some(pointer) = seat.get_pointer() else {
+                    return None;
+                };
+                pointer.button(
+                    self,
+                    &ButtonEvent {
+                        button: event.button_code(),
+                        state: event.state(),
+                        serial: SCOUNTER.next_serial(),
+                        time: event.time_msec(),
+                    },
+                );
+                pointer.frame(self);
+            }
+            InputEvent::PointerAxis { event, .. } => {
+                let Some(pointer) = seat.get_pointer() else {
+                    return None;
+                };
+                let frame = self.pointer_axis_frame::<B>(&event);
+                pointer.axis(self, frame);
+                pointer.frame(self);
+            }
+            _ => {}
+        }
+        None
+    }
+
+    /// Windows drawn besides the shown one
+    pub fn window_layout(&self) -> WindowLayout {
+        WindowLayout {
+            insets: self.inset_windows(),
+            split: self
+                .split_screen
+                .as_ref()
+                .map(|split| (split.window.clone(), self.config.split_screen.orientation)),
+        }
+    }
+}
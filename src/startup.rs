@@ -0,0 +1,104 @@
+use std::process::Command;
+
+use smithay::{
+    backend::renderer::{
+        element::{
+            solid::{SolidColorBuffer, SolidColorRenderElement},
+            Kind,
+        },
+        ImportAll, ImportMem, Renderer, Texture,
+    },
+    output::Output,
+    utils::Scale,
+};
+use tracing::{debug, info, warn};
+
+use crate::{
+    drawing::{BackgroundElement, CLEAR_COLOR},
+    render::CustomRenderElements,
+    state::{AnvilState, Backend, BackgroundScale, ServiceConfiguration},
+};
+
+/// Image shown over everything else until the first window maps
+#[derive(Debug)]
+pub struct SplashScreen<T: Texture> {
+    image: BackgroundElement<T>,
+    backdrop: SolidColorBuffer,
+}
+
+impl<T: Texture + Clone + 'static> SplashScreen<T> {
+    pub fn new(texture: T) -> Self {
+        Self {
+            image: BackgroundElement::new(texture, BackgroundScale::Fit),
+            backdrop: SolidColorBuffer::new((0, 0), CLEAR_COLOR),
+        }
+    }
+
+    pub fn render_elements<R>(&mut self, output: &Output) -> Vec<CustomRenderElements<R>>
+    where
+        R: Renderer<TextureId = T> + ImportAll + ImportMem,
+    {
+        let Some(mode) = output.current_mode() else {
+            return Vec::new();
+        };
+        let size = output.current_transform().transform_size(mode.size);
+        self.image.position(size);
+        self.backdrop.resize((size.w, size.h));
+        vec![
+            CustomRenderElements::Background(self.image.clone()),
+            CustomRenderElements::Solid(SolidColorRenderElement::from_buffer(
+                &self.backdrop,
+                (0, 0),
+                Scale::from(1.0),
+                1.0,
+                Kind::Unspecified,
+            )),
+        ]
+    }
+}
+
+impl<BackendData: Backend> AnvilState<BackendData> {
+    /// Start the session applications of the configuration, keeping the critical ones running
+    pub fn start_session_applications(&mut self) {
+        for application in self.config.startup.applications.clone() {
+            if application.critical {
+                self.supervise_session_application(ServiceConfiguration {
+                    name: application.name,
+                    command: application.command,
+                    args: application.args,
+                });
+                continue;
+            }
+            match Command::new(&application.command)
+                .args(&application.args)
+                .envs(self.client_environment())
+                .spawn()
+            {
+                Ok(child) => {
+                    info!(
+                        name = application.name,
+                        pid = child.id(),
+                        "Started session application"
+                    );
+                    self.launched.insert(child.id());
+                }
+                Err(err) => {
+                    warn!(
+                        name = application.name,
+                        ?err,
+                        "Unable to start session application"
+                    )
+                }
+            }
+        }
+    }
+
+    /// Whether the splash screen is still shown. It is hidden for good once a window maps
+    pub fn refresh_splash(&mut self) -> bool {
+        if self.splash && self.elements.iter().any(|window| !window.bbox().is_empty()) {
+            debug!("First window mapped, hiding the splash screen");
+            self.splash = false;
+        }
+        self.splash
+    }
+}
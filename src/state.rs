@@ -1,33 +1,82 @@
 use notify::{Event, RecommendedWatcher, RecursiveMode, Watcher};
 use serde::{Deserialize, Serialize};
 use std::{
+    cell::{Cell, RefCell},
     collections::HashMap,
-    os::unix::io::OwnedFd,
-    sync::{atomic::AtomicBool, Arc},
-    time::Duration,
+    os::{
+        linux::net::SocketAddrExt,
+        unix::{
+            io::OwnedFd,
+            net::{SocketAddr, UnixListener},
+        },
+    },
+    path::PathBuf,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, OnceLock,
+    },
+    time::{Duration, Instant},
 };
-use tracing::{info, warn};
+use tracing::{debug, error, info, warn};
 
 use crate::{
-    delegate_foreign_toplevel, delegate_output_management,
+    animation::AnimationClock,
+    backlight::Backlight,
+    clipboard::{send_cached_selection, ClipboardStore, SelectionOrigin, DISCONNECTED_CLIENTS},
+    dbus::DBusServer,
+    delegate_foreign_toplevel, delegate_hud, delegate_output_management, delegate_scaling,
+    delegate_tearing_control,
+    dock::DockState,
+    drawing::CLEAR_COLOR,
+    gestures::GestureDetector,
+    key_actions::CustomKeyAction,
+    metrics::{Metrics, CONNECTED_CLIENTS},
+    notifications::Notifications,
+    picture_in_picture::PictureInPicture,
+    policy::{client_executable, executable_allowed, PrivilegedProtocol, ProtocolPolicy},
+    progress::ProgressReports,
+    protocol_trace::ProtocolTrace,
+    screenshot::ScreenshotState,
+    services::ServiceSupervisor,
     shell::{
+        content_type::window_content_policy,
+        hud::{self, HudHandler, HudManagerState},
+        is_hidden, is_minimized,
+        launch_groups::{is_descendant, LaunchedCommands},
         output_manager::{
             self, OutputId, OutputManagementHandler, OutputManagementManagerState, Outputs,
         },
-        toplevel_manager::{ForeignToplevelHandler, ForeignToplevelManagerState},
+        peek::WindowPeek,
+        scaling::{self, ScalingHandler, ScalingManagerState},
+        set_minimized,
+        tearing::TearingControlState,
+        toplevel_manager::{self, ForeignToplevelHandler, ForeignToplevelManagerState},
+        transition::WindowTransitions,
+        window_app_id, window_sandbox_app_id,
     },
+    split_screen::SplitScreen,
+    status::StatusOverlay,
+    text_entry::TextEntry,
+    time_settings::TimeState,
+    watchdog::AppWatchdog,
+    window_cycling::WindowCycling,
+    zoom::ZoomState,
 };
 use smithay::{
     backend::{
+        allocator::dmabuf::Dmabuf,
         input::TabletToolDescriptor,
-        renderer::element::{
-            default_primary_scanout_output_compare, utils::select_dmabuf_feedback,
-            RenderElementStates,
+        renderer::{
+            element::{
+                default_primary_scanout_output_compare, utils::select_dmabuf_feedback,
+                RenderElementStates,
+            },
+            utils::RendererSurfaceStateUserData,
         },
     },
-    delegate_compositor, delegate_data_control, delegate_data_device, delegate_fractional_scale,
-    delegate_input_method_manager, delegate_keyboard_shortcuts_inhibit, delegate_layer_shell,
-    delegate_output, delegate_pointer_constraints, delegate_pointer_gestures,
+    delegate_compositor, delegate_content_type, delegate_data_control, delegate_data_device,
+    delegate_fractional_scale, delegate_input_method_manager, delegate_keyboard_shortcuts_inhibit,
+    delegate_layer_shell, delegate_output, delegate_pointer_constraints, delegate_pointer_gestures,
     delegate_presentation, delegate_primary_selection, delegate_relative_pointer, delegate_seat,
     delegate_security_context, delegate_shm, delegate_tablet_manager, delegate_text_input_manager,
     delegate_viewporter, delegate_virtual_keyboard_manager, delegate_xdg_activation,
@@ -46,22 +95,29 @@ use smithay::{
     },
     output::Output,
     reexports::{
-        calloop::{generic::Generic, Interest, LoopHandle, Mode, PostAction},
+        calloop::{
+            channel::{self, channel},
+            generic::Generic,
+            timer::{TimeoutAction, Timer},
+            Interest, LoopHandle, Mode, PostAction,
+        },
         wayland_protocols::xdg::decoration::{
             self as xdg_decoration,
             zv1::server::zxdg_toplevel_decoration_v1::Mode as DecorationMode,
         },
+        wayland_protocols_wlr::foreign_toplevel::v1::server::zwlr_foreign_toplevel_handle_v1::ZwlrForeignToplevelHandleV1,
         wayland_server::{
             self,
-            backend::{ClientData, ClientId, DisconnectReason},
+            backend::{ClientData, ClientId, Credentials, DisconnectReason},
             protocol::{wl_data_source::WlDataSource, wl_output::WlOutput, wl_surface::WlSurface},
-            Display, DisplayHandle, Resource,
+            Client, Display, DisplayHandle, Resource,
         },
     },
-    utils::{Clock, Monotonic, Rectangle},
+    utils::{Clock, Logical, Monotonic, Physical, Point, Rectangle, Size},
     wayland::{
-        compositor::{with_states, CompositorClientState, CompositorState},
-        dmabuf::DmabufFeedback,
+        compositor::{get_children, with_states, CompositorClientState, CompositorState},
+        content_type::ContentTypeState,
+        dmabuf::{get_dmabuf, DmabufFeedback},
         fractional_scale::{
             with_fractional_scale, FractionalScaleHandler, FractionalScaleManagerState,
         },
@@ -91,7 +147,7 @@ use smithay::{
                 set_primary_focus, PrimarySelectionHandler, PrimarySelectionState,
             },
             wlr_data_control::{DataControlHandler, DataControlState},
-            SelectionHandler,
+            SelectionHandler, SelectionSource, SelectionTarget,
         },
         shell::{
             wlr_layer::WlrLayerShellState,
@@ -117,53 +173,1434 @@ use smithay::{
 
 #[cfg(feature = "xwayland")]
 use crate::cursor::Cursor;
+#[cfg(feature = "udev")]
+use crate::cursor_visibility::CursorVisibility;
 use crate::focus::{KeyboardFocusTarget, PointerFocusTarget};
+#[cfg(feature = "udev")]
+use crate::hibernation::HibernationState;
+#[cfg(feature = "udev")]
+use crate::portal::Portal;
+#[cfg(feature = "xwayland")]
+use crate::xwayland_on_demand::XWaylandOnDemand;
 #[cfg(feature = "xwayland")]
 use smithay::{
     delegate_xwayland_keyboard_grab, delegate_xwayland_shell,
-    utils::{Point, Size},
-    wayland::selection::{SelectionSource, SelectionTarget},
+    reexports::calloop::RegistrationToken,
     wayland::xwayland_keyboard_grab::{XWaylandKeyboardGrabHandler, XWaylandKeyboardGrabState},
     wayland::xwayland_shell,
     xwayland::{X11Wm, XWayland, XWaylandEvent},
 };
 
+/// How often windows and launched commands that went away unnoticed are dropped
+const REAP_INTERVAL: Duration = Duration::from_secs(10);
+/// Thumbnails of windows are rendered again at most this often
+const THUMBNAIL_INTERVAL: Duration = Duration::from_millis(500);
+
+/// Last thumbnail of a window, kept in its user data
+#[derive(Debug, Default)]
+struct Thumbnail(RefCell<Option<(Instant, Size<i32, Physical>, Dmabuf)>>);
+
+/// The dmabuf `window` presented last, if it shows one without subsurfaces
+fn presented_dmabuf(window: &Window) -> Option<Dmabuf> {
+    let surface = window.wl_surface()?;
+    if !get_children(&surface).is_empty() {
+        return None;
+    }
+    with_states(&surface, |states| {
+        let state = states
+            .data_map
+            .get::<RendererSurfaceStateUserData>()?
+            .lock()
+            .unwrap();
+        get_dmabuf(state.buffer()?).ok().cloned()
+    })
+}
+
 #[derive(Debug, Default)]
 pub struct ClientState {
     pub compositor_state: CompositorClientState,
     pub security_context: Option<SecurityContext>,
+    /// Connected through the privileged socket, see `ProtocolPolicyConfiguration`
+    pub privileged: bool,
+    /// Of the process behind a client relayed for protocol tracing, whose own
+    /// connection comes from the compositor
+    pub credentials: Option<Credentials>,
 }
 impl ClientData for ClientState {
     /// Notification that a client was initialized
-    fn initialized(&self, _client_id: ClientId) {}
+    fn initialized(&self, _client_id: ClientId) {
+        CONNECTED_CLIENTS.fetch_add(1, Ordering::Relaxed);
+    }
     /// Notification that a client is disconnected
-    fn disconnected(&self, _client_id: ClientId, _reason: DisconnectReason) {}
+    fn disconnected(&self, _client_id: ClientId, _reason: DisconnectReason) {
+        CONNECTED_CLIENTS.fetch_sub(1, Ordering::Relaxed);
+        DISCONNECTED_CLIENTS.fetch_add(1, Ordering::Relaxed);
+    }
+}
+
+/// Credentials of the process behind `client`, also when it's relayed for
+/// protocol tracing
+pub fn client_credentials(client: &Client, display: &DisplayHandle) -> Option<Credentials> {
+    client
+        .get_data::<ClientState>()
+        .and_then(|client_state| client_state.credentials)
+        .or_else(|| client.get_credentials(display).ok())
+}
+
+/// Filter of privileged globals, which sandboxed clients connecting through a
+/// security context socket don't get
+pub fn unsandboxed(client: &Client) -> bool {
+    client
+        .get_data::<ClientState>()
+        .map_or(true, |client_state| client_state.security_context.is_none())
 }
 
 #[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
 pub struct Configuration {
     pub framerate_limit: Option<f64>,
+    /// Path of the DRM device rendering and importing client buffers, like
+    /// "/dev/dri/renderD129" to prefer a discrete gpu. Read on startup only
+    pub primary_gpu: Option<String>,
+    /// Frame callbacks per second for windows that are not shown, 0 pauses them.
+    /// Once per second when unset
+    pub background_frame_rate: Option<f64>,
     pub show_fps: bool,
     pub background: BackgroundConfiguration,
+    pub window_rules: Vec<WindowRule>,
+    /// Paths of fragment shaders drawing windows, by the name of the preset that
+    /// window rules and clients pick. Files are read again when they change
+    pub shader_presets: HashMap<String, String>,
+    /// Windows at most this many pixels tall, like emulators and old games, are
+    /// scaled by `ScalingMode::Integer` unless a window rule or the client picks
+    /// another mode
+    pub low_resolution_height: Option<i32>,
+    pub letterbox: LetterboxConfiguration,
+    pub input: InputConfiguration,
+    pub button_bindings: Vec<ButtonBinding>,
+    pub swipe_gestures: SwipeConfiguration,
+    pub attention_peek: Option<AttentionPeekConfiguration>,
+    /// Animate switching the active window
+    pub window_transition: Option<TransitionConfiguration>,
+    /// Animations advance at most this many times per second, instead of on every
+    /// refresh of the fastest output
+    pub animation_rate: Option<f64>,
+    /// Serve metrics for Prometheus on this localhost port. Read on startup only
+    pub metrics_port: Option<u16>,
+    /// Read on startup only
+    pub log_shipping: Option<LogShippingConfiguration>,
+    /// Read on startup only
+    pub logging: LoggingConfiguration,
+    /// Show notifications when no other notification daemon runs. Read on startup only
+    pub notifications: Option<NotificationConfiguration>,
+    /// Progress bars of long-running operations
+    pub progress: ProgressConfiguration,
+    /// On-screen keyboard for entering text with a gamepad
+    pub text_entry: TextEntryConfiguration,
+    pub time: TimeConfiguration,
+    /// Helper daemons kept running next to the compositor
+    pub services: Vec<ServiceConfiguration>,
+    /// Read on startup only
+    pub startup: StartupConfiguration,
+    pub dock: Option<DockConfiguration>,
+    /// Applied when an output connects
+    pub output_scale: OutputScaleConfiguration,
+    /// Connectors offered to VR runtimes over `wp_drm_lease_v1`. Applied when a
+    /// connector connects
+    pub drm_lease: DrmLeaseConfiguration,
+    /// Turn the outputs off after a long time without input or sound
+    pub hibernation: Option<HibernationConfiguration>,
+    /// Only let one application show windows, like on an arcade cabinet
+    pub kiosk: Option<KioskConfiguration>,
+    /// Absolute paths of the executables of HUD clients allowed to capture any
+    /// toplevel. Others can only capture toplevels of processes they started
+    pub capture_clients: Vec<String>,
+    /// Limit privileged protocols to some clients, all unsandboxed clients may use
+    /// them when unset
+    pub protocol_policy: Option<ProtocolPolicyConfiguration>,
+    pub cursor: CursorConfiguration,
+    /// Share the shown window over VNC. Read on startup only
+    pub remote_desktop: Option<RemoteDesktopConfiguration>,
+    /// Wayland sockets clients connect to. Read on startup only
+    pub sockets: SocketConfiguration,
+    /// Running as the systemd user session. Read on startup only
+    pub session: SessionConfiguration,
+    /// Serve xdg-desktop-portal screenshots and settings, so sandboxed apps find
+    /// them. Read on startup, but the appearance follows changes
+    pub portal: Option<PortalConfiguration>,
+    pub xwayland: XWaylandConfiguration,
+    pub window_cycling: WindowCyclingConfiguration,
+    pub backlight: BacklightConfiguration,
+    pub status: StatusConfiguration,
+    pub picture_in_picture: PictureInPictureConfiguration,
+    pub split_screen: SplitScreenConfiguration,
+    pub zoom: ZoomConfiguration,
+    pub color_filter: ColorFilterConfiguration,
+    pub screenshot: ScreenshotConfiguration,
+    pub clipboard: ClipboardConfiguration,
+    /// Outputs of `--backend headless`. Read on startup only
+    pub headless: HeadlessConfiguration,
+    /// Where new windows go, window rules can override it per app
+    pub new_windows: NewWindowPlacement,
+}
+
+/// Configuration file given with `--config`
+static CONFIG_PATH: OnceLock<PathBuf> = OnceLock::new();
+
+/// Use the configuration file at `path` instead of the one in the config directory.
+/// Only the first call counts
+pub fn set_config_path(path: PathBuf) {
+    let _ = CONFIG_PATH.set(path);
+}
+
+pub fn config_path() -> Result<PathBuf, confy::ConfyError> {
+    match CONFIG_PATH.get() {
+        Some(path) => Ok(path.clone()),
+        None => confy::get_configuration_file_path("consolation", None),
+    }
+}
+
+/// Load the config file, failing with what's wrong with it
+pub fn load_config() -> Result<Configuration, String> {
+    let path = config_path().map_err(|err| err.to_string())?;
+    let config: Configuration = confy::load_path(path).map_err(|err| err.to_string())?;
+    config.validate()?;
+    Ok(config)
+}
+
+pub fn store_config(config: &Configuration) -> Result<(), confy::ConfyError> {
+    confy::store_path(config_path()?, config)
 }
 
 impl Configuration {
+    /// Reject values that parse but can't be used
+    fn validate(&self) -> Result<(), String> {
+        let minimum = self.backlight.minimum;
+        if !(0.0..=1.0).contains(&minimum) {
+            return Err(format!(
+                "backlight.minimum must be between 0 and 1, not {minimum}"
+            ));
+        }
+        let osd_secs = self.backlight.osd_secs;
+        if !osd_secs.is_finite() {
+            return Err(format!("backlight.osd_secs must be finite, not {osd_secs}"));
+        }
+        let valid_scale = |scale: f64| scale.is_finite() && scale > 0.0;
+        let scales = &self.output_scale.scales;
+        if let Some((name, scale)) = scales.iter().find(|(_, scale)| !valid_scale(**scale)) {
+            return Err(format!(
+                "output_scale.scales.{name} must be above 0, not {scale}"
+            ));
+        }
+        let dock_scale = self.dock.as_ref().and_then(|dock| dock.scale);
+        if let Some(scale) = dock_scale.filter(|scale| !valid_scale(*scale)) {
+            return Err(format!("dock.scale must be above 0, not {scale}"));
+        }
+        Ok(())
+    }
+
     pub fn set_from(&mut self, other: Configuration) {
         self.framerate_limit = other.framerate_limit;
+        self.primary_gpu = other.primary_gpu;
+        self.background_frame_rate = other.background_frame_rate;
         self.show_fps = other.show_fps;
         self.background = other.background;
+        self.window_rules = other.window_rules;
+        self.shader_presets = other.shader_presets;
+        self.low_resolution_height = other.low_resolution_height;
+        self.letterbox = other.letterbox;
+        self.input = other.input;
+        self.button_bindings = other.button_bindings;
+        self.swipe_gestures = other.swipe_gestures;
+        self.attention_peek = other.attention_peek;
+        self.window_transition = other.window_transition;
+        self.animation_rate = other.animation_rate;
+        self.metrics_port = other.metrics_port;
+        self.log_shipping = other.log_shipping;
+        self.logging = other.logging;
+        self.notifications = other.notifications;
+        self.progress = other.progress;
+        self.text_entry = other.text_entry;
+        self.time = other.time;
+        self.services = other.services;
+        self.startup = other.startup;
+        self.dock = other.dock;
+        self.output_scale = other.output_scale;
+        self.drm_lease = other.drm_lease;
+        self.hibernation = other.hibernation;
+        self.kiosk = other.kiosk;
+        self.capture_clients = other.capture_clients;
+        self.protocol_policy = other.protocol_policy;
+        self.cursor = other.cursor;
+        self.remote_desktop = other.remote_desktop;
+        self.sockets = other.sockets;
+        self.session = other.session;
+        self.portal = other.portal;
+        self.xwayland = other.xwayland;
+        self.window_cycling = other.window_cycling;
+        self.backlight = other.backlight;
+        self.status = other.status;
+        self.picture_in_picture = other.picture_in_picture;
+        self.split_screen = other.split_screen;
+        self.zoom = other.zoom;
+        self.color_filter = other.color_filter;
+        self.screenshot = other.screenshot;
+        self.clipboard = other.clipboard;
+        self.headless = other.headless;
+        self.new_windows = other.new_windows;
+    }
+
+    pub fn window_rule(&self, app_id: &str) -> Option<&WindowRule> {
+        self.window_rules.iter().find(|rule| rule.app_id == app_id)
+    }
+
+    /// The rule for `window`. Windows of sandboxed clients are matched by the app id
+    /// of their sandbox, which unlike the one they set can't take on the rules of
+    /// another app
+    pub fn window_rule_for(&self, window: &Window) -> Option<&WindowRule> {
+        match window_sandbox_app_id(window) {
+            Some(app_id) => self.window_rule(&app_id),
+            None => window_app_id(window).and_then(|app_id| self.window_rule(&app_id)),
+        }
+    }
+}
+
+/// Absolute paths of the executables of the clients allowed to use each privileged
+/// protocol. Symlinks are resolved, names alone match nothing
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ProtocolPolicyConfiguration {
+    /// wlr-output-management, for changing modes and arranging outputs
+    pub output_management: Vec<String>,
+    /// wlr-foreign-toplevel-management, for listing and activating windows
+    pub foreign_toplevel: Vec<String>,
+    /// wlr-virtual-pointer, for moving the pointer and clicking in any window
+    pub virtual_pointer: Vec<String>,
+    /// Name of a Wayland socket in XDG_RUNTIME_DIR whose clients may use all of them.
+    /// Read on startup only
+    pub privileged_socket: Option<String>,
+}
+
+/// Pointer image and when to hide it. The theme is also passed to the clients
+/// started by the compositor
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct CursorConfiguration {
+    /// Xcursor theme, XCURSOR_THEME or "default" when unset
+    pub theme: Option<String>,
+    /// Nominal size at scale 1, XCURSOR_SIZE or 24 when unset
+    pub size: Option<u32>,
+    /// Hide the pointer after this many seconds without motion, never when unset
+    pub hide_after: Option<f64>,
+    /// Hide the pointer on key presses, including buttons of controllers, until it moves
+    pub hide_on_keys: bool,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct RemoteDesktopConfiguration {
+    /// Address and port to listen on. Only this machine can connect by default
+    pub listen: String,
+    /// VNC password, of which only the first 8 characters count. Anyone reaching the
+    /// port may connect when unset
+    pub password: Option<String>,
+    /// Width and height of the shared picture, that of the first output when unset.
+    /// Smaller pictures take less bandwidth
+    pub size: Option<(u16, u16)>,
+    /// Picture updates per second at most
+    pub frame_rate: f64,
+}
+
+impl Default for RemoteDesktopConfiguration {
+    fn default() -> Self {
+        Self {
+            listen: "127.0.0.1:5900".into(),
+            password: None,
+            size: None,
+            frame_rate: 30.0,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct SocketConfiguration {
+    /// Name of the socket given to apps as `WAYLAND_DISPLAY`, the first free
+    /// `wayland-N` when unset. `--socket <name>` takes precedence
+    pub name: Option<String>,
+    /// More sockets to listen on, like names older setups expect
+    pub extra: Vec<String>,
+    /// Also listen on this abstract socket, which containers sharing the network
+    /// namespace can reach without mounting the runtime directory
+    pub abstract_name: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct SessionConfiguration {
+    /// libseat backend taking the seat: `logind`, `seatd` or `builtin`. Any that
+    /// works when unset
+    pub seat_backend: Option<String>,
+    /// Import the environment apps get into the systemd user manager and D-Bus
+    /// activation, so portals and services started by them find the display
+    pub import_environment: bool,
+    /// systemd user unit started once the environment is imported, like
+    /// a `consolation-session.target` that binds `graphical-session.target`
+    pub target: Option<String>,
+}
+
+impl Default for SessionConfiguration {
+    fn default() -> Self {
+        Self {
+            seat_backend: None,
+            import_environment: true,
+            target: None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct PortalConfiguration {
+    /// Where screenshots are saved, `$XDG_PICTURES_DIR` or `~/Pictures` when unset
+    pub screenshot_dir: Option<PathBuf>,
+    pub color_scheme: ColorScheme,
+    /// Red, green and blue from 0.0 to 1.0, the app default when unset
+    pub accent_color: Option<[f64; 3]>,
+    pub high_contrast: bool,
+}
+
+/// Color scheme apps are asked to prefer
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum ColorScheme {
+    #[default]
+    NoPreference,
+    Dark,
+    Light,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct XWaylandConfiguration {
+    /// Keep the size X11 windows ask for when they go fullscreen or maximized,
+    /// instead of giving them the output size. They are scaled to fit either way.
+    /// Games changing the resolution through RandR or VidMode need it, Xwayland
+    /// only emulates the mode they ask for
+    pub keep_requested_size: bool,
+    /// Given to X11 apps as `Xft.dpi`, which toolkits scale their interface by.
+    /// Applied when Xwayland starts
+    pub dpi: Option<u32>,
+    /// Only start Xwayland once an X11 client connects to its display. Read on
+    /// startup only
+    pub on_demand: bool,
+    /// Seconds after the last X11 window closed to stop the on demand Xwayland.
+    /// X11 clients without windows stop with it
+    pub exit_when_idle: Option<f64>,
+}
+
+impl Default for XWaylandConfiguration {
+    fn default() -> Self {
+        Self {
+            keep_requested_size: true,
+            dpi: None,
+            on_demand: false,
+            exit_when_idle: None,
+        }
+    }
+}
+
+/// libinput settings for pointer devices. Unset values keep the device defaults
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct InputConfiguration {
+    pub accel_profile: Option<AccelProfile>,
+    /// Between -1.0 (slowest) and 1.0 (fastest)
+    pub accel_speed: Option<f64>,
+    pub natural_scroll: Option<bool>,
+    pub tap_to_click: Option<bool>,
+    /// libinput calibration matrices of touchscreens, by device name
+    pub touch_calibration: HashMap<String, [f32; 6]>,
+    /// Response of continuous scroll axes, like sticks mapped to scrolling
+    pub analog_axes: Vec<AnalogAxisConfiguration>,
+}
+
+impl InputConfiguration {
+    /// The entry naming `device`, else the one for every device
+    pub fn analog_axis(&self, device: &str) -> Option<&AnalogAxisConfiguration> {
+        let axes = &self.analog_axes;
+        axes.iter()
+            .find(|axis| axis.device == device)
+            .or_else(|| axes.iter().find(|axis| axis.device.is_empty()))
+    }
+}
+
+/// Dead-zone and response curve for a device. An empty `device` matches every device
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct AnalogAxisConfiguration {
+    pub device: String,
+    /// Values below this are dropped, in scroll units
+    pub dead_zone: f64,
+    /// Value reported at full deflection, in scroll units
+    pub range: f64,
+    /// 1.0 is linear, larger values give finer control around the center
+    pub curve: f64,
+}
+
+impl Default for AnalogAxisConfiguration {
+    fn default() -> Self {
+        Self {
+            device: String::new(),
+            dead_zone: 0.0,
+            range: 15.0,
+            curve: 1.0,
+        }
+    }
+}
+
+impl AnalogAxisConfiguration {
+    pub fn apply(&self, value: f64) -> f64 {
+        let magnitude = value.abs();
+        if magnitude <= self.dead_zone || self.range <= self.dead_zone {
+            return 0.0;
+        }
+        let normalized = ((magnitude - self.dead_zone) / (self.range - self.dead_zone)).min(1.0);
+        value.signum() * normalized.powf(self.curve.max(0.1)) * self.range
+    }
+}
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+pub enum AccelProfile {
+    Flat,
+    Adaptive,
+}
+
+/// Settings applied to every window matching `app_id`
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct WindowRule {
+    pub app_id: String,
+    /// EXPERIMENTAL: Only send frame callbacks on every n-th repaint of the output.
+    /// Setting 2 on a 60Hz output paces 30fps content so each frame is shown
+    /// exactly twice, instead of following an uneven vsync cadence. Frames are
+    /// only repeated, never blended with the one before, since the previous buffer
+    /// of a window isn't kept to draw it again.
+    pub experimental_frame_divisor: Option<u32>,
+    /// Overrides the scaling mode requested by the client
+    pub scaling_mode: Option<ScalingMode>,
+    /// Overrides the crop offset requested by the client, see `ScalingMode::Crop`.
+    /// Both between -1 and 1, (0, 0) keeps the center
+    pub crop_offset: Option<(f64, f64)>,
+    /// Zoom in on black bars baked into the frames, like in letterboxed videos
+    pub detect_letterbox: bool,
+    /// Overrides the shader preset requested by the client
+    pub shader_preset: Option<String>,
+    /// Overrides the sampling, which is nearest for windows scaled by
+    /// `ScalingMode::Integer` and linear otherwise
+    pub sampling: Option<Sampling>,
+    /// Overrides the fill of `letterbox`
+    pub letterbox_fill: Option<LetterboxFill>,
+    pub access: WindowAccess,
+    /// Overrides `background_frame_rate`, like for apps that play media in the background
+    pub background_frame_rate: Option<f64>,
+    /// Fullscreen and maximized X11 windows get the output size divided by this, so
+    /// games render fewer pixels that are scaled up to the output
+    pub x11_scale: Option<f64>,
+    /// Overrides `new_windows`
+    pub new_window: Option<NewWindowPlacement>,
+}
+
+/// Where windows opening while another one shows end up
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum NewWindowPlacement {
+    /// Shown and focused right away
+    #[default]
+    Focus,
+    /// Kept right behind the shown window, one Alt+Tab away
+    Background,
+    /// Kept behind like `Background`, with a toast telling it opened
+    Prompt,
+}
+
+/// How the windows of an app may show
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum WindowAccess {
+    /// Shown and focused like any window
+    #[default]
+    Normal,
+    /// Kept below the other windows and never raised
+    Background,
+    /// Never mapped, like stray popups of helper tools
+    Deny,
+}
+
+/// Which windows may show in kiosk mode. Launching programs and switching
+/// windows through bindings, gestures and the menu is disabled
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct KioskConfiguration {
+    /// App ids of windows allowed to show
+    pub app_ids: Vec<String>,
+    /// Also allow windows of the `--app` command and the processes it started
+    pub app_command: bool,
+    pub other_windows: KioskOtherWindows,
+}
+
+impl Default for KioskConfiguration {
+    fn default() -> Self {
+        Self {
+            app_ids: Vec::new(),
+            app_command: true,
+            other_windows: KioskOtherWindows::default(),
+        }
+    }
+}
+
+/// What happens to windows not allowed in kiosk mode
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum KioskOtherWindows {
+    /// Never shown or focused
+    #[default]
+    Hide,
+    /// Kept below the allowed windows
+    Background,
+}
+
+/// How a window is scaled to the usable area of the output
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum ScalingMode {
+    /// Largest size keeping the aspect ratio
+    #[default]
+    Fit,
+    /// Largest whole multiple of the window size, for pixel art
+    Integer,
+    /// Fill the whole area, ignoring the aspect ratio
+    Stretch,
+    /// Fill the whole area keeping the aspect ratio, like 16:9 content on 32:9 outputs.
+    /// The crop offset, from -1 to 1 on each axis, picks the part that stays visible
+    Crop,
+}
+
+/// How the pixels of a scaled window are sampled
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Sampling {
+    /// Smooth, blurring the edges of pixel art
+    #[default]
+    Linear,
+    /// Sharp square pixels, evenly sized with `ScalingMode::Integer`
+    Nearest,
+}
+
+/// Show background windows that repeatedly ask for attention in a corner
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct AttentionPeekConfiguration {
+    /// Keysym name of the key switching to the peeked window
+    pub switch_key: String,
+    pub duration_secs: f64,
+}
+
+impl Default for AttentionPeekConfiguration {
+    fn default() -> Self {
+        Self {
+            switch_key: "y".to_string(),
+            duration_secs: 5.0,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct NotificationConfiguration {
+    /// Path of a TrueType font, a common system font when unset
+    pub font: Option<String>,
+    /// Seconds a notification stays unless it asks for another time
+    pub timeout_secs: f64,
+    /// Keysym name of the key dismissing the newest notification
+    pub dismiss_key: String,
+}
+
+impl Default for NotificationConfiguration {
+    fn default() -> Self {
+        Self {
+            font: None,
+            timeout_secs: 5.0,
+            dismiss_key: "x".to_string(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ProgressConfiguration {
+    /// Path of a TrueType font, a common system font when unset. Read on startup only
+    pub font: Option<String>,
+    /// Let scripts show progress bars over `org.consolation.Progress` on the session
+    /// bus. Read on startup only
+    pub serve_dbus: bool,
+}
+
+impl Default for ProgressConfiguration {
+    fn default() -> Self {
+        Self {
+            font: None,
+            serve_dbus: true,
+        }
+    }
+}
+
+/// Switching windows with Alt+Tab, Alt+Shift+Tab and bound buttons
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct WindowCyclingConfiguration {
+    /// Seconds the title of the window switched to is shown, 0 shows none
+    pub indicator_secs: f64,
+    /// Path of a TrueType font, a common system font when unset. Read on startup only
+    pub font: Option<String>,
+}
+
+impl Default for WindowCyclingConfiguration {
+    fn default() -> Self {
+        Self {
+            indicator_secs: 1.0,
+            font: None,
+        }
+    }
+}
+
+/// Screen brightness of handhelds and laptops, changed with keys, bound buttons
+/// or over `org.consolation.Backlight` on the session bus
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct BacklightConfiguration {
+    /// Name of the device under /sys/class/backlight, the first one when unset.
+    /// Read on startup only
+    pub device: Option<String>,
+    /// Fraction of the full brightness a step changes
+    pub step: f64,
+    /// Fraction of the full brightness never gone below, so the screen stays readable
+    pub minimum: f64,
+    /// Keysym names of the keys changing the brightness
+    pub up_key: String,
+    pub down_key: String,
+    /// Seconds the brightness is shown after a change, 0 shows nothing
+    pub osd_secs: f64,
+    /// Path of a TrueType font, a common system font when unset. Read on startup only
+    pub font: Option<String>,
+    /// Read on startup only
+    pub serve_dbus: bool,
+}
+
+impl Default for BacklightConfiguration {
+    fn default() -> Self {
+        Self {
+            device: None,
+            step: 0.1,
+            minimum: 0.05,
+            up_key: "XF86MonBrightnessUp".into(),
+            down_key: "XF86MonBrightnessDown".into(),
+            osd_secs: 1.5,
+            font: None,
+            serve_dbus: true,
+        }
+    }
+}
+
+/// Battery, time and network state in the top right corner, for handhelds
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct StatusConfiguration {
+    /// Shown from the start. Read on startup only
+    pub show: bool,
+    /// Keysym name of the key showing and hiding it. Read on startup only
+    pub toggle_key: Option<String>,
+    /// Path of a TrueType font, a common system font when unset. Read on startup only
+    pub font: Option<String>,
+}
+
+/// A second window drawn small above the shown one, like a chat or a video.
+/// It doesn't get input, raising it swaps nothing but ends picture-in-picture
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct PictureInPictureConfiguration {
+    /// Corner it is drawn in until moved
+    pub corner: Corner,
+    /// Width and height as a fraction of the usable area
+    pub size: f64,
+    /// Keysym name of the key showing the most recently used other window in it,
+    /// or hiding it
+    pub toggle_key: Option<String>,
+    /// Keysym name of the key moving it to the next corner clockwise
+    pub move_key: Option<String>,
+}
+
+impl Default for PictureInPictureConfiguration {
+    fn default() -> Self {
+        Self {
+            corner: Corner::BottomRight,
+            size: 0.25,
+            toggle_key: None,
+            move_key: None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Corner {
+    TopLeft,
+    TopRight,
+    BottomLeft,
+    #[default]
+    BottomRight,
+}
+
+impl Corner {
+    pub fn clockwise(self) -> Self {
+        match self {
+            Self::TopLeft => Self::TopRight,
+            Self::TopRight => Self::BottomRight,
+            Self::BottomRight => Self::BottomLeft,
+            Self::BottomLeft => Self::TopLeft,
+        }
+    }
+}
+
+/// Two windows at once for couch co-op, each getting the input of its own devices
+/// through its own seat
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct SplitScreenConfiguration {
+    pub orientation: SplitOrientation,
+    /// Names of the input devices whose keys and pointer go to the second window,
+    /// as libinput lists them. All others go to the shown window
+    pub devices: Vec<String>,
+    /// Keysym name of the key showing the most recently used other window next to
+    /// the shown one, or ending the split
+    pub toggle_key: Option<String>,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum SplitOrientation {
+    #[default]
+    SideBySide,
+    TopBottom,
+}
+
+/// Magnifying the whole screen around the pointer, for accessibility and close-ups
+/// when streaming
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ZoomConfiguration {
+    /// Keysym name of the key zooming in to the level last used, or back out
+    pub toggle_key: Option<String>,
+    /// Modifier making a vertical scroll zoom in or out instead of scrolling
+    pub scroll_modifier: Option<ZoomModifier>,
+    /// Factor each scroll wheel click zooms by
+    pub step: f64,
+    pub max_level: f64,
+}
+
+impl Default for ZoomConfiguration {
+    fn default() -> Self {
+        Self {
+            toggle_key: None,
+            scroll_modifier: None,
+            step: 1.25,
+            max_level: 8.0,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum ZoomModifier {
+    Super,
+    Alt,
+    Ctrl,
+}
+
+/// Color transform of everything drawn, for color blindness and low vision
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ColorFilterConfiguration {
+    /// Applied from the start. Read on startup only
+    pub enabled: bool,
+    pub filter: ColorFilter,
+    /// Keysym name of the key turning the filter on and off
+    pub toggle_key: Option<String>,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum ColorFilter {
+    #[default]
+    Grayscale,
+    /// Correction for red blindness, moving reds to colors still told apart
+    Protanopia,
+    /// Correction for green blindness, moving greens to colors still told apart
+    Deuteranopia,
+    Invert,
+}
+
+/// Keys saving screenshots as png files, confirmed with a toast
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ScreenshotConfiguration {
+    /// Keysym name of the key saving the whole first output
+    pub output_key: Option<String>,
+    /// Keysym name of the key saving the shown window at the resolution it renders at
+    pub window_key: Option<String>,
+    /// Keysym name of the key starting to select a region from the pointer. Moving
+    /// the pointer or the arrow keys resize it, the key again or Enter saves it and
+    /// Escape cancels
+    pub region_key: Option<String>,
+    /// Where screenshots are saved, `$XDG_PICTURES_DIR` or `~/Pictures` when unset
+    pub directory: Option<PathBuf>,
+}
+
+/// What is kept of the clipboard once the client it was copied from goes away
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ClipboardConfiguration {
+    /// Mime types copied out of the client, anything else is lost together with it
+    pub mime_types: Vec<String>,
+    /// Bytes copied at most over all the mime types of a selection
+    pub max_size: usize,
+}
+
+impl Default for ClipboardConfiguration {
+    fn default() -> Self {
+        Self {
+            mime_types: [
+                "text/plain;charset=utf-8",
+                "text/plain",
+                "UTF8_STRING",
+                "STRING",
+                "TEXT",
+                "image/png",
+            ]
+            .map(String::from)
+            .into(),
+            max_size: 16 * 1024 * 1024,
+        }
+    }
+}
+
+/// Rendering without a display, for tests and benchmarks of the render path
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct HeadlessConfiguration {
+    pub outputs: Vec<HeadlessOutputConfiguration>,
+    /// Frames rendered before exiting with a summary of the frame times, no limit
+    /// when unset
+    pub frames: Option<u64>,
+}
+
+impl Default for HeadlessConfiguration {
+    fn default() -> Self {
+        Self {
+            outputs: vec![HeadlessOutputConfiguration::default()],
+            frames: None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct HeadlessOutputConfiguration {
+    pub name: String,
+    pub width: i32,
+    pub height: i32,
+    /// Frames per second rendered
+    pub refresh_rate: f64,
+    pub scale: f64,
+}
+
+impl Default for HeadlessOutputConfiguration {
+    fn default() -> Self {
+        Self {
+            name: "HEADLESS-1".into(),
+            width: 1920,
+            height: 1080,
+            refresh_rate: 60.0,
+            scale: 1.0,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct TimeConfiguration {
+    /// Show the time in the top left corner of every output
+    pub show_clock: bool,
+    pub twenty_four_hour: bool,
+    /// Path of a TrueType font, a common system font when unset. Read on startup only
+    pub font: Option<String>,
+    /// Keysym name of the key opening the time zone and NTP settings. Changing them
+    /// needs a polkit rule allowing the compositor user to
+    pub settings_key: Option<String>,
+}
+
+impl Default for TimeConfiguration {
+    fn default() -> Self {
+        Self {
+            show_clock: false,
+            twenty_four_hour: true,
+            font: None,
+            settings_key: None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct TextEntryConfiguration {
+    /// Rows of keys by layout name. Keys type their text, besides "shift", "space",
+    /// "backspace" and "done"
+    pub layouts: HashMap<String, Vec<Vec<String>>>,
+    /// Layout for searching and Wi-Fi passphrases
+    pub text_layout: String,
+    /// Layout for PINs
+    pub pin_layout: String,
+    /// Path of a TrueType font, a common system font when unset
+    pub font: Option<String>,
+    /// Run with `sh -c` on moving, typing, finishing and cancelling, with
+    /// CONSOLATION_FEEDBACK set to "move", "key", "done" or "cancel". Can play a
+    /// sound or rumble the gamepad
+    pub feedback_command: Option<String>,
+}
+
+impl Default for TextEntryConfiguration {
+    fn default() -> Self {
+        let layout = |rows: &[&str]| -> Vec<Vec<String>> {
+            rows.iter()
+                .map(|row| row.split_whitespace().map(str::to_string).collect())
+                .collect()
+        };
+        let layouts = HashMap::from([
+            (
+                "qwerty".to_string(),
+                layout(&[
+                    "1 2 3 4 5 6 7 8 9 0",
+                    "q w e r t y u i o p",
+                    "a s d f g h j k l -",
+                    "z x c v b n m . _ @",
+                    "! ? # $ % & * / : ;",
+                    "shift space backspace done",
+                ]),
+            ),
+            (
+                "pin".to_string(),
+                layout(&["1 2 3", "4 5 6", "7 8 9", "backspace 0 done"]),
+            ),
+        ]);
+        Self {
+            layouts,
+            text_layout: "qwerty".to_string(),
+            pin_layout: "pin".to_string(),
+            font: None,
+            feedback_command: None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct TransitionConfiguration {
+    pub style: TransitionStyle,
+    pub duration_ms: u64,
+}
+
+impl Default for TransitionConfiguration {
+    fn default() -> Self {
+        Self {
+            style: TransitionStyle::default(),
+            duration_ms: 250,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum TransitionStyle {
+    /// The new window fades in over the old one
+    #[default]
+    Fade,
+    /// The new window comes in from the right, pushing the old one out
+    Slide,
+}
+
+/// Where log lines go besides the terminal
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct LoggingConfiguration {
+    /// Info lines and above kept in memory for `consolation --logs`, 0 keeps none
+    pub recent_lines: usize,
+    /// Also write to the systemd journal, for sessions not started as a service
+    pub journald: bool,
+}
+
+impl Default for LoggingConfiguration {
+    fn default() -> Self {
+        Self {
+            recent_lines: 1000,
+            journald: false,
+        }
+    }
+}
+
+/// Forward warnings and errors to a remote endpoint. `syslog` takes precedence over `http`
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct LogShippingConfiguration {
+    /// Address of a syslog server, like "10.0.0.1:514"
+    pub syslog: Option<String>,
+    /// Plain http URL the log lines are posted to
+    pub http: Option<String>,
+    pub max_per_minute: u32,
+}
+
+impl Default for LogShippingConfiguration {
+    fn default() -> Self {
+        Self {
+            syslog: None,
+            http: None,
+            max_per_minute: 30,
+        }
+    }
+}
+
+/// Applied while a handheld sits in its dock, detected by an external display,
+/// external power and a USB hub all being present
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct DockConfiguration {
+    /// Scale of the external display
+    pub scale: Option<f64>,
+    /// Replace `button_bindings` while docked
+    pub button_bindings: Option<Vec<ButtonBinding>>,
+    /// power-profiles-daemon profile while docked, like "performance"
+    pub performance_profile: Option<String>,
+}
+
+/// Connectors are listed by output name, like "DP-2", or by make and model, like
+/// "Valve Corporation Index HMD". Connectors marked non-desktop, like most headsets,
+/// are offered unless listed in `never_lease`
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct DrmLeaseConfiguration {
+    /// Also offered instead of becoming outputs, for headsets missing the non-desktop quirk
+    pub connectors: Vec<String>,
+    /// Never offered, so the TV can't be leased away
+    pub never_lease: Vec<String>,
+}
+
+impl DrmLeaseConfiguration {
+    fn lists(names: &[String], output_name: &str, make_model: &str) -> bool {
+        names
+            .iter()
+            .any(|name| name == output_name || name == make_model)
+    }
+
+    /// Whether a connector is offered for leasing instead of becoming an output
+    pub fn leasable(&self, output_name: &str, make_model: &str, non_desktop: bool) -> bool {
+        (non_desktop || Self::lists(&self.connectors, output_name, make_model))
+            && !Self::lists(&self.never_lease, output_name, make_model)
+    }
+}
+
+/// Default scale of outputs, picked from their physical size and viewing distance
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct OutputScaleConfiguration {
+    /// Pick a scale for outputs missing in `scales`
+    pub suggest: bool,
+    /// Scale per output name, like "HDMI-A-1"
+    pub scales: HashMap<String, f64>,
+    /// Viewing distance in centimeters per output name. Guessed from the size
+    /// of the output otherwise: 30 for handhelds, 60 for monitors and 300 for TVs
+    pub viewing_distances: HashMap<String, f64>,
+}
+
+impl Default for OutputScaleConfiguration {
+    fn default() -> Self {
+        Self {
+            suggest: true,
+            scales: HashMap::new(),
+            viewing_distances: HashMap::new(),
+        }
     }
 }
 
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct HibernationConfiguration {
+    /// Minutes without input or playing sound before hibernating
+    pub idle_minutes: u64,
+    /// Write the programs that had windows to `session-checkpoint` in the state directory
+    pub checkpoint: bool,
+    /// Ask every window to close
+    pub close_windows: bool,
+}
+
+impl Default for HibernationConfiguration {
+    fn default() -> Self {
+        Self {
+            idle_minutes: 240,
+            checkpoint: true,
+            close_windows: true,
+        }
+    }
+}
+
+/// Program restarted whenever it exits, like an on-screen keyboard or audio agent
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ServiceConfiguration {
+    pub name: String,
+    pub command: String,
+    pub args: Vec<String>,
+}
+
+/// What is shown and started while the session comes up
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct StartupConfiguration {
+    /// Cover the outputs with a splash screen until the first window maps
+    pub splash: bool,
+    /// Image of the splash screen, the logo if unset
+    pub splash_image: Option<String>,
+    /// Started after the autostart script
+    pub applications: Vec<SessionApplication>,
+}
+
+impl Default for StartupConfiguration {
+    fn default() -> Self {
+        Self {
+            splash: true,
+            splash_image: None,
+            applications: Vec::new(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct SessionApplication {
+    pub name: String,
+    pub command: String,
+    pub args: Vec<String>,
+    /// Restarted like a service when it exits
+    pub critical: bool,
+}
+
+/// Command started by a gesture on one or more buttons
 #[derive(Debug, Clone, Default, Serialize, Deserialize)]
-pub enum BackgroundConfiguration {
+#[serde(default)]
+pub struct ButtonBinding {
+    /// Keysym names, like "XF86HomePage". Several keys held together form a chord
+    pub keys: Vec<String>,
+    pub gesture: ButtonGesture,
+    pub command: String,
+    /// Done instead of running `command` when set, like switching windows or a
+    /// `Custom` action such as a power menu
+    pub action: SwipeAction,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum ButtonGesture {
     #[default]
+    Press,
+    LongPress,
+    DoubleTap,
+}
+
+/// Touchpad swipes handled by the compositor instead of being sent to clients
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct SwipeConfiguration {
+    /// Swipes with other finger counts reach clients, 0 turns them all over to clients
+    pub fingers: u32,
+    /// How far a swipe has to go to trigger, in pointer units
+    pub distance: f64,
+    pub left: SwipeAction,
+    pub right: SwipeAction,
+    pub up: SwipeAction,
+    pub down: SwipeAction,
+}
+
+impl Default for SwipeConfiguration {
+    fn default() -> Self {
+        Self {
+            fingers: 3,
+            distance: 100.0,
+            left: SwipeAction::NextWindow,
+            right: SwipeAction::PreviousWindow,
+            up: SwipeAction::Run("consolation-switcher".into()),
+            down: SwipeAction::None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub enum SwipeAction {
+    #[default]
+    None,
+    /// Switch to the most recently used other window, or further back when repeated
+    NextWindow,
+    /// Raise the window that was active the longest time ago
+    PreviousWindow,
+    BrightnessUp,
+    BrightnessDown,
+    /// Show or hide the status overlay
+    ToggleStatus,
+    TogglePictureInPicture,
+    MovePictureInPicture,
+    ToggleSplitScreen,
+    ToggleZoom,
+    ToggleColorFilter,
+    /// Raise the window shown in the attention peek
+    SwitchToPeek,
+    DismissNotification,
+    OpenTimeSettings,
+    Quit,
+    Run(String),
+    /// The action registered with `AnvilState::register_key_action` under this name
+    Custom(String),
+}
+
+/// Drawn below the windows. Without either set, the logo is shown
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(from = "BackgroundSetting")]
+pub struct BackgroundConfiguration {
+    /// Path of an image file
+    pub image: Option<String>,
+    pub scale: BackgroundScale,
+    /// Like "#1d2021", shown around the image or if it can't be loaded
+    pub color: Option<String>,
+}
+
+/// The background as written by earlier builds, or the current table
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum BackgroundSetting {
+    Legacy(LegacyBackground),
+    Current {
+        #[serde(default)]
+        image: Option<String>,
+        #[serde(default)]
+        scale: BackgroundScale,
+        #[serde(default)]
+        color: Option<String>,
+    },
+}
+
+#[derive(Deserialize)]
+enum LegacyBackground {
     None,
     Color(String),
     Image(String),
     ImageScaled(String),
 }
 
+impl From<BackgroundSetting> for BackgroundConfiguration {
+    fn from(setting: BackgroundSetting) -> Self {
+        match setting {
+            BackgroundSetting::Current {
+                image,
+                scale,
+                color,
+            } => Self {
+                image,
+                scale,
+                color,
+            },
+            BackgroundSetting::Legacy(LegacyBackground::None) => Self::default(),
+            BackgroundSetting::Legacy(LegacyBackground::Color(color)) => Self {
+                color: Some(color),
+                ..Self::default()
+            },
+            BackgroundSetting::Legacy(LegacyBackground::Image(image)) => Self {
+                image: Some(image),
+                ..Self::default()
+            },
+            BackgroundSetting::Legacy(LegacyBackground::ImageScaled(image)) => Self {
+                image: Some(image),
+                scale: BackgroundScale::Fill,
+                ..Self::default()
+            },
+        }
+    }
+}
+
+impl BackgroundConfiguration {
+    pub fn clear_color(&self) -> [f32; 4] {
+        let Some(color) = self.color.as_deref() else {
+            return CLEAR_COLOR;
+        };
+        hex_color(color).unwrap_or_else(|| {
+            warn!(color, "Invalid background color");
+            CLEAR_COLOR
+        })
+    }
+}
+
+/// The opaque color written like "#1d2021"
+pub fn hex_color(color: &str) -> Option<[f32; 4]> {
+    let channel = |index: usize| {
+        color
+            .trim_start_matches('#')
+            .get(index * 2..index * 2 + 2)
+            .and_then(|hex| u8::from_str_radix(hex, 16).ok())
+            .map(|value| value as f32 / 255.0)
+    };
+    Some([channel(0)?, channel(1)?, channel(2)?, 1.0])
+}
+
+/// What fills the bars around windows that don't cover their whole zone, like
+/// 4:3 games on 16:9 outputs
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct LetterboxConfiguration {
+    pub fill: LetterboxFill,
+    /// Like "#000000", for `LetterboxFill::Color`
+    pub color: Option<String>,
+    /// Path of an image file for `LetterboxFill::Image`, covering the zone of the
+    /// window
+    pub image: Option<String>,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum LetterboxFill {
+    /// The output background shows through
+    #[default]
+    Background,
+    /// The configured color
+    Color,
+    /// A blurred and darkened copy of the window, stretched over its zone
+    Blur,
+    /// The configured image
+    Image,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum BackgroundScale {
+    /// Original size in the middle of the output
+    #[default]
+    Center,
+    /// Largest size showing the whole image
+    Fit,
+    /// Covers the whole output, cropping the image
+    Fill,
+    /// Repeated from the top left corner
+    Tile,
+}
+
+/// Icon attached to an ongoing drag-and-drop, drawn at the pointer
+#[derive(Debug, Clone)]
+pub struct DndIcon {
+    pub surface: WlSurface,
+    /// Hotspot of the icon, accumulated from the buffer offsets of its commits
+    pub offset: Point<i32, Logical>,
+}
+
 #[derive(Debug)]
 pub struct AnvilState<BackendData: Backend + 'static> {
     pub backend_data: BackendData,
@@ -201,7 +1638,44 @@ pub struct AnvilState<BackendData: Backend + 'static> {
     pub xwayland_shell_state: xwayland_shell::XWaylandShellState,
     pub single_pixel_buffer_state: SinglePixelBufferState,
 
-    pub dnd_icon: Option<WlSurface>,
+    pub dnd_icon: Option<DndIcon>,
+    pub clipboard: ClipboardStore,
+    pub button_gestures: GestureDetector,
+    pub window_peek: Option<WindowPeek>,
+    pub picture_in_picture: Option<PictureInPicture>,
+    pub split_screen: Option<SplitScreen>,
+    /// Seat of the second window of a split screen, added when first split
+    pub second_seat: Option<Seat<AnvilState<BackendData>>>,
+    pub zoom: ZoomState,
+    /// Whether `config.color_filter` applies
+    pub color_filter_enabled: bool,
+    pub screenshot: ScreenshotState,
+    pub window_cycling: WindowCycling,
+    pub backlight: Backlight,
+    pub window_transitions: WindowTransitions,
+    pub animation_clock: AnimationClock,
+    /// The splash screen is shown until the first window maps
+    pub splash: bool,
+    pub app_watchdog: Option<AppWatchdog>,
+    pub notifications: Notifications,
+    pub progress: ProgressReports,
+    pub status: StatusOverlay,
+    pub text_entry: Option<TextEntry>,
+    pub time: TimeState,
+    pub custom_key_actions: Vec<CustomKeyAction<BackendData>>,
+    #[cfg(feature = "udev")]
+    pub hibernation: HibernationState,
+    #[cfg(feature = "udev")]
+    pub cursor_visibility: CursorVisibility,
+    #[cfg(feature = "udev")]
+    pub portal: Portal,
+    /// Session bus connection serving every object of the compositor
+    pub dbus: DBusServer,
+    pub protocol_trace: ProtocolTrace,
+    pub metrics: Metrics,
+    pub services: ServiceSupervisor,
+    pub launched: LaunchedCommands,
+    pub dock: DockState,
 
     // input-related fields
     pub suppressed_keys: Vec<Keysym>,
@@ -215,12 +1689,16 @@ pub struct AnvilState<BackendData: Backend + 'static> {
     pub xwm: Option<X11Wm>,
     #[cfg(feature = "xwayland")]
     pub xdisplay: Option<u32>,
+    #[cfg(feature = "xwayland")]
+    pub xwayland_on_demand: Option<XWaylandOnDemand>,
     pub outputs_config: Option<Outputs>,
 
     pub config: Configuration,
-    pub config_watcher: std::sync::mpsc::Receiver<Result<Event, notify::Error>>,
 
     pub toplevel_manager: ForeignToplevelManagerState,
+    pub protocol_policy: ProtocolPolicy,
+    pub hud_manager_state: HudManagerState,
+    pub scaling_manager_state: ScalingManagerState,
     pub config_watcher_obj: notify::INotifyWatcher,
 }
 
@@ -232,6 +1710,9 @@ impl<BackendData: Backend> DataDeviceHandler for AnvilState<BackendData> {
     }
 }
 
+/// Drags started by clients run as a pointer grab of the data device, which sends
+/// enter, motion and drop to the surface under the pointer as focused by the
+/// pointer motion, so the shown window and its popups. Only the icon is kept here
 impl<BackendData: Backend> ClientDndGrabHandler for AnvilState<BackendData> {
     fn started(
         &mut self,
@@ -239,7 +1720,10 @@ impl<BackendData: Backend> ClientDndGrabHandler for AnvilState<BackendData> {
         icon: Option<WlSurface>,
         _seat: Seat<Self>,
     ) {
-        self.dnd_icon = icon;
+        self.dnd_icon = icon.map(|surface| DndIcon {
+            surface,
+            offset: (0, 0).into(),
+        });
     }
     fn dropped(&mut self, _seat: Seat<Self>) {
         self.dnd_icon = None;
@@ -256,34 +1740,58 @@ impl<BackendData: Backend> OutputHandler for AnvilState<BackendData> {}
 delegate_output!(@<BackendData: Backend + 'static> AnvilState<BackendData>);
 
 impl<BackendData: Backend> SelectionHandler for AnvilState<BackendData> {
-    type SelectionUserData = ();
+    type SelectionUserData = SelectionOrigin;
 
-    #[cfg(feature = "xwayland")]
     fn new_selection(
         &mut self,
         ty: SelectionTarget,
         source: Option<SelectionSource>,
         _seat: Seat<Self>,
     ) {
+        let mime_types = match (ty, source) {
+            (SelectionTarget::Clipboard, Some(source)) => {
+                self.cache_clipboard_selection(&source);
+                Some(source.mime_types())
+            }
+            // Restored later if the clipboard went away with its client
+            (SelectionTarget::Clipboard, None) => {
+                self.clipboard_cleared();
+                None
+            }
+            (SelectionTarget::Primary, source) => source.map(|source| source.mime_types()),
+        };
+
+        #[cfg(feature = "xwayland")]
         if let Some(xwm) = self.xwm.as_mut() {
-            if let Err(err) = xwm.new_selection(ty, source.map(|source| source.mime_types())) {
+            if let Err(err) = xwm.new_selection(ty, mime_types) {
                 warn!(?err, ?ty, "Failed to set Xwayland selection");
             }
         }
+        #[cfg(not(feature = "xwayland"))]
+        let _ = mime_types;
     }
 
-    #[cfg(feature = "xwayland")]
     fn send_selection(
         &mut self,
         ty: SelectionTarget,
         mime_type: String,
         fd: OwnedFd,
         _seat: Seat<Self>,
-        _user_data: &(),
+        user_data: &SelectionOrigin,
     ) {
-        if let Some(xwm) = self.xwm.as_mut() {
-            if let Err(err) = xwm.send_selection(ty, mime_type, fd, self.handle.clone()) {
-                warn!(?err, "Failed to send primary (X11 -> Wayland)");
+        match user_data {
+            SelectionOrigin::Cached(contents) => send_cached_selection(contents, &mime_type, fd),
+            #[cfg(feature = "xwayland")]
+            SelectionOrigin::Xwayland => {
+                if let Some(xwm) = self.xwm.as_mut() {
+                    if let Err(err) = xwm.send_selection(ty, mime_type, fd, self.handle.clone()) {
+                        warn!(?err, "Failed to send primary (X11 -> Wayland)");
+                    }
+                }
+            }
+            #[cfg(not(feature = "xwayland"))]
+            SelectionOrigin::Xwayland => {
+                let _ = ty;
             }
         }
     }
@@ -329,12 +1837,17 @@ impl<BackendData: Backend> SeatHandler for AnvilState<BackendData> {
         set_data_device_focus(dh, seat, focus.clone());
         set_primary_focus(dh, seat, focus);
     }
-    fn cursor_image(&mut self, _seat: &Seat<Self>, image: CursorImageStatus) {
-        self.cursor_status = image;
+    fn cursor_image(&mut self, seat: &Seat<Self>, image: CursorImageStatus) {
+        // Only the pointer of the shown window has a cursor drawn
+        if seat == &self.seat {
+            self.cursor_status = image;
+        }
     }
 
-    fn led_state_changed(&mut self, _seat: &Seat<Self>, led_state: LedState) {
-        self.backend_data.update_led_state(led_state)
+    fn led_state_changed(&mut self, seat: &Seat<Self>, led_state: LedState) {
+        if seat == &self.seat {
+            self.backend_data.update_led_state(led_state)
+        }
     }
 }
 delegate_seat!(@<BackendData: Backend + 'static> AnvilState<BackendData>);
@@ -399,9 +1912,74 @@ delegate_foreign_toplevel!(@<BackendData: Backend + 'static> AnvilState<BackendD
 
 delegate_output_management!(@<BackendData: Backend + 'static> AnvilState<BackendData>);
 
+impl<BackendData: Backend> HudHandler for AnvilState<BackendData> {
+    fn hud_manager_state(&mut self) -> &mut HudManagerState {
+        &mut self.hud_manager_state
+    }
+
+    fn capture_toplevel(
+        &mut self,
+        client: &Client,
+        toplevel: &ZwlrForeignToplevelHandleV1,
+        max_size: Option<u32>,
+    ) -> Option<Dmabuf> {
+        let surface = self.toplevel_manager.surface_for_handle(toplevel)?;
+        // Launch groups are listed by their oldest window, capture the one in use
+        let window = self.launch_group_window(&self.window_for_surface(&surface)?);
+
+        let pid = client_credentials(client, &self.display_handle)
+            .and_then(|credentials| u32::try_from(credentials.pid).ok())?;
+        let allowed = self
+            .window_pid(&window)
+            .is_some_and(|window_pid| is_descendant(window_pid, pid))
+            || client_executable(client, &self.display_handle)
+                .is_some_and(|path| executable_allowed(&path, &self.config.capture_clients));
+        if !allowed {
+            warn!(pid, "Denied toplevel capture");
+            return None;
+        }
+
+        let geometry = window.geometry().size.to_physical(1);
+        let Some(max_size) = max_size else {
+            return presented_dmabuf(&window)
+                .or_else(|| self.backend_data.capture_window(&window, geometry));
+        };
+        let scale = (max_size as f64 / geometry.w.max(geometry.h).max(1) as f64).min(1.0);
+        let size = Size::from((
+            (geometry.w as f64 * scale).round().max(1.0) as i32,
+            (geometry.h as f64 * scale).round().max(1.0) as i32,
+        ));
+
+        // Menus ask on every frame, rendering the whole window each time is too costly
+        window.user_data().insert_if_missing(Thumbnail::default);
+        let thumbnail = window.user_data().get::<Thumbnail>().unwrap();
+        if let Some((rendered, thumbnail_size, dmabuf)) = thumbnail.0.borrow().as_ref() {
+            if *thumbnail_size == size && rendered.elapsed() < THUMBNAIL_INTERVAL {
+                return Some(dmabuf.clone());
+            }
+        }
+        // A new buffer each time, clients may still be reading the last one
+        let dmabuf = self.backend_data.capture_window(&window, size)?;
+        *thumbnail.0.borrow_mut() = Some((Instant::now(), size, dmabuf.clone()));
+        Some(dmabuf)
+    }
+}
+delegate_hud!(@<BackendData: Backend + 'static> AnvilState<BackendData>);
+
+impl<BackendData: Backend> ScalingHandler for AnvilState<BackendData> {
+    fn scaling_manager_state(&mut self) -> &mut ScalingManagerState {
+        &mut self.scaling_manager_state
+    }
+}
+delegate_scaling!(@<BackendData: Backend + 'static> AnvilState<BackendData>);
+
+delegate_tearing_control!(@<BackendData: Backend + 'static> AnvilState<BackendData>);
+
+delegate_content_type!(@<BackendData: Backend + 'static> AnvilState<BackendData>);
+
 impl<BackendData: Backend> PointerConstraintsHandler for AnvilState<BackendData> {
     fn new_constraint(&mut self, surface: &WlSurface, pointer: &PointerHandle<Self>) {
-        // XXX region
+        // Confined regions are enforced on motion in `on_pointer_move`
         let Some(current_focus) = pointer.current_focus() else {
             return;
         };
@@ -430,7 +2008,16 @@ impl<BackendData: Backend> XdgActivationHandler for AnvilState<BackendData> {
                     .map(|last_enter| serial.is_no_older_than(&last_enter))
                     .unwrap_or(false)
         } else {
-            false
+            // Launchers often create tokens without an input serial to hand them
+            // to the game they start, so trust the surface holding the keyboard focus
+            let keyboard = self.seat.get_keyboard().unwrap();
+            match (data.surface.as_ref(), keyboard.current_focus()) {
+                (Some(surface), Some(focus)) => focus
+                    .wl_surface()
+                    .map(|focused| *focused == *surface)
+                    .unwrap_or(false),
+                _ => false,
+            }
         }
     }
 
@@ -450,6 +2037,9 @@ impl<BackendData: Backend> XdgActivationHandler for AnvilState<BackendData> {
             if let Some(window) = w {
                 self.raise_window(&window);
             }
+        } else if let Some(window) = self.get_window(&surface) {
+            // Not allowed to take the focus anymore, only ask for attention
+            self.request_attention(&window);
         }
     }
 }
@@ -574,10 +2164,7 @@ impl<BackendData: Backend + 'static> SecurityContextHandler for AnvilState<Backe
                     security_context: Some(security_context.clone()),
                     ..ClientState::default()
                 };
-                if let Err(err) = data
-                    .display_handle
-                    .insert_client(client_stream, Arc::new(client_state))
-                {
+                if let Err(err) = data.insert_client(client_stream, client_state) {
                     warn!("Error adding wayland client: {}", err);
                 };
             })
@@ -621,6 +2208,34 @@ impl<BackendData: Backend> AnvilState<BackendData> {
         }
         None
     }
+
+    /// Environment for programs started by the compositor to connect to it
+    pub fn client_environment(&self) -> Vec<(&'static str, String)> {
+        self.socket_name
+            .clone()
+            .map(|v| ("WAYLAND_DISPLAY", v))
+            .into_iter()
+            .chain(
+                #[cfg(feature = "xwayland")]
+                self.xdisplay.map(|v| ("DISPLAY", format!(":{}", v))),
+                #[cfg(not(feature = "xwayland"))]
+                None,
+            )
+            .chain(
+                self.config
+                    .cursor
+                    .theme
+                    .clone()
+                    .map(|theme| ("XCURSOR_THEME", theme)),
+            )
+            .chain(
+                self.config
+                    .cursor
+                    .size
+                    .map(|size| ("XCURSOR_SIZE", size.to_string())),
+            )
+            .collect()
+    }
 }
 
 impl<BackendData: Backend> XdgForeignHandler for AnvilState<BackendData> {
@@ -642,36 +2257,96 @@ smithay::delegate_xdg_foreign!(@<BackendData: Backend + 'static> AnvilState<Back
 
 smithay::delegate_single_pixel_buffer!(@<BackendData: Backend + 'static> AnvilState<BackendData>);
 
+/// Listen on the main socket, the extra and abstract ones, returning the name of
+/// the main one
+fn listen_on_sockets<BackendData: Backend + 'static>(
+    handle: &LoopHandle<'static, AnvilState<BackendData>>,
+    name: Option<String>,
+    config: &SocketConfiguration,
+) -> String {
+    let source = match name {
+        Some(name) => ListeningSocketSource::with_name(&name).unwrap_or_else(|err| {
+            warn!(
+                name,
+                ?err,
+                "Unable to create wayland socket, picking a free name"
+            );
+            ListeningSocketSource::new_auto().unwrap()
+        }),
+        None => ListeningSocketSource::new_auto().unwrap(),
+    };
+    let socket_name = source.socket_name().to_string_lossy().into_owned();
+    insert_socket_source(handle, source);
+    info!(name = socket_name, "Listening on wayland socket");
+
+    for name in &config.extra {
+        match ListeningSocketSource::with_name(name) {
+            Ok(source) => {
+                insert_socket_source(handle, source);
+                info!(name, "Listening on extra wayland socket");
+            }
+            Err(err) => warn!(name, ?err, "Unable to create extra wayland socket"),
+        }
+    }
+
+    if let Some(name) = &config.abstract_name {
+        match bind_abstract_socket(name) {
+            Ok(listener) => {
+                handle
+                    .insert_source(
+                        Generic::new(listener, Interest::READ, Mode::Level),
+                        |_, listener, data| {
+                            while let Ok((stream, _)) = listener.accept() {
+                                if let Err(err) = data.insert_client(stream, ClientState::default())
+                                {
+                                    warn!("Error adding wayland client: {}", err);
+                                }
+                            }
+                            Ok(PostAction::Continue)
+                        },
+                    )
+                    .expect("Failed to init abstract socket source");
+                info!(name, "Listening on abstract wayland socket");
+            }
+            Err(err) => warn!(name, ?err, "Unable to create abstract wayland socket"),
+        }
+    }
+
+    socket_name
+}
+
+fn insert_socket_source<BackendData: Backend + 'static>(
+    handle: &LoopHandle<'static, AnvilState<BackendData>>,
+    source: ListeningSocketSource,
+) {
+    handle
+        .insert_source(source, |client_stream, _, data| {
+            if let Err(err) = data.insert_client(client_stream, ClientState::default()) {
+                warn!("Error adding wayland client: {}", err);
+            };
+        })
+        .expect("Failed to init wayland socket source");
+}
+
+fn bind_abstract_socket(name: &str) -> std::io::Result<UnixListener> {
+    let addr = SocketAddr::from_abstract_name(name)?;
+    let listener = UnixListener::bind_addr(&addr)?;
+    listener.set_nonblocking(true)?;
+    Ok(listener)
+}
+
 impl<BackendData: Backend + 'static> AnvilState<BackendData> {
     pub fn init(
         display: Display<AnvilState<BackendData>>,
         handle: LoopHandle<'static, AnvilState<BackendData>>,
         backend_data: BackendData,
         listen_on_socket: bool,
+        socket_name: Option<String>,
     ) -> AnvilState<BackendData> {
         let dh = display.handle();
 
         let clock = Clock::new();
 
-        // init wayland clients
-        let socket_name = if listen_on_socket {
-            let source = ListeningSocketSource::new_auto().unwrap();
-            let socket_name = source.socket_name().to_string_lossy().into_owned();
-            handle
-                .insert_source(source, |client_stream, _, data| {
-                    if let Err(err) = data
-                        .display_handle
-                        .insert_client(client_stream, Arc::new(ClientState::default()))
-                    {
-                        warn!("Error adding wayland client: {}", err);
-                    };
-                })
-                .expect("Failed to init wayland socket source");
-            info!(name = socket_name, "Listening on wayland socket");
-            Some(socket_name)
-        } else {
-            None
-        };
         handle
             .insert_source(
                 Generic::new(display, Interest::READ, Mode::Level),
@@ -692,8 +2367,9 @@ impl<BackendData: Backend + 'static> AnvilState<BackendData> {
         let layer_shell_state = WlrLayerShellState::new::<Self>(&dh);
         let output_manager_state = OutputManagerState::new_with_xdg_output::<Self>(&dh);
         let primary_selection_state = PrimarySelectionState::new::<Self>(&dh);
+        let protocol_policy = ProtocolPolicy::new(dh.clone());
         let data_control_state =
-            DataControlState::new::<Self, _>(&dh, Some(&primary_selection_state), |_| true);
+            DataControlState::new::<Self, _>(&dh, Some(&primary_selection_state), unsandboxed);
         let mut seat_state = SeatState::new();
         let shm_state = ShmState::new::<Self>(&dh, vec![]);
         let viewporter_state = ViewporterState::new::<Self>(&dh);
@@ -704,11 +2380,13 @@ impl<BackendData: Backend + 'static> AnvilState<BackendData> {
         let fractional_scale_manager_state = FractionalScaleManagerState::new::<Self>(&dh);
         let xdg_foreign_state = XdgForeignState::new::<Self>(&dh);
         let single_pixel_buffer_state = SinglePixelBufferState::new::<Self>(&dh);
-        let output_management_manager_state =
-            OutputManagementManagerState::new::<Self, _>(&dh, |_| true);
+        let output_management_manager_state = OutputManagementManagerState::new::<Self, _>(
+            &dh,
+            protocol_policy.filter(PrivilegedProtocol::OutputManagement),
+        );
         TextInputManagerState::new::<Self>(&dh);
-        InputMethodManagerState::new::<Self, _>(&dh, |_client| true);
-        VirtualKeyboardManagerState::new::<Self, _>(&dh, |_client| true);
+        InputMethodManagerState::new::<Self, _>(&dh, unsandboxed);
+        VirtualKeyboardManagerState::new::<Self, _>(&dh, unsandboxed);
         // Expose global only if backend supports relative motion events
         if BackendData::HAS_RELATIVE_MOTION {
             RelativePointerManagerState::new::<Self>(&dh);
@@ -718,12 +2396,15 @@ impl<BackendData: Backend + 'static> AnvilState<BackendData> {
             PointerGesturesState::new::<Self>(&dh);
         }
         TabletManagerState::new::<Self>(&dh);
-        let toplevel_manager = ForeignToplevelManagerState::new::<Self, _>(&dh, |_client| true);
-        SecurityContextState::new::<Self, _>(&dh, |client| {
-            client
-                .get_data::<ClientState>()
-                .map_or(true, |client_state| client_state.security_context.is_none())
-        });
+        let toplevel_manager = ForeignToplevelManagerState::new::<Self, _>(
+            &dh,
+            protocol_policy.filter(PrivilegedProtocol::ForeignToplevel),
+        );
+        let hud_manager_state = HudManagerState::new::<Self, _>(&dh, unsandboxed);
+        let scaling_manager_state = ScalingManagerState::new::<Self>(&dh);
+        TearingControlState::new::<Self>(&dh);
+        ContentTypeState::new::<Self>(&dh);
+        SecurityContextState::new::<Self, _>(&dh, unsandboxed);
 
         // init input
         let seat_name = backend_data.seat_name();
@@ -742,27 +2423,73 @@ impl<BackendData: Backend + 'static> AnvilState<BackendData> {
         XWaylandKeyboardGrabState::new::<Self>(&dh.clone());
 
         // Config Watcher
-        let config_path = confy::get_configuration_file_path("consolation", None)
-            .expect("Unable to find config path");
+        let config_path = config_path().expect("Unable to find config path");
 
-        // Load the config or have a default file
-        let config = match confy::load("consolation", None) {
+        // Load the config, a missing file is created with the defaults. One that
+        // doesn't parse is left alone for the user to fix
+        let config = match load_config() {
             Ok(config) => config,
-            Err(_) => {
-                let config = Configuration::default();
-                confy::store("consolation", None, config.clone())
-                    .unwrap_or_else(|err| println!("Unable to save config: {:?}", err));
-                config
+            Err(err) => {
+                warn!(?err, path = %config_path.display(), "Unable to load config, using defaults");
+                Configuration::default()
             }
         };
 
-        // Create Watcher
-        let (tx, rx) = std::sync::mpsc::channel();
-        let mut watcher: RecommendedWatcher =
-            Watcher::new(tx, notify::Config::default()).expect("Unable start config watcher");
+        protocol_policy.update(config.protocol_policy.clone());
+        if let Some(name) = config
+            .protocol_policy
+            .as_ref()
+            .and_then(|policy| policy.privileged_socket.as_deref())
+        {
+            match ListeningSocketSource::with_name(name) {
+                Ok(source) => {
+                    handle
+                        .insert_source(source, |client_stream, _, data| {
+                            let client_state = ClientState {
+                                privileged: true,
+                                ..ClientState::default()
+                            };
+                            if let Err(err) = data.insert_client(client_stream, client_state) {
+                                warn!("Error adding privileged wayland client: {}", err);
+                            };
+                        })
+                        .expect("Failed to init privileged socket source");
+                    info!(name, "Listening on privileged wayland socket");
+                }
+                Err(err) => warn!(name, ?err, "Unable to create privileged socket"),
+            }
+        }
+
+        // init wayland clients
+        let socket_name = if listen_on_socket {
+            Some(listen_on_sockets(
+                &handle,
+                socket_name.or_else(|| config.sockets.name.clone()),
+                &config.sockets,
+            ))
+        } else {
+            None
+        };
+
+        // Create Watcher, waking the loop on changes
+        let (tx, rx) = channel();
+        let mut watcher: RecommendedWatcher = Watcher::new(
+            move |event| {
+                let _ = tx.send(event);
+            },
+            notify::Config::default(),
+        )
+        .expect("Unable start config watcher");
         watcher
             .watch(&config_path, RecursiveMode::NonRecursive)
             .expect("Unable to watch config file");
+        handle
+            .insert_source(rx, |event, _, data| {
+                if let channel::Event::Msg(event) = event {
+                    data.process_config_event(event);
+                }
+            })
+            .expect("Unable to insert config watcher source");
 
         AnvilState {
             backend_data,
@@ -791,6 +2518,39 @@ impl<BackendData: Backend + 'static> AnvilState<BackendData> {
             xdg_foreign_state,
             single_pixel_buffer_state,
             dnd_icon: None,
+            clipboard: ClipboardStore::default(),
+            button_gestures: GestureDetector::default(),
+            window_peek: None,
+            picture_in_picture: None,
+            split_screen: None,
+            second_seat: None,
+            zoom: ZoomState::default(),
+            color_filter_enabled: config.color_filter.enabled,
+            screenshot: ScreenshotState::default(),
+            window_cycling: WindowCycling::default(),
+            backlight: Backlight::default(),
+            window_transitions: WindowTransitions::default(),
+            animation_clock: AnimationClock::new(config.animation_rate),
+            splash: config.startup.splash,
+            app_watchdog: None,
+            notifications: Notifications::default(),
+            progress: ProgressReports::default(),
+            status: StatusOverlay::default(),
+            text_entry: None,
+            time: TimeState::default(),
+            custom_key_actions: Vec::new(),
+            #[cfg(feature = "udev")]
+            hibernation: HibernationState::default(),
+            #[cfg(feature = "udev")]
+            cursor_visibility: CursorVisibility::default(),
+            #[cfg(feature = "udev")]
+            portal: Portal::default(),
+            dbus: DBusServer::default(),
+            protocol_trace: ProtocolTrace::default(),
+            metrics: Metrics::default(),
+            services: ServiceSupervisor::default(),
+            launched: LaunchedCommands::default(),
+            dock: DockState::default(),
             suppressed_keys: Vec::new(),
             cursor_status: CursorImageStatus::default_named(),
             seat_name,
@@ -803,64 +2563,214 @@ impl<BackendData: Backend + 'static> AnvilState<BackendData> {
             xwm: None,
             #[cfg(feature = "xwayland")]
             xdisplay: None,
+            #[cfg(feature = "xwayland")]
+            xwayland_on_demand: None,
             toplevel_manager,
+            protocol_policy,
+            hud_manager_state,
+            scaling_manager_state,
             output_management_state: output_management_manager_state,
             output_states: HashMap::new(),
             config: config,
-            config_watcher: rx,
             config_watcher_obj: watcher,
             outputs_config: None,
         }
     }
 
+    /// Default cursor of X11 windows, from the theme of the pointer
+    #[cfg(feature = "xwayland")]
+    pub fn update_xwayland_cursor(&mut self) {
+        let Some(wm) = self.xwm.as_mut() else {
+            return;
+        };
+        let image = Cursor::load(&self.config.cursor).get_image(1, Duration::ZERO);
+        if let Err(err) = wm.set_cursor(
+            &image.pixels_rgba,
+            Size::from((image.width as u16, image.height as u16)),
+            Point::from((image.xhot as u16, image.yhot as u16)),
+        ) {
+            warn!(?err, "Failed to set xwayland default cursor");
+        }
+    }
+
+    /// Merge `Xft.dpi` into the X resources, on a thread as xrdb waits on the server
+    #[cfg(feature = "xwayland")]
+    pub(crate) fn apply_xwayland_dpi(&self) {
+        use std::{
+            io::Write,
+            process::{Command, Stdio},
+        };
+
+        let (Some(dpi), Some(display)) = (self.config.xwayland.dpi, self.xdisplay) else {
+            return;
+        };
+        std::thread::spawn(move || {
+            let res = Command::new("xrdb")
+                .arg("-merge")
+                .env("DISPLAY", format!(":{display}"))
+                .stdin(Stdio::piped())
+                .spawn()
+                .and_then(|mut child| {
+                    if let Some(mut stdin) = child.stdin.take() {
+                        writeln!(stdin, "Xft.dpi: {dpi}")?;
+                    }
+                    child.wait()
+                });
+            match res {
+                Ok(status) if status.success() => info!(dpi, "Set the Xft.dpi resource"),
+                Ok(status) => warn!(dpi, %status, "Unable to set the Xft.dpi resource"),
+                Err(err) => warn!(dpi, ?err, "Unable to set the Xft.dpi resource"),
+            }
+        });
+    }
+
     #[cfg(feature = "xwayland")]
     pub fn start_xwayland(&mut self) {
+        self.spawn_xwayland(None).expect("failed to start XWayland");
+    }
+
+    /// Start Xwayland on `display`, or the first free one. Removing the returned
+    /// source from the event loop frees the display again
+    #[cfg(feature = "xwayland")]
+    pub(crate) fn spawn_xwayland(
+        &mut self,
+        display: Option<u32>,
+    ) -> std::io::Result<(RegistrationToken, Client)> {
         use std::process::Stdio;
 
         let (xwayland, client) = XWayland::spawn(
             &self.display_handle,
-            None,
+            display,
             std::iter::empty::<(String, String)>(),
             true,
             Stdio::null(),
             Stdio::null(),
             |_| (),
-        )
-        .expect("failed to start XWayland");
+        )?;
 
-        let ret = self
-            .handle
+        let wm_client = client.clone();
+        self.handle
             .insert_source(xwayland, move |event, _, data| match event {
                 XWaylandEvent::Ready {
                     x11_socket,
                     display_number,
                 } => {
-                    let mut wm = X11Wm::start_wm(data.handle.clone(), x11_socket, client.clone())
+                    let wm = X11Wm::start_wm(data.handle.clone(), x11_socket, wm_client.clone())
                         .expect("Failed to attach X11 Window Manager");
 
-                    let cursor = Cursor::load();
-                    let image = cursor.get_image(1, Duration::ZERO);
-                    wm.set_cursor(
-                        &image.pixels_rgba,
-                        Size::from((image.width as u16, image.height as u16)),
-                        Point::from((image.xhot as u16, image.yhot as u16)),
-                    )
-                    .expect("Failed to set xwayland default cursor");
                     data.xwm = Some(wm);
+                    data.update_xwayland_cursor();
                     data.xdisplay = Some(display_number);
+                    data.apply_xwayland_dpi();
+                    data.update_systemd_environment();
                 }
                 XWaylandEvent::Error => {
                     warn!("XWayland crashed on startup");
                 }
+            })
+            .map(|token| (token, client))
+            .map_err(|err| std::io::Error::other(err.error))
+    }
+
+    /// Periodically drop windows and launched commands that went away without notice
+    pub fn start_reaping(&mut self) {
+        let res = self
+            .handle
+            .insert_source(Timer::from_duration(REAP_INTERVAL), |_, _, data| {
+                let size = data.elements.len();
+                data.elements.retain(|window| window.alive());
+                if size != data.elements.len() {
+                    debug!(reaped = size - data.elements.len(), "Reaped dead windows");
+                    data.update_keyboard_focus();
+                }
+                data.launched.reap();
+                TimeoutAction::ToDuration(REAP_INTERVAL)
             });
-        if let Err(e) = ret {
-            tracing::error!(
-                "Failed to insert the XWaylandSource into the event loop: {}",
-                e
-            );
+        if let Err(err) = res {
+            warn!(?err, "Unable to insert reaping timer");
+        }
+    }
+
+    /// Keep the windows in order after each dispatch of the event loop, for
+    /// every backend
+    pub fn refresh_windows(&mut self) {
+        let size = self.elements.len();
+        self.elements.retain(|window| window.alive());
+        if size != self.elements.len() {
+            self.update_keyboard_focus();
+        }
+        self.refresh_window_placement();
+        self.refresh_window_access();
+        self.refresh_peek();
+        self.refresh_picture_in_picture();
+        self.refresh_split_screen();
+        self.refresh_transition();
+    }
+
+    /// How long the main loop may sleep waiting for events, until the earliest
+    /// deadline of the work done after a dispatch. Timers, vblanks and client
+    /// requests wake it on their own, so it sleeps until one of them comes once
+    /// nothing else is pending
+    pub fn dispatch_timeout(&self) -> Option<Duration> {
+        self.window_transitions
+            .current
+            .as_ref()
+            .map(|transition| transition.remaining())
+    }
+
+    /// Sync protocol state with the windows, once the frames of a dispatch are out
+    pub fn refresh_protocol_state(&mut self) {
+        toplevel_manager::refresh(self);
+        hud::refresh(self);
+        scaling::refresh(self);
+        self.popups.cleanup();
+    }
+
+    /// Reload the config once its file changed
+    fn process_config_event(&mut self, event: Result<Event, notify::Error>) {
+        let event = match event {
+            Ok(event) => event,
+            Err(err) => {
+                error!("Watcher error : {:?}", err);
+                return;
+            }
+        };
+        match event.kind {
+            notify::EventKind::Create(_) => info!("Config create"),
+            notify::EventKind::Modify(_) => {
+                info!("Configuration file changed");
+                if let Err(err) = self.reload_config() {
+                    error!("Unable to load new config : {}", err)
+                }
+            }
+            notify::EventKind::Remove(_) => {
+                warn!("Config removed");
+                // Some editors (like vim) delete the file in the process of writing. Start a new watch
+                let config_path = config_path().expect("Unable to find config path");
+                self.config_watcher_obj
+                    .watch(&config_path, RecursiveMode::NonRecursive)
+                    .expect("Unable to watch config file");
+            }
+            _ => {}
         }
     }
 
+    /// Read the config file again and apply it, the backend applies what only it
+    /// uses
+    pub fn reload_config(&mut self) -> Result<(), String> {
+        let config = load_config()?;
+        let old = self.config.clone();
+        self.config.set_from(config);
+        self.protocol_policy
+            .update(self.config.protocol_policy.clone());
+        self.sync_services();
+        self.start_dock_detection();
+        self.animation_clock.set_rate(self.config.animation_rate);
+        BackendData::config_reloaded(self, &old);
+        info!("Configuration reloaded");
+        Ok(())
+    }
+
     pub fn unmap_window(&mut self, window: &Window) {
         if let Some(index) = self.elements.iter().position(|w| w == window) {
             self.elements.remove(index);
@@ -878,6 +2788,18 @@ impl<BackendData: Backend + 'static> AnvilState<BackendData> {
     }
 
     pub fn raise_window(&mut self, window: &Window) {
+        if self.window_blocked(window) {
+            return;
+        }
+        if is_minimized(window) {
+            set_minimized(window, false);
+            #[cfg(feature = "xwayland")]
+            if let Some(xwindow) = window.x11_surface() {
+                if let Err(err) = xwindow.set_minimized(false) {
+                    warn!(?err, "Unable to unminimize X11 window");
+                }
+            }
+        }
         if let Some(windex) = self.elements.iter().position(|w| w == window) {
             self.raise_window_number(windex);
         }
@@ -935,32 +2857,21 @@ impl<BackendData: Backend + 'static> AnvilState<BackendData> {
         }
     }
 
+    /// Send `window` to the back, out of the window rotation until raised again
     pub fn minimize_window(&mut self, window: &Window) {
-        if window.is_x11() {
-            #[cfg(feature = "xwayland")]
-            if let Some(xwindow) = window.x11_surface() {
-                if let Some(xwmid) = xwindow.xwm_id() {
-                    XwmHandler::minimize_request(self, xwmid, xwindow.clone());
-                }
-            }
-        } else if window.is_wayland() {
-            if let Some(toplevel) = window.toplevel() {
-                XdgShellHandler::minimize_request(self, toplevel.clone());
+        set_minimized(window, true);
+        #[cfg(feature = "xwayland")]
+        if let Some(xwindow) = window.x11_surface() {
+            if let Err(err) = xwindow.set_minimized(true) {
+                warn!(?err, "Unable to minimize X11 window");
             }
         }
+        self.lower_window(window);
+        self.update_keyboard_focus();
     }
 
     pub fn unminimize_window(&mut self, window: &Window) {
-        if window.is_x11() {
-            #[cfg(feature = "xwayland")]
-            if let Some(xwindow) = window.x11_surface() {
-                if let Some(xwmid) = xwindow.xwm_id() {
-                    XwmHandler::unminimize_request(self, xwmid, xwindow.clone());
-                }
-            }
-        } else if window.is_wayland() {
-            self.raise_window(window);
-        }
+        self.raise_window(window);
     }
 
     pub fn fullscreen_window(&mut self, window: &Window, output: Option<WlOutput>) {
@@ -978,8 +2889,11 @@ impl<BackendData: Backend + 'static> AnvilState<BackendData> {
 
     pub fn close_window(&mut self, window: &Window) {
         if window.is_x11() {
+            // WM_DELETE_WINDOW when the window takes it, else its client is killed
             if let Some(xwindow) = window.x11_surface() {
-                let _res = xwindow.close();
+                if let Err(err) = xwindow.close() {
+                    warn!(?err, "Unable to close X11 window");
+                }
             }
         } else {
             if let Some(toplevel) = window.toplevel() {
@@ -996,7 +2910,11 @@ impl<BackendData: Backend + 'static> ForeignToplevelHandler for AnvilState<Backe
 
     fn activate(&mut self, wl_surface: WlSurface) {
         match self.get_window(&wl_surface) {
-            Some(window) => self.raise_window(&window),
+            Some(window) => {
+                // The entry stands for its whole launch group
+                let window = self.launch_group_window(&window);
+                self.raise_window(&window)
+            }
             None => {}
         }
     }
@@ -1061,6 +2979,10 @@ pub struct SurfaceDmabufFeedback<'a> {
     pub scanout_feedback: &'a DmabufFeedback,
 }
 
+/// Number of repaints of an output, used to pace frame callbacks
+#[derive(Debug, Default)]
+struct OutputRepaintCount(Cell<u64>);
+
 #[profiling::function]
 pub fn post_repaint(
     output: &Output,
@@ -1068,10 +2990,18 @@ pub fn post_repaint(
     elements: &Vec<Window>,
     dmabuf_feedback: Option<SurfaceDmabufFeedback<'_>>,
     time: impl Into<Duration>,
+    config: &Configuration,
 ) {
     let time = time.into();
     let throttle = Some(Duration::from_secs(1));
 
+    output
+        .user_data()
+        .insert_if_missing(OutputRepaintCount::default);
+    let repaint_count = output.user_data().get::<OutputRepaintCount>().unwrap();
+    let repaint = repaint_count.0.get();
+    repaint_count.0.set(repaint.wrapping_add(1));
+
     elements.iter().for_each(|window| {
         window.with_surfaces(|surface, states| {
             let primary_scanout_output = update_surface_primary_scanout_output(
@@ -1089,7 +3019,30 @@ pub fn post_repaint(
             }
         });
 
-        window.send_frame(output, time, throttle, surface_primary_scanout_output);
+        let rule = config.window_rule_for(window);
+        let frame_divisor = rule
+            .filter(|_| window_content_policy(window).pace_frames)
+            .and_then(|rule| rule.experimental_frame_divisor)
+            .unwrap_or(1)
+            .max(1);
+        // Surfaces not shown on their primary output only get frame callbacks once per
+        // throttle interval, and none without one
+        let background_throttle = match rule
+            .and_then(|rule| rule.background_frame_rate)
+            .or(config.background_frame_rate)
+        {
+            Some(rate) if rate > 0.0 => Some(Duration::from_secs_f64(1.0 / rate)),
+            Some(_) => None,
+            None => throttle,
+        };
+        if repaint % frame_divisor as u64 == 0 && !is_hidden(window) {
+            window.send_frame(
+                output,
+                time,
+                background_throttle,
+                surface_primary_scanout_output,
+            );
+        }
         if let Some(dmabuf_feedback) = dmabuf_feedback {
             window.send_dmabuf_feedback(output, surface_primary_scanout_output, |surface, _| {
                 select_dmabuf_feedback(
@@ -1175,4 +3128,21 @@ pub trait Backend {
     fn reset_buffers(&mut self, output: &Output);
     fn early_import(&mut self, surface: &WlSurface);
     fn update_led_state(&mut self, led_state: LedState);
+    /// Copy what `window` last committed into a new dmabuf, stretched to `size`
+    fn capture_window(&mut self, window: &Window, size: Size<i32, Physical>) -> Option<Dmabuf>;
+    /// Draw `window` stretched to `size` and read back its pixels as RGBA
+    fn sample_window(&mut self, window: &Window, size: Size<i32, Physical>) -> Option<Vec<u8>>;
+    /// Apply the parts of a reloaded config only this backend uses, `old` being the
+    /// config before
+    fn config_reloaded(_state: &mut AnvilState<Self>, _old: &Configuration)
+    where
+        Self: Sized,
+    {
+    }
+    /// Apply the input config to the devices, after it changed without a reload
+    fn input_config_changed(_state: &mut AnvilState<Self>)
+    where
+        Self: Sized,
+    {
+    }
 }
@@ -0,0 +1,248 @@
+use std::{
+    fmt,
+    fs::{read_dir, read_to_string},
+    path::Path,
+    time::Duration,
+};
+
+use rusttype::Font;
+use smithay::{
+    backend::{
+        allocator::Fourcc,
+        renderer::{
+            element::{
+                memory::{MemoryRenderBuffer, MemoryRenderBufferRenderElement},
+                Kind,
+            },
+            ImportMem, Renderer,
+        },
+    },
+    input::keyboard::Keysym,
+    output::Output,
+    reexports::calloop::timer::{TimeoutAction, Timer},
+    utils::{Physical, Point, Size, Transform},
+};
+use tracing::{debug, warn};
+use xkbcommon::xkb;
+
+use crate::{
+    input_handler::KeyAction,
+    state::{AnvilState, Backend},
+    text::{draw_text, load_font, text_width},
+};
+
+const POLL_INTERVAL: Duration = Duration::from_secs(5);
+const MARGIN: i32 = 16;
+const PADDING: i32 = 8;
+const FONT_SIZE: f32 = 20.0;
+/// Premultiplied BGRA
+const BACKDROP_COLOR: [u8; 4] = [20, 20, 20, 200];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct Battery {
+    percent: u32,
+    charging: bool,
+}
+
+/// Battery, time and network state in the top right corner of every output
+#[derive(Default)]
+pub struct StatusOverlay {
+    shown: bool,
+    font: Option<Font<'static>>,
+    battery: Option<Battery>,
+    network_up: bool,
+    /// Drawn overlay, the output scale and text it was drawn for
+    buffer: Option<(f64, String, MemoryRenderBuffer, Size<i32, Physical>)>,
+}
+
+impl fmt::Debug for StatusOverlay {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("StatusOverlay")
+            .field("shown", &self.shown)
+            .field("font", &self.font.is_some())
+            .field("battery", &self.battery)
+            .field("network_up", &self.network_up)
+            .finish_non_exhaustive()
+    }
+}
+
+/// The first battery power supply, the ones of gamepads and mice are scoped to
+/// their device
+fn read_battery() -> Option<Battery> {
+    let supplies = read_dir("/sys/class/power_supply").ok()?;
+    supplies.flatten().find_map(|supply| {
+        let path = supply.path();
+        let read = |file: &str| read_to_string(path.join(file)).ok();
+        if read("type")?.trim() != "Battery"
+            || read("scope").is_some_and(|scope| scope.trim() == "Device")
+        {
+            return None;
+        }
+        Some(Battery {
+            percent: read("capacity")?.trim().parse().ok()?,
+            charging: read("status").is_some_and(|status| status.trim() == "Charging"),
+        })
+    })
+}
+
+/// Whether any interface besides loopback is up
+fn network_up() -> bool {
+    let Ok(interfaces) = read_dir("/sys/class/net") else {
+        return false;
+    };
+    interfaces
+        .flatten()
+        .filter(|interface| interface.file_name() != "lo")
+        .any(|interface| operstate_up(&interface.path()))
+}
+
+fn operstate_up(path: &Path) -> bool {
+    read_to_string(path.join("operstate")).is_ok_and(|state| state.trim() == "up")
+}
+
+fn draw_status(
+    font: &Font<'static>,
+    text: &str,
+    scale: f64,
+) -> (MemoryRenderBuffer, Size<i32, Physical>) {
+    let padding = (PADDING as f64 * scale).round() as i32;
+    let font_size = FONT_SIZE * scale as f32;
+    let width = text_width(font, text, font_size).ceil() as i32 + padding * 2;
+    let height = font_size.ceil() as i32 + padding * 2;
+
+    let mut pixels = BACKDROP_COLOR.repeat((width * height) as usize);
+    let origin = (padding as f32, padding as f32);
+    draw_text(&mut pixels, width, font, text, font_size, origin, width);
+
+    let buffer = MemoryRenderBuffer::from_slice(
+        &pixels,
+        Fourcc::Argb8888,
+        (width, height),
+        1,
+        Transform::Normal,
+        None,
+    );
+    (buffer, Size::from((width, height)))
+}
+
+impl StatusOverlay {
+    fn text(&self, twenty_four_hour: bool) -> String {
+        let mut parts = Vec::new();
+        if let Some(battery) = self.battery {
+            let charging = if battery.charging { "+" } else { "" };
+            parts.push(format!("{}%{charging}", battery.percent));
+        }
+        let network = if self.network_up {
+            "Net up"
+        } else {
+            "Net down"
+        };
+        parts.push(network.to_string());
+        let format = if twenty_four_hour {
+            "%H:%M"
+        } else {
+            "%-I:%M %p"
+        };
+        parts.push(chrono::Local::now().format(format).to_string());
+        parts.join("  ")
+    }
+
+    /// The overlay in the top right corner of `output` while shown
+    pub fn render_elements<R>(
+        &mut self,
+        renderer: &mut R,
+        output: &Output,
+        twenty_four_hour: bool,
+    ) -> Vec<MemoryRenderBufferRenderElement<R>>
+    where
+        R: Renderer + ImportMem,
+        <R as Renderer>::TextureId: Send + Clone + 'static,
+    {
+        if !self.shown {
+            return Vec::new();
+        }
+        let Some(font) = self.font.as_ref() else {
+            return Vec::new();
+        };
+        let Some(mode) = output.current_mode() else {
+            return Vec::new();
+        };
+        let size: Size<i32, Physical> = output.current_transform().transform_size(mode.size);
+        let scale = output.current_scale().fractional_scale();
+
+        let text = self.text(twenty_four_hour);
+        if self
+            .buffer
+            .as_ref()
+            .map(|(drawn, drawn_text, ..)| (*drawn, drawn_text.as_str()))
+            != Some((scale, text.as_str()))
+        {
+            let (buffer, size) = draw_status(font, &text, scale);
+            self.buffer = Some((scale, text, buffer, size));
+        }
+        let (.., buffer, status_size) = self.buffer.as_ref().unwrap();
+        let margin = (MARGIN as f64 * scale).round() as i32;
+        let location = Point::<i32, Physical>::from((size.w - status_size.w - margin, margin));
+        match MemoryRenderBufferRenderElement::from_buffer(
+            renderer,
+            location.to_f64(),
+            buffer,
+            None,
+            None,
+            None,
+            Kind::Unspecified,
+        ) {
+            Ok(element) => vec![element],
+            Err(err) => {
+                warn!(?err, "Unable to upload status");
+                Vec::new()
+            }
+        }
+    }
+}
+
+impl<BackendData: Backend> AnvilState<BackendData> {
+    /// Load the font and poll the battery and network if the overlay can be shown
+    pub fn start_status_overlay(&mut self) {
+        let config = &self.config.status;
+        if !config.show && config.toggle_key.is_none() {
+            return;
+        }
+        self.status.shown = config.show;
+        self.status.font = load_font(config.font.as_deref());
+        self.refresh_status();
+        let res = self
+            .handle
+            .insert_source(Timer::from_duration(POLL_INTERVAL), |_, _, data| {
+                data.refresh_status();
+                TimeoutAction::ToDuration(POLL_INTERVAL)
+            });
+        if let Err(err) = res {
+            warn!(?err, "Unable to insert status timer");
+        }
+    }
+
+    fn refresh_status(&mut self) {
+        if !self.status.shown {
+            return;
+        }
+        self.status.battery = read_battery();
+        self.status.network_up = network_up();
+    }
+
+    /// Check if `keysym` shows or hides the status overlay
+    pub fn status_key_action(&self, keysym: Keysym) -> Option<KeyAction> {
+        let toggle_key = self.config.status.toggle_key.as_ref()?;
+        let toggle_key = xkb::keysym_from_name(toggle_key, xkb::KEYSYM_CASE_INSENSITIVE);
+        (toggle_key.raw() == keysym.raw()).then_some(KeyAction::ToggleStatus)
+    }
+
+    pub fn toggle_status(&mut self) {
+        if self.status.font.is_none() {
+            return;
+        }
+        self.status.shown = !self.status.shown;
+        debug!(shown = self.status.shown, "Toggled status overlay");
+        self.refresh_status();
+    }
+}
@@ -0,0 +1,132 @@
+use std::{
+    collections::HashMap,
+    env, io,
+    os::{
+        linux::net::SocketAddrExt,
+        unix::{
+            ffi::OsStrExt,
+            net::{SocketAddr, UnixDatagram},
+        },
+    },
+    thread,
+};
+
+use tracing::{debug, info, warn};
+use zbus::blocking::{fdo::DBusProxy, Connection};
+
+use crate::state::{AnvilState, Backend};
+
+#[zbus::proxy(
+    interface = "org.freedesktop.systemd1.Manager",
+    default_service = "org.freedesktop.systemd1",
+    default_path = "/org/freedesktop/systemd1"
+)]
+trait SystemdManager {
+    fn set_environment(&self, assignments: &[&str]) -> zbus::Result<()>;
+
+    fn start_unit(&self, name: &str, mode: &str) -> zbus::Result<zbus::zvariant::OwnedObjectPath>;
+}
+
+impl<BackendData: Backend> AnvilState<BackendData> {
+    /// Give the display to the systemd user manager and D-Bus activation, start the
+    /// session target and tell systemd the session is up. Done on a thread as the
+    /// managers may take a while to answer
+    pub fn start_systemd_session(&self) {
+        let environment = self
+            .config
+            .session
+            .import_environment
+            .then(|| self.session_environment());
+        let target = self.config.session.target.clone();
+        thread::spawn(move || {
+            if let Some(environment) = environment {
+                import_environment(&environment);
+            }
+            if let Some(target) = target {
+                start_target(&target);
+            }
+            if let Err(err) = notify("READY=1") {
+                warn!(?err, "Unable to notify systemd");
+            }
+        });
+    }
+
+    /// Import the environment again once it changed, like when Xwayland is ready
+    pub fn update_systemd_environment(&self) {
+        if !self.config.session.import_environment {
+            return;
+        }
+        let environment = self.session_environment();
+        thread::spawn(move || import_environment(&environment));
+    }
+
+    /// What apps get, and what portals look at to pick their backends
+    fn session_environment(&self) -> Vec<(&'static str, String)> {
+        let mut environment = self.client_environment();
+        environment.push(("XDG_SESSION_TYPE", "wayland".into()));
+        environment.push((
+            "XDG_CURRENT_DESKTOP",
+            env::var("XDG_CURRENT_DESKTOP").unwrap_or_else(|_| "consolation".into()),
+        ));
+        environment
+    }
+}
+
+/// Tell systemd about a state change of the service, when started by it
+pub fn notify(state: &str) -> io::Result<()> {
+    let Some(path) = env::var_os("NOTIFY_SOCKET") else {
+        return Ok(());
+    };
+    let addr = match path.as_bytes().strip_prefix(b"@") {
+        Some(name) => SocketAddr::from_abstract_name(name)?,
+        None => SocketAddr::from_pathname(&path)?,
+    };
+    UnixDatagram::unbound()?.send_to_addr(state.as_bytes(), &addr)?;
+    debug!(state, "Notified systemd");
+    Ok(())
+}
+
+fn import_environment(environment: &[(&str, String)]) {
+    let connection = match Connection::session() {
+        Ok(connection) => connection,
+        Err(err) => {
+            warn!(?err, "Unable to connect to the session bus");
+            return;
+        }
+    };
+
+    let assignments = environment
+        .iter()
+        .map(|(name, value)| format!("{name}={value}"))
+        .collect::<Vec<_>>();
+    let assignments = assignments.iter().map(String::as_str).collect::<Vec<_>>();
+    match SystemdManagerProxyBlocking::new(&connection)
+        .and_then(|manager| manager.set_environment(&assignments))
+    {
+        Ok(()) => info!(?assignments, "Imported the environment into systemd"),
+        Err(err) => warn!(?err, "Unable to import the environment into systemd"),
+    }
+
+    let activation = environment
+        .iter()
+        .map(|(name, value)| (*name, value.as_str()))
+        .collect::<HashMap<_, _>>();
+    let res = match DBusProxy::new(&connection) {
+        Ok(dbus) => dbus
+            .update_activation_environment(activation)
+            .map_err(zbus::Error::from),
+        Err(err) => Err(err),
+    };
+    if let Err(err) = res {
+        warn!(?err, "Unable to update the D-Bus activation environment");
+    }
+}
+
+fn start_target(target: &str) {
+    match Connection::session().and_then(|connection| {
+        SystemdManagerProxyBlocking::new(&connection)?.start_unit(target, "replace")
+    }) {
+        Ok(_) => info!(target, "Started systemd target"),
+        Err(err) => warn!(target, ?err, "Unable to start systemd target"),
+    }
+}
@@ -0,0 +1,81 @@
+use rusttype::{point, Font, Scale as FontScale};
+use tracing::warn;
+
+/// Tried in order when no font is configured
+const FONT_PATHS: &[&str] = &[
+    "/usr/share/fonts/TTF/DejaVuSans.ttf",
+    "/usr/share/fonts/truetype/dejavu/DejaVuSans.ttf",
+    "/usr/share/fonts/dejavu/DejaVuSans.ttf",
+    "/usr/share/fonts/noto/NotoSans-Regular.ttf",
+    "/usr/share/fonts/truetype/noto/NotoSans-Regular.ttf",
+];
+
+/// The font at `path`, or a common system font
+pub fn load_font(path: Option<&str>) -> Option<Font<'static>> {
+    let paths = path.map_or_else(|| FONT_PATHS.to_vec(), |path| vec![path]);
+    let font = paths.into_iter().find_map(|path| {
+        let data = std::fs::read(path).ok()?;
+        Font::try_from_vec(data)
+    });
+    if font.is_none() {
+        warn!(?path, "No font found, drawing without text");
+    }
+    font
+}
+
+/// Fill a rectangle of premultiplied BGRA `pixels`, `width` pixels wide
+pub fn fill_rect(pixels: &mut [u8], width: i32, rect: (i32, i32, i32, i32), color: [u8; 4]) {
+    let height = pixels.len() as i32 / 4 / width;
+    let (x, y, w, h) = rect;
+    for row in y.max(0)..(y + h).min(height) {
+        for column in x.max(0)..(x + w).min(width) {
+            let offset = ((row * width + column) * 4) as usize;
+            pixels[offset..offset + 4].copy_from_slice(&color);
+        }
+    }
+}
+
+/// Width of `text` at a font `size` in pixels
+pub fn text_width(font: &Font<'static>, text: &str, size: f32) -> f32 {
+    font.layout(text, FontScale::uniform(size), point(0.0, 0.0))
+        .last()
+        .map(|glyph| glyph.position().x + glyph.unpositioned().h_metrics().advance_width)
+        .unwrap_or(0.0)
+}
+
+/// Draw white `text` onto premultiplied BGRA `pixels`, `width` pixels wide.
+///
+/// `origin` is the top left corner of the line, glyphs reaching past `max_x` are cut off
+pub fn draw_text(
+    pixels: &mut [u8],
+    width: i32,
+    font: &Font<'static>,
+    text: &str,
+    size: f32,
+    origin: (f32, f32),
+    max_x: i32,
+) {
+    let height = pixels.len() as i32 / 4 / width;
+    let size = FontScale::uniform(size);
+    let ascent = font.v_metrics(size).ascent;
+    for glyph in font.layout(text, size, point(origin.0, origin.1 + ascent)) {
+        let Some(bounds) = glyph.pixel_bounding_box() else {
+            continue;
+        };
+        if bounds.max.x > max_x {
+            break;
+        }
+        glyph.draw(|x, y, coverage| {
+            let x = bounds.min.x + x as i32;
+            let y = bounds.min.y + y as i32;
+            if x < 0 || y < 0 || x >= width || y >= height {
+                return;
+            }
+            let offset = ((y * width + x) * 4) as usize;
+            for channel in &mut pixels[offset..offset + 4] {
+                let blended = *channel as f32 * (1.0 - coverage) + 255.0 * coverage;
+                *channel = blended.round() as u8;
+            }
+        });
+    }
+}
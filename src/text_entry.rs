@@ -0,0 +1,427 @@
+use std::{fmt, process::Command};
+
+use rusttype::Font;
+use smithay::{
+    backend::{
+        allocator::Fourcc,
+        renderer::{
+            element::{
+                memory::{MemoryRenderBuffer, MemoryRenderBufferRenderElement},
+                Kind,
+            },
+            ImportMem, Renderer,
+        },
+    },
+    input::keyboard::Keysym,
+    output::Output,
+    reexports::calloop::channel::Sender,
+    utils::{Physical, Point, Size, Transform},
+};
+use tracing::{debug, warn};
+
+use crate::{
+    input_handler::KeyAction,
+    state::{AnvilState, Backend},
+    text::{draw_text, fill_rect, load_font, text_width},
+};
+
+const KEY_SIZE: i32 = 56;
+const KEY_SPACING: i32 = 6;
+const PANEL_PADDING: i32 = 16;
+const FIELD_HEIGHT: i32 = 48;
+const FONT_SIZE: f32 = 22.0;
+/// Premultiplied BGRA
+const PANEL_COLOR: [u8; 4] = [24, 24, 24, 240];
+const FIELD_COLOR: [u8; 4] = [0, 0, 0, 255];
+const KEY_COLOR: [u8; 4] = [64, 64, 64, 255];
+const SELECTED_COLOR: [u8; 4] = [200, 120, 40, 255];
+/// Shown instead of the characters of passphrases and PINs
+const MASK: &str = "•";
+
+/// What the text is entered for
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TextEntryPurpose {
+    Search,
+    WifiPsk,
+    Pin,
+}
+
+impl TextEntryPurpose {
+    fn masked(self) -> bool {
+        self != TextEntryPurpose::Search
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum EntryKey {
+    Text(String),
+    Shift,
+    Space,
+    Backspace,
+    Done,
+}
+
+impl EntryKey {
+    fn parse(name: &str) -> Self {
+        match name {
+            "shift" => EntryKey::Shift,
+            "space" => EntryKey::Space,
+            "backspace" => EntryKey::Backspace,
+            "done" => EntryKey::Done,
+            text => EntryKey::Text(text.to_string()),
+        }
+    }
+
+    fn label(&self, shift: bool) -> String {
+        match self {
+            EntryKey::Text(text) if shift => text.to_uppercase(),
+            EntryKey::Text(text) => text.clone(),
+            EntryKey::Shift => "Shift".to_string(),
+            EntryKey::Space => "Space".to_string(),
+            EntryKey::Backspace => "Del".to_string(),
+            EntryKey::Done => "OK".to_string(),
+        }
+    }
+}
+
+/// Events passed to the feedback command
+#[derive(Debug, Clone, Copy)]
+enum Feedback {
+    Move,
+    Key,
+    Done,
+    Cancel,
+}
+
+impl Feedback {
+    fn name(self) -> &'static str {
+        match self {
+            Feedback::Move => "move",
+            Feedback::Key => "key",
+            Feedback::Done => "done",
+            Feedback::Cancel => "cancel",
+        }
+    }
+}
+
+/// Grid keyboard for entering text with a gamepad.
+///
+/// The thumbstick or d-pad moves the selection, the confirm button presses the
+/// selected key and the back button deletes a character or cancels when there is none.
+/// The text, or `None` when cancelled, is sent to the channel given on opening
+pub struct TextEntry {
+    purpose: TextEntryPurpose,
+    rows: Vec<Vec<EntryKey>>,
+    /// Row and column of the selected key
+    selected: (usize, usize),
+    text: String,
+    shift: bool,
+    done: Sender<Option<String>>,
+    font: Option<Font<'static>>,
+    /// Drawn panel, its size and the output scale it was drawn for
+    buffer: Option<(f64, MemoryRenderBuffer, Size<i32, Physical>)>,
+}
+
+impl fmt::Debug for TextEntry {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("TextEntry")
+            .field("purpose", &self.purpose)
+            .field("selected", &self.selected)
+            .field("shift", &self.shift)
+            .finish_non_exhaustive()
+    }
+}
+
+impl TextEntry {
+    fn new(
+        purpose: TextEntryPurpose,
+        layout: &[Vec<String>],
+        font: Option<Font<'static>>,
+        done: Sender<Option<String>>,
+    ) -> Self {
+        let mut rows: Vec<Vec<EntryKey>> = layout
+            .iter()
+            .map(|row| row.iter().map(|key| EntryKey::parse(key)).collect())
+            .filter(|row: &Vec<EntryKey>| !row.is_empty())
+            .collect();
+        // Finishing has to be possible with any layout
+        if !rows.iter().flatten().any(|key| *key == EntryKey::Done) {
+            rows.push(vec![EntryKey::Backspace, EntryKey::Done]);
+        }
+        Self {
+            purpose,
+            rows,
+            selected: (0, 0),
+            text: String::new(),
+            shift: false,
+            done,
+            font,
+            buffer: None,
+        }
+    }
+
+    fn move_selection(&mut self, dx: i32, dy: i32) {
+        let (row, column) = self.selected;
+        let rows = self.rows.len() as i32;
+        let row = (row as i32 + dy).rem_euclid(rows) as usize;
+        let columns = self.rows[row].len() as i32;
+        let column = (column as i32).min(columns - 1);
+        let column = (column + dx).rem_euclid(columns) as usize;
+        self.selected = (row, column);
+        self.buffer = None;
+    }
+
+    /// Press the selected key, returning the text once done
+    fn press(&mut self) -> Option<String> {
+        let (row, column) = self.selected;
+        match &self.rows[row][column] {
+            EntryKey::Text(text) => {
+                let text = if self.shift {
+                    text.to_uppercase()
+                } else {
+                    text.clone()
+                };
+                self.text.push_str(&text);
+                self.shift = false;
+            }
+            EntryKey::Shift => self.shift = !self.shift,
+            EntryKey::Space => self.text.push(' '),
+            EntryKey::Backspace => {
+                self.text.pop();
+            }
+            EntryKey::Done => return Some(self.text.clone()),
+        }
+        self.buffer = None;
+        None
+    }
+
+    /// Delete the last character, returning false if there was none
+    fn back(&mut self) -> bool {
+        self.buffer = None;
+        self.text.pop().is_some()
+    }
+
+    fn draw(&self, scale: f64) -> (MemoryRenderBuffer, Size<i32, Physical>) {
+        let scaled = |value: i32| (value as f64 * scale).round() as i32;
+        let columns = self.rows.iter().map(Vec::len).max().unwrap_or(1) as i32;
+        let padding = scaled(PANEL_PADDING);
+        let key_size = scaled(KEY_SIZE);
+        let spacing = scaled(KEY_SPACING);
+        let field_height = scaled(FIELD_HEIGHT);
+        let font_size = FONT_SIZE * scale as f32;
+        let width = padding * 2 + columns * (key_size + spacing) - spacing;
+        let height =
+            padding * 2 + field_height + spacing + self.rows.len() as i32 * (key_size + spacing)
+                - spacing;
+
+        let mut pixels = PANEL_COLOR.repeat((width * height) as usize);
+        let field = (padding, padding, width - padding * 2, field_height);
+        fill_rect(&mut pixels, width, field, FIELD_COLOR);
+        let text_y = |top: i32, height: i32| top as f32 + (height as f32 - font_size) / 2.0;
+
+        if let Some(font) = self.font.as_ref() {
+            let mut text = if self.purpose.masked() {
+                MASK.repeat(self.text.chars().count())
+            } else {
+                self.text.clone()
+            };
+            text.push('_');
+            // Keep the end of long text visible
+            let field_width = (field.2 - spacing * 2) as f32;
+            while text_width(font, &text, font_size) > field_width {
+                text.remove(0);
+            }
+            let origin = ((field.0 + spacing) as f32, text_y(field.1, field.3));
+            draw_text(
+                &mut pixels,
+                width,
+                font,
+                &text,
+                font_size,
+                origin,
+                field.0 + field.2,
+            );
+        }
+
+        for (row, keys) in self.rows.iter().enumerate() {
+            let y = padding + field_height + spacing + row as i32 * (key_size + spacing);
+            for (column, key) in keys.iter().enumerate() {
+                let x = padding + column as i32 * (key_size + spacing);
+                let color = if self.selected == (row, column) {
+                    SELECTED_COLOR
+                } else {
+                    KEY_COLOR
+                };
+                fill_rect(&mut pixels, width, (x, y, key_size, key_size), color);
+                if let Some(font) = self.font.as_ref() {
+                    let label = key.label(self.shift);
+                    let label_size = if label.chars().count() > 1 {
+                        font_size * 0.6
+                    } else {
+                        font_size
+                    };
+                    let label_width = text_width(font, &label, label_size);
+                    let origin = (
+                        x as f32 + (key_size as f32 - label_width) / 2.0,
+                        y as f32 + (key_size as f32 - label_size) / 2.0,
+                    );
+                    draw_text(
+                        &mut pixels,
+                        width,
+                        font,
+                        &label,
+                        label_size,
+                        origin,
+                        x + key_size,
+                    );
+                }
+            }
+        }
+
+        let buffer = MemoryRenderBuffer::from_slice(
+            &pixels,
+            Fourcc::Argb8888,
+            (width, height),
+            1,
+            Transform::Normal,
+            None,
+        );
+        (buffer, Size::from((width, height)))
+    }
+
+    /// The keyboard panel, centered at the bottom of `output`
+    pub fn render_elements<R>(
+        &mut self,
+        renderer: &mut R,
+        output: &Output,
+    ) -> Vec<MemoryRenderBufferRenderElement<R>>
+    where
+        R: Renderer + ImportMem,
+        <R as Renderer>::TextureId: Send + Clone + 'static,
+    {
+        let Some(mode) = output.current_mode() else {
+            return Vec::new();
+        };
+        let size: Size<i32, Physical> = output.current_transform().transform_size(mode.size);
+        let scale = output.current_scale().fractional_scale();
+        if self.buffer.as_ref().map(|(drawn, ..)| *drawn) != Some(scale) {
+            let (buffer, size) = self.draw(scale);
+            self.buffer = Some((scale, buffer, size));
+        }
+        let (_, buffer, panel_size) = self.buffer.as_ref().unwrap();
+        let margin = (PANEL_PADDING as f64 * scale).round() as i32;
+        let location = Point::<i32, Physical>::from((
+            (size.w - panel_size.w) / 2,
+            size.h - panel_size.h - margin,
+        ));
+        match MemoryRenderBufferRenderElement::from_buffer(
+            renderer,
+            location.to_f64(),
+            buffer,
+            None,
+            None,
+            None,
+            Kind::Unspecified,
+        ) {
+            Ok(element) => vec![element],
+            Err(err) => {
+                warn!(?err, "Unable to upload text entry");
+                Vec::new()
+            }
+        }
+    }
+}
+
+impl<BackendData: Backend> AnvilState<BackendData> {
+    /// Show the text entry, sending the entered text or `None` to `done` once closed.
+    /// A text entry that is still open is cancelled
+    pub fn open_text_entry(&mut self, purpose: TextEntryPurpose, done: Sender<Option<String>>) {
+        if self.text_entry.is_some() {
+            self.finish_text_entry(None);
+        }
+        let config = &self.config.text_entry;
+        let layout_name = match purpose {
+            TextEntryPurpose::Search | TextEntryPurpose::WifiPsk => &config.text_layout,
+            TextEntryPurpose::Pin => &config.pin_layout,
+        };
+        let layout = config.layouts.get(layout_name).cloned().unwrap_or_else(|| {
+            warn!(layout = layout_name, "Unknown text entry layout");
+            Vec::new()
+        });
+        let font = load_font(config.font.as_deref());
+        debug!(?purpose, "Opening text entry");
+        self.text_entry = Some(TextEntry::new(purpose, &layout, font, done));
+    }
+
+    /// Keys navigating the text entry while it is open
+    pub fn text_entry_key_action(&self, keysym: Keysym) -> Option<KeyAction> {
+        self.text_entry.as_ref()?;
+        match keysym {
+            Keysym::Up | Keysym::KP_Up => Some(KeyAction::ArrowUp),
+            Keysym::Down | Keysym::KP_Down => Some(KeyAction::ArrowDown),
+            Keysym::Left | Keysym::KP_Left => Some(KeyAction::ArrowLeft),
+            Keysym::Right | Keysym::KP_Right => Some(KeyAction::ArrowRight),
+            Keysym::Return | Keysym::KP_Enter => Some(KeyAction::Select),
+            Keysym::Escape | Keysym::BackSpace => Some(KeyAction::Back),
+            _ => None,
+        }
+    }
+
+    pub fn move_text_entry(&mut self, dx: i32, dy: i32) {
+        if let Some(entry) = self.text_entry.as_mut() {
+            entry.move_selection(dx, dy);
+            self.text_entry_feedback(Feedback::Move);
+        }
+    }
+
+    pub fn press_text_entry(&mut self) {
+        let Some(entry) = self.text_entry.as_mut() else {
+            return;
+        };
+        match entry.press() {
+            Some(text) => self.finish_text_entry(Some(text)),
+            None => self.text_entry_feedback(Feedback::Key),
+        }
+    }
+
+    pub fn text_entry_back(&mut self) {
+        let Some(entry) = self.text_entry.as_mut() else {
+            return;
+        };
+        if entry.back() {
+            self.text_entry_feedback(Feedback::Key);
+        } else {
+            self.finish_text_entry(None);
+        }
+    }
+
+    fn finish_text_entry(&mut self, text: Option<String>) {
+        let Some(entry) = self.text_entry.take() else {
+            return;
+        };
+        let feedback = if text.is_some() {
+            Feedback::Done
+        } else {
+            Feedback::Cancel
+        };
+        let _ = entry.done.send(text);
+        self.text_entry_feedback(feedback);
+    }
+
+    /// Run the feedback command, which can play a sound or rumble the gamepad
+    fn text_entry_feedback(&mut self, feedback: Feedback) {
+        let Some(command) = self.config.text_entry.feedback_command.as_ref() else {
+            return;
+        };
+        match Command::new("sh")
+            .arg("-c")
+            .arg(command)
+            .env("CONSOLATION_FEEDBACK", feedback.name())
+            .envs(self.client_environment())
+            .spawn()
+        {
+            Ok(child) => self.launched.insert(child.id()),
+            Err(err) => warn!(?err, "Unable to run text entry feedback command"),
+        }
+    }
+}
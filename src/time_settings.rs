@@ -0,0 +1,437 @@
+use std::{fmt, sync::mpsc, thread};
+
+use rusttype::Font;
+use smithay::{
+    backend::{
+        allocator::Fourcc,
+        renderer::{
+            element::{
+                memory::{MemoryRenderBuffer, MemoryRenderBufferRenderElement},
+                Kind,
+            },
+            ImportMem, Renderer,
+        },
+    },
+    input::keyboard::Keysym,
+    output::Output,
+    reexports::calloop::channel::{channel, Event, Sender},
+    utils::{Physical, Point, Size, Transform},
+};
+use tracing::{debug, warn};
+use xkbcommon::xkb;
+use zbus::{blocking::Connection, proxy::CacheProperties};
+
+use crate::{
+    input_handler::KeyAction,
+    state::{AnvilState, Backend, TimeConfiguration},
+    text::{draw_text, fill_rect, load_font, text_width},
+};
+
+const CLOCK_MARGIN: i32 = 16;
+const CLOCK_PADDING: i32 = 8;
+const CLOCK_FONT_SIZE: f32 = 20.0;
+const PANEL_WIDTH: i32 = 560;
+const PANEL_PADDING: i32 = 16;
+const ROW_HEIGHT: i32 = 48;
+const ROW_SPACING: i32 = 6;
+const FONT_SIZE: f32 = 22.0;
+/// Premultiplied BGRA
+const BACKDROP_COLOR: [u8; 4] = [20, 20, 20, 200];
+const PANEL_COLOR: [u8; 4] = [24, 24, 24, 240];
+const ROW_COLOR: [u8; 4] = [64, 64, 64, 255];
+const SELECTED_COLOR: [u8; 4] = [200, 120, 40, 255];
+
+#[zbus::proxy(
+    interface = "org.freedesktop.timedate1",
+    default_service = "org.freedesktop.timedate1",
+    default_path = "/org/freedesktop/timedate1"
+)]
+trait Timedate {
+    fn set_timezone(&self, timezone: &str, interactive: bool) -> zbus::Result<()>;
+
+    #[zbus(name = "SetNTP")]
+    fn set_ntp(&self, use_ntp: bool, interactive: bool) -> zbus::Result<()>;
+
+    fn list_timezones(&self) -> zbus::Result<Vec<String>>;
+
+    #[zbus(property)]
+    fn timezone(&self) -> zbus::Result<String>;
+
+    #[zbus(property, name = "NTP")]
+    fn ntp(&self) -> zbus::Result<bool>;
+
+    #[zbus(property, name = "NTPSynchronized")]
+    fn ntp_synchronized(&self) -> zbus::Result<bool>;
+}
+
+#[derive(Debug)]
+enum TimedateRequest {
+    Refresh,
+    SetTimezone(String),
+    SetNtp(bool),
+}
+
+#[derive(Debug, Clone, Default)]
+struct TimedateStatus {
+    timezone: String,
+    ntp: bool,
+    synchronized: bool,
+    timezones: Vec<String>,
+}
+
+/// Talk to timedated on its own thread, as changing settings can wait on polkit
+fn run_timedate(requests: mpsc::Receiver<TimedateRequest>, statuses: Sender<TimedateStatus>) {
+    let proxy = match Connection::system().and_then(|connection| {
+        TimedateProxyBlocking::builder(&connection)
+            .cache_properties(CacheProperties::No)
+            .build()
+    }) {
+        Ok(proxy) => proxy,
+        Err(err) => {
+            warn!(?err, "Unable to connect to timedated");
+            return;
+        }
+    };
+    let timezones = proxy.list_timezones().unwrap_or_else(|err| {
+        warn!(?err, "Unable to list time zones");
+        Vec::new()
+    });
+
+    for request in requests {
+        let res = match &request {
+            TimedateRequest::Refresh => Ok(()),
+            TimedateRequest::SetTimezone(timezone) => proxy.set_timezone(timezone, false),
+            TimedateRequest::SetNtp(ntp) => proxy.set_ntp(*ntp, false),
+        };
+        if let Err(err) = res {
+            warn!(?request, ?err, "Timedated refused the change");
+        }
+        let status = TimedateStatus {
+            timezone: proxy.timezone().unwrap_or_default(),
+            ntp: proxy.ntp().unwrap_or_default(),
+            synchronized: proxy.ntp_synchronized().unwrap_or_default(),
+            timezones: timezones.clone(),
+        };
+        if statuses.send(status).is_err() {
+            break;
+        }
+    }
+}
+
+/// Page for choosing the time zone and toggling NTP with a gamepad
+#[derive(Debug, Default)]
+struct TimeSettings {
+    /// 0 for the time zone, 1 for NTP
+    selected: usize,
+    status: Option<TimedateStatus>,
+    /// Index into the time zones of the one shown, applied when select is pressed
+    zone: Option<usize>,
+    /// Drawn page, its size and the output scale it was drawn for
+    buffer: Option<(f64, MemoryRenderBuffer, Size<i32, Physical>)>,
+}
+
+impl TimeSettings {
+    fn set_status(&mut self, status: TimedateStatus) {
+        self.zone = status
+            .timezones
+            .iter()
+            .position(|timezone| *timezone == status.timezone);
+        self.status = Some(status);
+        self.buffer = None;
+    }
+
+    fn move_selection(&mut self, dx: i32, dy: i32) {
+        self.selected = (self.selected as i32 + dy).rem_euclid(2) as usize;
+        if self.selected == 0 && dx != 0 {
+            if let Some(status) = self.status.as_ref().filter(|s| !s.timezones.is_empty()) {
+                let count = status.timezones.len() as i32;
+                let zone = self.zone.map_or(0, |zone| zone as i32 + dx);
+                self.zone = Some(zone.rem_euclid(count) as usize);
+            }
+        }
+        self.buffer = None;
+    }
+
+    fn rows(&self) -> [String; 2] {
+        let Some(status) = self.status.as_ref() else {
+            return ["Waiting for timedated…".to_string(), String::new()];
+        };
+        let timezone = self
+            .zone
+            .and_then(|zone| status.timezones.get(zone))
+            .unwrap_or(&status.timezone);
+        let ntp = match (status.ntp, status.synchronized) {
+            (true, true) => "On, synchronized",
+            (true, false) => "On, not synchronized",
+            (false, _) => "Off",
+        };
+        [
+            format!("Time zone: ‹ {} ›", timezone),
+            format!("Automatic time: {}", ntp),
+        ]
+    }
+
+    fn draw(
+        &self,
+        font: Option<&Font<'static>>,
+        scale: f64,
+    ) -> (MemoryRenderBuffer, Size<i32, Physical>) {
+        let scaled = |value: i32| (value as f64 * scale).round() as i32;
+        let width = scaled(PANEL_WIDTH);
+        let padding = scaled(PANEL_PADDING);
+        let row_height = scaled(ROW_HEIGHT);
+        let spacing = scaled(ROW_SPACING);
+        let font_size = FONT_SIZE * scale as f32;
+        let rows = self.rows();
+        let height = padding * 2 + rows.len() as i32 * (row_height + spacing) - spacing;
+
+        let mut pixels = PANEL_COLOR.repeat((width * height) as usize);
+        for (index, label) in rows.iter().enumerate() {
+            let y = padding + index as i32 * (row_height + spacing);
+            let color = if self.selected == index {
+                SELECTED_COLOR
+            } else {
+                ROW_COLOR
+            };
+            let row = (padding, y, width - padding * 2, row_height);
+            fill_rect(&mut pixels, width, row, color);
+            if let Some(font) = font {
+                let origin = (
+                    (padding * 2) as f32,
+                    y as f32 + (row_height as f32 - font_size) / 2.0,
+                );
+                draw_text(
+                    &mut pixels,
+                    width,
+                    font,
+                    label,
+                    font_size,
+                    origin,
+                    width - padding * 2,
+                );
+            }
+        }
+
+        let buffer = MemoryRenderBuffer::from_slice(
+            &pixels,
+            Fourcc::Argb8888,
+            (width, height),
+            1,
+            Transform::Normal,
+            None,
+        );
+        (buffer, Size::from((width, height)))
+    }
+}
+
+/// Clock in the top left corner of every output and the time settings page
+#[derive(Default)]
+pub struct TimeState {
+    font: Option<Font<'static>>,
+    /// Drawn clock, the output scale and time it was drawn for
+    clock: Option<(f64, String, MemoryRenderBuffer, Size<i32, Physical>)>,
+    timedate: Option<mpsc::Sender<TimedateRequest>>,
+    settings: Option<TimeSettings>,
+}
+
+impl fmt::Debug for TimeState {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("TimeState")
+            .field("font", &self.font.is_some())
+            .field("timedate", &self.timedate.is_some())
+            .field("settings", &self.settings)
+            .finish_non_exhaustive()
+    }
+}
+
+fn draw_clock(
+    font: &Font<'static>,
+    text: &str,
+    scale: f64,
+) -> (MemoryRenderBuffer, Size<i32, Physical>) {
+    let padding = (CLOCK_PADDING as f64 * scale).round() as i32;
+    let font_size = CLOCK_FONT_SIZE * scale as f32;
+    let width = text_width(font, text, font_size).ceil() as i32 + padding * 2;
+    let height = font_size.ceil() as i32 + padding * 2;
+
+    let mut pixels = BACKDROP_COLOR.repeat((width * height) as usize);
+    let origin = (padding as f32, padding as f32);
+    draw_text(&mut pixels, width, font, text, font_size, origin, width);
+
+    let buffer = MemoryRenderBuffer::from_slice(
+        &pixels,
+        Fourcc::Argb8888,
+        (width, height),
+        1,
+        Transform::Normal,
+        None,
+    );
+    (buffer, Size::from((width, height)))
+}
+
+impl TimeState {
+    /// The clock if enabled and the settings page while open
+    pub fn render_elements<R>(
+        &mut self,
+        renderer: &mut R,
+        output: &Output,
+        config: &TimeConfiguration,
+    ) -> Vec<MemoryRenderBufferRenderElement<R>>
+    where
+        R: Renderer + ImportMem,
+        <R as Renderer>::TextureId: Send + Clone + 'static,
+    {
+        let Some(mode) = output.current_mode() else {
+            return Vec::new();
+        };
+        let size: Size<i32, Physical> = output.current_transform().transform_size(mode.size);
+        let scale = output.current_scale().fractional_scale();
+        let mut placed = Vec::new();
+
+        if let Some(settings) = self.settings.as_mut() {
+            if settings.buffer.as_ref().map(|(drawn, ..)| *drawn) != Some(scale) {
+                let (buffer, size) = settings.draw(self.font.as_ref(), scale);
+                settings.buffer = Some((scale, buffer, size));
+            }
+            let (_, buffer, panel_size) = settings.buffer.as_ref().unwrap();
+            let location = Point::<i32, Physical>::from((
+                (size.w - panel_size.w) / 2,
+                (size.h - panel_size.h) / 2,
+            ));
+            placed.push((location, buffer));
+        }
+
+        if let Some(font) = self.font.as_ref().filter(|_| config.show_clock) {
+            // The local time zone is looked up again once /etc/localtime changes
+            let format = if config.twenty_four_hour {
+                "%H:%M"
+            } else {
+                "%-I:%M %p"
+            };
+            let text = chrono::Local::now().format(format).to_string();
+            if self
+                .clock
+                .as_ref()
+                .map(|(drawn, drawn_text, ..)| (*drawn, drawn_text.as_str()))
+                != Some((scale, text.as_str()))
+            {
+                let (buffer, size) = draw_clock(font, &text, scale);
+                self.clock = Some((scale, text, buffer, size));
+            }
+            let (.., buffer, _) = self.clock.as_ref().unwrap();
+            let margin = (CLOCK_MARGIN as f64 * scale).round() as i32;
+            placed.push((Point::from((margin, margin)), buffer));
+        }
+
+        placed
+            .into_iter()
+            .filter_map(|(location, buffer)| {
+                MemoryRenderBufferRenderElement::from_buffer(
+                    renderer,
+                    location.to_f64(),
+                    buffer,
+                    None,
+                    None,
+                    None,
+                    Kind::Unspecified,
+                )
+                .map_err(|err| warn!(?err, "Unable to upload clock"))
+                .ok()
+            })
+            .collect()
+    }
+}
+
+impl<BackendData: Backend> AnvilState<BackendData> {
+    pub fn start_clock(&mut self) {
+        let config = &self.config.time;
+        if config.show_clock || config.settings_key.is_some() {
+            self.time.font = load_font(config.font.as_deref());
+        }
+    }
+
+    /// Show the time settings page, connecting to timedated on first use
+    pub fn open_time_settings(&mut self) {
+        if self.time.timedate.is_none() {
+            let (requests, receiver) = mpsc::channel();
+            let (sender, statuses) = channel();
+            let res = self.handle.insert_source(statuses, |event, _, data| {
+                if let Event::Msg(status) = event {
+                    if let Some(settings) = data.time.settings.as_mut() {
+                        settings.set_status(status);
+                    }
+                }
+            });
+            if let Err(err) = res {
+                warn!(?err, "Unable to insert timedated source");
+                return;
+            }
+            thread::spawn(move || run_timedate(receiver, sender));
+            self.time.timedate = Some(requests);
+        }
+        debug!("Opening time settings");
+        self.time.settings = Some(TimeSettings::default());
+        self.timedate_request(TimedateRequest::Refresh);
+    }
+
+    pub fn close_time_settings(&mut self) {
+        self.time.settings = None;
+    }
+
+    fn timedate_request(&mut self, request: TimedateRequest) {
+        let Some(timedate) = self.time.timedate.as_ref() else {
+            return;
+        };
+        if timedate.send(request).is_err() {
+            warn!("Timedated connection is gone");
+            self.time.timedate = None;
+        }
+    }
+
+    pub(crate) fn time_settings_open(&self) -> bool {
+        self.time.settings.is_some()
+    }
+
+    /// Keys navigating the time settings while open, or the key opening them
+    pub fn time_settings_key_action(&self, keysym: Keysym) -> Option<KeyAction> {
+        if self.time_settings_open() {
+            return match keysym {
+                Keysym::Up | Keysym::KP_Up => Some(KeyAction::ArrowUp),
+                Keysym::Down | Keysym::KP_Down => Some(KeyAction::ArrowDown),
+                Keysym::Left | Keysym::KP_Left => Some(KeyAction::ArrowLeft),
+                Keysym::Right | Keysym::KP_Right => Some(KeyAction::ArrowRight),
+                Keysym::Return | Keysym::KP_Enter => Some(KeyAction::Select),
+                Keysym::Escape | Keysym::BackSpace => Some(KeyAction::Back),
+                _ => None,
+            };
+        }
+        let settings_key = self.config.time.settings_key.as_ref()?;
+        let settings_key = xkb::keysym_from_name(settings_key, xkb::KEYSYM_CASE_INSENSITIVE);
+        (settings_key.raw() == keysym.raw()).then_some(KeyAction::OpenTimeSettings)
+    }
+
+    pub fn move_time_settings(&mut self, dx: i32, dy: i32) {
+        if let Some(settings) = self.time.settings.as_mut() {
+            settings.move_selection(dx, dy);
+        }
+    }
+
+    /// Apply the shown time zone or toggle NTP
+    pub fn press_time_settings(&mut self) {
+        let Some(settings) = self.time.settings.as_ref() else {
+            return;
+        };
+        let Some(status) = settings.status.as_ref() else {
+            return;
+        };
+        let request = if settings.selected == 0 {
+            let Some(timezone) = settings.zone.and_then(|zone| status.timezones.get(zone)) else {
+                return;
+            };
+            TimedateRequest::SetTimezone(timezone.clone())
+        } else {
+            TimedateRequest::SetNtp(!status.ntp)
+        };
+        self.timedate_request(request);
+    }
+}
@@ -1,20 +1,49 @@
-use notify::{RecursiveMode, Watcher};
 use std::{
     collections::hash_map::HashMap,
     io,
     path::Path,
     process::Command,
-    sync::atomic::Ordering,
+    sync::atomic::{AtomicU64, Ordering},
     time::{Duration, Instant},
 };
 
 use crate::{
+    backlight::Backlight,
+    calibration::TouchCalibration,
+    color_filter::ColorFilterPass,
+    delegate_virtual_pointer,
     drawing::*,
+    notifications::Notifications,
+    output_scale::output_scale,
+    policy::PrivilegedProtocol,
+    progress::ProgressReports,
     render::*,
-    shell::output_manager::{self, OutputId},
-    state::{post_repaint, take_presentation_feedback, AnvilState, Backend},
+    screenshot::ScreenshotState,
+    shaders::GlesAccess,
+    shell::{
+        output_manager::{self, OutputId, Outputs},
+        virtual_pointer::{VirtualPointerHandler, VirtualPointerManagerState},
+    },
+    startup::SplashScreen,
+    state::{
+        post_repaint, take_presentation_feedback, AnvilState, Backend, BackgroundConfiguration,
+        ColorFilter, Configuration, CursorConfiguration, DndIcon,
+    },
+    status::StatusOverlay,
+    text_entry::TextEntry,
+    time_settings::TimeState,
+    watchdog::AppWatchdog,
+    window_cycling::WindowCycling,
+};
+use crate::{
+    shell::{
+        content_type::{window_content_policy, ContentPolicy},
+        is_hidden, shown_window,
+        transition::WindowTransition,
+        window_geometry,
+    },
+    state::SurfaceDmabufFeedback,
 };
-use crate::{shell::toplevel_manager, state::SurfaceDmabufFeedback};
 #[cfg(feature = "renderer_sync")]
 use smithay::backend::drm::compositor::PrimaryPlaneElement;
 #[cfg(feature = "egl")]
@@ -27,10 +56,10 @@ use smithay::{
 use smithay::{
     backend::{
         allocator::{
-            dmabuf::Dmabuf,
+            dmabuf::{AsDmabuf, Dmabuf},
             format::FormatSet,
             gbm::{GbmAllocator, GbmBufferFlags, GbmDevice},
-            Fourcc,
+            Allocator, Fourcc, Modifier,
         },
         drm::{
             compositor::DrmCompositor, CreateDrmNodeError, DrmAccessError, DrmDevice, DrmDeviceFd,
@@ -38,15 +67,16 @@ use smithay::{
             NodeType,
         },
         egl::{self, context::ContextPriority, EGLDevice, EGLDisplay},
-        input::InputEvent,
+        input::{Event, InputEvent},
         libinput::{LibinputInputBackend, LibinputSessionInterface},
         renderer::{
             damage::{Error as OutputDamageTrackerError, OutputDamageTracker},
             element::{
-                memory::MemoryRenderBuffer, AsRenderElements, RenderElement, RenderElementStates,
+                memory::MemoryRenderBuffer, surface::WaylandSurfaceRenderElement, AsRenderElements,
+                RenderElement, RenderElementStates,
             },
-            gles::{GlesRenderer, GlesTexture},
-            multigpu::{gbm::GbmGlesBackend, GpuManager, MultiRenderer},
+            gles::{GlesError, GlesFrame, GlesRenderer, GlesTexture},
+            multigpu::{gbm::GbmGlesBackend, GpuManager, MultiError, MultiRenderer},
             sync::SyncPoint,
             Bind, DebugFlags, ExportMem, ImportDma, ImportMemWl, Offscreen, Renderer,
         },
@@ -59,8 +89,11 @@ use smithay::{
     },
     delegate_dmabuf, delegate_drm_lease,
     desktop::{space::SurfaceTree, utils::OutputPresentationFeedback, Window},
-    input::{keyboard::LedState, pointer::CursorImageStatus},
-    output::{Mode as WlMode, Output, PhysicalProperties},
+    input::{
+        keyboard::LedState,
+        pointer::{AxisFrame, CursorImageStatus},
+    },
+    output::{Mode as WlMode, Output, PhysicalProperties, Scale as OutputScale},
     reexports::{
         calloop::{
             timer::{TimeoutAction, Timer},
@@ -76,12 +109,17 @@ use smithay::{
             linux_dmabuf::zv1::server::zwp_linux_dmabuf_feedback_v1,
             presentation_time::server::wp_presentation_feedback,
         },
-        wayland_server::{backend::GlobalId, protocol::wl_surface, Display, DisplayHandle},
+        wayland_server::{
+            backend::GlobalId,
+            protocol::{wl_pointer, wl_surface},
+            Display, DisplayHandle,
+        },
     },
     utils::{
         Clock, DeviceFd, IsAlive, Logical, Monotonic, Physical, Point, Rectangle, Scale, Transform,
     },
     wayland::{
+        compositor::with_states,
         dmabuf::{
             DmabufFeedback, DmabufFeedbackBuilder, DmabufGlobal, DmabufHandler, DmabufState,
             ImportNotifier,
@@ -120,6 +158,27 @@ type UdevRenderer<'a> = MultiRenderer<
     GbmGlesBackend<GlesRenderer, DrmDeviceFd>,
 >;
 
+// Both the render and the target gpu draw with GLES
+impl GlesAccess for UdevRenderer<'_> {
+    fn gles_renderer(&mut self) -> &mut GlesRenderer {
+        self.as_mut()
+    }
+
+    fn gles_frame<'a, 'frame>(frame: &'a mut Self::Frame<'frame>) -> &'a mut GlesFrame<'frame>
+    where
+        Self: 'frame,
+    {
+        frame.as_mut()
+    }
+
+    fn gles_error(err: GlesError) -> Self::Error {
+        MultiError::Render(err)
+    }
+}
+
+/// Repaint count when a buffer of a surface was last imported early
+struct EarlyImport(AtomicU64);
+
 #[derive(Debug, PartialEq)]
 struct UdevOutputId {
     device_id: DrmNode,
@@ -134,14 +193,29 @@ pub struct UdevData {
     primary_gpu: DrmNode,
     gpus: GpuManager<GbmGlesBackend<GlesRenderer, DrmDeviceFd>>,
     backends: HashMap<DrmNode, BackendData>,
-    pointer_images: Vec<(xcursor::parser::Image, MemoryRenderBuffer)>,
+    /// Uploaded frames of the pointer image and the scale they were chosen for
+    pointer_images: Vec<(xcursor::parser::Image, u32, MemoryRenderBuffer)>,
     pointer_element: PointerElement,
     #[cfg(feature = "debug")]
     fps_texture: Option<MultiTexture>,
     background_texture: Option<MultiTexture>,
+    /// Dropped once the splash screen is hidden
+    splash_texture: Option<MultiTexture>,
+    /// Renders queued as idle callbacks that didn't run yet
+    pending_renders: usize,
+    /// Repaints of any output so far
+    repaints: u64,
+    housekeeping_queued: bool,
+    /// Render nodes whose renderer is rebuilt after losing its context
+    recovering_gpus: Vec<DrmNode>,
+    /// The system is about to sleep or sleeping, DRM devices are paused
+    sleeping: bool,
     pointer_image: crate::cursor::Cursor,
     debug_flags: DebugFlags,
     keyboards: Vec<smithay::reexports::input::Device>,
+    pointers: Vec<smithay::reexports::input::Device>,
+    pub touchscreens: Vec<smithay::reexports::input::Device>,
+    pub touch_calibration: Option<TouchCalibration>,
 }
 
 impl UdevData {
@@ -208,6 +282,19 @@ impl Backend for UdevData {
     }
 
     fn early_import(&mut self, surface: &wl_surface::WlSurface) {
+        // Clients committing faster than we repaint only get their first buffer per repaint
+        // imported early. Replaced buffers are released on commit, the repaint imports the last one
+        let repaints = self.repaints;
+        let imported = with_states(surface, |states| {
+            let last = states
+                .data_map
+                .get_or_insert_threadsafe(|| EarlyImport(AtomicU64::new(u64::MAX)));
+            last.0.swap(repaints, Ordering::Relaxed) == repaints
+        });
+        if imported {
+            trace!(?surface, "Coalescing commit");
+            return;
+        }
         if let Err(err) = self.gpus.early_import(self.primary_gpu, surface) {
             warn!("Early buffer import failed: {}", err);
         }
@@ -218,16 +305,212 @@ impl Backend for UdevData {
             keyboard.led_update(led_state.into());
         }
     }
+
+    fn capture_window(&mut self, window: &Window, size: Size<i32, Physical>) -> Option<Dmabuf> {
+        let gbm = self
+            .backends
+            .values()
+            .find(|backend| backend.render_node == self.primary_gpu)?
+            .gbm
+            .clone();
+        let geometry = window_geometry(window).size.to_f64();
+        if geometry.w <= 0.0 || geometry.h <= 0.0 || size.w <= 0 || size.h <= 0 {
+            return None;
+        }
+        let scale = Scale::from((size.w as f64 / geometry.w, size.h as f64 / geometry.h));
+
+        // Linear, so clients can map it to write thumbnails
+        let mut allocator = GbmAllocator::new(gbm, GbmBufferFlags::RENDERING);
+        let dmabuf = match allocator
+            .create_buffer(
+                size.w as u32,
+                size.h as u32,
+                Fourcc::Argb8888,
+                &[Modifier::Linear],
+            )
+            .map_err(|err| err.to_string())
+            .and_then(|buffer| buffer.export().map_err(|err| err.to_string()))
+        {
+            Ok(dmabuf) => dmabuf,
+            Err(err) => {
+                warn!("Unable to allocate capture buffer: {}", err);
+                return None;
+            }
+        };
+
+        let mut renderer = self.gpus.single_renderer(&self.primary_gpu).ok()?;
+        if let Err(err) = renderer.bind(dmabuf.clone()) {
+            warn!("Unable to bind capture buffer: {}", err);
+            return None;
+        }
+        render_window_geometry(&mut renderer, window, size, scale).then_some(dmabuf)
+    }
+
+    fn sample_window(&mut self, window: &Window, size: Size<i32, Physical>) -> Option<Vec<u8>> {
+        let geometry = window_geometry(window).size.to_f64();
+        if geometry.w <= 0.0 || geometry.h <= 0.0 {
+            return None;
+        }
+        let scale = Scale::from((size.w as f64 / geometry.w, size.h as f64 / geometry.h));
+
+        let mut renderer = self.gpus.single_renderer(&self.primary_gpu).ok()?;
+        let texture: GlesTexture = renderer
+            .create_buffer(
+                Fourcc::Abgr8888,
+                size.to_logical(1).to_buffer(1, Transform::Normal),
+            )
+            .ok()?;
+        renderer.bind(texture).ok()?;
+        if !render_window_geometry(&mut renderer, window, size, scale) {
+            return None;
+        }
+        let region = Rectangle::from_loc_and_size(
+            (0, 0),
+            size.to_logical(1).to_buffer(1, Transform::Normal),
+        );
+        let mapping = renderer.copy_framebuffer(region, Fourcc::Abgr8888).ok()?;
+        renderer
+            .map_texture(&mapping)
+            .ok()
+            .map(|pixels| pixels.to_vec())
+    }
+
+    fn config_reloaded(state: &mut AnvilState<Self>, old: &Configuration) {
+        Self::input_config_changed(state);
+        state.reload_background();
+        state.reload_cursor(old.cursor.clone());
+        state.reload_output_scales();
+        state.reload_portal();
+    }
+
+    fn input_config_changed(state: &mut AnvilState<Self>) {
+        let mut devices = state.backend_data.pointers.clone();
+        devices.extend(state.backend_data.touchscreens.clone());
+        for device in devices.iter_mut() {
+            state.apply_input_config(device);
+        }
+    }
+}
+
+fn fps_texture(renderer: &mut UdevRenderer<'_>) -> Option<MultiTexture> {
+    let fps_image = image::ImageReader::with_format(
+        std::io::Cursor::new(FPS_NUMBERS_PNG),
+        image::ImageFormat::Png,
+    )
+    .decode()
+    .unwrap();
+    match renderer.import_memory(
+        &fps_image.to_rgba8(),
+        Fourcc::Abgr8888,
+        (fps_image.width() as i32, fps_image.height() as i32).into(),
+        false,
+    ) {
+        Ok(texture) => Some(texture),
+        Err(err) => {
+            warn!(?err, "Unable to upload FPS texture");
+            None
+        }
+    }
 }
 
-pub fn run_udev() {
+/// Whether the device `node` renders with its own gpu, unlike display-only devices
+/// borrowing the renderer of the primary gpu
+fn owns_renderer(node: DrmNode, render_node: DrmNode) -> bool {
+    node == render_node
+        || node.node_with_type(NodeType::Render).and_then(Result::ok) == Some(render_node)
+}
+
+/// The configured background image, the logo if none is configured, or nothing for only a color
+fn background_texture(
+    renderer: &mut UdevRenderer<'_>,
+    config: &BackgroundConfiguration,
+) -> Option<MultiTexture> {
+    if config.image.is_none() && config.color.is_some() {
+        return None;
+    }
+    image_texture(renderer, config.image.as_deref())
+}
+
+/// The image at `path`, or the logo
+fn image_texture(renderer: &mut UdevRenderer<'_>, path: Option<&str>) -> Option<MultiTexture> {
+    let image = match path {
+        Some(path) => match image::open(path) {
+            Ok(image) => image,
+            Err(err) => {
+                warn!(path, ?err, "Unable to load image");
+                return None;
+            }
+        },
+        None => image::ImageReader::with_format(
+            std::io::Cursor::new(BACKGROUND_PNG),
+            image::ImageFormat::Png,
+        )
+        .decode()
+        .unwrap(),
+    };
+    match renderer.import_memory(
+        &image.to_rgba8(),
+        Fourcc::Abgr8888,
+        (image.width() as i32, image.height() as i32).into(),
+        false,
+    ) {
+        Ok(texture) => Some(texture),
+        Err(err) => {
+            warn!(?err, "Unable to upload image texture");
+            None
+        }
+    }
+}
+
+/// Draw the geometry of `window` into the bound target of `size`, without
+/// waiting for the GPU to finish.
+fn render_window_geometry<'a>(
+    renderer: &mut UdevRenderer<'a>,
+    window: &Window,
+    size: Size<i32, Physical>,
+    scale: Scale<f64>,
+) -> bool {
+    let location = Point::from((0, 0)) - window.geometry().loc.to_physical_precise_round(scale);
+    let elements: Vec<WaylandSurfaceRenderElement<UdevRenderer<'a>>> =
+        window.render_elements(renderer, location, scale, 1.0);
+    let mut damage_tracker = OutputDamageTracker::new(size, 1.0, Transform::Normal);
+    // Not waiting for the GPU, the dmabuf is fenced for its readers and reading
+    // back the framebuffer waits for the rendering on its own
+    match damage_tracker.render_output(renderer, 0, &elements, [0.0, 0.0, 0.0, 0.0]) {
+        Ok(_) => true,
+        Err(err) => {
+            warn!("Unable to render window: {:?}", err);
+            false
+        }
+    }
+}
+
+/// Run the session on the DRM backend. `app` is the primary application to keep running,
+/// and `socket` the name of the wayland socket given to apps
+pub fn run_udev(app: Option<Vec<String>>, socket: Option<String>) {
+    run_udev_with(app, socket, |_| {});
+}
+
+/// Run the udev backend, calling `setup` once the state exists, like to register
+/// custom key actions
+pub fn run_udev_with(
+    app: Option<Vec<String>>,
+    socket: Option<String>,
+    setup: impl FnOnce(&mut AnvilState<UdevData>),
+) {
     let mut event_loop = EventLoop::try_new().unwrap();
     let display = Display::new().unwrap();
     let mut display_handle = display.handle();
 
+    let startup_config = crate::state::load_config().unwrap_or_default();
+
     /*
      * Initialize session
      */
+    if let Some(backend) = &startup_config.session.seat_backend {
+        // libseat tries logind, seatd and its builtin backend in turn otherwise
+        std::env::set_var("LIBSEAT_BACKEND", backend);
+    }
     let (session, notifier) = match LibSeatSession::new() {
         Ok(ret) => ret,
         Err(err) => {
@@ -236,11 +519,28 @@ pub fn run_udev() {
         }
     };
 
+    info!(seat = session.seat(), "Took the seat");
+
     /*
      * Initialize the compositor
      */
+    let configured_gpu =
+        startup_config
+            .primary_gpu
+            .as_ref()
+            .and_then(|path| match DrmNode::from_path(path) {
+                Ok(node) => Some(node),
+                Err(err) => {
+                    warn!(%path, ?err, "Ignoring invalid primary gpu");
+                    None
+                }
+            });
     let primary_gpu = if let Ok(var) = std::env::var("ANVIL_DRM_DEVICE") {
         DrmNode::from_path(var).expect("Invalid drm device path")
+    } else if let Some(node) = configured_gpu {
+        node.node_with_type(NodeType::Render)
+            .and_then(Result::ok)
+            .unwrap_or(node)
     } else {
         primary_gpu(session.seat())
             .unwrap()
@@ -271,16 +571,49 @@ pub fn run_udev() {
         primary_gpu,
         gpus,
         backends: HashMap::new(),
-        pointer_image: crate::cursor::Cursor::load(),
+        pointer_image: crate::cursor::Cursor::load(&startup_config.cursor),
         pointer_images: Vec::new(),
         pointer_element: PointerElement::default(),
         #[cfg(feature = "debug")]
         fps_texture: None,
         background_texture: None,
+        splash_texture: None,
+        pending_renders: 0,
+        repaints: 0,
+        housekeeping_queued: false,
+        recovering_gpus: Vec::new(),
+        sleeping: false,
         debug_flags: DebugFlags::empty(),
         keyboards: Vec::new(),
+        pointers: Vec::new(),
+        touchscreens: Vec::new(),
+        touch_calibration: None,
     };
-    let mut state = AnvilState::init(display, event_loop.handle(), data, true);
+    let mut state = AnvilState::init(display, event_loop.handle(), data, true, socket);
+    state.start_metrics_server();
+    state.sync_services();
+    state.start_dock_detection();
+    state.start_letterbox_detection();
+    state.start_reaping();
+    state.start_hibernation_watch();
+    state.start_sleep_watch();
+    state.start_cursor_hiding();
+    state.start_notifications();
+    state.start_progress_reports();
+    state.start_window_cycling();
+    state.start_backlight();
+    state.start_clock();
+    state.start_status_overlay();
+    state.start_remote_desktop();
+    state.start_portal();
+    state.start_config_reload();
+    state.start_input_settings();
+    state.start_log_retrieval();
+    state.start_inspector();
+    state.start_protocol_trace();
+    state.start_picture_in_picture();
+    state.start_split_screen();
+    setup(&mut state);
 
     /*
      * Initialize the udev backend
@@ -319,6 +652,14 @@ pub fn run_udev() {
         }
     }
 
+    // Remote controls, like phone apps and VNC servers
+    VirtualPointerManagerState::new::<AnvilState<UdevData>, _>(
+        &display_handle,
+        state
+            .protocol_policy
+            .filter(PrivilegedProtocol::VirtualPointer),
+    );
+
     /*
      * Bind all our objects that get driven by the event loop
      */
@@ -326,6 +667,12 @@ pub fn run_udev() {
         .handle()
         .insert_source(libinput_backend, move |mut event, _, data| {
             let dh = data.backend_data.dh.clone();
+            if let Some(time) = input_event_time(&event) {
+                let now = Duration::from(data.clock.now());
+                if let Some(latency) = now.checked_sub(Duration::from_micros(time)) {
+                    data.metrics.input_latency.observe(latency);
+                }
+            }
             if let InputEvent::DeviceAdded { device } = &mut event {
                 if device.has_capability(DeviceCapability::Keyboard) {
                     if let Some(led_state) = data
@@ -337,69 +684,81 @@ pub fn run_udev() {
                     }
                     data.backend_data.keyboards.push(device.clone());
                 }
+                if device.has_capability(DeviceCapability::Pointer) {
+                    data.apply_input_config(device);
+                    data.backend_data.pointers.push(device.clone());
+                }
+                if device.has_capability(DeviceCapability::Touch) {
+                    data.apply_input_config(device);
+                    data.backend_data.touchscreens.push(device.clone());
+                }
             } else if let InputEvent::DeviceRemoved { ref device } = event {
                 if device.has_capability(DeviceCapability::Keyboard) {
                     data.backend_data.keyboards.retain(|item| item != device);
                 }
+                if device.has_capability(DeviceCapability::Pointer) {
+                    data.backend_data.pointers.retain(|item| item != device);
+                }
+                if device.has_capability(DeviceCapability::Touch) {
+                    data.backend_data.touchscreens.retain(|item| item != device);
+                    let calibrating = data.backend_data.touch_calibration.as_ref();
+                    if calibrating.is_some_and(|calibration| calibration.device() == device) {
+                        data.backend_data.touch_calibration = None;
+                    }
+                }
             }
 
+            if !matches!(
+                event,
+                InputEvent::DeviceAdded { .. } | InputEvent::DeviceRemoved { .. }
+            ) {
+                data.input_activity();
+                data.set_outputs_active(true);
+                data.update_cursor_visibility(&event);
+            }
             data.process_input_event(&dh, event)
         })
         .unwrap();
 
-    let handle = event_loop.handle();
     event_loop
         .handle()
         .insert_source(notifier, move |event, &mut (), data| match event {
             SessionEvent::PauseSession => {
                 libinput_context.suspend();
                 info!("pausing session");
-
-                for backend in data.backend_data.backends.values_mut() {
-                    backend.drm.pause();
-                    backend.active_leases.clear();
-                    if let Some(lease_global) = backend.leasing_global.as_mut() {
-                        lease_global.suspend();
-                    }
-                }
+                data.pause_session();
             }
             SessionEvent::ActivateSession => {
                 info!("resuming session");
 
+                // Devices come back as added, in case they changed while away
                 if let Err(err) = libinput_context.resume() {
                     error!("Failed to resume libinput context: {:?}", err);
                 }
-                for (node, backend) in data
-                    .backend_data
-                    .backends
-                    .iter_mut()
-                    .map(|(handle, backend)| (*handle, backend))
-                {
-                    // if we do not care about flicking (caused by modesetting) we could just
-                    // pass true for disable connectors here. this would make sure our drm
-                    // device is in a known state (all connectors and planes disabled).
-                    // but for demonstration we choose a more optimistic path by leaving the
-                    // state as is and assume it will just work. If this assumption fails
-                    // we will try to reset the state when trying to queue a frame.
-                    backend
-                        .drm
-                        .activate(false)
-                        .expect("failed to activate drm backend");
-                    if let Some(lease_global) = backend.leasing_global.as_mut() {
-                        lease_global.resume::<AnvilState<UdevData>>();
-                    }
-                    for surface in backend.surfaces.values_mut() {
-                        if let Err(err) = surface.compositor.reset_state() {
-                            warn!("Failed to reset drm surface state: {}", err);
-                        }
-                    }
-                    handle.insert_idle(move |data| data.render(node, None));
+                if data.backend_data.sleeping {
+                    // Waking up sets the outputs up anew
+                    return;
                 }
+                // if we do not care about flicking (caused by modesetting) we could just
+                // pass true for disable connectors here. this would make sure our drm
+                // device is in a known state (all connectors and planes disabled).
+                // but for demonstration we choose a more optimistic path by leaving the
+                // state as is and assume it will just work. If this assumption fails
+                // we will try to reset the state when trying to queue a frame.
+                data.activate_devices(false);
             }
         })
         .unwrap();
 
-    for (device_id, path) in udev_backend.device_list() {
+    // Outputs of other devices may be rendered by the primary gpu, so it goes first
+    let mut devices: Vec<_> = udev_backend.device_list().collect();
+    devices.sort_by_key(|(device_id, _)| {
+        DrmNode::from_dev_id(*device_id).ok().map_or(true, |node| {
+            node != primary_gpu
+                && node.node_with_type(NodeType::Render).and_then(Result::ok) != Some(primary_gpu)
+        })
+    });
+    for (device_id, path) in devices {
         if let Err(err) = DrmNode::from_dev_id(device_id)
             .map_err(DeviceAddError::DrmNode)
             .and_then(|node| state.device_added(node, path))
@@ -424,56 +783,37 @@ pub fn run_udev() {
         .unwrap();
 
     {
-        let fps_image = image::ImageReader::with_format(
-            std::io::Cursor::new(FPS_NUMBERS_PNG),
-            image::ImageFormat::Png,
-        )
-        .decode()
-        .unwrap();
-        let fps_texture = renderer
-            .import_memory(
-                &fps_image.to_rgba8(),
-                Fourcc::Abgr8888,
-                (fps_image.width() as i32, fps_image.height() as i32).into(),
-                false,
-            )
-            .expect("Unable to upload FPS texture");
-
+        let fps_texture = fps_texture(&mut renderer);
         for backend in state.backend_data.backends.values_mut() {
             for surface in backend.surfaces.values_mut() {
-                surface.fps_element = Some(FpsElement::new(fps_texture.clone()));
+                surface.fps_element = fps_texture.clone().map(FpsElement::new);
             }
         }
-        state.backend_data.fps_texture = Some(fps_texture);
+        state.backend_data.fps_texture = fps_texture;
     }
 
     {
-        let background_image = image::ImageReader::with_format(
-            std::io::Cursor::new(BACKGROUND_PNG),
-            image::ImageFormat::Png,
-        )
-        .decode()
-        .unwrap();
-        let background_texture = renderer
-            .import_memory(
-                &background_image.to_rgba8(),
-                Fourcc::Abgr8888,
-                (
-                    background_image.width() as i32,
-                    background_image.height() as i32,
-                )
-                    .into(),
-                false,
-            )
-            .expect("Unable to upload Background texture");
+        let background_texture = background_texture(&mut renderer, &state.config.background);
+        let scale = state.config.background.scale;
+        for backend in state.backend_data.backends.values_mut() {
+            for surface in backend.surfaces.values_mut() {
+                surface.background_element = background_texture
+                    .clone()
+                    .map(|texture| BackgroundElement::new(texture, scale));
+            }
+        }
+        state.backend_data.background_texture = background_texture;
+    }
 
+    if state.splash {
+        let splash_texture =
+            image_texture(&mut renderer, state.config.startup.splash_image.as_deref());
         for backend in state.backend_data.backends.values_mut() {
             for surface in backend.surfaces.values_mut() {
-                surface.background_element =
-                    Some(BackgroundElement::new(background_texture.clone()));
+                surface.splash = splash_texture.clone().map(SplashScreen::new);
             }
         }
-        state.backend_data.background_texture = Some(background_texture);
+        state.backend_data.splash_texture = splash_texture;
     }
 
     #[cfg(feature = "egl")]
@@ -547,7 +887,11 @@ pub fn run_udev() {
      * Start XWayland if supported
      */
     #[cfg(feature = "xwayland")]
-    state.start_xwayland();
+    if state.config.xwayland.on_demand {
+        state.start_xwayland_on_demand();
+    } else {
+        state.start_xwayland();
+    }
 
     info!("Attempting autostart");
 
@@ -572,53 +916,22 @@ pub fn run_udev() {
     } else {
         info!("No autostart script to run");
     }
+    state.start_session_applications();
+    state.start_systemd_session();
+    if let Some(app) = app {
+        state.start_app_watchdog(app);
+    }
 
     /*
      * And run our loop
      */
 
     while state.running.load(Ordering::SeqCst) {
-        let result = event_loop.dispatch(Some(Duration::from_millis(16)), &mut state);
+        let timeout = state.dispatch_timeout();
+        let result = event_loop.dispatch(timeout, &mut state);
         if result.is_err() {
             state.running.store(false, Ordering::SeqCst);
         } else {
-            match state.config_watcher.try_recv() {
-                Ok(event) => match event {
-                    Ok(event) => match event.kind {
-                        notify::EventKind::Access(_access_kind) => {}
-                        notify::EventKind::Create(_create_kind) => {
-                            info!("Config create");
-                        }
-                        notify::EventKind::Modify(_modify_kind) => {
-                            match confy::load("consolation", None) {
-                                Ok(config) => {
-                                    state.config.set_from(config);
-                                    info!("Configuration file changed");
-                                }
-                                Err(err) => {
-                                    error!("Unable to load new config : {:?}", err)
-                                }
-                            };
-                        }
-                        notify::EventKind::Remove(_remove_kind) => {
-                            warn!("Config removed");
-                            // Some editors (like vim) delete the file in the process of writing. Start a new watch
-                            let config_path =
-                                confy::get_configuration_file_path("consolation", None)
-                                    .expect("Unable to find config path");
-                            state
-                                .config_watcher_obj
-                                .watch(&config_path, RecursiveMode::NonRecursive)
-                                .expect("Unable to watch config file");
-                        }
-                        notify::EventKind::Other => {}
-                        notify::EventKind::Any => {}
-                    },
-                    Err(err) => error!("Watcher error : {:?}", err),
-                },
-                Err(_) => {}
-            }
-
             // Update from output config
             if state.outputs_config.clone().is_some() {
                 event_loop.handle().insert_idle(|state| {
@@ -674,6 +987,7 @@ pub fn run_udev() {
                             }
                         }
                     }
+                    state.apply_output_power(&config);
                     state.outputs_config = None;
                     state
                         .output_management_state
@@ -681,18 +995,20 @@ pub fn run_udev() {
                 });
             }
 
-            let size = state.elements.len();
-            state.elements.retain(|window| window.alive());
-            if size != state.elements.len() {
-                state.update_keyboard_focus();
-            }
-            toplevel_manager::refresh(&mut state);
-            state.popups.cleanup();
+            state.refresh_windows();
+            state.queue_housekeeping();
             display_handle.flush_clients().unwrap();
         }
     }
+
+    if let Err(err) = crate::systemd::notify("STOPPING=1") {
+        warn!(?err, "Unable to notify systemd");
+    }
 }
 
+/// Wait before rebuilding a lost renderer, giving a resetting gpu time to come back
+const GPU_RECOVERY_DELAY: Duration = Duration::from_secs(1);
+
 impl DrmLeaseHandler for AnvilState<UdevData> {
     fn drm_lease_state(&mut self, node: DrmNode) -> &mut DrmLeaseState {
         self.backend_data
@@ -779,6 +1095,36 @@ impl DrmSyncobjHandler for AnvilState<UdevData> {
 }
 smithay::delegate_drm_syncobj!(AnvilState<UdevData>);
 
+impl VirtualPointerHandler for AnvilState<UdevData> {
+    fn virtual_pointer_motion(&mut self, delta: Point<f64, Logical>, time: u32) {
+        self.virtual_input_activity();
+        self.cursor_moved();
+        self.pointer_motion(delta, delta, time as u64 * 1000, time);
+    }
+
+    fn virtual_pointer_motion_absolute(&mut self, (x, y): (f64, f64), time: u32) {
+        self.virtual_input_activity();
+        self.cursor_moved();
+        self.pointer_motion_absolute(
+            |size| Point::from((x * size.w as f64, y * size.h as f64)),
+            time,
+        );
+    }
+
+    fn virtual_pointer_button(&mut self, button: u32, state: wl_pointer::ButtonState, time: u32) {
+        self.virtual_input_activity();
+        self.pointer_button(button, state, time);
+    }
+
+    fn virtual_pointer_axis(&mut self, frame: AxisFrame) {
+        self.virtual_input_activity();
+        let pointer = self.pointer.clone();
+        pointer.axis(self, frame);
+        pointer.frame(self);
+    }
+}
+delegate_virtual_pointer!(AnvilState<UdevData>);
+
 pub type RenderSurface =
     GbmBufferedSurface<GbmAllocator<DrmDeviceFd>, Option<OutputPresentationFeedback>>;
 
@@ -974,9 +1320,56 @@ struct SurfaceData {
     fps: fps_ticker::Fps,
     fps_element: Option<FpsElement<MultiTexture>>,
     background_element: Option<BackgroundElement<MultiTexture>>,
+    splash: Option<SplashScreen<MultiTexture>>,
     dmabuf_feedback: Option<DrmSurfaceDmabufFeedback>,
     name: String,
     connector: Handle,
+    /// Applied for the content of the window shown, `None` until the first frame
+    content_policy: Option<ContentPolicy>,
+    /// Why the output is off, `None` while it is on
+    power_off: Option<PowerOff>,
+    /// Largest pointer image scanned out on the cursor plane, `None` without plane assignment
+    cursor_size: Option<Size<i32, Physical>>,
+    color_filter: ColorFilterPass,
+}
+
+/// Why an output does not show anything
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum PowerOff {
+    /// Disabled through output management until enabled again
+    Disabled,
+    /// Blanked after a long idle time until the next input
+    Idle,
+}
+
+impl SurfaceData {
+    fn apply_content_policy(&mut self, policy: ContentPolicy) {
+        debug!(output = self.name, ?policy, "Switching content policy");
+        let disable_direct_scanout = std::env::var("ANVIL_DISABLE_DIRECT_SCANOUT").is_ok();
+        if let SurfaceComposition::Compositor(compositor) = &mut self.compositor {
+            compositor.use_direct_scanout(policy.direct_scanout && !disable_direct_scanout);
+        }
+        if let Err(err) = set_vrr(self.compositor.surface(), policy.vrr) {
+            debug!(output = self.name, ?err, "Unable to change VRR");
+        }
+        self.content_policy = Some(policy);
+    }
+}
+
+/// Toggle variable refresh rate on the CRTC of `surface`
+fn set_vrr(surface: &DrmSurface, enabled: bool) -> io::Result<()> {
+    let crtc = surface.crtc();
+    let properties = surface.get_properties(crtc)?;
+    let (handles, _) = properties.as_props_and_values();
+    for handle in handles {
+        if surface.get_property(*handle)?.name().to_bytes() == b"VRR_ENABLED" {
+            return surface.set_property(crtc, *handle, enabled as u64);
+        }
+    }
+    Err(io::Error::new(
+        io::ErrorKind::Unsupported,
+        "CRTC has no VRR_ENABLED property",
+    ))
 }
 
 impl Drop for SurfaceData {
@@ -1069,6 +1462,264 @@ fn get_surface_dmabuf_feedback(
 }
 
 impl AnvilState<UdevData> {
+    /// Input of virtual devices wakes up the outputs like that of real ones
+    pub(crate) fn virtual_input_activity(&mut self) {
+        self.input_activity();
+        self.set_outputs_active(true);
+    }
+
+    /// Red, green and blue from 0.0 to 1.0 of the pixel under the pointer on the
+    /// first output, composited again without the pointer and overlays
+    pub fn pick_color(&mut self) -> Option<[f64; 3]> {
+        let output = self.outputs.first()?.clone();
+        let mode = output.current_mode()?;
+        let size = output.current_transform().transform_size(mode.size);
+        let (window, _) = self.current_window();
+        let (_, position) = pointer_position(
+            &output,
+            window.as_ref(),
+            self.pointer.current_location(),
+            &self.config,
+        );
+        if !Rectangle::from_loc_and_size((0, 0), size).contains(position) {
+            return None;
+        }
+
+        let region = Rectangle::from_loc_and_size(position, (1, 1));
+        let pixel = self.read_output_region(region)?;
+        let [r, g, b, _]: [u8; 4] = pixel.get(..4)?.try_into().ok()?;
+        Some([r, g, b].map(|channel| channel as f64 / 255.0))
+    }
+
+    /// RGBA pixels of `region` of the first output, composited again without the
+    /// pointer and overlays
+    pub(crate) fn read_output_region(
+        &mut self,
+        region: Rectangle<i32, Physical>,
+    ) -> Option<Vec<u8>> {
+        let output = self.outputs.first()?.clone();
+        let mode = output.current_mode()?;
+        let size = output.current_transform().transform_size(mode.size);
+        let layout = self.window_layout();
+        let mut renderer = self
+            .backend_data
+            .gpus
+            .single_renderer(&self.backend_data.primary_gpu)
+            .ok()?;
+        let texture: GlesTexture = renderer
+            .create_buffer(
+                Fourcc::Abgr8888,
+                size.to_logical(1).to_buffer(1, Transform::Normal),
+            )
+            .ok()?;
+        renderer.bind(texture).ok()?;
+        let (elements, clear_color) = output_elements(
+            &output,
+            &self.elements,
+            &layout,
+            self.window_transitions.current.as_ref(),
+            &self.config,
+            std::iter::empty(),
+            None,
+            &mut renderer,
+        );
+        let mut damage_tracker = OutputDamageTracker::new(
+            size,
+            output.current_scale().fractional_scale(),
+            Transform::Normal,
+        );
+        match damage_tracker.render_output(&mut renderer, 0, &elements, clear_color) {
+            Ok(res) => {
+                res.sync.wait();
+            }
+            Err(err) => {
+                warn!(?err, "Unable to render the output offscreen");
+                return None;
+            }
+        }
+
+        let region = Rectangle::from_loc_and_size(
+            (region.loc.x, region.loc.y),
+            (region.size.w, region.size.h),
+        );
+        let mapping = renderer.copy_framebuffer(region, Fourcc::Abgr8888).ok()?;
+        renderer
+            .map_texture(&mapping)
+            .ok()
+            .map(|pixels| pixels.to_vec())
+    }
+
+    /// Blank all enabled outputs until the next input, or turn blanked ones back on
+    pub(crate) fn set_outputs_active(&mut self, active: bool) {
+        let surfaces: Vec<_> = self
+            .backend_data
+            .backends
+            .iter()
+            .flat_map(|(node, backend)| {
+                backend
+                    .surfaces
+                    .iter()
+                    .map(|(crtc, surface)| (*node, *crtc, surface.power_off))
+            })
+            .collect();
+        for (node, crtc, power_off) in surfaces {
+            match (active, power_off) {
+                (false, None) => self.set_output_power(node, crtc, Some(PowerOff::Idle)),
+                (true, Some(PowerOff::Idle)) => self.set_output_power(node, crtc, None),
+                _ => {}
+            }
+        }
+    }
+
+    /// Stop compositing for an output and turn it off, or turn it back on and repaint it
+    fn set_output_power(&mut self, node: DrmNode, crtc: crtc::Handle, power_off: Option<PowerOff>) {
+        let Some(surface) = self
+            .backend_data
+            .backends
+            .get_mut(&node)
+            .and_then(|backend| backend.surfaces.get_mut(&crtc))
+        else {
+            return;
+        };
+        let was_on = surface.power_off.is_none();
+        surface.power_off = power_off;
+        match power_off {
+            Some(reason) if was_on => {
+                info!(output = surface.name, ?reason, "Turning output off");
+                if let Err(err) = surface.compositor.surface().clear_state() {
+                    warn!(?crtc, ?err, "Unable to turn output off");
+                }
+            }
+            None if !was_on => {
+                info!(output = surface.name, "Turning output on");
+                if let Err(err) = surface.compositor.reset_state() {
+                    warn!(?crtc, ?err, "Unable to turn output on");
+                }
+                self.render(node, Some(crtc));
+            }
+            _ => {}
+        }
+    }
+
+    /// Apply the `off` state of outputs changed through output management
+    fn apply_output_power(&mut self, config: &Outputs) {
+        let surfaces: Vec<_> =
+            self.backend_data
+                .backends
+                .iter()
+                .flat_map(|(node, backend)| {
+                    backend.surfaces.iter().map(|(crtc, surface)| {
+                        (*node, *crtc, surface.name.clone(), surface.power_off)
+                    })
+                })
+                .collect();
+        for (node, crtc, name, power_off) in surfaces {
+            let Some(output) = config.find(&name) else {
+                continue;
+            };
+            match (output.off, power_off) {
+                (true, power_off) if power_off != Some(PowerOff::Disabled) => {
+                    self.set_output_power(node, crtc, Some(PowerOff::Disabled))
+                }
+                (false, Some(PowerOff::Disabled)) => self.set_output_power(node, crtc, None),
+                _ => {}
+            }
+            if let Some(state) = self
+                .output_states
+                .values_mut()
+                .find(|state| state.name == name)
+            {
+                state.off = output.off;
+            }
+        }
+    }
+
+    /// Apply the configured scales to the connected outputs
+    fn reload_output_scales(&mut self) {
+        for output in &self.outputs {
+            let Some(mode) = output.current_mode() else {
+                continue;
+            };
+            let size = output.physical_properties().size;
+            let scale = output_scale(
+                &self.config.output_scale,
+                &output.name(),
+                (size.w > 0 && size.h > 0).then_some((size.w as u32, size.h as u32)),
+                mode.size,
+            )
+            .unwrap_or(1.0);
+            if output.current_scale().fractional_scale() != scale {
+                debug!(output = output.name(), scale, "Rescaling output");
+                output.change_current_state(None, None, Some(OutputScale::Fractional(scale)), None);
+            }
+        }
+    }
+
+    /// Load the background again after the configuration changed
+    fn reload_background(&mut self) {
+        let Ok(mut renderer) = self
+            .backend_data
+            .gpus
+            .single_renderer(&self.backend_data.primary_gpu)
+        else {
+            return;
+        };
+        let texture = background_texture(&mut renderer, &self.config.background);
+        let scale = self.config.background.scale;
+        for backend in self.backend_data.backends.values_mut() {
+            for surface in backend.surfaces.values_mut() {
+                surface.background_element = texture
+                    .clone()
+                    .map(|texture| BackgroundElement::new(texture, scale));
+            }
+        }
+        self.backend_data.background_texture = texture;
+    }
+
+    /// Load the cursor theme again when its configuration changed
+    fn reload_cursor(&mut self, old: CursorConfiguration) {
+        let cursor = &self.config.cursor;
+        if cursor.theme == old.theme && cursor.size == old.size {
+            return;
+        }
+        self.backend_data.pointer_image = crate::cursor::Cursor::load(&self.config.cursor);
+        self.backend_data.pointer_images.clear();
+        #[cfg(feature = "xwayland")]
+        self.update_xwayland_cursor();
+    }
+
+    /// Upload the textures again after the renderer of the primary gpu was rebuilt
+    fn reload_textures(&mut self) {
+        self.reload_background();
+        let Ok(mut renderer) = self
+            .backend_data
+            .gpus
+            .single_renderer(&self.backend_data.primary_gpu)
+        else {
+            return;
+        };
+        #[cfg(feature = "debug")]
+        {
+            let fps_texture = fps_texture(&mut renderer);
+            for backend in self.backend_data.backends.values_mut() {
+                for surface in backend.surfaces.values_mut() {
+                    surface.fps_element = fps_texture.clone().map(FpsElement::new);
+                }
+            }
+            self.backend_data.fps_texture = fps_texture;
+        }
+        if self.backend_data.splash_texture.is_some() {
+            let splash_texture =
+                image_texture(&mut renderer, self.config.startup.splash_image.as_deref());
+            for backend in self.backend_data.backends.values_mut() {
+                for surface in backend.surfaces.values_mut() {
+                    surface.splash = splash_texture.clone().map(SplashScreen::new);
+                }
+            }
+            self.backend_data.splash_texture = splash_texture;
+        }
+    }
+
     fn device_added(&mut self, node: DrmNode, path: &Path) -> Result<(), DeviceAddError> {
         // Try to open the device
         let fd = self
@@ -1102,17 +1753,30 @@ impl AnvilState<UdevData> {
             )
             .unwrap();
 
-        let render_node =
-            EGLDevice::device_for_display(&unsafe { EGLDisplay::new(gbm.clone()).unwrap() })
-                .ok()
-                .and_then(|x| x.try_get_render_node().ok().flatten())
-                .unwrap_or(node);
-
-        self.backend_data
-            .gpus
-            .as_mut()
-            .add_node(render_node, gbm.clone())
-            .map_err(DeviceAddError::AddNode)?;
+        let render_node = match unsafe { EGLDisplay::new(gbm.clone()) } {
+            Ok(display) => Some(
+                EGLDevice::device_for_display(&display)
+                    .ok()
+                    .and_then(|x| x.try_get_render_node().ok().flatten())
+                    .unwrap_or(node),
+            ),
+            Err(err) => {
+                // Display-only devices like DisplayLink docks scan out buffers of another gpu
+                info!(?err, "No rendering on {}, using the primary gpu", node);
+                None
+            }
+        };
+        let render_node = match render_node {
+            Some(render_node) => {
+                self.backend_data
+                    .gpus
+                    .as_mut()
+                    .add_node(render_node, gbm.clone())
+                    .map_err(DeviceAddError::AddNode)?;
+                render_node
+            }
+            None => self.backend_data.primary_gpu,
+        };
 
         self.backend_data.backends.insert(
             node,
@@ -1155,11 +1819,16 @@ impl AnvilState<UdevData> {
             return;
         };
 
-        let mut renderer = self
-            .backend_data
-            .gpus
-            .single_renderer(&device.render_node)
-            .unwrap();
+        let mut renderer = match self.backend_data.gpus.single_renderer(&device.render_node) {
+            Ok(renderer) => renderer,
+            Err(err) => {
+                warn!(
+                    ?err,
+                    "No renderer for {}, skipping connector", device.render_node
+                );
+                return;
+            }
+        };
         let render_formats = renderer
             .as_mut()
             .egl_context()
@@ -1203,9 +1872,24 @@ impl AnvilState<UdevData> {
             .and_then(|info| info.model())
             .unwrap_or_else(|| "Unknown".into());
 
-        if non_desktop {
+        let make_model = format!("{} {}", make, model);
+        if non_desktop
+            && !self
+                .config
+                .drm_lease
+                .leasable(&output_name, &make_model, true)
+        {
             info!(
-                "Connector {} is non-desktop, setting up for leasing",
+                "Connector {} is non-desktop and never leased, ignoring it",
+                output_name
+            );
+        } else if self
+            .config
+            .drm_lease
+            .leasable(&output_name, &make_model, non_desktop)
+        {
+            info!(
+                "Connector {} is leasable, setting up for leasing",
                 output_name
             );
             device
@@ -1215,7 +1899,7 @@ impl AnvilState<UdevData> {
                 lease_state.add_connector::<AnvilState<UdevData>>(
                     connector.handle(),
                     output_name,
-                    format!("{} {}", make, model),
+                    make_model,
                 );
             }
         } else {
@@ -1254,7 +1938,18 @@ impl AnvilState<UdevData> {
                 output.add_mode(WlMode::from(*mode));
             }
             output.set_preferred(wl_mode);
-            output.change_current_state(Some(wl_mode), None, None, None);
+            let scale = output_scale(
+                &self.config.output_scale,
+                &output_name,
+                connector.size(),
+                wl_mode.size,
+            );
+            output.change_current_state(
+                Some(wl_mode),
+                None,
+                scale.map(OutputScale::Fractional),
+                None,
+            );
             self.outputs.push(output.clone());
             output.user_data().insert_if_missing(|| UdevOutputId {
                 crtc,
@@ -1263,11 +1958,17 @@ impl AnvilState<UdevData> {
 
             #[cfg(feature = "debug")]
             let fps_element = self.backend_data.fps_texture.clone().map(FpsElement::new);
+            let background_scale = self.config.background.scale;
             let background_element = self
                 .backend_data
                 .background_texture
                 .clone()
-                .map(BackgroundElement::new);
+                .map(|texture| BackgroundElement::new(texture, background_scale));
+            let splash = self
+                .backend_data
+                .splash_texture
+                .clone()
+                .map(SplashScreen::new);
 
             let allocator = GbmAllocator::new(
                 device.gbm.clone(),
@@ -1348,6 +2049,11 @@ impl AnvilState<UdevData> {
                 SurfaceComposition::Compositor(compositor)
             };
 
+            let cursor_size = matches!(compositor, SurfaceComposition::Compositor(_)).then(|| {
+                let size = device.drm.cursor_size();
+                Size::from((size.w as i32, size.h as i32))
+            });
+
             let dmabuf_feedback = get_surface_dmabuf_feedback(
                 self.backend_data.primary_gpu,
                 device.render_node,
@@ -1365,8 +2071,13 @@ impl AnvilState<UdevData> {
                 fps_element,
                 dmabuf_feedback,
                 background_element,
+                splash,
                 name: output_name.clone(),
                 connector: connector.handle(),
+                content_policy: None,
+                power_off: None,
+                cursor_size,
+                color_filter: ColorFilterPass::default(),
             };
 
             device.surfaces.insert(crtc, surface);
@@ -1388,12 +2099,14 @@ impl AnvilState<UdevData> {
                     name: output_name,
                     logical: Default::default(),
                     transform: Default::default(),
-                    scale: Some(1.0),
+                    scale: Some(scale.unwrap_or(1.0)),
                     off: false,
                     variable_refresh_rate: false,
                     make,
                     model,
-                    physical_size: Default::default(),
+                    physical_size: connector
+                        .size()
+                        .and_then(|(w, h)| Some((u16::try_from(w).ok()?, u16::try_from(h).ok()?))),
                 },
             );
 
@@ -1476,6 +2189,82 @@ impl AnvilState<UdevData> {
         }
     }
 
+    /// Stop rendering and give up DRM master while another session has the seat
+    fn pause_session(&mut self) {
+        for backend in self.backend_data.backends.values_mut() {
+            backend.drm.pause();
+            backend.active_leases.clear();
+            if let Some(lease_global) = backend.leasing_global.as_mut() {
+                lease_global.suspend();
+            }
+        }
+        // Releases of keys held when switching away never arrive
+        self.suppressed_keys.clear();
+        self.release_held_buttons();
+    }
+
+    /// Take DRM master again, import all buffers anew and scan for connectors
+    /// plugged meanwhile, then repaint and send frame callbacks so clients that
+    /// waited on them draw again
+    fn activate_devices(&mut self, disable_connectors: bool) {
+        let nodes: Vec<_> = self.backend_data.backends.keys().copied().collect();
+        for node in nodes {
+            let Some(backend) = self.backend_data.backends.get_mut(&node) else {
+                continue;
+            };
+            if let Err(err) = backend.drm.activate(disable_connectors) {
+                warn!(?err, %node, "Unable to activate drm device");
+                continue;
+            }
+            if let Some(lease_global) = backend.leasing_global.as_mut() {
+                lease_global.resume::<AnvilState<UdevData>>();
+            }
+            for surface in backend.surfaces.values_mut() {
+                if let Err(err) = surface.compositor.reset_state() {
+                    warn!("Failed to reset drm surface state: {}", err);
+                }
+                surface.compositor.reset_buffers();
+            }
+            self.device_changed(node);
+            self.handle.insert_idle(move |data| data.render(node, None));
+        }
+
+        let time = self.clock.now();
+        for output in &self.outputs {
+            for window in self.elements.iter().filter(|window| !is_hidden(window)) {
+                window.send_frame(output, time, Some(Duration::ZERO), |_, _| {
+                    Some(output.clone())
+                });
+            }
+        }
+    }
+
+    /// Stop rendering and give up DRM master before the system sleeps
+    pub(crate) fn pause_for_sleep(&mut self) {
+        if self.backend_data.sleeping {
+            return;
+        }
+        info!("Pausing for sleep");
+        self.backend_data.sleeping = true;
+        self.pause_session();
+    }
+
+    /// Take DRM master again after sleep, with a full modeset and imports of all
+    /// buffers, as the state kept from before may no longer match the hardware
+    pub(crate) fn resume_from_sleep(&mut self) {
+        if !self.backend_data.sleeping {
+            return;
+        }
+        self.backend_data.sleeping = false;
+        if !self.backend_data.session.is_active() {
+            // Activating the session takes DRM master again once the seat is back
+            return;
+        }
+        info!("Resuming from sleep");
+        // Start out with all connectors and planes disabled
+        self.activate_devices(true);
+    }
+
     fn device_removed(&mut self, node: DrmNode) {
         let device = if let Some(device) = self.backend_data.backends.get_mut(&node) {
             device
@@ -1501,10 +2290,15 @@ impl AnvilState<UdevData> {
                 leasing_global.disable_global::<AnvilState<UdevData>>();
             }
 
-            self.backend_data
-                .gpus
-                .as_mut()
-                .remove_node(&backend_data.render_node);
+            if owns_renderer(node, backend_data.render_node) {
+                self.backend_data
+                    .gpus
+                    .as_mut()
+                    .remove_node(&backend_data.render_node);
+                if backend_data.render_node == self.backend_data.primary_gpu {
+                    error!("The primary gpu was removed, outputs of other gpus can't be rendered");
+                }
+            }
 
             self.handle.remove(backend_data.registration_token);
 
@@ -1591,6 +2385,9 @@ impl AnvilState<UdevData> {
             }
             Err(err) => {
                 //warn!("Error during rendering: {:?}", err);
+                if !matches!(err, SwapBuffersError::AlreadySwapped) {
+                    self.metrics.dropped_frames += 1;
+                }
                 match err {
                     SwapBuffersError::AlreadySwapped => true,
                     // If the device has been deactivated do not reschedule, this will be done
@@ -1610,7 +2407,14 @@ impl AnvilState<UdevData> {
                             ..
                         })) if source.kind() == io::ErrorKind::PermissionDenied
                     ),
-                    SwapBuffersError::ContextLost(err) => panic!("Rendering loop lost: {}", err),
+                    SwapBuffersError::ContextLost(err) => {
+                        // A messy VT switch leaves the device in an unknown state, start over
+                        warn!("Rendering loop lost: {}", err);
+                        if let Err(err) = surface.compositor.reset_state() {
+                            warn!("Failed to reset drm surface state: {}", err);
+                        }
+                        true
+                    }
                 }
             }
         };
@@ -1675,13 +2479,44 @@ impl AnvilState<UdevData> {
 
             self.handle
                 .insert_source(timer, move |_, _, data| {
-                    data.render(dev_id, Some(crtc));
+                    data.queue_render(dev_id, crtc);
                     TimeoutAction::Drop
                 })
                 .expect("failed to schedule frame timer");
         }
     }
 
+    /// Render once every event source of the current dispatch ran.
+    ///
+    /// calloop has no priorities, but idle callbacks only run after all ready sources,
+    /// so input that arrived together with the repaint timer still makes it into the frame.
+    fn queue_render(&mut self, node: DrmNode, crtc: crtc::Handle) {
+        self.backend_data.pending_renders += 1;
+        self.handle.insert_idle(move |data| {
+            data.backend_data.pending_renders -= 1;
+            data.render(node, Some(crtc));
+        });
+    }
+
+    /// Sync protocol state with the windows once the frames of this dispatch are out
+    fn queue_housekeeping(&mut self) {
+        if !self.backend_data.housekeeping_queued {
+            self.backend_data.housekeeping_queued = true;
+            self.handle.insert_idle(|data| data.housekeeping(false));
+        }
+    }
+
+    fn housekeeping(&mut self, deferred: bool) {
+        // Idle callbacks run in the order they were queued, let renders go first.
+        // Deferring only once keeps a busy render loop from starving us
+        if self.backend_data.pending_renders > 0 && !deferred {
+            self.handle.insert_idle(|data| data.housekeeping(true));
+            return;
+        }
+        self.backend_data.housekeeping_queued = false;
+        self.refresh_protocol_state();
+    }
+
     // If crtc is `Some()`, render it, else render all crtcs
     fn render(&mut self, node: DrmNode, crtc: Option<crtc::Handle>) {
         let device_backend = match self.backend_data.backends.get_mut(&node) {
@@ -1704,6 +2539,27 @@ impl AnvilState<UdevData> {
 
     fn render_surface(&mut self, node: DrmNode, crtc: crtc::Handle) {
         profiling::scope!("render_surface", &format!("{crtc:?}"));
+        // Outputs that are off get neither repaints nor frame callbacks, nor do any
+        // while another session has the seat or the system sleeps
+        if self.backend_data.sleeping
+            || !self.backend_data.session.is_active()
+            || self
+                .backend_data
+                .backends
+                .get(&node)
+                .and_then(|backend| backend.surfaces.get(&crtc))
+                .is_some_and(|surface| surface.power_off.is_some())
+        {
+            return;
+        }
+        self.backend_data.repaints += 1;
+        let splash = self.refresh_splash();
+        if !splash {
+            self.backend_data.splash_texture = None;
+        }
+        let layout = self.window_layout();
+        let color_filter = self.active_color_filter();
+        let screenshot_region = self.screenshot_region();
         let device = if let Some(device) = self.backend_data.backends.get_mut(&node) {
             device
         } else {
@@ -1718,73 +2574,110 @@ impl AnvilState<UdevData> {
 
         let start = Instant::now();
 
-        // TODO get scale from the rendersurface when supporting HiDPI
-        let frame = self
-            .backend_data
-            .pointer_image
-            .get_image(1 /*scale*/, self.clock.now().into());
-
         let render_node = surface.render_node;
         let primary_gpu = self.backend_data.primary_gpu;
-        let mut renderer = if primary_gpu == render_node {
+        let renderer = if primary_gpu == render_node {
             self.backend_data.gpus.single_renderer(&render_node)
         } else {
             let format = surface.compositor.format();
             self.backend_data
                 .gpus
                 .renderer(&primary_gpu, &render_node, format)
-        }
-        .unwrap();
+        };
+        let mut renderer = match renderer {
+            Ok(renderer) => renderer,
+            Err(err) => {
+                warn!(?err, "No renderer for {}", render_node);
+                return;
+            }
+        };
+
+        let output = if let Some(output) = self.outputs.iter().find(|o| {
+            o.user_data().get::<UdevOutputId>()
+                == Some(&UdevOutputId {
+                    device_id: surface.device_id,
+                    crtc,
+                })
+        }) {
+            output.clone()
+        } else {
+            // somehow we got called with an invalid output
+            return;
+        };
 
+        // Themes have larger images for HiDPI, drawn at their own buffer scale
+        let cursor_scale = output.current_scale().fractional_scale().ceil().max(1.0) as u32;
+        let frame = self
+            .backend_data
+            .pointer_image
+            .get_image(cursor_scale, self.clock.now().into());
+        let hotspot = Point::<f64, Logical>::from((
+            frame.xhot as f64 / cursor_scale as f64,
+            frame.yhot as f64 / cursor_scale as f64,
+        ));
         let pointer_images = &mut self.backend_data.pointer_images;
         let pointer_image = pointer_images
             .iter()
-            .find_map(|(image, texture)| {
-                if image == &frame {
-                    Some(texture.clone())
-                } else {
-                    None
-                }
+            .find_map(|(image, scale, buffer)| {
+                (image == &frame && *scale == cursor_scale).then(|| buffer.clone())
             })
             .unwrap_or_else(|| {
                 let buffer = MemoryRenderBuffer::from_slice(
                     &frame.pixels_rgba,
                     Fourcc::Argb8888,
                     (frame.width as i32, frame.height as i32),
-                    1,
+                    cursor_scale as i32,
                     Transform::Normal,
                     None,
                 );
-                pointer_images.push((frame, buffer.clone()));
+                pointer_images.push((frame, cursor_scale, buffer.clone()));
                 buffer
             });
+        self.backend_data
+            .pointer_element
+            .set_buffer(pointer_image, hotspot);
+        self.backend_data
+            .pointer_element
+            .set_hidden(self.cursor_visibility.hidden());
 
-        let output = if let Some(output) = self.outputs.iter().find(|o| {
-            o.user_data().get::<UdevOutputId>()
-                == Some(&UdevOutputId {
-                    device_id: surface.device_id,
-                    crtc,
-                })
-        }) {
-            output.clone()
-        } else {
-            // somehow we got called with an invalid output
-            return;
+        let zoom = self.zoom.output_zoom(
+            &output,
+            &self.elements,
+            self.pointer.current_location(),
+            &self.config,
+        );
+        let mut overlays = Overlays {
+            zoom,
+            color_filter,
+            touch_calibration: self.backend_data.touch_calibration.as_mut(),
+            app_watchdog: self.app_watchdog.as_mut(),
+            text_entry: self.text_entry.as_mut(),
+            screenshot: &mut self.screenshot,
+            screenshot_region: screenshot_region.as_ref(),
+            notifications: &mut self.notifications,
+            progress: &mut self.progress,
+            window_cycling: &mut self.window_cycling,
+            backlight: &mut self.backlight,
+            time: &mut self.time,
+            status: &mut self.status,
         };
-
         let result = render_surface(
             surface,
             &mut renderer,
             &self.elements,
+            &layout,
+            self.window_transitions.current.as_ref(),
+            splash,
             &output,
             self.pointer.current_location(),
-            &pointer_image,
             &mut self.backend_data.pointer_element,
             &self.dnd_icon,
+            &mut overlays,
             &mut self.cursor_status,
             &self.clock,
             self.config.clone(),
         );
+        let mut context_lost = false;
         let reschedule = match &result {
             Ok(has_rendered) => !has_rendered,
             Err(err) => {
@@ -1810,11 +2703,19 @@ impl AnvilState<UdevData> {
                                 .expect("failed to reset drm device");
                             true
                         }
-                        _ => panic!("Rendering loop lost: {}", err),
+                        _ => {
+                            warn!("Rendering loop lost: {}", err);
+                            context_lost = true;
+                            false
+                        }
                     },
                 }
             }
         };
+        if context_lost {
+            self.recover_gpu(render_node);
+            return;
+        }
 
         if reschedule {
             let output_refresh = match output.current_mode() {
@@ -1841,6 +2742,7 @@ impl AnvilState<UdevData> {
         } else {
             let elapsed = start.elapsed();
             tracing::trace!(?elapsed, "rendered surface");
+            self.metrics.frame_time.observe(elapsed);
         }
 
         profiling::finish_frame!();
@@ -1865,9 +2767,12 @@ impl AnvilState<UdevData> {
         };
 
         let node = surface.render_node;
-        let result = {
-            let mut renderer = self.backend_data.gpus.single_renderer(&node).unwrap();
-            initial_render(surface, &mut renderer)
+        let result = match self.backend_data.gpus.single_renderer(&node) {
+            Ok(mut renderer) => initial_render(surface, &mut renderer),
+            Err(err) => {
+                warn!(?err, "No renderer for {}", node);
+                return;
+            }
         };
 
         if let Err(err) = result {
@@ -1880,70 +2785,208 @@ impl AnvilState<UdevData> {
                     evt_handle
                         .insert_idle(move |data| data.schedule_initial_render(node, crtc, handle));
                 }
-                SwapBuffersError::ContextLost(err) => panic!("Rendering loop lost: {}", err),
+                SwapBuffersError::ContextLost(err) => {
+                    warn!("Rendering loop lost: {}", err);
+                    self.recover_gpu(node);
+                }
+            }
+        }
+    }
+
+    /// Rebuild the renderer of `render_node` after its context was lost, like on a gpu
+    /// reset, instead of ending the session
+    fn recover_gpu(&mut self, render_node: DrmNode) {
+        if self.backend_data.recovering_gpus.contains(&render_node) {
+            return;
+        }
+        self.backend_data.recovering_gpus.push(render_node);
+        let res = self.handle.insert_source(
+            Timer::from_duration(GPU_RECOVERY_DELAY),
+            move |_, _, data| {
+                data.rebuild_renderer(render_node);
+                TimeoutAction::Drop
+            },
+        );
+        if let Err(err) = res {
+            error!(?err, "Unable to schedule gpu recovery");
+        }
+    }
+
+    fn rebuild_renderer(&mut self, render_node: DrmNode) {
+        self.backend_data
+            .recovering_gpus
+            .retain(|node| *node != render_node);
+        let Some(gbm) = self
+            .backend_data
+            .backends
+            .iter()
+            .find(|(node, backend)| {
+                backend.render_node == render_node && owns_renderer(**node, render_node)
+            })
+            .map(|(_, backend)| backend.gbm.clone())
+        else {
+            return;
+        };
+
+        let gpus = self.backend_data.gpus.as_mut();
+        gpus.remove_node(&render_node);
+        if let Err(err) = gpus.add_node(render_node, gbm) {
+            error!(?err, "Unable to rebuild the renderer of {}", render_node);
+            return;
+        }
+        info!("Rebuilt the renderer of {}", render_node);
+
+        // Client buffers are imported again by the new renderer on their own
+        let primary = render_node == self.backend_data.primary_gpu;
+        if primary {
+            self.reload_textures();
+        }
+        let nodes: Vec<_> = self.backend_data.backends.keys().copied().collect();
+        for node in nodes {
+            let Some(backend) = self.backend_data.backends.get_mut(&node) else {
+                continue;
+            };
+            for surface in backend.surfaces.values_mut() {
+                if primary || surface.render_node == render_node {
+                    surface.compositor.reset_buffers();
+                }
             }
+            self.render(node, None);
         }
     }
 }
 
+/// What is drawn above the windows of an output, and how the output is zoomed
+/// and filtered
+struct Overlays<'s> {
+    zoom: Option<Zoom>,
+    color_filter: Option<ColorFilter>,
+    touch_calibration: Option<&'s mut TouchCalibration>,
+    app_watchdog: Option<&'s mut AppWatchdog>,
+    text_entry: Option<&'s mut TextEntry>,
+    screenshot: &'s mut ScreenshotState,
+    screenshot_region: Option<&'s (Output, Rectangle<i32, Physical>)>,
+    notifications: &'s mut Notifications,
+    progress: &'s mut ProgressReports,
+    window_cycling: &'s mut WindowCycling,
+    backlight: &'s mut Backlight,
+    time: &'s mut TimeState,
+    status: &'s mut StatusOverlay,
+}
+
+impl Overlays<'_> {
+    fn render_elements<'a>(
+        &mut self,
+        renderer: &mut UdevRenderer<'a>,
+        output: &Output,
+        config: &crate::state::Configuration,
+    ) -> Vec<CustomRenderElements<UdevRenderer<'a>>> {
+        let mut elements: Vec<CustomRenderElements<_>> = Vec::new();
+        if let Some(calibration) = self.touch_calibration.as_deref_mut() {
+            elements.extend(
+                calibration
+                    .render_elements(output)
+                    .into_iter()
+                    .map(CustomRenderElements::Solid),
+            );
+        }
+        if let Some(watchdog) = self.app_watchdog.as_deref_mut() {
+            elements.extend(
+                watchdog
+                    .render_elements(output)
+                    .into_iter()
+                    .map(CustomRenderElements::Solid),
+            );
+        }
+        if let Some(text_entry) = self.text_entry.as_deref_mut() {
+            elements.extend(
+                text_entry
+                    .render_elements(renderer, output)
+                    .into_iter()
+                    .map(CustomRenderElements::Memory),
+            );
+        }
+        elements.extend(
+            self.screenshot
+                .render_elements(output, self.screenshot_region)
+                .into_iter()
+                .map(CustomRenderElements::Solid),
+        );
+        elements.extend(
+            self.notifications
+                .render_elements(renderer, output)
+                .into_iter()
+                .map(CustomRenderElements::Memory),
+        );
+        elements.extend(
+            self.progress
+                .render_elements(renderer, output)
+                .into_iter()
+                .map(CustomRenderElements::Memory),
+        );
+        elements.extend(
+            self.window_cycling
+                .render_elements(renderer, output)
+                .into_iter()
+                .map(CustomRenderElements::Memory),
+        );
+        elements.extend(
+            self.backlight
+                .render_elements(renderer, output)
+                .into_iter()
+                .map(CustomRenderElements::Memory),
+        );
+        elements.extend(
+            self.time
+                .render_elements(renderer, output, &config.time)
+                .into_iter()
+                .map(CustomRenderElements::Memory),
+        );
+        elements.extend(
+            self.status
+                .render_elements(renderer, output, config.time.twenty_four_hour)
+                .into_iter()
+                .map(CustomRenderElements::Memory),
+        );
+        elements
+    }
+}
+
 #[allow(clippy::too_many_arguments)]
 #[profiling::function]
 fn render_surface<'a>(
     surface: &'a mut SurfaceData,
     renderer: &mut UdevRenderer<'a>,
     window_elements: &Vec<Window>,
+    layout: &WindowLayout,
+    transition: Option<&WindowTransition>,
+    splash: bool,
     output: &Output,
     pointer_location: Point<f64, Logical>,
-    pointer_image: &MemoryRenderBuffer,
     pointer_element: &mut PointerElement,
-    dnd_icon: &Option<wl_surface::WlSurface>,
+    dnd_icon: &Option<DndIcon>,
+    overlays: &mut Overlays<'_>,
     cursor_status: &mut CursorImageStatus,
     clock: &Clock<Monotonic>,
     config: crate::state::Configuration,
 ) -> Result<bool, SwapBuffersError> {
-    let scale = Scale::from(output.current_scale().fractional_scale());
-
-    let mut custom_elements: Vec<CustomRenderElements<_>> = Vec::new();
+    let mut custom_elements = overlays.render_elements(renderer, output, &config);
     let mut background_element: Option<CustomRenderElements<_>> = None;
 
-    let mut maybe_window = None;
+    let (maybe_window, _) = shown_window(window_elements);
 
-    for element in window_elements {
-        if element.is_wayland() {
-            maybe_window = Some(element.clone());
-            break;
-        }
-        if let Some(x11surf) = element.x11_surface() {
-            if !x11surf.is_override_redirect() {
-                maybe_window = Some(element.clone());
-                break;
-            }
-        }
+    let content_policy = maybe_window
+        .as_ref()
+        .map(window_content_policy)
+        .unwrap_or_default();
+    if surface.content_policy != Some(content_policy) {
+        surface.apply_content_policy(content_policy);
     }
 
-    let (scale, offset) = if let Some(window) = maybe_window {
-        let layer_map = smithay::desktop::layer_map_for_output(output);
-        let constrain = layer_map.non_exclusive_zone().size.to_f64();
-        let reference = window.bbox().size.to_f64();
-
-        let mouse_scale: Scale<f64> = constrain / reference;
-        let mouse_scale = Scale::from(f64::min(mouse_scale.x, mouse_scale.y));
-
-        let scaled_reference = reference.to_f64().upscale(mouse_scale);
-        let top_offset = (constrain.h as f64 - scaled_reference.h as f64) / 2f64;
-        let left_offset = (constrain.w as f64 - scaled_reference.w as f64) / 2f64;
-        let offset: Point<f64, Physical> = Point::from((left_offset, top_offset));
-        (mouse_scale, offset)
-    } else {
-        let offset: Point<f64, Physical> = Point::from((0 as f64, 0 as f64));
-        (scale, offset)
-    };
-
-    let cursor_pos = pointer_location;
-    let cursor_pos_scaled = cursor_pos.to_physical(scale);
-    let cursor_pos_scaled: Point<i32, Physical> = (cursor_pos_scaled + offset).to_i32_round();
+    let (scale, cursor_pos_scaled) =
+        pointer_position(output, maybe_window.as_ref(), pointer_location, &config);
     // set cursor
-    pointer_element.set_buffer(pointer_image.clone());
+    pointer_element.set_cursor_size(surface.cursor_size);
 
     // draw the cursor as relevant
     {
@@ -1968,12 +3011,13 @@ fn render_surface<'a>(
 
     // draw the dnd icon if applicable
     {
-        if let Some(wl_surface) = dnd_icon.as_ref() {
-            if wl_surface.alive() {
+        if let Some(icon) = dnd_icon.as_ref() {
+            if icon.surface.alive() {
+                let icon_pos = cursor_pos_scaled + icon.offset.to_physical_precise_round(scale);
                 custom_elements.extend(AsRenderElements::<UdevRenderer<'a>>::render_elements(
-                    &SurfaceTree::from_surface(wl_surface),
+                    &SurfaceTree::from_surface(&icon.surface),
                     renderer,
-                    cursor_pos_scaled,
+                    icon_pos,
                     scale,
                     1.0,
                 ));
@@ -2021,21 +3065,46 @@ fn render_surface<'a>(
         }
     }
 
+    if splash {
+        if let Some(splash) = surface.splash.as_mut() {
+            custom_elements.extend(splash.render_elements(output));
+        }
+    } else {
+        surface.splash = None;
+    }
+
     let (elements, clear_color) = output_elements(
         output,
         window_elements,
+        layout,
+        transition,
+        &config,
         custom_elements,
         background_element,
         renderer,
     );
+    let elements = zoom_elements(elements, overlays.zoom);
+    let filtered = overlays.color_filter.and_then(|filter| {
+        surface
+            .color_filter
+            .render(renderer, output, &elements, clear_color, filter)
+    });
     let SurfaceCompositorRenderResult {
         rendered,
         states,
         sync,
         damage,
-    } = surface
-        .compositor
-        .render_frame::<_, _, GlesTexture>(renderer, &elements, clear_color)?;
+    } = match filtered {
+        Some((element, states)) => surface
+            .compositor
+            .render_frame::<_, _, GlesTexture>(renderer, &[element], clear_color)
+            .map(|result| SurfaceCompositorRenderResult { states, ..result })?,
+        None => surface.compositor.render_frame::<_, _, GlesTexture>(
+            renderer,
+            &elements,
+            clear_color,
+        )?,
+    };
 
     post_repaint(
         output,
@@ -2049,12 +3118,15 @@ fn render_surface<'a>(
                 scanout_feedback: &feedback.scanout_feedback,
             }),
         clock.now(),
+        &config,
     );
 
     if rendered {
         let output_presentation_feedback =
             take_presentation_feedback(output, window_elements, &states);
         let damage = damage.cloned();
+        // TODO flip async when the shown window allows tearing and the device
+        // reports DRM_CAP_ASYNC_PAGE_FLIP, once the DrmCompositor takes page flip flags
         surface
             .compositor
             .queue_frame(sync, damage, Some(output_presentation_feedback))
@@ -2076,3 +3148,17 @@ fn initial_render(
 
     Ok(())
 }
+
+/// Kernel timestamp of input events, in microseconds of the monotonic clock
+fn input_event_time(event: &InputEvent<LibinputInputBackend>) -> Option<u64> {
+    match event {
+        InputEvent::Keyboard { event, .. } => Some(Event::time_usec(event)),
+        InputEvent::PointerMotion { event, .. } => Some(Event::time_usec(event)),
+        InputEvent::PointerMotionAbsolute { event, .. } => Some(Event::time_usec(event)),
+        InputEvent::PointerButton { event, .. } => Some(Event::time_usec(event)),
+        InputEvent::PointerAxis { event, .. } => Some(Event::time_usec(event)),
+        InputEvent::TouchDown { event, .. } => Some(Event::time_usec(event)),
+        InputEvent::TouchMotion { event, .. } => Some(Event::time_usec(event)),
+        _ => None,
+    }
+}
@@ -0,0 +1,254 @@
+use std::{
+    process::{Child, Command, ExitStatus},
+    time::{Duration, Instant},
+};
+
+use smithay::{
+    backend::renderer::element::{
+        solid::{SolidColorBuffer, SolidColorRenderElement},
+        Kind,
+    },
+    input::keyboard::Keysym,
+    output::Output,
+    reexports::calloop::timer::{TimeoutAction, Timer},
+    utils::{Logical, Point, Scale, Size},
+};
+use tracing::{info, warn};
+
+use crate::{
+    input_handler::KeyAction,
+    state::{AnvilState, Backend},
+};
+
+const POLL_INTERVAL: Duration = Duration::from_millis(250);
+/// Time the overlay is shown before the app is started again
+const RESTART_DELAY: Duration = Duration::from_secs(5);
+/// Exiting this often within `CRASH_WINDOW` stops automatic restarts
+const MAX_CRASHES: usize = 3;
+const CRASH_WINDOW: Duration = Duration::from_secs(60);
+
+const BACKDROP_COLOR: [f32; 4] = [0.15, 0.0, 0.0, 0.85];
+const BAR_COLOR: [f32; 4] = [0.9, 0.9, 0.9, 1.0];
+const BAR_HEIGHT: i32 = 8;
+
+#[derive(Debug)]
+enum AppState {
+    Running(Child),
+    /// Restarted automatically at `restart_at`, or only on request when `None`
+    Exited {
+        since: Instant,
+        restart_at: Option<Instant>,
+    },
+    /// The user chose to go on without the app
+    Dismissed,
+}
+
+/// Primary application of a kiosk session given with `--app`.
+///
+/// When it exits an overlay covers the outputs, counting down to a restart.
+/// Enter restarts it right away, Escape gives up on it
+#[derive(Debug)]
+pub struct AppWatchdog {
+    command: Vec<String>,
+    state: AppState,
+    exits: Vec<Instant>,
+    backdrop: SolidColorBuffer,
+    bar: SolidColorBuffer,
+}
+
+impl AppWatchdog {
+    pub fn new(command: Vec<String>) -> Self {
+        Self {
+            command,
+            state: AppState::Exited {
+                since: Instant::now(),
+                restart_at: Some(Instant::now()),
+            },
+            exits: Vec::new(),
+            backdrop: SolidColorBuffer::new((0, 0), BACKDROP_COLOR),
+            bar: SolidColorBuffer::new((0, 0), BAR_COLOR),
+        }
+    }
+
+    /// Process of the application while it runs
+    pub fn pid(&self) -> Option<u32> {
+        match &self.state {
+            AppState::Running(child) => Some(child.id()),
+            _ => None,
+        }
+    }
+
+    /// Whether the error overlay covers the outputs
+    pub fn overlay_shown(&self) -> bool {
+        matches!(self.state, AppState::Exited { .. })
+    }
+
+    fn exited(&mut self, status: Option<ExitStatus>) {
+        warn!(command = ?self.command, ?status, "Application exited");
+        let now = Instant::now();
+        self.exits
+            .retain(|exit| now.duration_since(*exit) < CRASH_WINDOW);
+        self.exits.push(now);
+        let restart_at = if self.exits.len() >= MAX_CRASHES {
+            warn!("Application keeps exiting, waiting for a retry");
+            None
+        } else {
+            Some(now + RESTART_DELAY)
+        };
+        self.state = AppState::Exited {
+            since: now,
+            restart_at,
+        };
+    }
+
+    pub fn render_elements(&mut self, output: &Output) -> Vec<SolidColorRenderElement> {
+        let AppState::Exited { since, restart_at } = self.state else {
+            return Vec::new();
+        };
+        let Some(mode) = output.current_mode() else {
+            return Vec::new();
+        };
+        let size: Size<i32, Logical> = output
+            .current_transform()
+            .transform_size(mode.size)
+            .to_logical(1);
+        self.backdrop.resize(size);
+
+        let mut elements = Vec::new();
+        // The bar shrinks until the restart
+        if let Some(restart_at) = restart_at {
+            let total = restart_at.saturating_duration_since(since).as_secs_f64();
+            let left = restart_at
+                .saturating_duration_since(Instant::now())
+                .as_secs_f64();
+            let width = if total > 0.0 {
+                (size.w as f64 / 2.0 * left / total).round() as i32
+            } else {
+                0
+            };
+            self.bar.resize((width, BAR_HEIGHT));
+            elements.push(SolidColorRenderElement::from_buffer(
+                &self.bar,
+                Point::from(((size.w - width) / 2, size.h * 3 / 4)),
+                Scale::from(1.0),
+                1.0,
+                Kind::Unspecified,
+            ));
+        }
+        elements.push(SolidColorRenderElement::from_buffer(
+            &self.backdrop,
+            (0, 0),
+            Scale::from(1.0),
+            1.0,
+            Kind::Unspecified,
+        ));
+        elements
+    }
+}
+
+impl Drop for AppWatchdog {
+    fn drop(&mut self) {
+        if let AppState::Running(child) = &mut self.state {
+            let _ = child.kill();
+            let _ = child.wait();
+        }
+    }
+}
+
+impl<BackendData: Backend> AnvilState<BackendData> {
+    /// Launch `command` and keep it running
+    pub fn start_app_watchdog(&mut self, command: Vec<String>) {
+        if command.is_empty() {
+            warn!("No application given to --app");
+            return;
+        }
+        self.app_watchdog = Some(AppWatchdog::new(command));
+        let res = self.handle.insert_source(Timer::immediate(), |_, _, data| {
+            data.poll_app_watchdog();
+            TimeoutAction::ToDuration(POLL_INTERVAL)
+        });
+        if let Err(err) = res {
+            warn!(?err, "Unable to insert app watchdog timer");
+        }
+    }
+
+    fn poll_app_watchdog(&mut self) {
+        let Some(watchdog) = self.app_watchdog.as_mut() else {
+            return;
+        };
+        let restart = match &mut watchdog.state {
+            AppState::Running(child) => {
+                match child.try_wait() {
+                    Ok(None) => {}
+                    Ok(status) => watchdog.exited(status),
+                    Err(err) => {
+                        warn!(?err, "Unable to check application");
+                        watchdog.exited(None);
+                    }
+                }
+                false
+            }
+            AppState::Exited {
+                restart_at: Some(restart_at),
+                ..
+            } => Instant::now() >= *restart_at,
+            _ => false,
+        };
+        if restart {
+            self.restart_app();
+        }
+    }
+
+    fn restart_app(&mut self) {
+        let env = self.client_environment();
+        let Some(watchdog) = self.app_watchdog.as_mut() else {
+            return;
+        };
+        match Command::new(&watchdog.command[0])
+            .args(&watchdog.command[1..])
+            .envs(env)
+            .spawn()
+        {
+            Ok(child) => {
+                info!(command = ?watchdog.command, pid = child.id(), "Started application");
+                self.launched.insert(child.id());
+                watchdog.state = AppState::Running(child);
+            }
+            Err(err) => {
+                warn!(command = ?watchdog.command, ?err, "Unable to start application");
+                watchdog.exited(None);
+            }
+        }
+    }
+
+    /// Keys choosing between the options of the error overlay
+    pub fn app_watchdog_key_action(&self, keysym: Keysym) -> Option<KeyAction> {
+        self.app_watchdog
+            .as_ref()
+            .filter(|watchdog| watchdog.overlay_shown())?;
+        match keysym {
+            Keysym::Return | Keysym::KP_Enter | Keysym::space => Some(KeyAction::RetryApp),
+            Keysym::Escape => Some(KeyAction::DismissApp),
+            _ => None,
+        }
+    }
+
+    pub fn retry_app(&mut self) {
+        if self
+            .app_watchdog
+            .as_ref()
+            .is_some_and(AppWatchdog::overlay_shown)
+        {
+            self.restart_app();
+        }
+    }
+
+    pub fn dismiss_app(&mut self) {
+        if let Some(watchdog) = self.app_watchdog.as_mut() {
+            if watchdog.overlay_shown() {
+                info!("Application dismissed");
+                watchdog.state = AppState::Dismissed;
+            }
+        }
+    }
+}
@@ -0,0 +1,260 @@
+use std::{
+    fmt,
+    time::{Duration, Instant},
+};
+
+use rusttype::Font;
+use smithay::{
+    backend::{
+        allocator::Fourcc,
+        renderer::{
+            element::{
+                memory::{MemoryRenderBuffer, MemoryRenderBufferRenderElement},
+                Kind,
+            },
+            ImportMem, Renderer,
+        },
+    },
+    desktop::Window,
+    output::Output,
+    reexports::calloop::timer::{TimeoutAction, Timer},
+    utils::{IsAlive, Physical, Point, Size, Transform},
+};
+use tracing::{debug, warn};
+
+use crate::{
+    shell::{is_hidden, is_popup, window_name},
+    state::{AnvilState, Backend},
+    text::{draw_text, load_font, text_width},
+};
+
+const BOX_MARGIN: i32 = 32;
+const BOX_PADDING: i32 = 12;
+const LINE_HEIGHT: i32 = 22;
+const MIN_WIDTH: i32 = 160;
+const MAX_WIDTH: i32 = 640;
+const FONT_SIZE: f32 = 16.0;
+/// Premultiplied BGRA
+const BACKDROP_COLOR: [u8; 4] = [20, 20, 20, 230];
+/// How long presses keep walking the same cycle when no title is shown
+const CYCLE_TIMEOUT: Duration = Duration::from_secs(1);
+
+#[derive(Debug)]
+struct Indicator {
+    title: String,
+    until: Instant,
+    /// Drawn indicator, its size and the output scale it was drawn for
+    buffer: Option<(f64, MemoryRenderBuffer, Size<i32, Physical>)>,
+}
+
+/// Windows from the most to the least recently used when cycling started, and
+/// the one switched to
+#[derive(Debug)]
+struct Cycle {
+    order: Vec<Window>,
+    index: usize,
+    until: Instant,
+}
+
+/// Switching windows by recency, and the title of the window switched to shown
+/// briefly at the top of every output
+#[derive(Default)]
+pub struct WindowCycling {
+    font: Option<Font<'static>>,
+    indicator: Option<Indicator>,
+    cycle: Option<Cycle>,
+}
+
+impl fmt::Debug for WindowCycling {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("WindowCycling")
+            .field("font", &self.font.is_some())
+            .field("indicator", &self.indicator)
+            .field("cycle", &self.cycle)
+            .finish()
+    }
+}
+
+/// Backdrop with the title, in physical pixels
+fn draw_indicator(
+    font: Option<&Font<'static>>,
+    title: &str,
+    scale: f64,
+) -> (MemoryRenderBuffer, Size<i32, Physical>) {
+    let scaled = |value: i32| (value as f64 * scale).round() as i32;
+    let padding = scaled(BOX_PADDING);
+    let size = FONT_SIZE * scale as f32;
+    let text = font.map_or(0, |font| text_width(font, title, size).ceil() as i32);
+    let width = (text + padding * 2).clamp(scaled(MIN_WIDTH), scaled(MAX_WIDTH));
+    let height = padding * 2 + scaled(LINE_HEIGHT);
+
+    let mut pixels = BACKDROP_COLOR.repeat((width * height) as usize);
+    if let Some(font) = font {
+        // Centered, unless cut off at the right
+        let x = ((width - text) / 2).max(padding);
+        let origin = (x as f32, padding as f32);
+        draw_text(
+            &mut pixels,
+            width,
+            font,
+            title,
+            size,
+            origin,
+            width - padding,
+        );
+    }
+
+    let buffer = MemoryRenderBuffer::from_slice(
+        &pixels,
+        Fourcc::Argb8888,
+        (width, height),
+        1,
+        Transform::Normal,
+        None,
+    );
+    (buffer, Size::from((width, height)))
+}
+
+impl WindowCycling {
+    /// The indicator at the top center of `output`
+    pub fn render_elements<R>(
+        &mut self,
+        renderer: &mut R,
+        output: &Output,
+    ) -> Vec<MemoryRenderBufferRenderElement<R>>
+    where
+        R: Renderer + ImportMem,
+        <R as Renderer>::TextureId: Send + Clone + 'static,
+    {
+        let Some(indicator) = self.indicator.as_mut() else {
+            return Vec::new();
+        };
+        let Some(mode) = output.current_mode() else {
+            return Vec::new();
+        };
+        let size: Size<i32, Physical> = output.current_transform().transform_size(mode.size);
+        let scale = output.current_scale().fractional_scale();
+        let margin = (BOX_MARGIN as f64 * scale).round() as i32;
+
+        if indicator.buffer.as_ref().map(|(drawn, ..)| *drawn) != Some(scale) {
+            let (buffer, size) = draw_indicator(self.font.as_ref(), &indicator.title, scale);
+            indicator.buffer = Some((scale, buffer, size));
+        }
+        let (_, buffer, indicator_size) = indicator.buffer.as_ref().unwrap();
+        let location = Point::<i32, Physical>::from(((size.w - indicator_size.w) / 2, margin));
+        match MemoryRenderBufferRenderElement::from_buffer(
+            renderer,
+            location.to_f64(),
+            buffer,
+            None,
+            None,
+            None,
+            Kind::Unspecified,
+        ) {
+            Ok(element) => vec![element],
+            Err(err) => {
+                warn!(?err, "Unable to upload window title");
+                Vec::new()
+            }
+        }
+    }
+}
+
+impl<BackendData: Backend> AnvilState<BackendData> {
+    /// Load the font of the indicator
+    pub fn start_window_cycling(&mut self) {
+        let config = &self.config.window_cycling;
+        if config.indicator_secs > 0.0 {
+            self.window_cycling.font = load_font(config.font.as_deref());
+        }
+    }
+
+    /// Switch to the most recently used other window. Pressing again while its
+    /// title shows, or while Alt is held, goes on to the one used before it
+    pub fn next_window(&mut self) {
+        self.cycle_windows(1);
+    }
+
+    /// Switch to the least recently used window, going further back on every press
+    pub fn previous_window(&mut self) {
+        self.cycle_windows(-1);
+    }
+
+    /// Stop walking the current cycle, the window switched to is now the most
+    /// recently used and the one before it is one press away again
+    pub fn end_window_cycle(&mut self) {
+        self.window_cycling.cycle = None;
+    }
+
+    fn cycle_windows(&mut self, step: isize) {
+        if self.kiosk_enabled() {
+            return;
+        }
+        let now = Instant::now();
+        let mut cycle = match self.window_cycling.cycle.take() {
+            Some(cycle) if cycle.until > now => cycle,
+            _ => Cycle {
+                order: self
+                    .elements
+                    .iter()
+                    .filter(|window| !is_popup(window) && !is_hidden(window))
+                    .cloned()
+                    .collect(),
+                index: 0,
+                until: now,
+            },
+        };
+        cycle.order.retain(|window| window.alive());
+        let len = cycle.order.len() as isize;
+        if len < 2 {
+            return;
+        }
+        cycle.index = (cycle.index as isize + step).rem_euclid(len) as usize;
+        let window = cycle.order[cycle.index].clone();
+        self.raise_window(&window);
+
+        let indicator = self.config.window_cycling.indicator_secs;
+        let duration = if indicator > 0.0 {
+            Duration::from_secs_f64(indicator)
+        } else {
+            CYCLE_TIMEOUT
+        };
+        let until = now + duration;
+        cycle.until = until;
+        self.window_cycling.cycle = Some(cycle);
+        if indicator > 0.0 {
+            let title = window_name(&window);
+            debug!(title, "Switched window");
+            self.window_cycling.indicator = Some(Indicator {
+                title,
+                until,
+                buffer: None,
+            });
+        }
+
+        let res = self
+            .handle
+            .insert_source(Timer::from_duration(duration), move |_, _, data| {
+                // Switching again started a new timer
+                let cycling = &mut data.window_cycling;
+                if cycling
+                    .indicator
+                    .as_ref()
+                    .is_some_and(|indicator| indicator.until == until)
+                {
+                    cycling.indicator = None;
+                }
+                if cycling
+                    .cycle
+                    .as_ref()
+                    .is_some_and(|cycle| cycle.until == until)
+                {
+                    cycling.cycle = None;
+                }
+                TimeoutAction::Drop
+            });
+        if let Err(err) = res {
+            warn!(?err, "Unable to insert window cycling timer");
+        }
+    }
+}
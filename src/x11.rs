@@ -0,0 +1,397 @@
+use std::{collections::HashSet, sync::atomic::Ordering};
+
+#[cfg(feature = "egl")]
+use smithay::backend::renderer::ImportEgl;
+use smithay::{
+    backend::{
+        allocator::{
+            dmabuf::{Dmabuf, DmabufAllocator},
+            gbm::{GbmAllocator, GbmBufferFlags, GbmDevice},
+        },
+        egl::{EGLContext, EGLDisplay},
+        input::LedState,
+        renderer::{damage::OutputDamageTracker, gles::GlesRenderer, ImportDma, ImportMemWl},
+        x11::{WindowBuilder, X11Backend, X11Event, X11Surface},
+    },
+    delegate_dmabuf,
+    desktop::Window,
+    output::{Mode as WlMode, Output, PhysicalProperties, Subpixel},
+    reexports::{
+        calloop::EventLoop,
+        wayland_server::{protocol::wl_surface::WlSurface, Display},
+    },
+    utils::{DeviceFd, Logical, Physical, Size},
+    wayland::dmabuf::{
+        DmabufFeedbackBuilder, DmabufGlobal, DmabufHandler, DmabufState, ImportNotifier,
+    },
+};
+use tracing::{error, info, warn};
+
+use crate::{
+    color_filter::ColorFilterPass,
+    render::{render_output, CustomRenderElements},
+    state::{post_repaint, AnvilState, Backend},
+};
+
+const OUTPUT_NAME: &str = "x11";
+/// The X server doesn't tell the refresh rate of the host window
+const REFRESH_RATE: i32 = 60_000;
+
+pub struct X11Data {
+    /// Set when the host presented the last frame or asked for a new one
+    render: bool,
+    surface: X11Surface,
+    renderer: GlesRenderer,
+    damage_tracker: OutputDamageTracker,
+    color_filter: ColorFilterPass,
+    dmabuf_state: Option<(DmabufState, DmabufGlobal)>,
+}
+
+impl std::fmt::Debug for X11Data {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("X11Data")
+            .field("render", &self.render)
+            .finish_non_exhaustive()
+    }
+}
+
+impl DmabufHandler for AnvilState<X11Data> {
+    fn dmabuf_state(&mut self) -> &mut DmabufState {
+        &mut self.backend_data.dmabuf_state.as_mut().unwrap().0
+    }
+
+    fn dmabuf_imported(
+        &mut self,
+        _global: &DmabufGlobal,
+        dmabuf: Dmabuf,
+        notifier: ImportNotifier,
+    ) {
+        if self
+            .backend_data
+            .renderer
+            .import_dmabuf(&dmabuf, None)
+            .is_ok()
+        {
+            let _ = notifier.successful::<AnvilState<X11Data>>();
+        } else {
+            notifier.failed();
+        }
+    }
+}
+delegate_dmabuf!(AnvilState<X11Data>);
+
+impl Backend for X11Data {
+    fn seat_name(&self) -> String {
+        String::from("x11")
+    }
+
+    fn reset_buffers(&mut self, _output: &Output) {
+        self.surface.reset_buffers();
+    }
+
+    fn early_import(&mut self, _surface: &WlSurface) {}
+
+    fn update_led_state(&mut self, _led_state: LedState) {}
+
+    fn capture_window(&mut self, _window: &Window, _size: Size<i32, Physical>) -> Option<Dmabuf> {
+        None
+    }
+
+    fn sample_window(&mut self, _window: &Window, _size: Size<i32, Physical>) -> Option<Vec<u8>> {
+        None
+    }
+}
+
+fn window_mode(size: Size<u16, Logical>) -> WlMode {
+    WlMode {
+        size: (size.w as i32, size.h as i32).into(),
+        refresh: REFRESH_RATE,
+    }
+}
+
+/// Run nested in a window of the X server of `DISPLAY`, rendering on its gpu with
+/// the same render path as udev
+pub fn run_x11(app: Option<Vec<String>>, socket: Option<String>) {
+    let mut event_loop = EventLoop::try_new().unwrap();
+    let display = Display::new().unwrap();
+    let display_handle = display.handle();
+
+    let backend = match X11Backend::new() {
+        Ok(backend) => backend,
+        Err(err) => {
+            error!("Could not connect to the X server: {}", err);
+            return;
+        }
+    };
+    let handle = backend.handle();
+    let (node, fd) = match handle.drm_node() {
+        Ok(node) => node,
+        Err(err) => {
+            error!("Could not get the DRM node of the X server: {}", err);
+            return;
+        }
+    };
+    let device = match GbmDevice::new(DeviceFd::from(fd)) {
+        Ok(device) => device,
+        Err(err) => {
+            error!("Could not create a gbm device: {}", err);
+            return;
+        }
+    };
+    let context =
+        match unsafe { EGLDisplay::new(device.clone()) }.and_then(|egl| EGLContext::new(&egl)) {
+            Ok(context) => context,
+            Err(err) => {
+                error!("Could not create an EGL context: {}", err);
+                return;
+            }
+        };
+    let modifiers = context
+        .dmabuf_render_formats()
+        .iter()
+        .map(|format| format.modifier)
+        .collect::<HashSet<_>>();
+
+    let window = match WindowBuilder::new().title("Consolation").build(&handle) {
+        Ok(window) => window,
+        Err(err) => {
+            error!("Could not create the host window: {}", err);
+            return;
+        }
+    };
+    let surface = match handle.create_surface(
+        &window,
+        DmabufAllocator(GbmAllocator::new(device, GbmBufferFlags::RENDERING)),
+        modifiers.into_iter(),
+    ) {
+        Ok(surface) => surface,
+        Err(err) => {
+            error!("Could not create the host window surface: {}", err);
+            return;
+        }
+    };
+    let renderer = match unsafe { GlesRenderer::new(context) } {
+        Ok(renderer) => renderer,
+        Err(err) => {
+            error!("Could not create a renderer: {}", err);
+            return;
+        }
+    };
+
+    let mode = window_mode(window.size());
+    let output = Output::new(
+        OUTPUT_NAME.to_string(),
+        PhysicalProperties {
+            size: (0, 0).into(),
+            subpixel: Subpixel::Unknown,
+            make: "Consolation".into(),
+            model: "X11".into(),
+        },
+    );
+    output.change_current_state(Some(mode), None, None, None);
+    output.set_preferred(mode);
+
+    let data = X11Data {
+        render: true,
+        surface,
+        renderer,
+        damage_tracker: OutputDamageTracker::from_output(&output),
+        color_filter: ColorFilterPass::default(),
+        dmabuf_state: None,
+    };
+    let mut state = AnvilState::init(display, event_loop.handle(), data, true, socket);
+    state.start_metrics_server();
+    state.sync_services();
+    state.start_dock_detection();
+    state.start_letterbox_detection();
+    state.start_reaping();
+    state.start_notifications();
+    state.start_progress_reports();
+    state.start_window_cycling();
+    state.start_clock();
+    state.start_status_overlay();
+    state.start_config_reload();
+    state.start_log_retrieval();
+    state.start_inspector();
+    state.start_protocol_trace();
+    state.start_picture_in_picture();
+    state.start_split_screen();
+
+    state
+        .shm_state
+        .update_formats(state.backend_data.renderer.shm_formats());
+    #[cfg(feature = "egl")]
+    match state.backend_data.renderer.bind_wl_display(&display_handle) {
+        Ok(_) => info!("EGL hardware-acceleration enabled"),
+        Err(err) => info!(?err, "Failed to initialize EGL hardware-acceleration"),
+    }
+    let dmabuf_formats = state.backend_data.renderer.dmabuf_formats();
+    let default_feedback = DmabufFeedbackBuilder::new(node.dev_id(), dmabuf_formats)
+        .build()
+        .unwrap();
+    let mut dmabuf_state = DmabufState::new();
+    let global = dmabuf_state.create_global_with_default_feedback::<AnvilState<X11Data>>(
+        &display_handle,
+        &default_feedback,
+    );
+    state.backend_data.dmabuf_state = Some((dmabuf_state, global));
+
+    let _global = output.create_global::<AnvilState<X11Data>>(&display_handle);
+    state.outputs.push(output.clone());
+
+    event_loop
+        .handle()
+        .insert_source(backend, move |event, _, data| match event {
+            X11Event::CloseRequested { .. } => {
+                data.running.store(false, Ordering::SeqCst);
+            }
+            X11Event::Resized { new_size, .. } => {
+                let mode = window_mode(new_size);
+                if let Some(current) = output.current_mode() {
+                    output.delete_mode(current);
+                }
+                output.change_current_state(Some(mode), None, None, None);
+                output.set_preferred(mode);
+                data.backend_data.render = true;
+            }
+            X11Event::PresentCompleted { .. } | X11Event::Refresh { .. } => {
+                data.backend_data.render = true;
+            }
+            X11Event::Input { event, .. } => data.process_input_event_windowed(event, OUTPUT_NAME),
+            X11Event::Focus { focused: false, .. } => data.release_all_keys(),
+            _ => {}
+        })
+        .unwrap();
+
+    #[cfg(feature = "xwayland")]
+    if state.config.xwayland.on_demand {
+        state.start_xwayland_on_demand();
+    } else {
+        state.start_xwayland();
+    }
+    state.start_session_applications();
+    if let Some(app) = app {
+        state.start_app_watchdog(app);
+    }
+    info!("Running nested in X11");
+
+    while state.running.load(Ordering::SeqCst) {
+        if state.backend_data.render {
+            state.render_x11();
+        }
+        let timeout = state.dispatch_timeout();
+        let result = event_loop.dispatch(timeout, &mut state);
+        if result.is_err() {
+            state.running.store(false, Ordering::SeqCst);
+        } else {
+            state.refresh_windows();
+            state.refresh_protocol_state();
+            display_handle.flush_clients().unwrap();
+        }
+    }
+}
+
+impl AnvilState<X11Data> {
+    fn render_x11(&mut self) {
+        let Some(output) = self.outputs.first().cloned() else {
+            return;
+        };
+        let layout = self.window_layout();
+        let zoom = self.output_zoom(&output);
+        let color_filter = self.active_color_filter();
+        let backend_data = &mut self.backend_data;
+        let (buffer, age) = match backend_data.surface.buffer() {
+            Ok(buffer) => buffer,
+            Err(err) => {
+                warn!(?err, "Unable to get a buffer of the host window");
+                return;
+            }
+        };
+        let renderer = &mut backend_data.renderer;
+
+        let mut custom_elements: Vec<CustomRenderElements<GlesRenderer>> = Vec::new();
+        if let Some(watchdog) = self.app_watchdog.as_mut() {
+            custom_elements.extend(
+                watchdog
+                    .render_elements(&output)
+                    .into_iter()
+                    .map(CustomRenderElements::Solid),
+            );
+        }
+        if let Some(text_entry) = self.text_entry.as_mut() {
+            custom_elements.extend(
+                text_entry
+                    .render_elements(renderer, &output)
+                    .into_iter()
+                    .map(CustomRenderElements::Memory),
+            );
+        }
+        custom_elements.extend(
+            self.notifications
+                .render_elements(renderer, &output)
+                .into_iter()
+                .map(CustomRenderElements::Memory),
+        );
+        custom_elements.extend(
+            self.progress
+                .render_elements(renderer, &output)
+                .into_iter()
+                .map(CustomRenderElements::Memory),
+        );
+        custom_elements.extend(
+            self.window_cycling
+                .render_elements(renderer, &output)
+                .into_iter()
+                .map(CustomRenderElements::Memory),
+        );
+        custom_elements.extend(
+            self.time
+                .render_elements(renderer, &output, &self.config.time)
+                .into_iter()
+                .map(CustomRenderElements::Memory),
+        );
+        custom_elements.extend(
+            self.status
+                .render_elements(renderer, &output, self.config.time.twenty_four_hour)
+                .into_iter()
+                .map(CustomRenderElements::Memory),
+        );
+
+        let states = match render_output(
+            &output,
+            &self.elements,
+            &layout,
+            self.window_transitions.current.as_ref(),
+            &self.config,
+            custom_elements,
+            None,
+            renderer,
+            buffer,
+            &mut backend_data.damage_tracker,
+            age.into(),
+            zoom,
+            color_filter.map(|filter| (filter, &mut backend_data.color_filter)),
+        ) {
+            Ok(result) => result.states,
+            Err(err) => {
+                warn!(?err, "Unable to render the host window");
+                backend_data.surface.reset_buffers();
+                return;
+            }
+        };
+        if let Err(err) = backend_data.surface.submit() {
+            warn!(?err, "Unable to present the host window");
+            backend_data.surface.reset_buffers();
+            return;
+        }
+        backend_data.render = false;
+        post_repaint(
+            &output,
+            &states,
+            &self.elements,
+            None,
+            self.clock.now(),
+            &self.config,
+        );
+    }
+}
@@ -0,0 +1,282 @@
+use std::{
+    fs::{self, OpenOptions},
+    io::{self, Write},
+    os::{
+        linux::net::SocketAddrExt,
+        unix::net::{SocketAddr, UnixListener, UnixStream},
+    },
+    sync::Arc,
+    thread,
+    time::{Duration, Instant},
+};
+
+use smithay::reexports::{
+    calloop::{
+        generic::Generic,
+        timer::{TimeoutAction, Timer},
+        Interest, Mode, PostAction, RegistrationToken,
+    },
+    wayland_server::{backend::DisconnectReason, Client},
+};
+use tracing::{info, warn};
+
+use crate::{
+    protocol_trace::relay,
+    state::{AnvilState, Backend},
+};
+
+const POLL_INTERVAL: Duration = Duration::from_secs(1);
+/// Display numbers tried in turn, like Xwayland does
+const DISPLAYS: std::ops::Range<u32> = 0..33;
+
+/// X11 sockets held open until a client connects, to then start Xwayland on their
+/// display. The clients that connected before it are relayed to it
+#[derive(Debug)]
+pub struct XWaylandOnDemand {
+    display: u32,
+    /// Held while no server runs, shared with the event loop sources
+    listeners: Vec<Arc<UnixListener>>,
+    tokens: Vec<RegistrationToken>,
+    /// The Xwayland source, holding the display while it runs, and its client
+    running: Option<(RegistrationToken, Client)>,
+    /// Since when the running server has no windows
+    idle_since: Option<Instant>,
+}
+
+impl Drop for XWaylandOnDemand {
+    fn drop(&mut self) {
+        // A running Xwayland frees the display itself
+        if self.running.is_none() {
+            let _ = fs::remove_file(lock_path(self.display));
+            let _ = fs::remove_file(socket_path(self.display));
+        }
+    }
+}
+
+fn lock_path(display: u32) -> String {
+    format!("/tmp/.X{display}-lock")
+}
+
+fn socket_path(display: u32) -> String {
+    format!("/tmp/.X11-unix/X{display}")
+}
+
+/// Take the lock file of `display`, the way X servers do
+fn lock_display(display: u32) -> io::Result<()> {
+    let mut lock = OpenOptions::new()
+        .write(true)
+        .create_new(true)
+        .open(lock_path(display))?;
+    writeln!(lock, "{:>10}", std::process::id())
+}
+
+/// The path socket and the abstract one of `display`
+fn bind_display(display: u32) -> io::Result<Vec<UnixListener>> {
+    let path = socket_path(display);
+    let _ = fs::create_dir_all("/tmp/.X11-unix");
+    let _ = fs::remove_file(&path);
+    let listeners = vec![
+        UnixListener::bind(&path)?,
+        UnixListener::bind_addr(&SocketAddr::from_abstract_name(&path)?)?,
+    ];
+    for listener in &listeners {
+        listener.set_nonblocking(true)?;
+    }
+    Ok(listeners)
+}
+
+/// Lock and bind `display`, leaving nothing behind if it's taken
+fn hold_display(display: u32) -> io::Result<Vec<UnixListener>> {
+    lock_display(display)?;
+    bind_display(display).map_err(|err| {
+        let _ = fs::remove_file(lock_path(display));
+        err
+    })
+}
+
+/// Pass what `client` and the Xwayland of `display` send each other, with the file
+/// descriptors of DRI3 and MIT-SHM
+fn relay_to_xwayland(client: UnixStream, display: u32) -> io::Result<()> {
+    client.set_nonblocking(false)?;
+    let server = UnixStream::connect(socket_path(display))?;
+    let (client_end, server_end) = (client.try_clone()?, server.try_clone()?);
+    thread::spawn(move || relay(client, server, |_| ()));
+    thread::spawn(move || relay(server_end, client_end, |_| ()));
+    Ok(())
+}
+
+impl<BackendData: Backend + 'static> AnvilState<BackendData> {
+    /// Give apps an X11 display without starting Xwayland until one connects
+    pub fn start_xwayland_on_demand(&mut self) {
+        let bound = DISPLAYS
+            .clone()
+            .find_map(|display| Some((display, hold_display(display).ok()?)));
+        let Some((display, listeners)) = bound else {
+            warn!("No free X11 display for Xwayland");
+            return;
+        };
+
+        self.xwayland_on_demand = Some(XWaylandOnDemand {
+            display,
+            listeners: Vec::new(),
+            tokens: Vec::new(),
+            running: None,
+            idle_since: None,
+        });
+        self.watch_x11_sockets(listeners);
+        let res = self
+            .handle
+            .insert_source(Timer::from_duration(POLL_INTERVAL), |_, _, data| {
+                data.poll_xwayland_on_demand();
+                TimeoutAction::ToDuration(POLL_INTERVAL)
+            });
+        if let Err(err) = res {
+            warn!(?err, "Unable to insert Xwayland timer");
+        }
+
+        info!(display, "Waiting for X11 clients");
+        self.xdisplay = Some(display);
+        self.update_systemd_environment();
+    }
+
+    /// Wake up for the first client connecting to `listeners`
+    fn watch_x11_sockets(&mut self, listeners: Vec<UnixListener>) {
+        let Some(on_demand) = self.xwayland_on_demand.as_mut() else {
+            return;
+        };
+        for listener in listeners {
+            let listener = Arc::new(listener);
+            let res = self.handle.insert_source(
+                Generic::new(listener.clone(), Interest::READ, Mode::Level),
+                |_, _, data| {
+                    data.x11_client_waiting();
+                    Ok(PostAction::Continue)
+                },
+            );
+            match res {
+                Ok(token) => on_demand.tokens.push(token),
+                Err(err) => warn!(?err, "Unable to insert X11 socket source"),
+            }
+            on_demand.listeners.push(listener);
+        }
+    }
+
+    /// Take the waiting clients and let go of the sockets, so Xwayland can bind
+    /// them once the sources are gone after this dispatch
+    fn x11_client_waiting(&mut self) {
+        let Some(on_demand) = self.xwayland_on_demand.as_mut() else {
+            return;
+        };
+        if on_demand.listeners.is_empty() {
+            return;
+        }
+        let waiting = on_demand
+            .listeners
+            .iter()
+            .flat_map(|listener| std::iter::from_fn(|| listener.accept().ok()))
+            .map(|(stream, _)| stream)
+            .collect::<Vec<_>>();
+        for token in on_demand.tokens.drain(..) {
+            self.handle.remove(token);
+        }
+        on_demand.listeners.clear();
+        self.handle
+            .insert_idle(move |data| data.start_xwayland_for(waiting));
+    }
+
+    fn start_xwayland_for(&mut self, waiting: Vec<UnixStream>) {
+        let Some(display) = self
+            .xwayland_on_demand
+            .as_ref()
+            .map(|on_demand| on_demand.display)
+        else {
+            return;
+        };
+        let _ = fs::remove_file(lock_path(display));
+        let _ = fs::remove_file(socket_path(display));
+
+        let (source, client) = match self.spawn_xwayland(Some(display)) {
+            Ok(ret) => ret,
+            Err(err) => {
+                // The waiting clients fail, the next ones try again
+                warn!(?err, "Unable to start Xwayland");
+                self.hold_x11_display(display);
+                return;
+            }
+        };
+        for stream in waiting {
+            if let Err(err) = relay_to_xwayland(stream, display) {
+                warn!(?err, "Unable to pass X11 client to Xwayland");
+            }
+        }
+        info!(display, "Started Xwayland");
+        if let Some(on_demand) = self.xwayland_on_demand.as_mut() {
+            on_demand.running = Some((source, client));
+            on_demand.idle_since = Some(Instant::now());
+        }
+    }
+
+    /// Hold `display` again after Xwayland let go of it
+    fn hold_x11_display(&mut self, display: u32) {
+        match hold_display(display) {
+            Ok(listeners) => self.watch_x11_sockets(listeners),
+            Err(err) => warn!(?err, display, "Unable to hold X11 display"),
+        }
+    }
+
+    /// Notice Xwayland exiting, and stop it once idle for long if configured
+    fn poll_xwayland_on_demand(&mut self) {
+        let has_windows = self
+            .elements
+            .iter()
+            .any(|window| window.x11_surface().is_some());
+        let exit_when_idle = self.config.xwayland.exit_when_idle;
+        let Some(on_demand) = self.xwayland_on_demand.as_mut() else {
+            return;
+        };
+        let Some((_, client)) = on_demand.running.as_ref() else {
+            return;
+        };
+        let handle = self.display_handle.backend_handle();
+
+        if handle.get_client_data(client.id()).is_err() {
+            info!("Xwayland exited");
+            self.stop_xwayland_on_demand();
+            return;
+        }
+        if has_windows {
+            on_demand.idle_since = None;
+            return;
+        }
+        let idle_since = *on_demand.idle_since.get_or_insert_with(Instant::now);
+        if let Some(delay) = exit_when_idle {
+            if idle_since.elapsed() >= Duration::from_secs_f64(delay.max(0.0)) {
+                info!("Stopping idle Xwayland");
+                // Xwayland exits with its wayland connection
+                handle.kill_client(client.id(), DisconnectReason::ConnectionClosed);
+                self.stop_xwayland_on_demand();
+            }
+        }
+    }
+
+    /// Drop the window manager and what's left of the X11 windows, and wait for
+    /// clients again
+    fn stop_xwayland_on_demand(&mut self) {
+        let Some(on_demand) = self.xwayland_on_demand.as_mut() else {
+            return;
+        };
+        let Some((source, _)) = on_demand.running.take() else {
+            return;
+        };
+        let display = on_demand.display;
+        self.handle.remove(source);
+        self.xwm = None;
+        let size = self.elements.len();
+        self.elements
+            .retain(|window| window.x11_surface().is_none());
+        if size != self.elements.len() {
+            self.update_keyboard_focus();
+        }
+        self.hold_x11_display(display);
+    }
+}
@@ -0,0 +1,132 @@
+use smithay::{
+    desktop::Window,
+    input::keyboard::{Keysym, ModifiersState},
+    output::Output,
+    utils::{Logical, Point},
+};
+use tracing::debug;
+use xkbcommon::xkb;
+
+use crate::{
+    input_handler::KeyAction,
+    render::{pointer_position, Zoom},
+    shell::shown_window,
+    state::{AnvilState, Backend, Configuration, ZoomModifier},
+};
+
+/// Magnification of the composited output around the pointer
+#[derive(Debug)]
+pub struct ZoomState {
+    /// 1.0 when not zoomed
+    pub level: f64,
+    /// Level restored when toggling zoom on again
+    last: f64,
+}
+
+impl Default for ZoomState {
+    fn default() -> Self {
+        Self {
+            level: 1.0,
+            last: 2.0,
+        }
+    }
+}
+
+impl ZoomState {
+    /// Zoom to apply to `output` when rendering it, around the pointer at
+    /// `pointer_location` over the shown one of `elements`
+    pub fn output_zoom(
+        &self,
+        output: &Output,
+        elements: &[Window],
+        pointer_location: Point<f64, Logical>,
+        config: &Configuration,
+    ) -> Option<Zoom> {
+        if self.level <= 1.0 {
+            return None;
+        }
+        let (_, center) = pointer_position(
+            output,
+            shown_window(elements).0.as_ref(),
+            pointer_location,
+            config,
+        );
+        Some(Zoom {
+            level: self.level,
+            center,
+        })
+    }
+}
+
+impl ZoomModifier {
+    fn is_held(self, modifiers: &ModifiersState) -> bool {
+        match self {
+            Self::Super => modifiers.logo,
+            Self::Alt => modifiers.alt,
+            Self::Ctrl => modifiers.ctrl,
+        }
+    }
+}
+
+impl<BackendData: Backend> AnvilState<BackendData> {
+    fn max_zoom(&self) -> f64 {
+        self.config.zoom.max_level.max(1.0)
+    }
+
+    fn set_zoom(&mut self, level: f64) {
+        let level = level.clamp(1.0, self.max_zoom());
+        if level != self.zoom.level {
+            debug!(level, "Zooming");
+        }
+        self.zoom.level = level;
+        if level > 1.0 {
+            self.zoom.last = level;
+        }
+    }
+
+    /// Zoom in to the level last used, or back out
+    pub fn toggle_zoom(&mut self) {
+        if self.zoom.level > 1.0 {
+            self.set_zoom(1.0);
+        } else {
+            self.set_zoom(self.zoom.last);
+        }
+    }
+
+    /// Check if `keysym` toggles zoom
+    pub fn zoom_key_action(&self, keysym: Keysym) -> Option<KeyAction> {
+        let toggle_key = self.config.zoom.toggle_key.as_ref()?;
+        let toggle_key = xkb::keysym_from_name(toggle_key, xkb::KEYSYM_CASE_INSENSITIVE);
+        (toggle_key.raw() == keysym.raw()).then_some(KeyAction::ToggleZoom)
+    }
+
+    /// Zoom in or out by a vertical scroll of `amount` if the zoom modifier is
+    /// held, in which case the scroll doesn't go to the window
+    pub fn zoom_scroll(&mut self, amount: f64) -> bool {
+        let Some(modifier) = self.config.zoom.scroll_modifier else {
+            return false;
+        };
+        let held = self
+            .seat
+            .get_keyboard()
+            .is_some_and(|keyboard| modifier.is_held(&keyboard.modifier_state()));
+        if !held {
+            return false;
+        }
+        // A wheel click scrolls by 15, which zooms by one step
+        let steps = -amount / 15.0;
+        let step = self.config.zoom.step.max(1.01);
+        self.set_zoom(self.zoom.level * step.powf(steps));
+        true
+    }
+
+    /// Zoom to apply to `output` when rendering it, around the pointer
+    pub fn output_zoom(&self, output: &Output) -> Option<Zoom> {
+        self.zoom.output_zoom(
+            output,
+            &self.elements,
+            self.pointer.current_location(),
+            &self.config,
+        )
+    }
+}